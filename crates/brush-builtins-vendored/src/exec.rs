@@ -1,5 +1,5 @@
 use clap::Parser;
-use std::{borrow::Cow, os::unix::process::CommandExt};
+use std::{borrow::Cow, os::unix::process::CommandExt, path::Path};
 
 use brush_core::{ErrorKind, ExecutionExitCode, ExecutionResult, builtins, commands};
 
@@ -41,6 +41,21 @@ impl builtins::Command for ExecCommand {
             return Ok(ExecutionResult::success());
         }
 
+        // `exec` launches the program itself rather than through
+        // `commands::execute`, so consult the command policy here.
+        let program = if self.args[0].contains(std::path::MAIN_SEPARATOR) {
+            Some(context.shell.absolute_path(Path::new(&self.args[0])))
+        } else {
+            context
+                .shell
+                .find_first_executable_in_path_using_cache(&self.args[0])
+        };
+        if let Some(denied) =
+            commands::check_program_policy(&context, self.args.clone(), program.as_deref()).await
+        {
+            return Ok(denied);
+        }
+
         let mut argv0 = Cow::Borrowed(self.name_for_argv0.as_ref().unwrap_or(&self.args[0]));
 
         if self.exec_as_login {
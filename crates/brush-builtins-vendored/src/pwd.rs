@@ -33,7 +33,11 @@ impl builtins::Command for PwdCommand {
             cwd = cwd.canonicalize()?.into();
         }
 
-        writeln!(context.stdout(), "{}", cwd.to_string_lossy())?;
+        writeln!(
+            context.stdout(),
+            "{}",
+            brush_core::sys::fs::to_shell_path(&cwd)
+        )?;
 
         Ok(ExecutionResult::success())
     }
@@ -1,8 +1,17 @@
 //! Command execution
 
-use std::{borrow::Cow, ffi::OsStr, fmt::Display, process::Stdio, sync::Arc};
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
 
 use brush_parser::ast;
+use futures::future::BoxFuture;
 use itertools::Itertools;
 use sys::commands::{CommandExt, CommandFdInjectionExt, CommandFgControlExt};
 
@@ -16,6 +25,20 @@ use crate::{
     sys, trace_categories, traps, variables,
 };
 
+/// A command about to run, as presented to a [`CommandPolicy`].
+#[derive(Clone, Debug)]
+pub struct CommandCheck {
+    /// The command name followed by its expanded arguments.
+    pub argv: Vec<String>,
+    /// The resolved path of an external program; `None` for builtins.
+    pub program: Option<PathBuf>,
+}
+
+/// Callback consulted before a builtin or external command runs. Returning
+/// `Err` with a reason denies the command, which then fails with exit code 126.
+pub type CommandPolicy =
+    Arc<dyn Fn(CommandCheck) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
 /// Encapsulates the result of waiting for a command to complete.
 pub enum CommandWaitResult {
     /// The command completed.
@@ -317,6 +340,9 @@ pub async fn execute(
         .as_ref()
         .is_some_and(|r| !r.disabled && r.special_builtin)
     {
+        if let Some(denied) = check_command_policy(&cmd_context, &args, None).await {
+            return Ok(denied);
+        }
         return execute_builtin_command(&builtin.unwrap(), cmd_context, args).await;
     }
 
@@ -337,6 +363,9 @@ pub async fn execute(
     // If we found a (non-special) builtin and it's not disabled, then invoke it.
     if let Some(builtin) = builtin {
         if !builtin.disabled {
+            if let Some(denied) = check_command_policy(&cmd_context, &args, None).await {
+                return Ok(denied);
+            }
             return execute_builtin_command(&builtin, cmd_context, args).await;
         }
     }
@@ -358,6 +387,9 @@ pub async fn execute(
         };
 
         if let Some(path) = path {
+            if let Some(denied) = check_command_policy(&cmd_context, &args, Some(&path)).await {
+                return Ok(denied);
+            }
             let resolved_path = path.to_string_lossy();
             execute_external_command(
                 cmd_context,
@@ -370,6 +402,10 @@ pub async fn execute(
         }
     } else {
         let resolved_path = cmd_context.command_name.clone();
+        let program = cmd_context.shell.absolute_path(Path::new(&resolved_path));
+        if let Some(denied) = check_command_policy(&cmd_context, &args, Some(&program)).await {
+            return Ok(denied);
+        }
 
         // Strip the command name off args.
         execute_external_command(
@@ -381,6 +417,38 @@ pub async fn execute(
     }
 }
 
+/// Consults the shell's command policy, if any, about running `args`.
+/// Returns the result to use in place of running the command if it's denied.
+async fn check_command_policy(
+    context: &ExecutionContext<'_>,
+    args: &[CommandArg],
+    program: Option<&Path>,
+) -> Option<ExecutionSpawnResult> {
+    let argv = args.iter().map(ToString::to_string).collect();
+    check_program_policy(context, argv, program)
+        .await
+        .map(Into::into)
+}
+
+/// Consults the shell's command policy, if any, about a program launched
+/// without going through [`execute`], e.g. by `exec`. Returns the result to
+/// use in place of running it if it's denied.
+pub async fn check_program_policy(
+    context: &ExecutionContext<'_>,
+    argv: Vec<String>,
+    program: Option<&Path>,
+) -> Option<ExecutionResult> {
+    let policy = context.shell.command_policy()?.clone();
+    let check = CommandCheck {
+        argv,
+        program: program.map(Path::to_path_buf),
+    };
+    let reason = policy(check).await.err()?;
+
+    let _ = writeln!(context.stderr(), "{}: {reason}", context.command_name);
+    Some(ExecutionResult::new(126))
+}
+
 pub(crate) fn execute_external_command(
     context: ExecutionContext<'_>,
     executable_path: &str,
@@ -431,6 +499,15 @@ pub(crate) fn execute_external_command(
         cmd.take_foreground();
     }
 
+    // A spawn hook sees the process before it runs: on Windows it may place
+    // it in a job object, which must happen before it can start children.
+    #[cfg(windows)]
+    let suspended = context.params.spawn_hook().is_some();
+    #[cfg(windows)]
+    if suspended {
+        sys::commands::start_suspended(&mut cmd, sys::commands::process_group_flags(new_pg));
+    }
+
     // When tracing is enabled, report.
     tracing::debug!(
         target: trace_categories::COMMANDS,
@@ -443,6 +520,15 @@ pub(crate) fn execute_external_command(
 
     match sys::process::spawn(cmd) {
         Ok(child) => {
+            if let (Some(hook), Some(pid)) = (context.params.spawn_hook(), child.id()) {
+                hook(pid);
+                #[cfg(windows)]
+                if suspended {
+                    // Dropping the child kills it should this fail.
+                    sys::commands::resume_process(pid)?;
+                }
+            }
+
             // Retrieve the pid.
             #[expect(clippy::cast_possible_wrap)]
             let pid = child.id().map(|id| id as i32);
@@ -454,9 +540,11 @@ pub(crate) fn execute_external_command(
                 tracing::warn!("could not retrieve pid for child process");
             }
 
-            Ok(ExecutionSpawnResult::StartedProcess(
-                processes::ChildProcess::new(pid, child),
-            ))
+            let mut process = processes::ChildProcess::new(pid, child);
+            if let Some(hook) = context.params.reap_hook() {
+                process.set_reap_hook(hook.clone());
+            }
+            Ok(ExecutionSpawnResult::StartedProcess(process))
         }
         Err(spawn_err) => {
             if context.shell.options.interactive {
@@ -553,6 +641,10 @@ pub(crate) async fn invoke_command_in_subshell_and_get_output(
     params: &ExecutionParameters,
     s: String,
 ) -> Result<String, error::Error> {
+    if !shell.command_substitution_enabled() {
+        return Err(error::ErrorKind::CommandSubstitutionDisabled.into());
+    }
+
     // Instantiate a subshell to run the command in.
     let subshell = shell.clone();
 
@@ -249,6 +249,10 @@ pub enum ErrorKind {
     #[error("command history is not enabled in this shell")]
     HistoryNotEnabled,
 
+    /// Command substitution is disabled in this shell.
+    #[error("command substitution is disabled")]
+    CommandSubstitutionDisabled,
+
     /// Unknown key binding function.
     #[error("unknown key binding function: {0}")]
     UnknownKeyBindingFunction(String),
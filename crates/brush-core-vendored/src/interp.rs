@@ -3,6 +3,7 @@ use itertools::Itertools;
 use std::collections::VecDeque;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use tokio::sync::OwnedSemaphorePermit;
 use tokio_util::sync::CancellationToken;
 use sys::commands::ExitStatusExt;
 
@@ -58,6 +59,13 @@ struct PipelineExecutionContext<'a> {
     process_group_id: Option<i32>,
 }
 
+/// Callback invoked with the pid of each external process spawned.
+pub type SpawnHook = std::sync::Arc<dyn Fn(u32) + Send + Sync>;
+
+/// Callback invoked with the pid of each spawned external process once brush
+/// has collected its exit status.
+pub type ReapHook = std::sync::Arc<dyn Fn(u32) + Send + Sync>;
+
 /// Parameters for execution.
 #[derive(Clone, Default)]
 pub struct ExecutionParameters {
@@ -67,6 +75,10 @@ pub struct ExecutionParameters {
     pub process_group_policy: ProcessGroupPolicy,
     /// Optional cancellation token shared with callers.
     cancel_token: Option<CancellationToken>,
+    /// Optional callback notified of spawned external processes.
+    spawn_hook: Option<SpawnHook>,
+    /// Optional callback notified of external processes brush has reaped.
+    reap_hook: Option<ReapHook>,
 }
 
 impl ExecutionParameters {
@@ -80,6 +92,28 @@ impl ExecutionParameters {
         self.cancel_token.clone()
     }
 
+	/// Assigns a callback notified with the pid of each external process
+	/// spawned, e.g. to place it in a Windows job object.
+	pub fn set_spawn_hook(&mut self, hook: SpawnHook) {
+		self.spawn_hook = Some(hook);
+	}
+
+	/// Returns the spawn callback, if present.
+	pub fn spawn_hook(&self) -> Option<&SpawnHook> {
+		self.spawn_hook.as_ref()
+	}
+
+	/// Assigns a callback notified with the pid of each external process
+	/// spawned once brush has waited on it, so the pid may be reused.
+	pub fn set_reap_hook(&mut self, hook: ReapHook) {
+		self.reap_hook = Some(hook);
+	}
+
+	/// Returns the reap callback, if present.
+	pub fn reap_hook(&self) -> Option<&ReapHook> {
+		self.reap_hook.as_ref()
+	}
+
 	/// Returns true when cancellation has been requested.
 	pub fn is_cancelled(&self) -> bool {
 		self
@@ -269,7 +303,23 @@ impl Execute for ast::CompoundList {
                 // TODO: Reenable launching in child process?
                 // let job = spawn_ao_list_in_child(ao_list, shell, params).await?;
 
-                let job = spawn_ao_list_in_task(ao_list, shell, params);
+                let mut permit = None;
+                if let Some(limit) = shell.background_job_limit().cloned() {
+                    permit = limit.acquire(params.cancel_token()).await;
+                    if permit.is_none() {
+                        ensure_not_cancelled(params)?;
+                        writeln!(
+                            params.stderr(shell),
+                            "{ao_list}: background job limit ({}) reached",
+                            limit.max()
+                        )?;
+                        result = ExecutionResult::general_error();
+                        *shell.last_exit_status_mut() = result.exit_code.into();
+                        continue;
+                    }
+                }
+
+                let job = spawn_ao_list_in_task(ao_list, shell, params, permit);
                 let job_formatted = job.to_pid_style_string();
 
                 if shell.options.interactive && !shell.is_subshell() {
@@ -295,6 +345,7 @@ fn spawn_ao_list_in_task<'a>(
     ao_list: &ast::AndOrList,
     shell: &'a mut Shell,
     params: &ExecutionParameters,
+    permit: Option<OwnedSemaphorePermit>,
 ) -> &'a jobs::Job {
     // Clone the inputs.
     let mut cloned_shell = shell.clone();
@@ -305,6 +356,8 @@ fn spawn_ao_list_in_task<'a>(
     cloned_shell.options.interactive = false;
 
     let join_handle = tokio::spawn(async move {
+        // Hold the background job slot until the job ends.
+        let _permit = permit;
         cloned_ao_list
             .execute(&mut cloned_shell, &cloned_params)
             .await
@@ -2,8 +2,11 @@
 
 use std::collections::VecDeque;
 use std::fmt::Display;
+use std::sync::Arc;
 
 use futures::FutureExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
 
 use crate::ExecutionResult;
 use crate::error;
@@ -22,6 +25,61 @@ pub struct JobManager {
     pub jobs: Vec<Job>,
 }
 
+/// Caps how many background jobs a shell and its subshells run at once.
+pub struct BackgroundJobLimit {
+    max: usize,
+    wait: bool,
+    slots: Arc<Semaphore>,
+}
+
+impl BackgroundJobLimit {
+    /// Returns a limit of `max` jobs.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - The number of background jobs allowed to run at once.
+    /// * `wait` - Whether launching past the limit waits for a slot instead of failing.
+    pub fn new(max: usize, wait: bool) -> Self {
+        Self {
+            max,
+            wait,
+            slots: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    /// Returns the number of background jobs allowed to run at once.
+    pub const fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Returns the number of background jobs currently holding a slot.
+    pub fn running(&self) -> usize {
+        self.max - self.slots.available_permits()
+    }
+
+    /// Claims a slot for a new background job, held until the job's task ends.
+    /// Returns `None` when the limit is reached and the limit doesn't wait,
+    /// or when `cancel_token` fires while waiting.
+    pub(crate) async fn acquire(
+        &self,
+        cancel_token: Option<CancellationToken>,
+    ) -> Option<OwnedSemaphorePermit> {
+        if !self.wait {
+            return self.slots.clone().try_acquire_owned().ok();
+        }
+        let cancelled = async {
+            match &cancel_token {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            permit = self.slots.clone().acquire_owned() => permit.ok(),
+            () = cancelled => None,
+        }
+    }
+}
+
 /// Represents a task that is part of a job.
 pub enum JobTask {
     /// An external process.
@@ -441,6 +499,14 @@ impl Job {
         None
     }
 
+    /// Returns the process IDs of the job's external tasks.
+    pub fn pids(&self) -> impl Iterator<Item = sys::process::ProcessId> + '_ {
+        self.tasks.iter().filter_map(|task| match task {
+            JobTask::External(p) => p.pid(),
+            JobTask::Internal(_) => None,
+        })
+    }
+
     /// Tries to retrieve the process group ID (PGID) of the job.
     pub fn process_group_id(&self) -> Option<sys::process::ProcessId> {
         // TODO: Don't assume that the first PID is the PGID.
@@ -39,9 +39,9 @@ pub mod traps;
 pub mod variables;
 mod wellknownvars;
 
-pub use commands::{CommandArg, ExecutionContext};
+pub use commands::{CommandArg, CommandCheck, CommandPolicy, ExecutionContext};
 pub use error::{BuiltinError, Error, ErrorKind};
-pub use interp::{ExecutionParameters, ProcessGroupPolicy};
+pub use interp::{ExecutionParameters, ProcessGroupPolicy, ReapHook, SpawnHook};
 pub use results::{ExecutionControlFlow, ExecutionExitCode, ExecutionResult, ExecutionSpawnResult};
 pub use shell::{CreateOptions, Shell, ShellBuilder, ShellBuilderState, ShellFd};
 pub use variables::{ShellValue, ShellVariable};
@@ -14,16 +14,21 @@ where
     filenames: Vec<String>,
 }
 
+/// Names to try for `filename` in each directory: the name itself, then the
+/// name with each `PATHEXT` extension appended, lowercased, in `PATHEXT`
+/// order. Names that already have an executable extension are only tried
+/// as-is; other dotted names (`tool.v2`) still get extensions appended, like
+/// `cmd.exe` does.
 #[cfg(windows)]
 fn candidate_filenames(filename: &str) -> Vec<String> {
-    if std::path::Path::new(filename).extension().is_some() {
+    if crate::sys::fs::has_executable_extension(std::path::Path::new(filename)) {
         return vec![filename.to_string()];
     }
 
     let mut candidates = Vec::new();
     candidates.push(filename.to_string());
     for ext in crate::sys::fs::executable_extensions() {
-        candidates.push(format!("{filename}{ext}"));
+        candidates.push(format!("{filename}{}", ext.to_ascii_lowercase()));
     }
     candidates
 }
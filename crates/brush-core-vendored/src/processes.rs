@@ -2,7 +2,7 @@
 
 use tokio_util::sync::CancellationToken;
 
-use crate::{error, sys};
+use crate::{error, interp::ReapHook, sys};
 
 /// Tracks a child process being awaited.
 pub struct ChildProcess {
@@ -12,12 +12,28 @@ pub struct ChildProcess {
     child: sys::process::Child,
     /// Tracks whether this process has already been reaped.
     reaped: bool,
+    /// Notified once the process has been reaped.
+    reap_hook: Option<ReapHook>,
 }
 
 impl ChildProcess {
     /// Wraps a child process and its future.
     pub fn new(pid: Option<sys::process::ProcessId>, child: sys::process::Child) -> Self {
-        Self { pid, child, reaped: false }
+        Self { pid, child, reaped: false, reap_hook: None }
+    }
+
+    /// Sets a callback notified with the pid once the process is reaped.
+    pub fn set_reap_hook(&mut self, hook: ReapHook) {
+        self.reap_hook = Some(hook);
+    }
+
+    /// Records that the process was reaped, notifying the reap hook.
+    fn mark_reaped(&mut self) {
+        self.reaped = true;
+        if let (Some(hook), Some(pid)) = (self.reap_hook.take(), self.pid) {
+            #[expect(clippy::cast_sign_loss, reason = "pids are positive")]
+            hook(pid as u32);
+        }
     }
 
     /// Returns the process's ID.
@@ -72,14 +88,14 @@ impl ChildProcess {
             return match status {
                 Some(status) => {
                     let status = status?;
-                    self.reaped = true;
+                    self.mark_reaped();
                     Ok(ProcessWaitResult::Completed(output_from_status(status)))
                 }
                 None => {
                     if self.child.kill().await.is_ok() {
-                        self.reaped = true;
+                        self.mark_reaped();
                     } else if let Ok(Some(_)) = self.child.try_wait() {
-                        self.reaped = true;
+                        self.mark_reaped();
                     }
                     Ok(ProcessWaitResult::Cancelled)
                 }
@@ -94,19 +110,19 @@ impl ChildProcess {
         }
 
         if let Ok(Some(_)) = self.child.try_wait() {
-            self.reaped = true;
+            self.mark_reaped();
             return;
         }
         let _ = self.child.start_kill();
         if let Ok(Some(_)) = self.child.try_wait() {
-            self.reaped = true;
+            self.mark_reaped();
     }
     }
 
     pub(crate) fn poll(&mut self) -> Option<Result<std::process::Output, error::Error>> {
         match self.child.try_wait() {
             Ok(Some(status)) => {
-                self.reaped = true;
+                self.mark_reaped();
                 Some(Ok(output_from_status(status)))
             }
             Ok(None) => None,
@@ -20,7 +20,7 @@ use crate::{
 };
 use crate::{
     builtins, commands, completion, env, error, expansion, functions, jobs, keywords, openfiles,
-    prompt, sys::users, traps,
+    prompt, sys, sys::users, traps,
 };
 
 /// Type for storing a key bindings helper.
@@ -43,6 +43,19 @@ pub struct Shell {
     /// The current working directory.
     working_dir: PathBuf,
 
+    /// Last working directory on each drive, for drive-relative paths like
+    /// `D:file.txt` (Windows only; always empty elsewhere).
+    drive_dirs: HashMap<char, PathBuf>,
+
+    /// Consulted before each builtin or external command runs.
+    command_policy: Option<commands::CommandPolicy>,
+
+    /// Caps concurrent background jobs; shared with subshells.
+    background_job_limit: Option<Arc<jobs::BackgroundJobLimit>>,
+
+    /// Whether command substitutions run; when off, expanding one fails.
+    command_substitution: bool,
+
     /// The shell environment, containing shell variables.
     pub env: ShellEnvironment,
 
@@ -122,6 +135,10 @@ impl Clone for Shell {
             traps: self.traps.clone(),
             open_files: self.open_files.clone(),
             working_dir: self.working_dir.clone(),
+            drive_dirs: self.drive_dirs.clone(),
+            command_policy: self.command_policy.clone(),
+            background_job_limit: self.background_job_limit.clone(),
+            command_substitution: self.command_substitution,
             env: self.env.clone(),
             funcs: self.funcs.clone(),
             options: self.options.clone(),
@@ -352,6 +369,10 @@ impl Shell {
             open_files: openfiles::OpenFiles::new(),
             // Populate working directory from the host environment.
             working_dir: std::env::current_dir()?,
+            drive_dirs: HashMap::default(),
+            command_policy: None,
+            background_job_limit: None,
+            command_substitution: true,
             env: env::ShellEnvironment::new(),
             funcs: functions::FunctionEnv::default(),
             options: RuntimeOptions::defaults_from(&options),
@@ -1239,6 +1260,51 @@ impl Shell {
         self.builtins.insert(name.into(), registration);
     }
 
+    /// Sets a policy consulted before each builtin or external command runs,
+    /// including those run from functions, subshells, and command substitutions.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy; an `Err` from it denies the command.
+    pub fn set_command_policy(&mut self, policy: commands::CommandPolicy) {
+        self.command_policy = Some(policy);
+    }
+
+    /// Returns the command policy, if one is set.
+    pub fn command_policy(&self) -> Option<&commands::CommandPolicy> {
+        self.command_policy.as_ref()
+    }
+
+    /// Caps how many background jobs this shell and its subshells run at once.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The limit; launching a job past it fails or waits for a slot.
+    pub fn set_background_job_limit(&mut self, limit: jobs::BackgroundJobLimit) {
+        self.background_job_limit = Some(Arc::new(limit));
+    }
+
+    /// Returns the background job limit, if one is set.
+    pub fn background_job_limit(&self) -> Option<&Arc<jobs::BackgroundJobLimit>> {
+        self.background_job_limit.as_ref()
+    }
+
+    /// Enables or disables command substitution for this shell and its
+    /// subshells. While disabled, expanding `$(...)` or backticks fails
+    /// without running anything.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether command substitutions run.
+    pub fn set_command_substitution_enabled(&mut self, enabled: bool) {
+        self.command_substitution = enabled;
+    }
+
+    /// Returns whether command substitutions run.
+    pub const fn command_substitution_enabled(&self) -> bool {
+        self.command_substitution
+    }
+
     /// Tries to retrieve a mutable reference to an existing builtin registration.
     /// Returns `None` if no such registration exists.
     ///
@@ -1361,11 +1427,26 @@ impl Shell {
         let path = path.as_ref();
         if path.as_os_str().is_empty() || path.is_absolute() {
             path.to_owned()
+        } else if let Some((drive, rest)) = sys::fs::split_drive_relative(path) {
+            self.drive_working_dir(drive).join(rest)
         } else {
             self.working_dir().join(path)
         }
     }
 
+    /// Gets the directory drive-relative paths on `drive` resolve against: the
+    /// working directory if it's on that drive, else the last directory used
+    /// there, else the drive's root.
+    fn drive_working_dir(&self, drive: char) -> PathBuf {
+        if sys::fs::path_drive(self.working_dir()) == Some(drive) {
+            return self.working_dir.clone();
+        }
+        self.drive_dirs
+            .get(&drive)
+            .cloned()
+            .unwrap_or_else(|| sys::fs::drive_root(drive))
+    }
+
     /// Opens the given file, using the context of this shell and the provided execution parameters.
     ///
     /// # Arguments
@@ -1421,7 +1502,7 @@ impl Shell {
         // Normalize the path (but don't canonicalize it).
         let cleaned_path = abs_path.normalize();
 
-        let pwd = cleaned_path.to_string_lossy().to_string();
+        let pwd = sys::fs::to_shell_path(&cleaned_path);
 
         self.env.update_or_add(
             "PWD",
@@ -1433,11 +1514,14 @@ impl Shell {
             EnvironmentLookup::Anywhere,
             EnvironmentScope::Global,
         )?;
+        if let Some(drive) = sys::fs::path_drive(&cleaned_path) {
+            self.drive_dirs.insert(drive, cleaned_path.clone());
+        }
         let oldpwd = std::mem::replace(self.working_dir_mut(), cleaned_path);
 
         self.env.update_or_add(
             "OLDPWD",
-            variables::ShellValueLiteral::Scalar(oldpwd.to_string_lossy().to_string()),
+            variables::ShellValueLiteral::Scalar(sys::fs::to_shell_path(&oldpwd)),
             |var| {
                 var.export();
                 Ok(())
@@ -77,3 +77,24 @@ pub fn get_default_standard_utils_paths() -> Vec<String> {
 pub fn open_null_file() -> Result<std::fs::File, error::Error> {
     Err(error::ErrorKind::NotSupportedOnThisPlatform("opening null file").into())
 }
+
+/// Returns the path as shown to scripts (`$PWD`, `pwd`).
+pub fn to_shell_path(path: &std::path::Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// Returns the drive letter a path is on (always `None` without drives).
+pub(crate) const fn path_drive(_path: &std::path::Path) -> Option<char> {
+    None
+}
+
+/// Splits a drive-relative path (`D:file.txt`) into its drive and the rest
+/// (always `None` without drives).
+pub(crate) const fn split_drive_relative(_path: &std::path::Path) -> Option<(char, std::path::PathBuf)> {
+    None
+}
+
+/// Returns the root directory of a drive.
+pub(crate) fn drive_root(drive: char) -> std::path::PathBuf {
+    std::path::PathBuf::from(format!("{drive}:/"))
+}
@@ -2,7 +2,7 @@
 
 use std::os::unix::ffi::OsStringExt;
 use std::os::unix::fs::FileTypeExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::error;
 
@@ -189,3 +189,24 @@ pub fn open_null_file() -> Result<std::fs::File, error::Error> {
 
     Ok(f)
 }
+
+/// Returns the path as shown to scripts (`$PWD`, `pwd`).
+pub fn to_shell_path(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// Returns the drive letter a path is on (always `None` without drives).
+pub(crate) const fn path_drive(_path: &Path) -> Option<char> {
+    None
+}
+
+/// Splits a drive-relative path (`D:file.txt`) into its drive and the rest
+/// (always `None` without drives).
+pub(crate) const fn split_drive_relative(_path: &Path) -> Option<(char, PathBuf)> {
+    None
+}
+
+/// Returns the root directory of a drive.
+pub(crate) fn drive_root(drive: char) -> PathBuf {
+    PathBuf::from(format!("{drive}:/"))
+}
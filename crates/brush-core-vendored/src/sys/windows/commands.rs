@@ -4,7 +4,18 @@ use std::ffi::OsStr;
 use std::os::windows::process::CommandExt as WindowsCommandExt;
 
 use crate::{ShellFd, error, openfiles};
-use windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP;
+use windows_sys::Win32::{
+	Foundation::{CloseHandle, FILETIME, HANDLE, INVALID_HANDLE_VALUE},
+	System::{
+		Diagnostics::ToolHelp::{
+			CreateToolhelp32Snapshot, THREADENTRY32, TH32CS_SNAPTHREAD, Thread32First, Thread32Next,
+		},
+		Threading::{
+			CREATE_NEW_PROCESS_GROUP, CREATE_SUSPENDED, GetThreadTimes, OpenThread, ResumeThread,
+			THREAD_QUERY_LIMITED_INFORMATION, THREAD_SUSPEND_RESUME,
+		},
+	},
+};
 
 /// Extension trait for Windows command extensions.
 pub trait CommandExt {
@@ -36,7 +47,7 @@ impl CommandExt for std::process::Command {
 
 	fn process_group(&mut self, pgroup: i32) -> &mut Self {
 		if pgroup == 0 {
-			self.creation_flags(CREATE_NEW_PROCESS_GROUP);
+			self.creation_flags(process_group_flags(true));
 		}
 		self
 	}
@@ -93,6 +104,102 @@ pub trait CommandFgControlExt {
 
 impl CommandFgControlExt for std::process::Command {
 	fn take_foreground(&mut self) {
-		self.creation_flags(CREATE_NEW_PROCESS_GROUP);
+		self.creation_flags(process_group_flags(true));
 	}
 }
+
+/// Creation flags [`CommandExt::process_group`] and
+/// [`CommandFgControlExt::take_foreground`] set for a command that does or
+/// doesn't lead its own process group.
+pub(crate) const fn process_group_flags(new_process_group: bool) -> u32 {
+	if new_process_group { CREATE_NEW_PROCESS_GROUP } else { 0 }
+}
+
+/// Arranges for the command's process to start with its main thread
+/// suspended, so it can be set up before it runs any code; see
+/// [`resume_process`].
+///
+/// `creation_flags` replaces rather than adds to the flags set so far, so
+/// `flags` must hold them (e.g. from [`process_group_flags`]); the suspend
+/// flag is added to them.
+pub(crate) fn start_suspended(cmd: &mut std::process::Command, flags: u32) {
+	cmd.creation_flags(flags | CREATE_SUSPENDED);
+}
+
+/// Creation time of a thread, or `None` if it can't be queried.
+fn thread_creation_time(thread: HANDLE) -> Option<u64> {
+	let mut times = [FILETIME { dwLowDateTime: 0, dwHighDateTime: 0 }; 4];
+	let [creation, exit, kernel, user] = &mut times;
+	// SAFETY: `thread` is an open handle and every out-pointer is valid.
+	let ok = unsafe { GetThreadTimes(thread, creation, exit, kernel, user) };
+	(ok != 0).then(|| u64::from(creation.dwHighDateTime) << 32 | u64::from(creation.dwLowDateTime))
+}
+
+/// Resumes the primary thread of a process started by [`start_suspended`].
+///
+/// Only that thread was suspended at creation. Others in the process (e.g.
+/// injected by a debugger) are left alone, so their suspend counts stay
+/// intact. The primary thread is the process's earliest-created one.
+pub(crate) fn resume_process(pid: u32) -> Result<(), error::Error> {
+	let snapshot = {
+		// SAFETY: CreateToolhelp32Snapshot requires valid flags.
+		unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) }
+	};
+	if snapshot == INVALID_HANDLE_VALUE {
+		return Err(std::io::Error::last_os_error().into());
+	}
+
+	let mut entry = THREADENTRY32 {
+		dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+		// SAFETY: zeroed struct is valid for THREADENTRY32.
+		..unsafe { std::mem::zeroed() }
+	};
+
+	// The snapshot lists every thread on the system; keep the earliest
+	// created of `pid`'s, open for resuming.
+	let mut primary: Option<(u64, HANDLE)> = None;
+	let mut result = {
+		// SAFETY: snapshot handle is valid and entry is initialized.
+		unsafe { Thread32First(snapshot, &mut entry) }
+	};
+	while result != 0 {
+		if entry.th32OwnerProcessID == pid {
+			let access = THREAD_SUSPEND_RESUME | THREAD_QUERY_LIMITED_INFORMATION;
+			// SAFETY: OpenThread accepts any thread id and returns null on failure.
+			let thread = unsafe { OpenThread(access, 0, entry.th32ThreadID) };
+			if !thread.is_null() {
+				let created = thread_creation_time(thread).unwrap_or(u64::MAX);
+				let stale = if primary.is_none_or(|(earliest, _)| created < earliest) {
+					primary.replace((created, thread)).map(|(_, handle)| handle)
+				} else {
+					Some(thread)
+				};
+				if let Some(handle) = stale {
+					// SAFETY: `handle` was opened above and is no longer used.
+					unsafe { CloseHandle(handle) };
+				}
+			}
+		}
+		result = {
+			// SAFETY: snapshot handle is valid and entry is initialized.
+			unsafe { Thread32Next(snapshot, &mut entry) }
+		};
+	}
+
+	let _ = {
+		// SAFETY: snapshot handle was returned by CreateToolhelp32Snapshot.
+		unsafe { CloseHandle(snapshot) }
+	};
+	let resumed = primary.is_some_and(|(_, thread)| {
+		// SAFETY: `thread` is an open handle, closed right after.
+		unsafe {
+			let resumed = ResumeThread(thread) != u32::MAX;
+			CloseHandle(thread);
+			resumed
+		}
+	});
+	if !resumed {
+		return Err(std::io::Error::other(format!("failed to resume process {pid}")).into());
+	}
+	Ok(())
+}
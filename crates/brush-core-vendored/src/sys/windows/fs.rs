@@ -1,7 +1,11 @@
 //! Filesystem utilities.
 
 use crate::error;
-use std::{ffi::OsStr, path::PathBuf, sync::OnceLock};
+use std::{
+	ffi::OsStr,
+	path::{Component, Path, PathBuf, Prefix},
+	sync::OnceLock,
+};
 
 impl crate::sys::fs::PathExt for std::path::Path {
 	fn readable(&self) -> bool {
@@ -13,16 +17,7 @@ impl crate::sys::fs::PathExt for std::path::Path {
 	}
 
 	fn executable(&self) -> bool {
-		if !self.is_file() {
-			return false;
-		}
-		let Some(ext) = self.extension().and_then(OsStr::to_str) else {
-			return false;
-		};
-		let ext = format!(".{ext}");
-		executable_extensions()
-			.iter()
-			.any(|known| known.eq_ignore_ascii_case(&ext))
+		self.is_file() && has_executable_extension(self)
 	}
 
 	fn exists_and_is_block_device(&self) -> bool {
@@ -127,6 +122,17 @@ fn system32_path() -> Option<PathBuf> {
 	Some(system_root.join("System32"))
 }
 
+/// Returns true if the path's extension is listed in `PATHEXT`.
+pub(crate) fn has_executable_extension(path: &Path) -> bool {
+	let Some(ext) = path.extension().and_then(OsStr::to_str) else {
+		return false;
+	};
+	let ext = format!(".{ext}");
+	executable_extensions()
+		.iter()
+		.any(|known| known.eq_ignore_ascii_case(&ext))
+}
+
 pub(crate) fn executable_extensions() -> &'static [String] {
 	static PATHEXT: OnceLock<Vec<String>> = OnceLock::new();
 	PATHEXT.get_or_init(|| {
@@ -158,3 +164,60 @@ pub(crate) fn executable_extensions() -> &'static [String] {
 		exts
 	})
 }
+
+/// Returns the path as shown to scripts (`$PWD`, `pwd`): forward slashes, an
+/// uppercase drive letter, no `\\?\` prefix, and no trailing separator
+/// except on a drive root. Backslashes would be escapes to the shell.
+pub fn to_shell_path(path: &Path) -> String {
+	let raw = path.to_string_lossy();
+	let plain = if let Some(unc) = raw.strip_prefix("\\\\?\\UNC\\") {
+		format!("\\\\{unc}")
+	} else {
+		raw.strip_prefix("\\\\?\\").unwrap_or(&raw).to_string()
+	};
+
+	let mut shell_path = plain.replace('\\', "/");
+	if shell_path.as_bytes().get(1) == Some(&b':') {
+		shell_path[..1].make_ascii_uppercase();
+	}
+	let is_drive_root = shell_path.len() == 3 && shell_path.ends_with(":/");
+	while shell_path.len() > 1 && shell_path.ends_with('/') && !is_drive_root {
+		shell_path.pop();
+	}
+	shell_path
+}
+
+/// Returns the uppercase drive letter a path is on (`C:\\x`, `\\\\?\\C:\\x`).
+pub(crate) fn path_drive(path: &Path) -> Option<char> {
+	match path.components().next()? {
+		Component::Prefix(prefix) => match prefix.kind() {
+			Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+				Some(char::from(letter).to_ascii_uppercase())
+			}
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+/// Splits a drive-relative path (`D:` or `D:file.txt`, a drive with no root)
+/// into its drive and the rest, which resolves against that drive's last
+/// working directory.
+pub(crate) fn split_drive_relative(path: &Path) -> Option<(char, PathBuf)> {
+	let mut components = path.components();
+	let Component::Prefix(prefix) = components.next()? else {
+		return None;
+	};
+	let Prefix::Disk(letter) = prefix.kind() else {
+		return None;
+	};
+	if path.has_root() {
+		return None;
+	}
+	Some((char::from(letter).to_ascii_uppercase(), components.as_path().to_path_buf()))
+}
+
+/// Returns the root directory of a drive.
+pub(crate) fn drive_root(drive: char) -> PathBuf {
+	PathBuf::from(format!("{drive}:\\"))
+}
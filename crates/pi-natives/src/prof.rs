@@ -3,7 +3,13 @@
 //! Samples are continuously collected into a fixed-size circular buffer.
 //! Call `get_work_profile()` to retrieve the last N seconds of profiling data.
 
-use std::{cell::RefCell, cmp::Reverse, collections::HashMap, sync::LazyLock, time::Instant};
+use std::{
+	cell::RefCell,
+	cmp::Reverse,
+	collections::HashMap,
+	sync::LazyLock,
+	time::{Duration, Instant},
+};
 
 use napi_derive::napi;
 use parking_lot::Mutex;
@@ -108,6 +114,17 @@ pub fn profile_region(region: &'static str) -> ProfileGuard {
 	ProfileGuard::new(region)
 }
 
+/// Record a sample for a span that wasn't timed by a guard, such as one that
+/// started on another thread.
+pub fn record_sample(stack: &[&'static str], duration: Duration) {
+	let sample = ProfileSample {
+		stack:        stack.iter().copied().collect(),
+		duration_us:  duration.as_micros() as u64,
+		timestamp_us: PROCESS_START.elapsed().as_micros() as u64,
+	};
+	PROFILE_BUFFER.lock().push(sample);
+}
+
 // ─────────────────────────────────────────────────────────────────────────────
 // Work Profile Results
 // ─────────────────────────────────────────────────────────────────────────────
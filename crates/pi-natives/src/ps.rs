@@ -4,7 +4,8 @@
 //! requiring processes to be spawned with `detached: true`.
 //!
 //! # Platform Implementation
-//! - **Linux**: Reads `/proc/{pid}/children` recursively
+//! - **Linux**: Reads `/proc/{pid}/children` recursively, or `/proc/*/stat` for
+//!   whole-session scans
 //! - **macOS**: Uses `libproc` (`proc_listchildpids`)
 //! - **Windows**: Uses `CreateToolhelp32Snapshot` to build parent-child
 //!   relationships
@@ -39,6 +40,33 @@ mod platform {
 		}
 	}
 
+	/// List `(pid, parent pid)` for every live process from `/proc/*/stat`.
+	/// Zombies are skipped since they have already exited.
+	pub fn process_table() -> Vec<(i32, i32)> {
+		let Ok(entries) = fs::read_dir("/proc") else {
+			return Vec::new();
+		};
+		entries
+			.flatten()
+			.filter_map(|entry| {
+				let pid = entry.file_name().to_str()?.parse::<i32>().ok()?;
+				let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+				// `pid (comm) state ppid ...`; comm may contain spaces and parens.
+				let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+				let state = fields.next()?;
+				let ppid = fields.next()?.parse().ok()?;
+				(state != "Z" && state != "X").then_some((pid, ppid))
+			})
+			.collect()
+	}
+
+	/// Check whether the environment of `pid` contains the `KEY=VALUE`
+	/// entry `entry`. Returns false when `/proc/{pid}/environ` is unreadable.
+	pub fn has_env_entry(pid: i32, entry: &str) -> bool {
+		fs::read(format!("/proc/{pid}/environ"))
+			.is_ok_and(|environ| environ.split(|&b| b == 0).any(|e| e == entry.as_bytes()))
+	}
+
 	/// Send `signal` to `pid`.
 	/// Returns true when the signal is delivered successfully.
 	pub fn kill_pid(pid: i32, signal: i32) -> bool {
@@ -266,6 +294,63 @@ pub fn kill_tree(pid: i32, signal: i32) -> u32 {
 	killed
 }
 
+/// List the live processes belonging to a shell session.
+///
+/// Covers `roots`, every process whose environment contains the `KEY=VALUE`
+/// entry `marker`, and all of their descendants. The marker catches
+/// processes that escaped the tree via `setsid` or a double fork and were
+/// reparented. Never includes the current process.
+#[cfg(target_os = "linux")]
+pub fn session_processes(roots: &[i32], marker: &str) -> Vec<i32> {
+	use std::collections::{HashMap, HashSet};
+
+	let table = platform::process_table();
+	let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+	for &(pid, ppid) in &table {
+		children.entry(ppid).or_default().push(pid);
+	}
+	let own = std::process::id() as i32;
+	let mut pending: Vec<i32> = table
+		.iter()
+		.map(|&(pid, _)| pid)
+		.filter(|&pid| roots.contains(&pid) || platform::has_env_entry(pid, marker))
+		.collect();
+	let mut seen = HashSet::new();
+	let mut pids = Vec::new();
+	while let Some(pid) = pending.pop() {
+		if pid == own || !seen.insert(pid) {
+			continue;
+		}
+		pids.push(pid);
+		if let Some(kids) = children.get(&pid) {
+			pending.extend(kids);
+		}
+	}
+	pids
+}
+
+/// List the live processes belonging to a shell session.
+///
+/// macOS does not expose other processes' environments, so `marker` is
+/// ignored and only `roots` and their descendants are found.
+#[cfg(target_os = "macos")]
+pub fn session_processes(roots: &[i32], _marker: &str) -> Vec<i32> {
+	let mut pids = roots.to_vec();
+	for &root in roots {
+		platform::collect_descendants(root, &mut pids);
+	}
+	let own = std::process::id() as i32;
+	let mut seen = std::collections::HashSet::new();
+	pids.retain(|&pid| pid != own && seen.insert(pid));
+	pids
+}
+
+/// Send `signal` to `pid` (Windows ignores `signal` and terminates it).
+/// Returns true when the signal is delivered successfully.
+pub fn kill_pid(pid: i32, signal: i32) -> bool {
+	platform::kill_pid(pid, signal)
+}
+
 /// Get the process group id for `pid`.
 /// Returns `None` when the process is missing or unsupported on the platform.
 pub fn process_group_id(pid: i32) -> Option<i32> {
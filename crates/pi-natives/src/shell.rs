@@ -12,35 +12,48 @@
 //! });
 //! ```
 
-#[cfg(windows)]
-use std::collections::HashSet;
 use std::{
-	collections::HashMap,
+	collections::{HashMap, HashSet},
 	fs,
+	future::Future,
 	io::{self, Write},
+	pin::Pin,
 	str,
-	sync::Arc,
-	time::Duration,
+	sync::{
+		Arc,
+		atomic::{AtomicBool, AtomicU64, Ordering},
+	},
+	time::{Duration, Instant},
 };
 
+mod encoding;
+mod isolate;
+mod preview;
+#[cfg(unix)]
+mod reap;
+mod snapshot;
 #[cfg(windows)]
 mod windows;
 
 use brush_builtins::{BuiltinSet, default_builtins};
 use brush_core::{
-	CreateOptions, ExecutionContext, ExecutionControlFlow, ExecutionExitCode, ExecutionResult,
-	ProcessGroupPolicy, Shell as BrushShell, ShellValue, ShellVariable, builtins,
+	CommandCheck, CommandPolicy, CreateOptions, ExecutionContext, ExecutionControlFlow,
+	ExecutionExitCode, ExecutionResult, ProcessGroupPolicy, Shell as BrushShell, ShellValue,
+	ShellVariable, builtins,
 	env::EnvironmentScope,
+	jobs::{BackgroundJobLimit, JobState},
 	openfiles::{self, OpenFile, OpenFiles},
 	sys, traps,
 };
 use clap::Parser;
+use encoding::{OutputDecoder, OutputEncoding};
+use isolate::{Isolation, ScratchDirs};
 use napi::{
 	bindgen_prelude::*,
 	threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode},
 	tokio::{
 		self,
-		sync::{Mutex as TokioMutex, mpsc},
+		sync::{Mutex as TokioMutex, mpsc, watch},
 		time,
 	},
 };
@@ -48,72 +61,546 @@ use napi_derive::napi;
 use tokio::io::AsyncReadExt as _;
 use tokio_util::sync::CancellationToken;
 #[cfg(windows)]
-use windows::configure_windows_path;
+use windows::{PathAugmentation, configure_windows_path, shell_env_value, to_native_path};
 
-use crate::task;
+#[cfg(unix)]
+use crate::ps;
+use crate::{prof, task};
 
 struct ShellSessionCore {
 	shell:         BrushShell,
 	current_abort: Option<task::AbortToken>,
+	/// Job object every spawned process joins, so cancellation kills whole
+	/// process trees.
+	#[cfg(windows)]
+	job:           Option<Arc<windows::ProcessJob>>,
+	/// `KEY=VALUE` entry exported into the session's environment, which
+	/// identifies its processes even after they leave the process tree.
+	#[cfg(unix)]
+	marker:        String,
+	/// Children spawned by the session, for reaping leaked zombies.
+	#[cfg(unix)]
+	children:      Arc<reap::ChildTracker>,
+}
+
+impl ShellSessionCore {
+	/// Kill everything the session spawned, returning how many processes died
+	/// in each wave.
+	#[cfg(windows)]
+	fn terminate_processes(&self) -> impl Future<Output = Option<Vec<u32>>> {
+		std::future::ready(self.job.as_ref().map(|job| vec![job.terminate()]))
+	}
+
+	/// Kill everything the session spawned, returning how many processes died
+	/// in each wave: SIGTERM, then SIGKILL for whatever is left after a grace
+	/// period. Each wave rescans, so children forked in between are caught.
+	#[cfg(unix)]
+	async fn terminate_processes(&self) -> Option<Vec<u32>> {
+		let roots: Vec<i32> = self
+			.shell
+			.jobs
+			.jobs
+			.iter()
+			.filter_map(|job| job.representative_pid())
+			.collect();
+		let mut waves = Vec::new();
+		for signal in [libc::SIGTERM, libc::SIGKILL] {
+			match waves.last() {
+				Some(0) => break,
+				Some(_) => time::sleep(Duration::from_millis(500)).await,
+				None => {},
+			}
+			let pids = ps::session_processes(&roots, &self.marker);
+			waves.push(
+				pids
+					.into_iter()
+					.filter(|&pid| ps::kill_pid(pid, signal))
+					.count() as u32,
+			);
+		}
+		Some(waves)
+	}
+
+	/// Reap leaked zombies among the session's children, returning how many
+	/// are still tracked. Pids that brush's jobs still wait on are left alone.
+	#[cfg(unix)]
+	fn reap_children(&self) -> u32 {
+		let waited: HashSet<i32> = self
+			.shell
+			.jobs
+			.jobs
+			.iter()
+			.flat_map(|job| job.pids())
+			.collect();
+		self.children.reap(&waited)
+	}
 }
 
+/// Variable carrying a session's marker, inherited by everything it spawns.
+#[cfg(unix)]
+const SESSION_MARKER_VAR: &str = "PI_SHELL_SESSION";
+
+/// Source of unique session markers within this process.
+#[cfg(unix)]
+static SESSION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Clone)]
 struct ShellConfig {
-	session_env:   Option<HashMap<String, String>>,
-	snapshot_path: Option<String>,
+	session_env:          Option<HashMap<String, String>>,
+	snapshot_path:        Option<String>,
+	isolation:            Isolation,
+	command_policy:       Option<Arc<CommandPolicyCallback>>,
+	/// `maxBackgroundJobs`, and whether launching past it waits for a slot.
+	background_job_limit: Option<(u32, bool)>,
+	exec_policy:          ExecPolicy,
+	allow_suspend:        bool,
+	#[cfg(windows)]
+	path_augmentation:    PathAugmentation,
+	/// Why an override in `windowsPathAugmentation` was ignored.
+	#[cfg(windows)]
+	path_warnings:        Vec<String>,
+	#[cfg(windows)]
+	utf8_env:             bool,
+	/// Shared by every session the shell creates, so stats outlive them.
+	#[cfg(unix)]
+	children:             Arc<reap::ChildTracker>,
+	/// Set by `dispose()`, after which no session is created again.
+	disposed:             Arc<AtomicBool>,
 }
 
-/// Options for configuring a persistent shell session.
+impl ShellConfig {
+	#[cfg_attr(
+		not(windows),
+		expect(clippy::unnecessary_wraps, reason = "path augmentation is only validated on Windows")
+	)]
+	fn new(
+		session_env: Option<HashMap<String, String>>,
+		snapshot_path: Option<String>,
+		path_augmentation: Option<Either<String, WindowsPathOverride>>,
+		utf8_env: Option<bool>,
+	) -> Result<Self> {
+		#[cfg(not(windows))]
+		let _ = (path_augmentation, utf8_env);
+		#[cfg(windows)]
+		let (path_augmentation, path_warning) = PathAugmentation::from_option(path_augmentation)?;
+		Ok(Self {
+			session_env,
+			snapshot_path,
+			isolation: Isolation::default(),
+			command_policy: None,
+			background_job_limit: None,
+			exec_policy: ExecPolicy::default(),
+			allow_suspend: false,
+			#[cfg(windows)]
+			path_augmentation,
+			#[cfg(windows)]
+			path_warnings: path_warning.into_iter().collect(),
+			#[cfg(windows)]
+			utf8_env: utf8_env.unwrap_or(true),
+			#[cfg(unix)]
+			children: Arc::default(),
+			disposed: Arc::default(),
+		})
+	}
+
+	/// Error if `dispose()` ended the shell.
+	fn check_disposed(&self) -> Result<()> {
+		if self.disposed.load(Ordering::Relaxed) {
+			return Err(Error::from_reason("Shell has been disposed"));
+		}
+		Ok(())
+	}
+}
+
+/// What the `exec` builtin may do in a session (`allowExec`).
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum ExecPolicy {
+	/// `exec` is disabled.
+	#[default]
+	None,
+	/// Only `exec` with redirections and no command, which never replaces the
+	/// shell process.
+	RedirectionsOnly,
+	/// brush's own `exec`, command replacement included.
+	Full,
+}
+
+impl ExecPolicy {
+	fn from_option(value: Option<&str>) -> Result<Self> {
+		match value {
+			None | Some("none") => Ok(Self::None),
+			Some("redirections-only") => Ok(Self::RedirectionsOnly),
+			Some("full") => Ok(Self::Full),
+			Some(other) => Err(Error::from_reason(format!(
+				"Invalid allowExec: {other} (expected \"full\", \"redirections-only\", or \"none\")"
+			))),
+		}
+	}
+}
+
+/// Explicit Unix tool directory or provider order for
+/// `windowsPathAugmentation`; set at most one of them.
+#[napi(object)]
+pub struct WindowsPathOverride {
+	/// Directory to append to PATH instead of `PI_SHELL_UNIX_TOOLS` or
+	/// discovery. Used only if it holds `ls.exe`; otherwise discovery runs
+	/// and `Shell.pathDiagnostics()` reports a warning.
+	#[napi(js_name = "unixTools")]
+	pub unix_tools: Option<String>,
+	/// Providers to discover, in preference order: "git", "msys2", "cygwin"
+	/// (the default order). Only the first one found is added to PATH.
+	pub providers:  Option<Vec<String>>,
+}
+
+/// Throwaway directories for `isolate`.
+#[napi(object)]
+pub struct ShellIsolation {
+	/// `true` for a scratch `HOME` removed with the shell, or a directory to
+	/// use instead.
+	pub home: Option<Either<bool, String>>,
+	/// Same for `TMPDIR`, `TEMP`, and `TMP`.
+	pub tmp:  Option<Either<bool, String>>,
+}
+
+/// A command about to run, as passed to `onCommandPolicy`.
+#[napi(object)]
+pub struct CommandPolicyRequest {
+	/// The command name followed by its expanded arguments.
+	pub argv:    Vec<String>,
+	/// Resolved path of the external program; absent for builtins.
+	pub program: Option<String>,
+}
+
+/// Verdict returned by `onCommandPolicy`.
 #[napi(object)]
+pub struct CommandPolicyDecision {
+	/// Whether the command may run.
+	pub allow:  bool,
+	/// Why it was denied, printed to stderr.
+	pub reason: Option<String>,
+}
+
+/// JS policy callback; may answer directly or with a promise. Weak, so a
+/// shell holding one doesn't keep Node alive.
+type CommandPolicyCallback = ThreadsafeFunction<
+	CommandPolicyRequest,
+	Either<Promise<CommandPolicyDecision>, CommandPolicyDecision>,
+	CommandPolicyRequest,
+	Status,
+	false,
+	true,
+>;
+
+/// Builtins `onCommandPolicy` is consulted for, besides every external
+/// program: the ones that reach outside the shell.
+const POLICY_BUILTINS: [&str; 7] = ["cd", "pushd", "popd", "kill", "ulimit", "source", "."];
+
+/// Options for configuring a persistent shell session.
+///
+/// On Windows every process a session starts is placed in a job object that
+/// is closed with the session, killing whatever is still running, background
+/// jobs and disowned processes included. A session ends on `dispose()`, when
+/// a command is cancelled or runs `exit`, and when the shell is dropped.
+#[napi(object, object_to_js = false)]
 pub struct ShellOptions {
 	/// Environment variables to apply once per session.
-	pub session_env:   Option<HashMap<String, String>>,
+	pub session_env:               Option<HashMap<String, String>>,
 	/// Optional snapshot file to source on session creation.
-	pub snapshot_path: Option<String>,
+	pub snapshot_path:             Option<String>,
+	/// How PATH is augmented with Unix tools on Windows: "auto" (default)
+	/// uses `PI_SHELL_UNIX_TOOLS` or discovers Git's `usr\bin` (falling back to
+	/// MSYS2, then Cygwin), "off" leaves PATH alone, `{ unixTools }` names the
+	/// directory, and `{ providers }` reorders discovery. Ignored on other
+	/// platforms.
+	#[napi(js_name = "windowsPathAugmentation")]
+	pub windows_path_augmentation: Option<Either<String, WindowsPathOverride>>,
+	/// Have `getCwd()` return native Windows paths (`C:\Users\me`) instead of
+	/// the forward-slash form scripts see as `$PWD`.
+	#[napi(js_name = "nativePaths")]
+	pub native_paths:              Option<bool>,
+	/// Ask child processes on Windows for UTF-8 output by exporting a UTF-8
+	/// `LANG`/`LC_ALL` and `PYTHONIOENCODING` where unset (default true).
+	/// Ignored on other platforms.
+	#[napi(js_name = "utf8Env")]
+	pub utf8_env:                  Option<bool>,
+	/// Give the session its own `HOME` and/or temp directory, exported before
+	/// the snapshot is sourced.
+	pub isolate:                   Option<ShellIsolation>,
+	/// Consulted before each external program and directory-, signal-, or
+	/// script-related builtin runs, including inside functions and subshells.
+	/// A denied command fails with exit code 126 and the reason on stderr.
+	#[napi(js_name = "onCommandPolicy")]
+	pub on_command_policy:         Option<CommandPolicyCallback>,
+	/// Most background jobs (`cmd &`) the session runs at once, counting those
+	/// started from subshells; at least 1. A slot frees when its job finishes.
+	#[napi(js_name = "maxBackgroundJobs")]
+	pub max_background_jobs:       Option<u32>,
+	/// What launching a job past `maxBackgroundJobs` does: "fail" (default)
+	/// reports the limit on stderr with status 1, "wait" blocks until a slot
+	/// frees.
+	#[napi(js_name = "backgroundJobPolicy")]
+	pub background_job_policy:     Option<String>,
+	/// What the `exec` builtin may do: "none" (default) disables it,
+	/// "redirections-only" allows `exec` without a command (e.g. `exec 3>log`)
+	/// but rejects replacing the shell, and "full" enables it entirely. Command
+	/// replacement is only available on Unix.
+	#[napi(js_name = "allowExec")]
+	pub allow_exec:                Option<String>,
+	/// Enable the `suspend` builtin (default false). Unix only.
+	#[napi(js_name = "allowSuspend")]
+	pub allow_suspend:             Option<bool>,
 }
 
 /// Options for running a shell command (internal, lifetime-free).
+#[derive(Default)]
 struct ShellRunConfig {
 	/// Command string to execute in the shell.
-	command: String,
+	command:         String,
 	/// Working directory for the command.
-	cwd:     Option<String>,
+	cwd:             Option<String>,
 	/// Environment variables to apply for this command only.
-	env:     Option<HashMap<String, String>>,
+	env:             Option<HashMap<String, String>>,
+	/// Code page the command's output is decoded from.
+	output_encoding: OutputEncoding,
+	/// Hide inherited exports other than PATH from the command.
+	clean_env:       bool,
+	/// Stop the command at the first invalid UTF-8 instead of replacing it.
+	strict_utf8:     bool,
+}
+
+/// What was read from each output stream of a run.
+#[derive(Default)]
+struct OutputStats {
+	stdout: StreamStats,
+	stderr: StreamStats,
+}
+
+/// Bytes read from one output stream, counted before decoding so they include
+/// output no callback received, and where strict UTF-8 decoding failed.
+struct StreamStats {
+	bytes:      AtomicU64,
+	/// Offset of the first invalid UTF-8 sequence, or `u64::MAX` for none.
+	invalid_at: AtomicU64,
+}
+
+impl Default for StreamStats {
+	fn default() -> Self {
+		Self { bytes: AtomicU64::new(0), invalid_at: AtomicU64::new(u64::MAX) }
+	}
+}
+
+/// Strict UTF-8 decoding of a run's output, shared by both streams.
+struct StrictUtf8 {
+	/// The run's cancellation token, cancelled at the first invalid sequence.
+	command: CancellationToken,
+	/// Set once a stream has recorded invalid UTF-8.
+	failed:  AtomicBool,
+}
+
+impl StrictUtf8 {
+	const fn new(command: CancellationToken) -> Self {
+		Self { command, failed: AtomicBool::new(false) }
+	}
+
+	/// Record invalid UTF-8 at `offset` of a stream and stop the command.
+	/// Returns `false`, recording nothing, when the command was already
+	/// stopped: by an abort or timeout, or by the other stream's failure.
+	fn fail(&self, stats: &StreamStats, offset: u64) -> bool {
+		if self.command.is_cancelled() || self.failed.swap(true, Ordering::AcqRel) {
+			return false;
+		}
+		stats.invalid_at.store(offset, Ordering::Relaxed);
+		self.command.cancel();
+		true
+	}
+}
+
+impl OutputStats {
+	fn stdout_bytes(&self) -> f64 {
+		self.stdout.bytes.load(Ordering::Relaxed) as f64
+	}
+
+	fn stderr_bytes(&self) -> f64 {
+		self.stderr.bytes.load(Ordering::Relaxed) as f64
+	}
+
+	/// Stream and byte offset of the invalid UTF-8 that stopped a strict run.
+	fn invalid_utf8(&self) -> Option<(&'static str, f64)> {
+		[("stdout", &self.stdout), ("stderr", &self.stderr)]
+			.into_iter()
+			.find_map(|(name, stream)| match stream.invalid_at.load(Ordering::Relaxed) {
+				u64::MAX => None,
+				offset => Some((name, offset as f64)),
+			})
+	}
 }
 
 /// Options for running a shell command.
 #[napi(object)]
 pub struct ShellRunOptions<'env> {
 	/// Command string to execute in the shell.
-	pub command:    String,
+	pub command:           String,
 	/// Working directory for the command.
-	pub cwd:        Option<String>,
+	pub cwd:               Option<String>,
 	/// Environment variables to apply for this command only.
-	pub env:        Option<HashMap<String, String>>,
+	pub env:               Option<HashMap<String, String>>,
 	/// Timeout in milliseconds before cancelling the command.
 	#[napi(js_name = "timeoutMs")]
-	pub timeout_ms: Option<u32>,
+	pub timeout_ms:        Option<u32>,
+	/// When `timeoutMs` starts counting: "enqueue" (default) when `run` is
+	/// called, so waiting for an earlier command counts against it, or "start"
+	/// when the command begins.
+	#[napi(js_name = "timeoutStartsAt")]
+	pub timeout_starts_at: Option<String>,
+	/// Code page to decode output from: "utf8" (default), "cp437", "cp1252",
+	/// or "auto" for the Windows OEM code page.
+	#[napi(js_name = "outputEncoding")]
+	pub output_encoding:   Option<String>,
+	/// Create a private temp directory for this run, exported as
+	/// `$PI_SCRATCH` and removed afterwards, even on cancel or timeout.
+	#[napi(js_name = "scratchDir")]
+	pub scratch_dir:       Option<bool>,
+	/// Run with only PATH and `env` exported, like `env -i`. Inherited
+	/// variables stay readable as shell variables and are re-exported after
+	/// the run. The session marker stays exported so the session can still
+	/// find and kill the run's descendants.
+	#[napi(js_name = "cleanEnv")]
+	pub clean_env:         Option<bool>,
+	/// How invalid UTF-8 in output is handled: "lossy" (default) replaces it
+	/// with U+FFFD, "strict" stops the command and reports where it occurred.
+	/// Only applies when `outputEncoding` is "utf8".
+	#[napi(js_name = "outputUtf8")]
+	pub output_utf8:       Option<String>,
 	/// Abort signal for cancelling the operation.
-	pub signal:     Option<Unknown<'env>>,
+	pub signal:            Option<Unknown<'env>>,
 }
 
 /// Result of running a shell command.
 #[napi(object)]
 pub struct ShellRunResult {
 	/// Exit code when the command completes normally.
-	pub exit_code: Option<i32>,
+	pub exit_code:            Option<i32>,
 	/// Whether the command was cancelled via abort.
-	pub cancelled: bool,
+	pub cancelled:            bool,
 	/// Whether the command timed out before completion.
-	pub timed_out: bool,
+	pub timed_out:            bool,
+	/// Milliseconds between `run` being called and the command starting:
+	/// waiting for earlier commands and creating the session.
+	#[napi(js_name = "queuedMs")]
+	pub queued_ms:            f64,
+	/// Milliseconds from the command starting until it finished or was
+	/// cancelled; 0 when cancelled while queued.
+	#[napi(js_name = "durationMs")]
+	pub duration_ms:          f64,
+	/// Processes killed on cancellation, in total.
+	#[napi(js_name = "terminatedProcesses")]
+	pub terminated_processes: Option<u32>,
+	/// Processes killed on cancellation by each wave: SIGTERM then SIGKILL on
+	/// Unix, a single job termination on Windows.
+	#[napi(js_name = "killWaves")]
+	pub kill_waves:           Option<Vec<u32>>,
+	/// Bytes the command wrote to stdout.
+	#[napi(js_name = "stdoutBytes")]
+	pub stdout_bytes:         f64,
+	/// Bytes the command wrote to stderr.
+	#[napi(js_name = "stderrBytes")]
+	pub stderr_bytes:         f64,
+	/// Whether the command wrote anything to stderr.
+	#[napi(js_name = "hadStderr")]
+	pub had_stderr:           bool,
+	/// `PIPESTATUS` after the command: the exit code of each stage of the
+	/// last pipeline it ran, when that pipeline had more than one stage.
+	#[napi(js_name = "pipeStatus")]
+	pub pipe_status:          Option<Vec<i32>>,
+	/// Whether `outputUtf8: "strict"` stopped the command at invalid UTF-8.
+	#[napi(js_name = "invalidUtf8")]
+	pub invalid_utf8:         bool,
+	/// Byte offset of the invalid sequence within its stream.
+	#[napi(js_name = "invalidUtf8Offset")]
+	pub invalid_utf8_offset:  Option<f64>,
+	/// Stream the invalid sequence appeared on: "stdout" or "stderr".
+	#[napi(js_name = "invalidUtf8Stream")]
+	pub invalid_utf8_stream:  Option<String>,
+	/// The `scratchDir` directory exported as `$PI_SCRATCH`.
+	#[napi(js_name = "scratchDir")]
+	pub scratch_dir:          Option<String>,
+	/// Whether the scratch directory was fully removed. When `false`, what's
+	/// left (typically files a surviving background job holds open) is
+	/// retried on `dispose()`.
+	#[napi(js_name = "scratchRemoved")]
+	pub scratch_removed:      Option<bool>,
+}
+
+/// What `Shell.preview()` found for one simple command.
+#[napi(object)]
+pub struct PreviewedCommand {
+	/// Command name and arguments after alias resolution and expansion.
+	pub argv:        Vec<String>,
+	/// Indices into `argv` of words kept as written because expanding them
+	/// would run a command or process substitution.
+	pub unevaluated: Vec<u32>,
+	/// "builtin", "function", "external", or "unknown" (not found, or the
+	/// name itself is unevaluated).
+	pub kind:        String,
+	/// Resolved path of an external program.
+	pub path:        Option<String>,
+}
+
+/// Result of `Shell.preview()`.
+#[napi(object)]
+pub struct CommandPreview {
+	/// Simple commands in source order, including those inside loops,
+	/// conditionals, and subshells.
+	pub commands: Vec<PreviewedCommand>,
+}
+
+/// A background or stopped job tracked by a shell session.
+#[napi(object)]
+pub struct ShellJob {
+	/// Job number, as used in `%1`.
+	pub id:      u32,
+	/// Command line the job runs.
+	pub command: String,
+	/// "running", "stopped", "done", or "unknown".
+	pub state:   String,
+	/// Process id of the job's first external process, if any.
+	pub pid:     Option<i32>,
+}
+
+/// Jobs of a shell session, from `Shell.listJobs()`.
+#[napi(object)]
+pub struct ShellJobs {
+	/// Jobs in the order they were started.
+	pub jobs:                    Vec<ShellJob>,
+	/// Background jobs holding a slot under `maxBackgroundJobs`, including
+	/// those started from subshells.
+	#[napi(js_name = "runningBackgroundJobs")]
+	pub running_background_jobs: u32,
+	/// The `maxBackgroundJobs` limit, if set.
+	#[napi(js_name = "maxBackgroundJobs")]
+	pub max_background_jobs:     Option<u32>,
+}
+
+/// Child process counts for a shell, from `Shell.processStats()`.
+#[napi(object)]
+pub struct ProcessStats {
+	/// Children the shell spawned that haven't been waited on yet.
+	#[napi(js_name = "liveChildren")]
+	pub live_children:  u32,
+	/// Exited children nothing waited on, reaped by the shell so far.
+	#[napi(js_name = "reapedZombies")]
+	pub reaped_zombies: u32,
 }
 
 /// Persistent brush-core shell session.
 #[napi]
 pub struct Shell {
-	session: Arc<TokioMutex<Option<ShellSessionCore>>>,
-	config:  ShellConfig,
+	session:      Arc<TokioMutex<Option<ShellSessionCore>>>,
+	config:       ShellConfig,
+	native_paths: bool,
+	/// Directories created for `isolate`, and `scratchDir` leftovers, removed
+	/// on `dispose()` or drop.
+	scratch:      Arc<parking_lot::Mutex<ScratchDirs>>,
 }
 
 #[napi]
@@ -122,12 +609,48 @@ impl Shell {
 	/// Create a new shell session from optional configuration.
 	///
 	/// The options set session-scoped environment variables and a snapshot path.
-	pub fn new(options: Option<ShellOptions>) -> Self {
-		let config = options.map_or_else(
-			|| ShellConfig { session_env: None, snapshot_path: None },
-			|opt| ShellConfig { session_env: opt.session_env, snapshot_path: opt.snapshot_path },
-		);
-		Self { session: Arc::new(TokioMutex::new(None)), config }
+	pub fn new(options: Option<ShellOptions>) -> Result<Self> {
+		let Some(opt) = options else {
+			return Ok(Self {
+				session:      Arc::new(TokioMutex::new(None)),
+				config:       ShellConfig::new(None, None, None, None)?,
+				native_paths: false,
+				scratch:      Arc::default(),
+			});
+		};
+		let mut config = ShellConfig::new(
+			opt.session_env,
+			opt.snapshot_path,
+			opt.windows_path_augmentation,
+			opt.utf8_env,
+		)?;
+		let (home, tmp) = opt
+			.isolate
+			.map_or((None, None), |isolate| (isolate.home, isolate.tmp));
+		let (isolation, scratch) = Isolation::from_options(home, tmp)?;
+		config.isolation = isolation;
+		config.command_policy = opt.on_command_policy.map(Arc::new);
+		let wait_for_slot = match opt.background_job_policy.as_deref() {
+			None | Some("fail") => false,
+			Some("wait") => true,
+			Some(other) => {
+				return Err(Error::from_reason(format!(
+					"Invalid backgroundJobPolicy: {other} (expected \"fail\" or \"wait\")"
+				)));
+			},
+		};
+		if opt.max_background_jobs == Some(0) {
+			return Err(Error::from_reason("Invalid maxBackgroundJobs: 0 (expected at least 1)"));
+		}
+		config.background_job_limit = opt.max_background_jobs.map(|max| (max, wait_for_slot));
+		config.exec_policy = ExecPolicy::from_option(opt.allow_exec.as_deref())?;
+		config.allow_suspend = opt.allow_suspend.unwrap_or(false);
+		Ok(Self {
+			session: Arc::new(TokioMutex::new(None)),
+			config,
+			native_paths: opt.native_paths.unwrap_or(false),
+			scratch: Arc::new(parking_lot::Mutex::new(scratch)),
+		})
 	}
 
 	/// Run a shell command using the provided options.
@@ -144,18 +667,108 @@ impl Shell {
 			ThreadsafeFunction<String>,
 		>,
 	) -> Result<PromiseRaw<'e, ShellRunResult>> {
-		let ct = task::CancelToken::new(options.timeout_ms, options.signal);
+		self.config.check_disposed()?;
+		let enqueued = Instant::now();
+		let output_encoding = OutputEncoding::from_option(options.output_encoding.as_deref())?;
+		let strict_utf8 = strict_utf8_from_option(options.output_utf8.as_deref())?;
+		let (ct, start_timeout) = match options.timeout_starts_at.as_deref() {
+			None | Some("enqueue") => {
+				(task::CancelToken::new(options.timeout_ms, options.signal), None)
+			},
+			Some("start") => (task::CancelToken::new(None, options.signal), options.timeout_ms),
+			Some(other) => {
+				return Err(Error::from_reason(format!(
+					"Invalid timeoutStartsAt: {other} (expected \"enqueue\" or \"start\")"
+				)));
+			},
+		};
 		let session = self.session.clone();
 		let config = self.config.clone();
 
-		let run_config =
-			ShellRunConfig { command: options.command, cwd: options.cwd, env: options.env };
+		let mut env_vars = options.env;
+		let mut run_scratch = ScratchDirs::default();
+		let scratch_dir = if options.scratch_dir == Some(true) {
+			let dir = run_scratch.create("run")?.to_string_lossy().into_owned();
+			env_vars
+				.get_or_insert_default()
+				.insert("PI_SCRATCH".to_owned(), dir.clone());
+			Some(dir)
+		} else {
+			None
+		};
+		let shell_scratch = self.scratch.clone();
+
+		let run_config = ShellRunConfig {
+			command: options.command,
+			cwd: options.cwd,
+			env: env_vars,
+			output_encoding,
+			clean_env: options.clean_env.unwrap_or(false),
+			strict_utf8,
+		};
 
 		task::future(env, "shell.run", async move {
-			run_shell_session(session, config, run_config, on_chunk, ct).await
+			let mut result =
+				run_shell_session(session, config, run_config, on_chunk, ct, enqueued, start_timeout)
+					.await?;
+			if scratch_dir.is_some() {
+				result.scratch_removed = Some(run_scratch.remove().is_empty());
+				result.scratch_dir = scratch_dir;
+				shell_scratch.lock().adopt(run_scratch);
+			}
+			Ok(result)
+		})
+	}
+
+	/// Current directory of the session, or `None` before its first command.
+	///
+	/// On Windows this is the forward-slash form scripts see as `$PWD`
+	/// (`C:/Users/me/project`), or the native form with `nativePaths`.
+	#[napi(js_name = "getCwd")]
+	pub async fn get_cwd(&self) -> Option<String> {
+		let session = self.session.lock().await;
+		let cwd = session.as_ref()?.shell.working_dir();
+		Some(if self.native_paths {
+			to_native_path(cwd)
+		} else {
+			sys::fs::to_shell_path(cwd)
 		})
 	}
 
+	/// How this shell's sessions augment PATH on Windows: the directory from
+	/// `windowsPathAugmentation.unixTools`, else the (cached) discovery for its
+	/// provider order, plus why an override was ignored. Empty with "off" and
+	/// on other platforms.
+	#[napi(js_name = "pathDiagnostics")]
+	pub fn path_diagnostics(&self) -> ShellPathDiagnostics {
+		#[cfg(windows)]
+		{
+			let mut diagnostics = match &self.config.path_augmentation {
+				PathAugmentation::Off => ShellPathDiagnostics::default(),
+				PathAugmentation::UnixTools(dir) => ShellPathDiagnostics {
+					source:   Some(windows::UnixToolsSource::Option.as_str().to_string()),
+					provider: dir.provider.map(|provider| provider.as_str().to_string()),
+					paths:    vec![dir.path.clone()],
+					probes:   vec![ShellPathProbe {
+						source:   windows::UnixToolsSource::Option.as_str().to_string(),
+						path:     Some(dir.path.clone()),
+						accepted: true,
+					}],
+					warnings: Vec::new(),
+				},
+				PathAugmentation::Auto(providers) => {
+					ShellPathDiagnostics::from(&*windows::cached_unix_tools_discovery(providers))
+				},
+			};
+			diagnostics
+				.warnings
+				.extend(self.config.path_warnings.iter().cloned());
+			diagnostics
+		}
+		#[cfg(not(windows))]
+		ShellPathDiagnostics::default()
+	}
+
 	/// Abort all running commands for this shell session.
 	///
 	/// Returns `Ok(())` even when no commands are running.
@@ -168,96 +781,366 @@ impl Shell {
 		}
 		Ok(())
 	}
+
+	/// List the session's jobs, first dropping those that have finished, and
+	/// how close it is to `maxBackgroundJobs`. Waits for a running command to
+	/// finish.
+	#[napi(js_name = "listJobs")]
+	pub async fn list_jobs(&self) -> Result<ShellJobs> {
+		let mut session = self.session.lock().await;
+		self.config.check_disposed()?;
+		let Some(core) = session.as_mut() else {
+			return Ok(ShellJobs {
+				jobs:                    Vec::new(),
+				running_background_jobs: 0,
+				max_background_jobs:     self.config.background_job_limit.map(|(max, _)| max),
+			});
+		};
+		let _ = core.shell.jobs.poll();
+		let jobs: Vec<ShellJob> = core
+			.shell
+			.jobs
+			.jobs
+			.iter()
+			.map(|job| ShellJob {
+				id:      job.id as u32,
+				command: job.command_line.clone(),
+				state:   match job.state {
+					JobState::Running => "running",
+					JobState::Stopped => "stopped",
+					JobState::Done => "done",
+					JobState::Unknown => "unknown",
+				}
+				.to_owned(),
+				pid:     job.representative_pid(),
+			})
+			.collect();
+		let limit = core.shell.background_job_limit();
+		let running = limit.map_or_else(
+			|| jobs.iter().filter(|job| job.state == "running").count(),
+			|limit| limit.running(),
+		);
+		Ok(ShellJobs {
+			jobs,
+			running_background_jobs: running as u32,
+			max_background_jobs: limit.map(|limit| limit.max() as u32),
+		})
+	}
+
+	/// Count the session's live child processes, first reaping any that
+	/// exited without being waited on.
+	///
+	/// Unix reaps only children the shell spawned itself, and skips reaping
+	/// while a command is running. Windows has no zombies; it reports the
+	/// processes in the session's job, waiting for a running command to finish.
+	#[napi(js_name = "processStats")]
+	#[cfg_attr(
+		unix,
+		allow(
+			clippy::unused_async,
+			clippy::unused_async_trait_impl,
+			reason = "Windows waits for the session lock"
+		)
+	)]
+	pub async fn process_stats(&self) -> Result<ProcessStats> {
+		#[cfg(unix)]
+		let live_children = match self.session.try_lock() {
+			Ok(session) => match session.as_ref() {
+				Some(core) => core.reap_children(),
+				None => self.config.children.reap(&HashSet::new()),
+			},
+			Err(_) => self.config.children.tracked(),
+		};
+		#[cfg(unix)]
+		let reaped_zombies = self.config.children.reaped();
+		#[cfg(windows)]
+		let (live_children, reaped_zombies) = {
+			let session = self.session.lock().await;
+			let job = session.as_ref().and_then(|core| core.job.as_ref());
+			(job.map_or(0, |job| job.active_processes()), 0)
+		};
+		Ok(ProcessStats { live_children, reaped_zombies })
+	}
+
+	/// Show what `command` would run without running it: each simple command
+	/// with aliases resolved, words expanded against the session's current
+	/// variables, and the target classified. Nothing is executed; words that
+	/// need a command substitution are returned unexpanded.
+	#[napi]
+	pub async fn preview(&self, command: String) -> Result<CommandPreview> {
+		let mut session_guard = self.session.lock().await;
+		let session = open_session(&mut session_guard, &self.config).await?;
+		let commands = preview::preview_command(&session.shell, &command).await?;
+		Ok(CommandPreview { commands })
+	}
+
+	/// End the session, stopping its background jobs, and remove the
+	/// directories created for `isolate`.
+	///
+	/// Returns the paths that couldn't be removed, typically files still held
+	/// open by a process that outlived the session. Afterwards `run`,
+	/// `preview`, and `listJobs` reject instead of starting a new session.
+	#[napi]
+	pub async fn dispose(&self) -> Vec<String> {
+		let session = {
+			let mut session = self.session.lock().await;
+			self.config.disposed.store(true, Ordering::Relaxed);
+			session.take()
+		};
+		if let Some(core) = session {
+			terminate_background_jobs(&core);
+			core.terminate_processes().await;
+		}
+		let leftover = self.scratch.lock().remove();
+		if leftover.is_empty() {
+			return leftover;
+		}
+		// Give killed processes a moment to release their handles.
+		time::sleep(Duration::from_millis(250)).await;
+		self.scratch.lock().remove()
+	}
+}
+
+/// Whether `outputUtf8` asks for strict decoding.
+fn strict_utf8_from_option(value: Option<&str>) -> Result<bool> {
+	match value {
+		None | Some("lossy") => Ok(false),
+		Some("strict") => Ok(true),
+		Some(other) => Err(Error::from_reason(format!(
+			"Invalid outputUtf8: {other} (expected \"lossy\" or \"strict\")"
+		))),
+	}
+}
+
+/// The session in `slot`, created on first use unless the shell was disposed.
+async fn open_session<'a>(
+	slot: &'a mut Option<ShellSessionCore>,
+	config: &ShellConfig,
+) -> Result<&'a mut ShellSessionCore> {
+	config.check_disposed()?;
+	match slot {
+		Some(session) => Ok(session),
+		None => Ok(slot.insert(create_session(config).await?)),
+	}
+}
+
+/// Milliseconds a run spent queued and executing, also recorded in the work
+/// profile as `shell.run.queued` and `shell.run.exec`.
+fn run_timing(enqueued: Instant, started: Option<Instant>) -> (f64, f64) {
+	let now = Instant::now();
+	let (queued, exec) = match started {
+		Some(started) => (started - enqueued, now - started),
+		None => (now - enqueued, Duration::ZERO),
+	};
+	prof::record_sample(&["shell.run", "shell.run.queued"], queued);
+	if started.is_some() {
+		prof::record_sample(&["shell.run", "shell.run.exec"], exec);
+	}
+	(queued.as_secs_f64() * 1000.0, exec.as_secs_f64() * 1000.0)
 }
 
 /// Run a shell command within a persistent session.
+///
+/// `start_timeout` is a timeout counted from when the command starts rather
+/// than from `enqueued`.
 async fn run_shell_session(
 	session: Arc<TokioMutex<Option<ShellSessionCore>>>,
 	config: ShellConfig,
 	run_config: ShellRunConfig,
 	on_chunk: Option<ThreadsafeFunction<String>>,
 	mut ct: task::CancelToken,
+	enqueued: Instant,
+	start_timeout: Option<u32>,
 ) -> Result<ShellRunResult> {
 	let tokio_cancel = CancellationToken::new();
+	let counts = Arc::new(OutputStats::default());
+	let (started_tx, started) = watch::channel(None);
 
 	let mut run_task = tokio::spawn({
 		let session = session.clone();
 		let tokio_cancel = tokio_cancel.clone();
+		let counts = counts.clone();
 		let at = ct.emplace_abort_token();
 		async move {
 			let mut session_guard = session.lock().await;
-
-			let session = match &mut *session_guard {
-				Some(session) => session,
-				None => session_guard.insert(create_session(&config).await?),
-			};
+			let session = open_session(&mut session_guard, &config).await?;
 			session.current_abort = Some(at);
-			run_shell_command(session, &run_config, on_chunk, tokio_cancel).await
+			started_tx.send_replace(Some(Instant::now()));
+			let result =
+				run_shell_command(session, &run_config, on_chunk, tokio_cancel, &counts).await?;
+			Ok((result, pipe_status(&session.shell)))
 		}
 	});
 
+	let start_deadline = {
+		let mut started = started.clone();
+		async move {
+			let Some(timeout_ms) = start_timeout else {
+				return std::future::pending().await;
+			};
+			let Ok(at) = started
+				.wait_for(Option::is_some)
+				.await
+				.map(|at| at.unwrap_or(enqueued))
+			else {
+				return std::future::pending().await;
+			};
+			time::sleep_until((at + Duration::from_millis(timeout_ms.into())).into()).await;
+		}
+	};
+	let aborted = async {
+		tokio::select! {
+			reason = ct.wait() => reason,
+			() = start_deadline => task::AbortReason::Timeout,
+		}
+	};
+
 	let res = tokio::select! {
 		res = &mut run_task => res,
-		reason = ct.wait() => {
+		reason = aborted => {
+			let (queued_ms, duration_ms) = run_timing(enqueued, *started.borrow());
 			tokio_cancel.cancel();
 			let graceful = time::timeout(Duration::from_secs(2), &mut run_task).await;
 			if graceful.is_err() {
 				run_task.abort();
 				let _ = run_task.await;
 			}
-			*session.lock().await = None;
+			let core = session.lock().await.take();
+			let kill_waves = match core {
+				Some(core) => core.terminate_processes().await,
+				None => None,
+			};
+			let invalid = counts.invalid_utf8();
 			return Ok(ShellRunResult {
 				exit_code: None,
 				cancelled: matches!(reason, task::AbortReason::Signal),
 				timed_out: matches!(reason, task::AbortReason::Timeout),
+				queued_ms,
+				duration_ms,
+				terminated_processes: kill_waves.as_ref().map(|waves| waves.iter().sum()),
+				kill_waves,
+				stdout_bytes: counts.stdout_bytes(),
+				stderr_bytes: counts.stderr_bytes(),
+				had_stderr: counts.stderr_bytes() > 0.0,
+				pipe_status: None,
+				invalid_utf8: invalid.is_some(),
+				invalid_utf8_offset: invalid.map(|(_, offset)| offset),
+				invalid_utf8_stream: invalid.map(|(stream, _)| stream.to_owned()),
+				scratch_dir: None,
+				scratch_removed: None,
 			});
 		}
 	};
 	let res =
 		res.unwrap_or_else(|e| Err(Error::from_reason(format!("Shell execution task failed: {e}"))));
 
-	let keepalive = res.as_ref().is_ok_and(session_keepalive);
+	let keepalive = res
+		.as_ref()
+		.is_ok_and(|(result, _)| session_keepalive(result));
 	if keepalive {
 		// Clear abort token when command completes
 		if let Some(session_core) = session.lock().await.as_mut() {
 			session_core.current_abort = None;
+			#[cfg(unix)]
+			session_core.reap_children();
 		}
 	} else {
 		*session.lock().await = None;
 	}
-	Ok(ShellRunResult { exit_code: Some(exit_code(&res?)), cancelled: false, timed_out: false })
+	let (result, pipe_status) = res?;
+	let invalid = counts.invalid_utf8();
+	let (queued_ms, duration_ms) = run_timing(enqueued, *started.borrow());
+	Ok(ShellRunResult {
+		exit_code: Some(exit_code(&result)),
+		cancelled: false,
+		timed_out: false,
+		queued_ms,
+		duration_ms,
+		terminated_processes: None,
+		kill_waves: None,
+		stdout_bytes: counts.stdout_bytes(),
+		stderr_bytes: counts.stderr_bytes(),
+		had_stderr: counts.stderr_bytes() > 0.0,
+		pipe_status,
+		invalid_utf8: invalid.is_some(),
+		invalid_utf8_offset: invalid.map(|(_, offset)| offset),
+		invalid_utf8_stream: invalid.map(|(stream, _)| stream.to_owned()),
+		scratch_dir: None,
+		scratch_removed: None,
+	})
 }
 
 /// Options for executing a shell command via brush-core.
 #[napi(object)]
 pub struct ShellExecuteOptions<'env> {
 	/// Command string to execute in the shell.
-	pub command:       String,
+	pub command: String,
 	/// Working directory for the command.
-	pub cwd:           Option<String>,
+	pub cwd: Option<String>,
 	/// Environment variables to apply for this command only.
-	pub env:           Option<HashMap<String, String>>,
+	pub env: Option<HashMap<String, String>>,
 	/// Environment variables to apply once per session.
-	pub session_env:   Option<HashMap<String, String>>,
+	pub session_env: Option<HashMap<String, String>>,
 	/// Timeout in milliseconds before cancelling the command.
 	#[napi(js_name = "timeoutMs")]
-	pub timeout_ms:    Option<u32>,
+	pub timeout_ms: Option<u32>,
 	/// Optional snapshot file to source on session creation.
 	#[napi(js_name = "snapshotPath")]
 	pub snapshot_path: Option<String>,
+	/// How PATH is augmented with Unix tools on Windows (see `ShellOptions`).
+	#[napi(js_name = "windowsPathAugmentation")]
+	pub windows_path_augmentation: Option<Either<String, WindowsPathOverride>>,
+	/// Export UTF-8 locale variables on Windows (see `ShellOptions`).
+	#[napi(js_name = "utf8Env")]
+	pub utf8_env: Option<bool>,
+	/// Code page to decode output from (see `ShellRunOptions`).
+	#[napi(js_name = "outputEncoding")]
+	pub output_encoding: Option<String>,
+	/// Handling of invalid UTF-8 in output (see `ShellRunOptions`).
+	#[napi(js_name = "outputUtf8")]
+	pub output_utf8: Option<String>,
 	/// Abort signal for cancelling the operation.
-	pub signal:        Option<Unknown<'env>>,
+	pub signal: Option<Unknown<'env>>,
 }
 
 /// Result of executing a shell command via brush-core.
 #[napi(object)]
 pub struct ShellExecuteResult {
 	/// Exit code when the command completes normally.
-	pub exit_code: Option<i32>,
+	pub exit_code:            Option<i32>,
 	/// Whether the command was cancelled via abort.
-	pub cancelled: bool,
+	pub cancelled:            bool,
 	/// Whether the command timed out before completion.
-	pub timed_out: bool,
+	pub timed_out:            bool,
+	/// Processes killed on cancellation, in total.
+	#[napi(js_name = "terminatedProcesses")]
+	pub terminated_processes: Option<u32>,
+	/// Processes killed on cancellation by each wave: SIGTERM then SIGKILL on
+	/// Unix, a single job termination on Windows.
+	#[napi(js_name = "killWaves")]
+	pub kill_waves:           Option<Vec<u32>>,
+	/// Bytes the command wrote to stdout.
+	#[napi(js_name = "stdoutBytes")]
+	pub stdout_bytes:         f64,
+	/// Bytes the command wrote to stderr.
+	#[napi(js_name = "stderrBytes")]
+	pub stderr_bytes:         f64,
+	/// Whether the command wrote anything to stderr.
+	#[napi(js_name = "hadStderr")]
+	pub had_stderr:           bool,
+	/// `PIPESTATUS` after the command: the exit code of each stage of the
+	/// last pipeline it ran, when that pipeline had more than one stage.
+	#[napi(js_name = "pipeStatus")]
+	pub pipe_status:          Option<Vec<i32>>,
+	/// Whether `outputUtf8: "strict"` stopped the command at invalid UTF-8.
+	#[napi(js_name = "invalidUtf8")]
+	pub invalid_utf8:         bool,
+	/// Byte offset of the invalid sequence within its stream.
+	#[napi(js_name = "invalidUtf8Offset")]
+	pub invalid_utf8_offset:  Option<f64>,
+	/// Stream the invalid sequence appeared on: "stdout" or "stderr".
+	#[napi(js_name = "invalidUtf8Stream")]
+	pub invalid_utf8_stream:  Option<String>,
 }
 
 /// Execute a brush shell command.
@@ -273,10 +1156,20 @@ pub fn execute_shell<'env>(
 		ThreadsafeFunction<String>,
 	>,
 ) -> Result<PromiseRaw<'env, ShellExecuteResult>> {
-	let config =
-		ShellConfig { session_env: options.session_env, snapshot_path: options.snapshot_path };
-	let run_config =
-		ShellRunConfig { command: options.command, cwd: options.cwd, env: options.env };
+	let config = ShellConfig::new(
+		options.session_env,
+		options.snapshot_path,
+		options.windows_path_augmentation,
+		options.utf8_env,
+	)?;
+	let run_config = ShellRunConfig {
+		command:         options.command,
+		cwd:             options.cwd,
+		env:             options.env,
+		output_encoding: OutputEncoding::from_option(options.output_encoding.as_deref())?,
+		clean_env:       false,
+		strict_utf8:     strict_utf8_from_option(options.output_utf8.as_deref())?,
+	};
 
 	let ct = task::CancelToken::new(options.timeout_ms, options.signal);
 	task::future(env, "shell.execute", async move {
@@ -292,12 +1185,19 @@ async fn run_shell_oneshot(
 	ct: task::CancelToken,
 ) -> Result<ShellExecuteResult> {
 	let tokio_cancel = CancellationToken::new();
+	let counts = Arc::new(OutputStats::default());
+	let session = Arc::new(TokioMutex::new(None::<ShellSessionCore>));
 
 	let mut task = tokio::spawn({
+		let session = session.clone();
 		let tokio_cancel = tokio_cancel.clone();
+		let counts = counts.clone();
 		async move {
-			let mut session = create_session(&config).await?;
-			run_shell_command(&mut session, &run_config, on_chunk, tokio_cancel).await
+			let mut session_guard = session.lock().await;
+			let session = session_guard.insert(create_session(&config).await?);
+			let result =
+				run_shell_command(session, &run_config, on_chunk, tokio_cancel, &counts).await?;
+			Ok((result, pipe_status(&session.shell)))
 		}
 	});
 
@@ -310,10 +1210,25 @@ async fn run_shell_oneshot(
 				task.abort();
 				let _ = task.await;
 			}
+			let core = session.lock().await.take();
+			let kill_waves = match core {
+				Some(core) => core.terminate_processes().await,
+				None => None,
+			};
+			let invalid = counts.invalid_utf8();
 			return Ok(ShellExecuteResult {
 				exit_code: None,
 				cancelled: matches!(reason, task::AbortReason::Signal),
 				timed_out: matches!(reason, task::AbortReason::Timeout),
+				terminated_processes: kill_waves.as_ref().map(|waves| waves.iter().sum()),
+				kill_waves,
+				stdout_bytes: counts.stdout_bytes(),
+				stderr_bytes: counts.stderr_bytes(),
+				had_stderr: counts.stderr_bytes() > 0.0,
+				pipe_status: None,
+				invalid_utf8: invalid.is_some(),
+				invalid_utf8_offset: invalid.map(|(_, offset)| offset),
+				invalid_utf8_stream: invalid.map(|(stream, _)| stream.to_owned()),
 			})
 		},
 	};
@@ -321,7 +1236,188 @@ async fn run_shell_oneshot(
 	let res = run_result
 		.unwrap_or_else(|e| Err(Error::from_reason(format!("Shell execution task failed: {e}"))));
 
-	Ok(ShellExecuteResult { exit_code: Some(exit_code(&res?)), cancelled: false, timed_out: false })
+	let (result, pipe_status) = res?;
+	let invalid = counts.invalid_utf8();
+	Ok(ShellExecuteResult {
+		exit_code: Some(exit_code(&result)),
+		cancelled: false,
+		timed_out: false,
+		terminated_processes: None,
+		kill_waves: None,
+		stdout_bytes: counts.stdout_bytes(),
+		stderr_bytes: counts.stderr_bytes(),
+		had_stderr: counts.stderr_bytes() > 0.0,
+		pipe_status,
+		invalid_utf8: invalid.is_some(),
+		invalid_utf8_offset: invalid.map(|(_, offset)| offset),
+		invalid_utf8_stream: invalid.map(|(stream, _)| stream.to_owned()),
+	})
+}
+
+/// Options for `createSnapshot()`.
+#[napi(object)]
+pub struct SnapshotOptions<'env> {
+	/// Login shell to capture (default `$SHELL`).
+	#[napi(js_name = "shellPath")]
+	pub shell_path:  Option<String>,
+	/// File to write the snapshot to, for use as `snapshotPath`.
+	#[napi(js_name = "outputPath")]
+	pub output_path: String,
+	/// Milliseconds to wait for the shell's startup files (default 10000).
+	#[napi(js_name = "timeoutMs")]
+	pub timeout_ms:  Option<u32>,
+	/// Abort signal for cancelling the operation.
+	pub signal:      Option<Unknown<'env>>,
+}
+
+/// What `createSnapshot()` captured.
+#[napi(object)]
+#[derive(Default)]
+pub struct SnapshotInfo {
+	/// File the snapshot was written to.
+	pub path:              String,
+	/// Login shell that was captured.
+	pub shell:             String,
+	/// Exported variables written.
+	pub variables:         u32,
+	/// Functions written.
+	pub functions:         u32,
+	/// Aliases written.
+	pub aliases:           u32,
+	/// Variables left out: shell-managed ones the session sets itself (`PWD`,
+	/// `SHLVL`, `PS1`, ...) and names a script can't assign.
+	#[napi(js_name = "skippedVariables")]
+	pub skipped_variables: Vec<String>,
+	/// Functions left out because brush can't parse them.
+	#[napi(js_name = "skippedFunctions")]
+	pub skipped_functions: Vec<String>,
+	/// Aliases left out: zsh global and suffix aliases, and those brush can't
+	/// parse.
+	#[napi(js_name = "skippedAliases")]
+	pub skipped_aliases:   Vec<String>,
+}
+
+/// Capture the user's login shell environment as a snapshot file.
+///
+/// Runs the login shell (`shellPath`, else `$SHELL`) in interactive login
+/// mode so its startup files apply, and writes its exported variables,
+/// functions, and aliases as a script `snapshotPath` can source. Rejects when
+/// the shell can't be launched, prints nothing, or runs past the timeout or
+/// abort signal.
+#[napi(js_name = "createSnapshot")]
+pub fn create_snapshot<'env>(
+	env: &'env Env,
+	options: SnapshotOptions<'env>,
+) -> Result<PromiseRaw<'env, SnapshotInfo>> {
+	let shell = options
+		.shell_path
+		.or_else(|| {
+			std::env::var("SHELL")
+				.ok()
+				.filter(|shell| !shell.is_empty())
+		})
+		.ok_or_else(|| Error::from_reason("createSnapshot needs shellPath when $SHELL is unset"))?;
+	let output_path = options.output_path;
+	let ct = task::CancelToken::new(Some(options.timeout_ms.unwrap_or(10_000)), options.signal);
+	task::future(env, "shell.snapshot", async move {
+		snapshot::create_snapshot(&shell, &output_path, ct).await
+	})
+}
+
+/// A probe made while locating Git's Unix tools on Windows.
+#[napi(object)]
+pub struct ShellPathProbe {
+	/// Where the probe looked: "option", "env", "registry", "registry-user",
+	/// "where", "scoop", "user-install", "program-files", "msys2", or
+	/// "cygwin".
+	pub source:   String,
+	/// Install root the probe pointed at, if any.
+	pub path:     Option<String>,
+	/// Whether the install was accepted (its tools directory holds `ls.exe`).
+	pub accepted: bool,
+}
+
+/// How the shell augments PATH on Windows.
+#[napi(object)]
+#[derive(Default)]
+pub struct ShellPathDiagnostics {
+	/// Source of the install whose directories are added to PATH.
+	pub source:   Option<String>,
+	/// Provider of that install: "git", "msys2", or "cygwin".
+	pub provider: Option<String>,
+	/// Directories appended to PATH, in order.
+	pub paths:    Vec<String>,
+	/// Every probe made, in probing order.
+	pub probes:   Vec<ShellPathProbe>,
+	/// Overrides (`PI_SHELL_UNIX_TOOLS`, `unixTools`) ignored because they
+	/// don't hold `ls.exe`.
+	pub warnings: Vec<String>,
+}
+
+#[cfg(windows)]
+impl From<&windows::UnixToolsDiscovery> for ShellPathDiagnostics {
+	fn from(discovery: &windows::UnixToolsDiscovery) -> Self {
+		Self {
+			source:   discovery.source.map(|source| source.as_str().to_string()),
+			provider: discovery
+				.provider
+				.map(|provider| provider.as_str().to_string()),
+			paths:    discovery.paths.clone(),
+			probes:   discovery
+				.probes
+				.iter()
+				.map(|probe| ShellPathProbe {
+					source:   probe.source.as_str().to_string(),
+					path:     probe.install_root.clone(),
+					accepted: probe.accepted,
+				})
+				.collect(),
+			warnings: discovery.warnings.clone(),
+		}
+	}
+}
+
+/// Report how shell sessions with the default `windowsPathAugmentation`
+/// locate Unix tools on Windows.
+///
+/// Lists each probe in order and the install that won (`PI_SHELL_UNIX_TOOLS`,
+/// else Git for Windows, else MSYS2, else Cygwin), from the cached discovery.
+/// `Shell.pathDiagnostics()` reports a specific shell's options instead.
+///
+/// On other platforms PATH is left alone and the report is empty.
+#[napi(js_name = "shellPathDiagnostics")]
+pub fn shell_path_diagnostics() -> ShellPathDiagnostics {
+	#[cfg(windows)]
+	{
+		let discovery = windows::cached_unix_tools_discovery(&windows::DEFAULT_PROVIDERS);
+		ShellPathDiagnostics::from(&*discovery)
+	}
+	#[cfg(not(windows))]
+	ShellPathDiagnostics::default()
+}
+
+/// Forget the cached Windows Unix tools discoveries so the next session
+/// probes again, e.g. after Git was installed while the process was running.
+#[cfg(windows)]
+#[napi(js_name = "invalidateWindowsPathCache")]
+pub fn invalidate_windows_path_cache() {
+	windows::invalidate_unix_tools_discovery();
+}
+
+/// No discovery is cached on other platforms.
+#[cfg(not(windows))]
+#[napi(js_name = "invalidateWindowsPathCache")]
+pub const fn invalidate_windows_path_cache() {}
+
+/// Paths are only rewritten for the shell on Windows.
+#[cfg(not(windows))]
+const fn shell_env_value(_key: &str, value: String) -> String {
+	value
+}
+
+#[cfg(not(windows))]
+fn to_native_path(path: &std::path::Path) -> String {
+	path.to_string_lossy().into_owned()
 }
 
 fn null_file() -> Result<OpenFile> {
@@ -416,14 +1512,26 @@ async fn create_session(config: &ShellConfig) -> Result<ShellSessionCore> {
 		.await
 		.map_err(|err| Error::from_reason(format!("Failed to initialize shell: {err}")))?;
 
-	if let Some(exec_builtin) = shell.builtin_mut("exec") {
-		exec_builtin.disabled = true;
+	match config.exec_policy {
+		ExecPolicy::None => {
+			if let Some(exec_builtin) = shell.builtin_mut("exec") {
+				exec_builtin.disabled = true;
+			}
+		},
+		ExecPolicy::RedirectionsOnly => {
+			shell.register_builtin("exec", builtins::builtin::<ExecRedirectionsCommand>().special());
+		},
+		ExecPolicy::Full => {},
 	}
-	if let Some(suspend_builtin) = shell.builtin_mut("suspend") {
+	if !config.allow_suspend
+		&& let Some(suspend_builtin) = shell.builtin_mut("suspend")
+	{
 		suspend_builtin.disabled = true;
 	}
 	shell.register_builtin("sleep", builtins::builtin::<SleepCommand>());
 	shell.register_builtin("timeout", builtins::builtin::<TimeoutCommand>());
+	#[cfg(windows)]
+	windows::register_drive_builtins(&mut shell);
 
 	let mut merged_path: Option<String> = None;
 	for (key, value) in std::env::vars() {
@@ -438,7 +1546,7 @@ async fn create_session(config: &ShellConfig) -> Result<ShellSessionCore> {
 			});
 			continue;
 		}
-		let mut var = ShellVariable::new(ShellValue::String(value));
+		let mut var = ShellVariable::new(ShellValue::String(shell_env_value(normalized_key, value)));
 		var.export();
 		shell
 			.env
@@ -468,7 +1576,8 @@ async fn create_session(config: &ShellConfig) -> Result<ShellSessionCore> {
 			if should_skip_env_var(normalized_key) {
 				continue;
 			}
-			let mut var = ShellVariable::new(ShellValue::String(value.clone()));
+			let mut var =
+				ShellVariable::new(ShellValue::String(shell_env_value(normalized_key, value.clone())));
 			var.export();
 			shell
 				.env
@@ -478,13 +1587,108 @@ async fn create_session(config: &ShellConfig) -> Result<ShellSessionCore> {
 	}
 
 	#[cfg(windows)]
-	configure_windows_path(&mut shell)?;
+	if config.utf8_env {
+		windows::set_utf8_env(&mut shell)?;
+	}
+
+	#[cfg(windows)]
+	configure_windows_path(&mut shell, &config.path_augmentation)?;
+
+	for (key, dir) in config.isolation.env_vars() {
+		let value = dir.to_string_lossy().into_owned();
+		let mut var = ShellVariable::new(ShellValue::String(shell_env_value(key, value)));
+		var.export();
+		shell
+			.env
+			.set_global(key, var)
+			.map_err(|err| Error::from_reason(format!("Failed to set env: {err}")))?;
+	}
 
 	if let Some(snapshot_path) = config.snapshot_path.as_ref() {
 		source_snapshot(&mut shell, snapshot_path).await?;
 	}
 
-	Ok(ShellSessionCore { shell, current_abort: None })
+	#[cfg(unix)]
+	let marker = {
+		let id = SESSION_COUNTER.fetch_add(1, Ordering::Relaxed);
+		let value = format!("{}-{id}", std::process::id());
+		let mut var = ShellVariable::new(ShellValue::String(value.clone()));
+		var.export();
+		shell
+			.env
+			.set_global(SESSION_MARKER_VAR, var)
+			.map_err(|err| Error::from_reason(format!("Failed to set env: {err}")))?;
+		format!("{SESSION_MARKER_VAR}={value}")
+	};
+
+	if let Some((max, wait)) = config.background_job_limit {
+		shell.set_background_job_limit(BackgroundJobLimit::new(max as usize, wait));
+	}
+
+	// Set last, so sourcing the snapshot isn't subject to the policy.
+	if let Some(callback) = config.command_policy.clone() {
+		shell.set_command_policy(command_policy(callback));
+	}
+
+	Ok(ShellSessionCore {
+		shell,
+		current_abort: None,
+		#[cfg(windows)]
+		job: windows::ProcessJob::new().map(Arc::new),
+		#[cfg(unix)]
+		marker,
+		#[cfg(unix)]
+		children: config.children.clone(),
+	})
+}
+
+/// Adapt the JS policy callback for brush. Builtins outside
+/// [`POLICY_BUILTINS`] pass without a round trip, and a callback that throws
+/// denies the command.
+fn command_policy(callback: Arc<CommandPolicyCallback>) -> CommandPolicy {
+	policy_adapter(move |request| {
+		let callback = callback.clone();
+		Box::pin(async move {
+			match callback.call_async(request).await {
+				Ok(Either::A(promise)) => promise.await,
+				Ok(Either::B(decision)) => Ok(decision),
+				Err(err) => Err(err),
+			}
+		})
+	})
+}
+
+/// Pending answer from the policy callback.
+type PolicyAnswer = Pin<Box<dyn Future<Output = Result<CommandPolicyDecision>> + Send>>;
+
+/// The policy [`command_policy`] installs, asking `call` for each decision.
+fn policy_adapter<F>(call: F) -> CommandPolicy
+where
+	F: Fn(CommandPolicyRequest) -> PolicyAnswer + Send + Sync + 'static,
+{
+	let call = Arc::new(call);
+	Arc::new(move |check: CommandCheck| {
+		let call = call.clone();
+		Box::pin(async move {
+			let name = check.argv.first().map_or("", String::as_str);
+			if check.program.is_none() && !POLICY_BUILTINS.contains(&name) {
+				return Ok(());
+			}
+			let request = CommandPolicyRequest {
+				argv:    check.argv,
+				program: check
+					.program
+					.map(|program| program.to_string_lossy().into_owned()),
+			};
+			match call(request).await {
+				Ok(CommandPolicyDecision { allow: true, .. }) => Ok(()),
+				Ok(CommandPolicyDecision { reason, .. }) => {
+					Err(reason.unwrap_or_else(|| "denied by command policy".to_owned()))
+				},
+				Err(err) => Err(format!("command policy failed: {err}")),
+			}
+		})
+	})
 }
 
 async fn source_snapshot(shell: &mut BrushShell, snapshot_path: &str) -> Result<()> {
@@ -493,12 +1697,24 @@ async fn source_snapshot(shell: &mut BrushShell, snapshot_path: &str) -> Result<
 	params.set_fd(OpenFiles::STDOUT_FD, null_file()?);
 	params.set_fd(OpenFiles::STDERR_FD, null_file()?);
 
+	#[cfg(windows)]
+	let lf_copy = windows::lf_script_copy(std::path::Path::new(snapshot_path))?;
+	#[cfg(windows)]
+	let snapshot_path = windows::extended_length_path(
+		lf_copy
+			.as_deref()
+			.unwrap_or_else(|| std::path::Path::new(snapshot_path)),
+	)
+	.to_string_lossy()
+	.into_owned();
 	let escaped = snapshot_path.replace('\'', "'\\''");
 	let command = format!("source '{escaped}'");
-	shell
-		.run_string(command, &params)
-		.await
-		.map_err(|err| Error::from_reason(format!("Failed to source snapshot: {err}")))?;
+	let result = shell.run_string(command, &params).await;
+	#[cfg(windows)]
+	if let Some(copy) = lf_copy {
+		let _ = std::fs::remove_file(copy);
+	}
+	result.map_err(|err| Error::from_reason(format!("Failed to source snapshot: {err}")))?;
 	Ok(())
 }
 
@@ -507,7 +1723,13 @@ async fn run_shell_command(
 	options: &ShellRunConfig,
 	on_chunk: Option<ThreadsafeFunction<String>>,
 	cancel_token: CancellationToken,
+	counts: &Arc<OutputStats>,
 ) -> Result<ExecutionResult> {
+	#[cfg(windows)]
+	if let Some(cwd) = options.cwd.as_deref() {
+		windows::set_working_dir(&mut session.shell, cwd)?;
+	}
+	#[cfg(not(windows))]
 	if let Some(cwd) = options.cwd.as_deref() {
 		session
 			.shell
@@ -515,14 +1737,10 @@ async fn run_shell_command(
 			.map_err(|err| Error::from_reason(format!("Failed to set cwd: {err}")))?;
 	}
 
-	let (reader_file, writer_file) = pipe_to_files("output")?;
-
-	let stdout_file = OpenFile::from(
-		writer_file
-			.try_clone()
-			.map_err(|err| Error::from_reason(format!("Failed to clone pipe: {err}")))?,
-	);
-	let stderr_file = OpenFile::from(writer_file);
+	let (stdout_reader, stdout_writer) = pipe_to_files("stdout")?;
+	let (stderr_reader, stderr_writer) = pipe_to_files("stderr")?;
+	let stdout_file = OpenFile::from(stdout_writer);
+	let stderr_file = OpenFile::from(stderr_writer);
 
 	let mut params = session.shell.default_exec_params();
 	params.set_fd(OpenFiles::STDIN_FD, null_file()?);
@@ -530,6 +1748,19 @@ async fn run_shell_command(
 	params.set_fd(OpenFiles::STDERR_FD, stderr_file);
 	params.process_group_policy = ProcessGroupPolicy::NewProcessGroup;
 	params.set_cancel_token(cancel_token.clone());
+	#[cfg(windows)]
+	if let Some(job) = session.job.clone() {
+		params.set_spawn_hook(Arc::new(move |pid| {
+			job.assign(pid);
+		}));
+	}
+	#[cfg(unix)]
+	{
+		let children = session.children.clone();
+		params.set_spawn_hook(Arc::new(move |pid| children.track(pid as i32)));
+		let children = session.children.clone();
+		params.set_reap_hook(Arc::new(move |pid| children.untrack(pid as i32)));
+	}
 
 	let mut env_scope_pushed = false;
 	if let Some(env) = options.env.as_ref() {
@@ -540,7 +1771,8 @@ async fn run_shell_command(
 			if should_skip_env_var(normalized_key) {
 				continue;
 			}
-			let mut var = ShellVariable::new(ShellValue::String(value.clone()));
+			let mut var =
+				ShellVariable::new(ShellValue::String(shell_env_value(normalized_key, value.clone())));
 			var.export();
 			if let Err(err) = session
 				.shell
@@ -552,13 +1784,46 @@ async fn run_shell_command(
 			}
 		}
 	}
+	let masked = if options.clean_env {
+		mask_exports(&mut session.shell, options.env.as_ref())
+	} else {
+		Vec::new()
+	};
 
 	let reader_cancel = CancellationToken::new();
 	let (activity_tx, mut activity_rx) = mpsc::channel::<()>(1);
 	let mut reader_handle = tokio::spawn({
 		let reader_cancel = reader_cancel.clone();
+		let counts = counts.clone();
+		let encoding = options.output_encoding;
+		let strict = options
+			.strict_utf8
+			.then(|| StrictUtf8::new(cancel_token.clone()));
 		async move {
-			read_output(reader_file, on_chunk, reader_cancel, activity_tx).await;
+			// Separate pipes, so each stream's bytes can be counted; both
+			// feed the same callback, which keeps each stream's order but not
+			// the order of output interleaved between them.
+			let on_chunk = on_chunk.as_ref();
+			tokio::join!(
+				read_output(
+					stdout_reader,
+					on_chunk,
+					encoding.decoder(),
+					reader_cancel.clone(),
+					activity_tx.clone(),
+					&counts.stdout,
+					strict.as_ref(),
+				),
+				read_output(
+					stderr_reader,
+					on_chunk,
+					encoding.decoder(),
+					reader_cancel,
+					activity_tx,
+					&counts.stderr,
+					strict.as_ref(),
+				),
+			);
 			Result::<()>::Ok(())
 		}
 	});
@@ -576,9 +1841,14 @@ async fn run_shell_command(
 		.await;
 
 	if cancel_token.is_cancelled() {
-		terminate_background_jobs(&session.shell);
+		terminate_background_jobs(session);
 	}
 
+	for name in &masked {
+		if let Some((_, var)) = session.shell.env.get_mut(name) {
+			var.export();
+		}
+	}
 	if env_scope_pushed {
 		session
 			.shell
@@ -624,11 +1894,45 @@ async fn run_shell_command(
 	cancel_bridge.abort();
 	let _ = cancel_bridge.await;
 
+	if counts.invalid_utf8().is_some() {
+		// Stopped by strict UTF-8 decoding; the result reports where.
+		return Ok(ExecutionResult::general_error());
+	}
 	result.map_err(|err| Error::from_reason(format!("Shell execution failed: {err}")))
 }
 
+/// Unexport every variable except PATH and those in the run's `env`, for
+/// `cleanEnv`. Returns the names to export again after the run. They are
+/// unexported in place because a non-exported shadow in the command scope
+/// wouldn't hide them from child processes.
+///
+/// The session marker stays exported so the run's descendants are still
+/// found when the session is killed.
+fn mask_exports(shell: &mut BrushShell, keep: Option<&HashMap<String, String>>) -> Vec<String> {
+	let masked: Vec<String> = shell
+		.env
+		.iter_exported()
+		.map(|(name, _)| name.clone())
+		.filter(|name| {
+			#[cfg(unix)]
+			if name == SESSION_MARKER_VAR {
+				return false;
+			}
+			name != "PATH"
+				&& !keep.is_some_and(|env| env.keys().any(|key| normalize_env_key(key) == name))
+		})
+		.collect();
+	for name in &masked {
+		if let Some((_, var)) = shell.env.get_mut(name) {
+			var.unexport();
+		}
+	}
+	masked
+}
+
 #[cfg(unix)]
-fn terminate_background_jobs(shell: &BrushShell) {
+fn terminate_background_jobs(session: &ShellSessionCore) {
+	let shell = &session.shell;
 	if shell.jobs.jobs.is_empty() {
 		return;
 	}
@@ -657,7 +1961,12 @@ fn terminate_background_jobs(shell: &BrushShell) {
 }
 
 #[cfg(windows)]
-fn terminate_background_jobs(shell: &BrushShell) {
+fn terminate_background_jobs(session: &ShellSessionCore) {
+	// Kill the process trees first; per-pid signals only reach the job leaders.
+	if let Some(job) = &session.job {
+		job.terminate();
+	}
+	let shell = &session.shell;
 	if shell.jobs.jobs.is_empty() {
 		return;
 	}
@@ -743,6 +2052,12 @@ fn should_skip_env_var(key: &str) -> bool {
 	)
 }
 
+/// `PIPESTATUS` of the last pipeline `shell` ran, if it had several stages.
+fn pipe_status(shell: &BrushShell) -> Option<Vec<i32>> {
+	let statuses = &shell.last_pipeline_statuses;
+	(statuses.len() > 1).then(|| statuses.iter().map(|&status| i32::from(status)).collect())
+}
+
 const fn session_keepalive(result: &ExecutionResult) -> bool {
 	match result.next_control_flow {
 		ExecutionControlFlow::Normal => true,
@@ -755,24 +2070,33 @@ const fn session_keepalive(result: &ExecutionResult) -> bool {
 
 async fn read_output(
 	reader: fs::File,
-	on_chunk: Option<ThreadsafeFunction<String>>,
+	on_chunk: Option<&ThreadsafeFunction<String>>,
+	mut decoder: Option<OutputDecoder>,
 	cancel_token: CancellationToken,
 	activity: mpsc::Sender<()>,
+	stats: &StreamStats,
+	strict: Option<&StrictUtf8>,
 ) {
 	const REPLACEMENT: &str = "\u{FFFD}";
 	const BUF: usize = 4096;
 	let mut buf = [0u8; BUF + 4]; // +4 for max UTF-8 char
 	let mut it = 0;
+	// Stream offset of `buf[0]`.
+	let mut consumed = 0u64;
+	let mut cancelled = false;
 
 	let reader = tokio::fs::File::from_std(reader);
 	tokio::pin!(reader);
 
-	loop {
+	'read: loop {
 		let read_future = reader.read(&mut buf[it..BUF]);
 		tokio::pin!(read_future);
 		let n = match tokio::select! {
 			res = &mut read_future => res,
-			() = cancel_token.cancelled() => break,
+			() = cancel_token.cancelled() => {
+				cancelled = true;
+				break;
+			},
 		} {
 			Ok(0) => break, // EOF
 			Ok(n) => n,
@@ -781,6 +2105,12 @@ async fn read_output(
 		};
 		if n > 0 {
 			let _ = activity.try_send(());
+			stats.bytes.fetch_add(n as u64, Ordering::Relaxed);
+		}
+		if let Some(decoder) = decoder.as_mut() {
+			// Legacy code pages: the decoder carries split characters itself.
+			emit_decoded(&decoder.decode(&buf[..n], false), on_chunk);
+			continue;
 		}
 		it += n;
 
@@ -789,7 +2119,8 @@ async fn read_output(
 			let pending = &buf[..it];
 			match str::from_utf8(pending) {
 				Ok(text) => {
-					emit_chunk(text, on_chunk.as_ref());
+					emit_chunk(text, on_chunk);
+					consumed += it as u64;
 					it = 0;
 					break;
 				},
@@ -798,19 +2129,26 @@ async fn read_output(
 					if p > 0 {
 						// SAFETY: [..p] is guaranteed valid UTF-8 by valid_up_to().
 						let text = unsafe { str::from_utf8_unchecked(&pending[..p]) };
-						emit_chunk(text, on_chunk.as_ref());
+						emit_chunk(text, on_chunk);
 						// copy p..it to the beginning of the buffer
 						buf.copy_within(p..it, 0);
 						it -= p;
+						consumed += p as u64;
 					}
 
 					match err.error_len() {
 						Some(p) => {
+							if let Some(strict) = strict {
+								strict.fail(stats, consumed);
+								it = 0;
+								break 'read;
+							}
 							// Invalid byte sequence: emit replacement and drop those bytes.
-							emit_chunk(REPLACEMENT, on_chunk.as_ref());
+							emit_chunk(REPLACEMENT, on_chunk);
 							// copy p..it to the beginning of the buffer
 							buf.copy_within(p..it, 0);
 							it -= p;
+							consumed += p as u64;
 							// continue loop in case more bytes remain after the
 							// invalid sequence
 						},
@@ -824,18 +2162,39 @@ async fn read_output(
 		}
 	}
 
+	if let Some(decoder) = decoder.as_mut() {
+		emit_decoded(&decoder.decode(&[], true), on_chunk);
+	}
+
+	// A sequence cut off by the end of the stream is invalid, unless it was
+	// only cut off by cancellation: the pipe's EOF after the command was
+	// stopped can arrive before the readers are cancelled.
+	if it > 0
+		&& !cancelled
+		&& let Some(strict) = strict
+		&& strict.fail(stats, consumed)
+	{
+		return;
+	}
+
 	// Flush whatever is left at EOF (including an incomplete final sequence).
 	for chunk in buf[..it].utf8_chunks() {
 		let valid = chunk.valid();
 		if !valid.is_empty() {
-			emit_chunk(valid, on_chunk.as_ref());
+			emit_chunk(valid, on_chunk);
 		}
 		if !chunk.invalid().is_empty() {
-			emit_chunk(REPLACEMENT, on_chunk.as_ref());
+			emit_chunk(REPLACEMENT, on_chunk);
 		}
 	}
 }
 
+fn emit_decoded(text: &str, callback: Option<&ThreadsafeFunction<String>>) {
+	if !text.is_empty() {
+		emit_chunk(text, callback);
+	}
+}
+
 fn emit_chunk(text: &str, callback: Option<&ThreadsafeFunction<String>>) {
 	if let Some(callback) = callback {
 		callback.call(Ok(text.to_string()), ThreadsafeFunctionCallMode::NonBlocking);
@@ -909,6 +2268,37 @@ impl builtins::Command for SleepCommand {
 	}
 }
 
+/// `exec` for `allowExec: "redirections-only"`: applies its redirections to
+/// the shell, but refuses to replace the shell with a command.
+#[derive(Parser)]
+#[command(disable_help_flag = true)]
+struct ExecRedirectionsCommand {
+	#[arg(num_args = 0.., trailing_var_arg = true, allow_hyphen_values = true)]
+	args: Vec<String>,
+}
+
+impl builtins::Command for ExecRedirectionsCommand {
+	type Error = brush_core::Error;
+
+	fn execute(
+		&self,
+		context: ExecutionContext<'_>,
+	) -> impl Future<Output = std::result::Result<ExecutionResult, brush_core::Error>> + Send {
+		let replaces_shell = !self.args.is_empty();
+		async move {
+			if replaces_shell {
+				let _ = writeln!(context.stderr(), "exec: replacing the shell is not allowed");
+				return Ok(ExecutionResult::new(126));
+			}
+			// Applying the redirections to the shell makes them persist.
+			#[expect(clippy::needless_collect, reason = "iter_fds borrows the shell")]
+			let fds: Vec<_> = context.iter_fds().collect();
+			context.shell.replace_open_files(fds.into_iter());
+			Ok(ExecutionResult::success())
+		}
+	}
+}
+
 #[derive(Parser)]
 #[command(disable_help_flag = true)]
 struct TimeoutCommand {
@@ -1022,3 +2412,409 @@ fn quote_arg(arg: &str) -> String {
 	let escaped = arg.replace('\'', "'\"'\"'");
 	format!("'{escaped}'")
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	async fn run(session: &mut ShellSessionCore, command: &str) -> i32 {
+		let options = ShellRunConfig { command: command.to_owned(), ..Default::default() };
+		let counts = Arc::default();
+		let result =
+			run_shell_command(session, &options, None, CancellationToken::new(), &counts).await;
+		exit_code(&result.unwrap())
+	}
+
+	#[tokio::test]
+	async fn test_command_policy() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		let checked = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		session.shell.set_command_policy({
+			let checked = checked.clone();
+			Arc::new(move |check: CommandCheck| {
+				checked.lock().push(check.argv.join(" "));
+				let denied = check.argv[0] == "rm" || check.argv[0] == "kill";
+				Box::pin(std::future::ready(if denied { Err("no".to_owned()) } else { Ok(()) }))
+			})
+		});
+
+		assert_eq!(run(&mut session, "rm -f /nonexistent").await, 126);
+		assert_eq!(run(&mut session, "f() { rm -rf \"$1\"; }; f /nonexistent").await, 126);
+		assert_eq!(run(&mut session, "(kill -0 $$)").await, 126);
+		assert_eq!(run(&mut session, "x=$(rm /nonexistent); exit $?").await, 126);
+		assert_eq!(run(&mut session, "true").await, 0);
+		assert!(checked.lock().contains(&"rm -rf /nonexistent".to_owned()));
+	}
+
+	/// Session whose adapted policy records each argv, denies `false` and
+	/// `env`, and throws for `cd`.
+	async fn policy_session(
+		exec_policy: ExecPolicy,
+	) -> (ShellSessionCore, Arc<parking_lot::Mutex<Vec<String>>>) {
+		let mut config = ShellConfig::new(None, None, None, None).unwrap();
+		config.exec_policy = exec_policy;
+		let mut session = create_session(&config).await.unwrap();
+		let asked = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		session.shell.set_command_policy(policy_adapter({
+			let asked = asked.clone();
+			move |request: CommandPolicyRequest| -> PolicyAnswer {
+				asked.lock().push(request.argv.join(" "));
+				let answer = match request.argv[0].as_str() {
+					"cd" => Err(Error::from_reason("callback threw")),
+					name => Ok(CommandPolicyDecision {
+						allow:  !matches!(name, "false" | "env"),
+						reason: None,
+					}),
+				};
+				Box::pin(std::future::ready(answer))
+			}
+		}));
+		(session, asked)
+	}
+
+	#[tokio::test]
+	async fn test_command_policy_adapter() {
+		let (mut session, asked) = policy_session(ExecPolicy::default()).await;
+		assert_eq!(run(&mut session, "env true").await, 126);
+		// A callback that throws denies the command.
+		assert_eq!(run(&mut session, "cd /").await, 126);
+		// Builtins outside the list never reach the callback.
+		assert_eq!(run(&mut session, "echo hi >/dev/null").await, 0);
+		assert!(!asked.lock().iter().any(|argv| argv.starts_with("echo")));
+	}
+
+	/// `exec` launches its program itself; the policy still sees it. Were it
+	/// allowed, `false` would replace the process running the session, so that
+	/// part runs in a child copy of the test binary.
+	#[cfg(unix)]
+	#[test]
+	fn test_command_policy_exec() {
+		const CHILD_ENV: &str = "PI_NATIVES_TEST_POLICY_EXEC";
+		if std::env::var_os(CHILD_ENV).is_none() {
+			let output = std::process::Command::new(std::env::current_exe().unwrap())
+				.args(["--exact", "shell::tests::test_command_policy_exec", "--nocapture"])
+				.env(CHILD_ENV, "1")
+				.output()
+				.unwrap();
+			let stdout = String::from_utf8_lossy(&output.stdout);
+			assert!(output.status.success(), "{stdout}{}", String::from_utf8_lossy(&output.stderr));
+			// The filter matched, so the exec really ran in the child.
+			assert!(stdout.contains("1 passed"), "{stdout}");
+			return;
+		}
+
+		let runtime = tokio::runtime::Runtime::new().unwrap();
+		runtime.block_on(async {
+			let (mut session, asked) = policy_session(ExecPolicy::Full).await;
+			assert_eq!(run(&mut session, "exec false").await, 126);
+			assert!(asked.lock().contains(&"false".to_owned()));
+		});
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_waited_children_untracked() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		assert_eq!(run(&mut session, "env true; env true | env true").await, 0);
+		// brush collected them all, so none are left for the reaper to wait on.
+		assert_eq!(session.children.tracked(), 0);
+	}
+
+	#[cfg(target_os = "linux")]
+	#[tokio::test]
+	async fn test_terminate_processes_reaps_escaped() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		assert_eq!(run(&mut session, "setsid -f sleep 300").await, 0);
+		let escaped: Vec<i32> = ps::session_processes(&[], &session.marker)
+			.into_iter()
+			.filter(|pid| {
+				fs::read(format!("/proc/{pid}/cmdline")).is_ok_and(|cmd| cmd.starts_with(b"sleep\0"))
+			})
+			.collect();
+		assert_eq!(escaped.len(), 1);
+
+		let waves = session.terminate_processes().await.unwrap();
+		assert!(waves[0] >= 1);
+		let stat = fs::read_to_string(format!("/proc/{}/stat", escaped[0])).unwrap_or_default();
+		assert!(stat.is_empty() || stat.contains(") Z "));
+	}
+
+	#[tokio::test]
+	async fn test_output_counts() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		let options = ShellRunConfig {
+			command: "printf a; printf de >&2; printf bc".to_owned(),
+			..Default::default()
+		};
+		let counts = Arc::new(OutputStats::default());
+		run_shell_command(&mut session, &options, None, CancellationToken::new(), &counts)
+			.await
+			.unwrap();
+		assert_eq!((counts.stdout_bytes(), counts.stderr_bytes()), (3.0, 2.0));
+	}
+
+	#[tokio::test]
+	async fn test_background_job_limit() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		session
+			.shell
+			.set_background_job_limit(BackgroundJobLimit::new(2, false));
+		assert_eq!(run(&mut session, "sleep 5 & sleep 5 & sleep 5 &").await, 1);
+		assert_eq!(session.shell.background_job_limit().unwrap().running(), 2);
+		terminate_background_jobs(&session);
+
+		let mut session = create_session(&config).await.unwrap();
+		session
+			.shell
+			.set_background_job_limit(BackgroundJobLimit::new(1, true));
+		assert_eq!(run(&mut session, "sleep 0.1 & sleep 0.1 &").await, 0);
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_queue_timing() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let session = Arc::new(TokioMutex::new(None));
+		let run = |command: &str, timeout_ms, start_timeout| {
+			let run_config = ShellRunConfig { command: command.to_owned(), ..Default::default() };
+			let ct = task::CancelToken::new(timeout_ms, None);
+			tokio::spawn(run_shell_session(
+				session.clone(),
+				config.clone(),
+				run_config,
+				None,
+				ct,
+				Instant::now(),
+				start_timeout,
+			))
+		};
+
+		// Queued behind a 400ms command, a 200ms timeout only expires when it
+		// counts the wait.
+		let first = run("sleep 0.4", None, None);
+		time::sleep(Duration::from_millis(100)).await;
+		let second = run("true", None, Some(200));
+		let first = first.await.unwrap().unwrap();
+		let second = second.await.unwrap().unwrap();
+		assert!(first.duration_ms >= 350.0);
+		assert_eq!(second.exit_code, Some(0));
+		assert!(second.queued_ms >= 200.0);
+		assert!(second.duration_ms < second.queued_ms);
+
+		let first = run("sleep 0.4", None, None);
+		time::sleep(Duration::from_millis(100)).await;
+		let second = run("true", Some(200), None);
+		let second = second.await.unwrap().unwrap();
+		assert!(second.timed_out);
+		assert_eq!(second.duration_ms, 0.0);
+		first.await.unwrap().unwrap();
+	}
+
+	// Subshell stages run on their own tasks.
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_pipe_status() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		assert_eq!(run(&mut session, "echo hi | (exit 3) | true").await, 0);
+		assert_eq!(pipe_status(&session.shell), Some(vec![0, 3, 0]));
+		assert_eq!(run(&mut session, "true | false").await, 1);
+		assert_eq!(pipe_status(&session.shell), Some(vec![0, 1]));
+		assert_eq!(run(&mut session, "false").await, 1);
+		assert_eq!(pipe_status(&session.shell), None);
+		assert_eq!(run(&mut session, "true | false; true").await, 0);
+		assert_eq!(pipe_status(&session.shell), None);
+	}
+
+	#[tokio::test]
+	async fn test_dispose() {
+		let shell = Shell::new(None).unwrap();
+		assert!(shell.preview("echo hi".to_owned()).await.is_ok());
+		assert!(shell.session.lock().await.is_some());
+		assert_eq!(shell.dispose().await, Vec::<String>::new());
+
+		// A disposed shell doesn't start a new session.
+		assert!(shell.preview("echo hi".to_owned()).await.is_err());
+		assert!(shell.list_jobs().await.is_err());
+		assert!(shell.session.lock().await.is_none());
+	}
+
+	#[tokio::test]
+	async fn test_exec_redirections_only() {
+		let mut config = ShellConfig::new(None, None, None, None).unwrap();
+		config.exec_policy = ExecPolicy::RedirectionsOnly;
+		let mut session = create_session(&config).await.unwrap();
+		let log = std::env::temp_dir().join(format!("pi-shell-exec-{}", std::process::id()));
+		let command = format!("exec 3>'{}'; echo hi >&3; exec 3>&-", log.display());
+		assert_eq!(run(&mut session, &command).await, 0);
+		assert_eq!(std::fs::read_to_string(&log).unwrap(), "hi\n");
+		std::fs::remove_file(&log).unwrap();
+		assert_eq!(run(&mut session, "exec").await, 0);
+
+		assert_eq!(run(&mut session, "exec true").await, 126);
+		assert_eq!(run(&mut session, "exec -a name true 2>/dev/null").await, 126);
+		// The shell survived the rejected exec.
+		assert_eq!(run(&mut session, "exit 7").await, 7);
+
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		assert_ne!(run(&mut session, "exec 3>/dev/null").await, 0);
+	}
+
+	// Command substitution needs a worker thread to read its pipe.
+	#[tokio::test(flavor = "multi_thread")]
+	async fn test_clean_env() {
+		fn exports(session: &ShellSessionCore) -> Vec<(String, String)> {
+			let mut vars: Vec<_> = session
+				.shell
+				.env
+				.iter_exported()
+				.map(|(name, var)| (name.clone(), var.value().to_cow_str(&session.shell).into_owned()))
+				.collect();
+			vars.sort();
+			vars
+		}
+
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		assert_eq!(run(&mut session, "export PI_CLEAN_ENV_TEST=1").await, 0);
+		let before = exports(&session);
+
+		let options = ShellRunConfig {
+			command: "[ -z \"$(printenv PI_CLEAN_ENV_TEST)\" ] && [ \"$(printenv KEPT)\" = 2 ] && [ \
+			          -n \"$(printenv PATH)\" ] && [ -z \"$(printenv HOME)\" ]"
+				.to_owned(),
+			env: Some(HashMap::from([("KEPT".to_owned(), "2".to_owned())])),
+			clean_env: true,
+			..Default::default()
+		};
+		let result =
+			run_shell_command(&mut session, &options, None, CancellationToken::new(), &Arc::default())
+				.await
+				.unwrap();
+		assert_eq!(exit_code(&result), 0);
+		assert_eq!(exports(&session), before);
+	}
+
+	#[cfg(target_os = "linux")]
+	#[tokio::test]
+	async fn test_clean_env_keeps_session_marker() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		let options = ShellRunConfig {
+			command: "setsid -f sleep 300".to_owned(),
+			clean_env: true,
+			..Default::default()
+		};
+		let result =
+			run_shell_command(&mut session, &options, None, CancellationToken::new(), &Arc::default())
+				.await
+				.unwrap();
+		assert_eq!(exit_code(&result), 0);
+		let escaped: Vec<i32> = ps::session_processes(&[], &session.marker)
+			.into_iter()
+			.filter(|pid| {
+				fs::read(format!("/proc/{pid}/cmdline")).is_ok_and(|cmd| cmd.starts_with(b"sleep\0"))
+			})
+			.collect();
+		assert_eq!(escaped.len(), 1);
+
+		session.terminate_processes().await.unwrap();
+		let stat = fs::read_to_string(format!("/proc/{}/stat", escaped[0])).unwrap_or_default();
+		assert!(stat.is_empty() || stat.contains(") Z "));
+	}
+
+	#[tokio::test]
+	async fn test_strict_utf8() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		let mut options = ShellRunConfig {
+			command: "printf 'ok\\n'; printf 'ab\\377cd' >&2; sleep 5".to_owned(),
+			strict_utf8: true,
+			..Default::default()
+		};
+		let counts = Arc::new(OutputStats::default());
+		let result =
+			run_shell_command(&mut session, &options, None, CancellationToken::new(), &counts)
+				.await
+				.unwrap();
+		assert_eq!(exit_code(&result), 1);
+		assert_eq!(counts.invalid_utf8(), Some(("stderr", 2.0)));
+
+		options.command = "printf 'ab\\377cd'".to_owned();
+		options.strict_utf8 = false;
+		let counts = Arc::new(OutputStats::default());
+		run_shell_command(&mut session, &options, None, CancellationToken::new(), &counts)
+			.await
+			.unwrap();
+		assert_eq!(counts.invalid_utf8(), None);
+
+		// `executeShell` reports it too.
+		options.strict_utf8 = true;
+		let ct = task::CancelToken::new(None, None);
+		let result = run_shell_oneshot(config, options, None, ct).await.unwrap();
+		assert!(result.invalid_utf8);
+		assert_eq!(result.invalid_utf8_offset, Some(2.0));
+		assert_eq!(result.invalid_utf8_stream.as_deref(), Some("stdout"));
+
+		// A character cut off by an abort is not invalid, and neither is one
+		// cut off on the other stream when the first invalid byte stops the
+		// run.
+		let half_euro = "printf 'a\\342\\202'";
+		for command in [
+			format!("{half_euro}; sleep 5"),
+			format!("{half_euro}; sleep 0.2; printf 'ab\\377' >&2; sleep 5"),
+		] {
+			let options = ShellRunConfig { command, strict_utf8: true, ..Default::default() };
+			let counts = Arc::new(OutputStats::default());
+			let cancel = CancellationToken::new();
+			let abort = tokio::spawn({
+				let cancel = cancel.clone();
+				async move {
+					time::sleep(Duration::from_millis(500)).await;
+					cancel.cancel();
+				}
+			});
+			run_shell_command(&mut session, &options, None, cancel, &counts)
+				.await
+				.unwrap();
+			abort.abort();
+			let expected = options.command.contains(">&2").then_some(("stderr", 2.0));
+			assert_eq!(counts.invalid_utf8(), expected, "{}", options.command);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_strict_utf8_after_stop() {
+		// Where a strict reader records invalid UTF-8 in `bytes`, when the
+		// stream ends before its reader is cancelled.
+		async fn invalid_at(bytes: &[u8], strict: &StrictUtf8) -> u64 {
+			let (reader, mut writer) = pipe_to_files("test").unwrap();
+			io::Write::write_all(&mut writer, bytes).unwrap();
+			drop(writer);
+			let (activity, _rx) = mpsc::channel(1);
+			let stats = StreamStats::default();
+			let cancel = CancellationToken::new();
+			read_output(reader, None, None, cancel, activity, &stats, Some(strict)).await;
+			stats.invalid_at.load(Ordering::Relaxed)
+		}
+
+		let strict = StrictUtf8::new(CancellationToken::new());
+		assert_eq!(invalid_at(b"ab\xff", &strict).await, 2);
+		assert!(strict.command.is_cancelled());
+		// Only the first failure is recorded.
+		assert_eq!(invalid_at(b"a\xe2\x82", &strict).await, u64::MAX);
+		assert_eq!(invalid_at(b"\xff", &strict).await, u64::MAX);
+
+		let strict = StrictUtf8::new(CancellationToken::new());
+		assert_eq!(invalid_at(b"a\xe2\x82", &strict).await, 1);
+
+		// Aborted: the pipe's EOF beat the cancellation to the reader.
+		let strict = StrictUtf8::new(CancellationToken::new());
+		strict.command.cancel();
+		assert_eq!(invalid_at(b"a\xe2\x82", &strict).await, u64::MAX);
+	}
+}
@@ -0,0 +1,172 @@
+use encoding_rs::{
+	BIG5, Decoder, EUC_KR, Encoding, GBK, IBM866, SHIFT_JIS, WINDOWS_874, WINDOWS_1252,
+};
+use napi::{Error, Result};
+
+/// Code page a command's output is decoded from before it reaches `on_chunk`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputEncoding {
+	/// UTF-8, with invalid bytes replaced (the default).
+	#[default]
+	Utf8,
+	/// The OEM code page of US consoles.
+	Cp437,
+	/// The Western European ANSI code page.
+	Cp1252,
+	/// The system OEM code page on Windows; UTF-8 elsewhere.
+	Auto,
+}
+
+impl OutputEncoding {
+	/// Parse the `outputEncoding` option; absent means UTF-8.
+	pub fn from_option(value: Option<&str>) -> Result<Self> {
+		match value {
+			None | Some("utf8") => Ok(Self::Utf8),
+			Some("cp437") => Ok(Self::Cp437),
+			Some("cp1252") => Ok(Self::Cp1252),
+			Some("auto") => Ok(Self::Auto),
+			Some(other) => Err(Error::from_reason(format!(
+				"Invalid outputEncoding: {other} (expected \"utf8\", \"cp437\", \"cp1252\", or \
+				 \"auto\")"
+			))),
+		}
+	}
+
+	/// Decoder for this encoding, or `None` when output is read as UTF-8.
+	pub fn decoder(self) -> Option<OutputDecoder> {
+		match self {
+			Self::Utf8 => None,
+			Self::Cp437 => Some(OutputDecoder::SingleByte(&CP437)),
+			Self::Cp1252 => {
+				Some(OutputDecoder::Legacy(WINDOWS_1252.new_decoder_without_bom_handling()))
+			},
+			Self::Auto => code_page_decoder(oem_code_page()),
+		}
+	}
+}
+
+/// Decoder for a Windows code page number, or `None` for UTF-8 (65001) and
+/// pages we have no decoder for.
+fn code_page_decoder(code_page: u32) -> Option<OutputDecoder> {
+	let encoding = match code_page {
+		437 => return Some(OutputDecoder::SingleByte(&CP437)),
+		850 => return Some(OutputDecoder::SingleByte(&CP850)),
+		866 => IBM866,
+		874 => WINDOWS_874,
+		932 => SHIFT_JIS,
+		936 => GBK,
+		949 => EUC_KR,
+		950 => BIG5,
+		1250..=1258 => Encoding::for_label(format!("windows-{code_page}").as_bytes())?,
+		_ => return None,
+	};
+	Some(OutputDecoder::Legacy(encoding.new_decoder_without_bom_handling()))
+}
+
+#[cfg(windows)]
+fn oem_code_page() -> u32 {
+	#[link(name = "kernel32")]
+	unsafe extern "system" {
+		fn GetOEMCP() -> u32;
+	}
+	// SAFETY: GetOEMCP takes no arguments and cannot fail.
+	unsafe { GetOEMCP() }
+}
+
+#[cfg(not(windows))]
+const fn oem_code_page() -> u32 {
+	65001
+}
+
+/// Incremental decoder from a legacy code page to UTF-8. The lead byte of a
+/// double-byte character split across reads is held until the next call.
+pub enum OutputDecoder {
+	/// ASCII plus a table for the high half.
+	SingleByte(&'static [char; 128]),
+	/// An `encoding_rs` decoder, which carries partial sequences itself.
+	Legacy(Decoder),
+}
+
+impl OutputDecoder {
+	/// Decode the next chunk of output. `last` flushes a dangling partial
+	/// character as U+FFFD.
+	pub fn decode(&mut self, bytes: &[u8], last: bool) -> String {
+		match self {
+			Self::SingleByte(high) => bytes
+				.iter()
+				.map(|&byte| {
+					if byte.is_ascii() {
+						char::from(byte)
+					} else {
+						high[usize::from(byte - 0x80)]
+					}
+				})
+				.collect(),
+			Self::Legacy(decoder) => {
+				let capacity = decoder
+					.max_utf8_buffer_length(bytes.len())
+					.unwrap_or(bytes.len() * 3 + 4);
+				let mut text = String::with_capacity(capacity);
+				let _ = decoder.decode_to_string(bytes, &mut text, last);
+				text
+			},
+		}
+	}
+}
+
+/// High half of code page 437.
+const CP437: [char; 128] = [
+	'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+	'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+	'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕',
+	'╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦',
+	'╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐',
+	'▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', '≡', '±',
+	'≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// High half of code page 850, the Western European OEM code page.
+const CP850: [char; 128] = [
+	'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+	'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+	'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©',
+	'╣', '║', '╗', '╝', '¢', '¥', '┐', '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦',
+	'╠', '═', '╬', '¤', 'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì',
+	'▀', 'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´', '\u{AD}',
+	'±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{A0}',
+];
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_output_encoding_option() {
+		assert_eq!(OutputEncoding::from_option(None).unwrap(), OutputEncoding::Utf8);
+		assert_eq!(OutputEncoding::from_option(Some("cp437")).unwrap(), OutputEncoding::Cp437);
+		assert_eq!(OutputEncoding::from_option(Some("auto")).unwrap(), OutputEncoding::Auto);
+		assert!(OutputEncoding::from_option(Some("latin1")).is_err());
+		assert!(OutputEncoding::Utf8.decoder().is_none());
+	}
+
+	#[test]
+	fn test_single_byte_decoding() {
+		let mut cp437 = OutputEncoding::Cp437.decoder().unwrap();
+		assert_eq!(cp437.decode(b"\xc9\xcd\xbb ok \x82", false), "╔═╗ ok é");
+		let mut cp1252 = OutputEncoding::Cp1252.decoder().unwrap();
+		assert_eq!(cp1252.decode(b"\x80 caf\xe9", false), "€ café");
+		let mut cp850 = code_page_decoder(850).unwrap();
+		assert_eq!(cp850.decode(b"\x9b\xd0", false), "øð");
+	}
+
+	#[test]
+	fn test_split_double_byte_decoding() {
+		// "日本" in Shift_JIS, split inside the second character.
+		let mut decoder = code_page_decoder(932).unwrap();
+		assert_eq!(decoder.decode(b"\x93\xfa\x96", false), "日");
+		assert_eq!(decoder.decode(b"\x7b!", false), "本!");
+		assert_eq!(decoder.decode(b"\x93", false), "");
+		assert_eq!(decoder.decode(b"", true), "\u{FFFD}");
+		assert!(code_page_decoder(65001).is_none());
+	}
+}
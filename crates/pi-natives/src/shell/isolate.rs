@@ -0,0 +1,219 @@
+use std::{
+	collections::hash_map::RandomState,
+	env, fs,
+	hash::{BuildHasher, Hasher},
+	io,
+	path::{Path, PathBuf},
+	sync::atomic::{AtomicU32, Ordering},
+	time::SystemTime,
+};
+
+use napi::{Either, Error, Result};
+
+/// Throwaway `HOME` and `TMPDIR` for a shell, from the `isolate` option.
+#[derive(Clone, Default)]
+pub struct Isolation {
+	/// Directory exported as `HOME`.
+	pub home: Option<PathBuf>,
+	/// Directory exported as `TMPDIR`, `TEMP`, and `TMP`.
+	pub tmp:  Option<PathBuf>,
+}
+
+impl Isolation {
+	/// Resolve `isolate.home` and `isolate.tmp`: `true` creates a scratch
+	/// directory under the system temp directory, a string names a directory
+	/// (created if missing), and `false` or absent leaves the variable alone.
+	/// Scratch directories are owned by the returned [`ScratchDirs`].
+	pub fn from_options(
+		home: Option<Either<bool, String>>,
+		tmp: Option<Either<bool, String>>,
+	) -> Result<(Self, ScratchDirs)> {
+		let mut scratch = ScratchDirs::default();
+		let isolation = Self {
+			home: resolve_dir(home, "home", &mut scratch)?,
+			tmp:  resolve_dir(tmp, "tmp", &mut scratch)?,
+		};
+		Ok((isolation, scratch))
+	}
+
+	/// Variables to export, in order.
+	pub fn env_vars(&self) -> Vec<(&'static str, &Path)> {
+		let mut vars = Vec::new();
+		if let Some(home) = &self.home {
+			vars.push(("HOME", home.as_path()));
+		}
+		if let Some(tmp) = &self.tmp {
+			vars.extend(["TMPDIR", "TEMP", "TMP"].map(|key| (key, tmp.as_path())));
+		}
+		vars
+	}
+}
+
+fn resolve_dir(
+	option: Option<Either<bool, String>>,
+	label: &str,
+	scratch: &mut ScratchDirs,
+) -> Result<Option<PathBuf>> {
+	let dir = match option {
+		None | Some(Either::A(false)) => return Ok(None),
+		Some(Either::A(true)) => return scratch.create(label).map(Some),
+		Some(Either::B(path)) => PathBuf::from(path),
+	};
+	fs::create_dir_all(&dir).map_err(|err| {
+		Error::from_reason(format!("Failed to create isolated {label} directory: {err}"))
+	})?;
+	Ok(Some(dir))
+}
+
+/// Scratch directories created for `isolate` or `scratchDir`, removed when
+/// dropped.
+#[derive(Default)]
+pub struct ScratchDirs(Vec<PathBuf>);
+
+impl ScratchDirs {
+	/// Create a new, randomly named directory under the system temp
+	/// directory, private to the current user on Unix. Never reuses an
+	/// existing path, so another user can't plant the directory (or a symlink
+	/// in its place) ahead of time.
+	pub fn create(&mut self, label: &str) -> Result<PathBuf> {
+		const ATTEMPTS: u32 = 16;
+
+		let builder = &mut fs::DirBuilder::new();
+		#[cfg(unix)]
+		std::os::unix::fs::DirBuilderExt::mode(builder, 0o700);
+		for _ in 0..ATTEMPTS {
+			let dir = env::temp_dir().join(format!("pi-shell-{label}-{:016x}", random_suffix()));
+			match builder.create(&dir) {
+				Ok(()) => {
+					self.0.push(dir.clone());
+					return Ok(dir);
+				},
+				Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {},
+				Err(err) => {
+					return Err(Error::from_reason(format!(
+						"Failed to create {label} scratch directory: {err}"
+					)));
+				},
+			}
+		}
+		Err(Error::from_reason(format!(
+			"Failed to create {label} scratch directory: {ATTEMPTS} random names already taken"
+		)))
+	}
+
+	/// Take over the directories `other` still owns.
+	pub fn adopt(&mut self, mut other: Self) {
+		self.0.append(&mut other.0);
+	}
+
+	/// Remove as much of each directory as possible. Returns the paths left
+	/// behind, typically files a lingering background job still holds open on
+	/// Windows; those directories are kept for another attempt.
+	pub fn remove(&mut self) -> Vec<String> {
+		let mut leftover = Vec::new();
+		self.0.retain(|dir| {
+			let before = leftover.len();
+			remove_tree(dir, &mut leftover);
+			leftover.len() > before
+		});
+		leftover
+	}
+}
+
+impl Drop for ScratchDirs {
+	fn drop(&mut self) {
+		let _ = self.remove();
+	}
+}
+
+/// Unpredictable name component for a scratch directory, from the standard
+/// library's randomly keyed hasher.
+fn random_suffix() -> u64 {
+	static NEXT: AtomicU32 = AtomicU32::new(0);
+
+	let mut hasher = RandomState::new().build_hasher();
+	hasher.write_u32(std::process::id());
+	hasher.write_u32(NEXT.fetch_add(1, Ordering::Relaxed));
+	if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+		hasher.write_u128(elapsed.as_nanos());
+	}
+	hasher.finish()
+}
+
+/// Remove `path` recursively, continuing past entries that can't be removed
+/// and recording them in `leftover`.
+fn remove_tree(path: &Path, leftover: &mut Vec<String>) {
+	if fs::remove_dir_all(path).is_ok() || !path.exists() {
+		return;
+	}
+	let before = leftover.len();
+	for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+		let entry_path = entry.path();
+		if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+			remove_tree(&entry_path, leftover);
+		} else if fs::remove_file(&entry_path).is_err() {
+			leftover.push(entry_path.display().to_string());
+		}
+	}
+	if fs::remove_dir(path).is_err() && leftover.len() == before {
+		leftover.push(path.display().to_string());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_isolation_dirs() {
+		let named = env::temp_dir().join(format!("pi-shell-named-{}", std::process::id()));
+		let (isolation, mut scratch) = Isolation::from_options(
+			Some(Either::A(true)),
+			Some(Either::B(named.to_string_lossy().into_owned())),
+		)
+		.unwrap();
+		let home = isolation.home.clone().unwrap();
+		assert!(home.is_dir() && named.is_dir());
+		let keys: Vec<&str> = isolation
+			.env_vars()
+			.into_iter()
+			.map(|(key, _)| key)
+			.collect();
+		assert_eq!(keys, ["HOME", "TMPDIR", "TEMP", "TMP"]);
+
+		// Only the scratch directory is removed, contents and all.
+		fs::create_dir_all(home.join(".config").join("tool")).unwrap();
+		fs::write(home.join(".config").join("tool").join("state"), "x").unwrap();
+		assert_eq!(scratch.remove(), Vec::<String>::new());
+		assert!(!home.exists());
+		assert!(named.is_dir());
+		fs::remove_dir_all(&named).unwrap();
+	}
+
+	#[test]
+	fn test_scratch_dirs_drop() {
+		let (isolation, scratch) = Isolation::from_options(None, Some(Either::A(true))).unwrap();
+		let tmp = isolation.tmp.unwrap();
+		assert!(isolation.home.is_none() && tmp.is_dir());
+		drop(scratch);
+		assert!(!tmp.exists());
+	}
+
+	#[test]
+	fn test_scratch_create_adopt() {
+		let mut run = ScratchDirs::default();
+		let dir = run.create("run").unwrap();
+		assert_ne!(dir, run.create("run").unwrap());
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+		}
+		fs::write(dir.join("out"), "x").unwrap();
+		let mut shell = ScratchDirs::default();
+		shell.adopt(run);
+		assert!(dir.is_dir());
+		assert_eq!(shell.remove(), Vec::<String>::new());
+		assert!(!dir.exists());
+	}
+}
@@ -0,0 +1,310 @@
+use std::collections::HashSet;
+
+use brush_core::Shell as BrushShell;
+use brush_parser::{
+	Token,
+	ast::{
+		AndOr, Command, CommandPrefixOrSuffixItem, CompoundCommand, CompoundList, Pipeline,
+		ProcessSubstitutionKind, SimpleCommand,
+	},
+};
+use napi::{Error, Result};
+
+use super::PreviewedCommand;
+
+/// Each simple command `command` would run, in source order, with aliases
+/// resolved and words expanded against a copy of `shell`, so expansions with
+/// side effects (`${x:=y}`, `$((i++))`) leave the session untouched. The copy
+/// has command substitution turned off. Words that would need a command or
+/// process substitution are kept as written and listed in `unevaluated`,
+/// including substitutions reached through a variable's value (`${p@P}`,
+/// `${!p}`); so are words whose expansion fails otherwise.
+pub async fn preview_command(shell: &BrushShell, command: &str) -> Result<Vec<PreviewedCommand>> {
+	let program = shell
+		.parse_string(command)
+		.map_err(|err| Error::from_reason(format!("Failed to parse command: {err}")))?;
+	let mut walk = Walk::default();
+	for list in &program.complete_commands {
+		walk.list(list);
+	}
+
+	let mut shell = shell.clone();
+	shell.set_command_substitution_enabled(false);
+	let params = shell.default_exec_params();
+	let mut previews = Vec::new();
+	for simple in walk.commands {
+		let mut words = simple_command_words(simple);
+		if let Some(Word::Text(name)) = words.first()
+			&& let Some(alias) = shell.aliases.get(name)
+		{
+			let body = alias_words(alias);
+			words.splice(..1, body);
+		}
+
+		let mut argv = Vec::new();
+		let mut unevaluated = Vec::new();
+		for word in words {
+			let expanded = match &word {
+				Word::Text(text) if !needs_substitution(text) => {
+					shell.full_expand_and_split_string(&params, text).await.ok()
+				},
+				_ => None,
+			};
+			if let Some(fields) = expanded {
+				argv.extend(fields);
+			} else {
+				unevaluated.push(argv.len() as u32);
+				argv.push(word.into_raw());
+			}
+		}
+
+		let Some(name) = argv.first().cloned() else {
+			continue;
+		};
+		let (kind, path) = if unevaluated.contains(&0) {
+			("unknown", None)
+		} else if shell.funcs().get(&name).is_some() || walk.functions.contains(name.as_str()) {
+			("function", None)
+		} else if shell
+			.builtins()
+			.get(&name)
+			.is_some_and(|builtin| !builtin.disabled)
+		{
+			("builtin", None)
+		} else if name.contains(['/', std::path::MAIN_SEPARATOR]) {
+			("external", Some(shell.absolute_path(&name).to_string_lossy().into_owned()))
+		} else {
+			match shell.find_first_executable_in_path_using_cache(&name) {
+				Some(path) => ("external", Some(path.to_string_lossy().into_owned())),
+				None => ("unknown", None),
+			}
+		};
+		previews.push(PreviewedCommand { argv, unevaluated, kind: kind.to_owned(), path });
+	}
+	Ok(previews)
+}
+
+/// Simple commands in the order they appear, and the functions defined along
+/// the way. Function bodies are skipped: defining one runs nothing.
+#[derive(Default)]
+struct Walk<'a> {
+	commands:  Vec<&'a SimpleCommand>,
+	functions: HashSet<&'a str>,
+}
+
+impl<'a> Walk<'a> {
+	fn list(&mut self, list: &'a CompoundList) {
+		for item in &list.0 {
+			self.pipeline(&item.0.first);
+			for next in &item.0.additional {
+				let (AndOr::And(pipeline) | AndOr::Or(pipeline)) = next;
+				self.pipeline(pipeline);
+			}
+		}
+	}
+
+	fn pipeline(&mut self, pipeline: &'a Pipeline) {
+		for command in &pipeline.seq {
+			match command {
+				Command::Simple(simple) => self.commands.push(simple),
+				Command::Compound(compound, _) => self.compound(compound),
+				Command::Function(function) => {
+					self.functions.insert(function.fname.value.as_str());
+				},
+				Command::ExtendedTest(_) => {},
+			}
+		}
+	}
+
+	fn compound(&mut self, compound: &'a CompoundCommand) {
+		match compound {
+			CompoundCommand::BraceGroup(group) => self.list(&group.list),
+			CompoundCommand::Subshell(subshell) => self.list(&subshell.list),
+			CompoundCommand::ForClause(clause) => self.list(&clause.body.list),
+			CompoundCommand::ArithmeticForClause(clause) => self.list(&clause.body.list),
+			CompoundCommand::CaseClause(clause) => {
+				for case in &clause.cases {
+					if let Some(list) = &case.cmd {
+						self.list(list);
+					}
+				}
+			},
+			CompoundCommand::IfClause(clause) => {
+				self.list(&clause.condition);
+				self.list(&clause.then);
+				for branch in clause.elses.iter().flatten() {
+					if let Some(condition) = &branch.condition {
+						self.list(condition);
+					}
+					self.list(&branch.body);
+				}
+			},
+			CompoundCommand::WhileClause(clause) | CompoundCommand::UntilClause(clause) => {
+				self.list(&clause.0);
+				self.list(&clause.1.list);
+			},
+			CompoundCommand::Arithmetic(_) => {},
+		}
+	}
+}
+
+/// A word of a simple command, before expansion.
+enum Word {
+	Text(String),
+	/// `<(…)` or `>(…)`, which always runs a command.
+	ProcessSubstitution(String),
+	/// An alias body that is more than a list of words (`a; b`).
+	Alias(String),
+}
+
+impl Word {
+	fn into_raw(self) -> String {
+		match self {
+			Self::Text(text) | Self::ProcessSubstitution(text) | Self::Alias(text) => text,
+		}
+	}
+}
+
+/// Words of an alias body, split with the shell's tokenizer so quoted words
+/// stay whole. A body with operators is kept as one unevaluated word.
+fn alias_words(alias: &str) -> Vec<Word> {
+	match brush_parser::tokenize_str(alias) {
+		Ok(tokens) if tokens.iter().all(|token| matches!(token, Token::Word(..))) => tokens
+			.iter()
+			.map(|token| Word::Text(token.to_str().to_owned()))
+			.collect(),
+		_ => vec![Word::Alias(alias.to_owned())],
+	}
+}
+
+/// Words of `simple` that become arguments: the name and everything after
+/// it but redirections. Leading assignments only set the command's
+/// environment; without a name there is no command.
+fn simple_command_words(simple: &SimpleCommand) -> Vec<Word> {
+	let Some(name) = &simple.word_or_name else {
+		return Vec::new();
+	};
+	let suffix = simple.suffix.iter().flat_map(|suffix| &suffix.0);
+	std::iter::once(Word::Text(name.value.clone()))
+		.chain(suffix.filter_map(|item| match item {
+			CommandPrefixOrSuffixItem::Word(word)
+			| CommandPrefixOrSuffixItem::AssignmentWord(_, word) => Some(Word::Text(word.value.clone())),
+			CommandPrefixOrSuffixItem::ProcessSubstitution(kind, subshell) => {
+				let sigil = match kind {
+					ProcessSubstitutionKind::Read => '<',
+					ProcessSubstitutionKind::Write => '>',
+				};
+				Some(Word::ProcessSubstitution(format!("{sigil}{subshell}")))
+			},
+			CommandPrefixOrSuffixItem::IoRedirect(_) => None,
+		}))
+		.collect()
+}
+
+/// Whether expanding `word` would run a command substitution (`$(…)` or
+/// backticks) outside single quotes.
+fn needs_substitution(word: &str) -> bool {
+	let mut chars = word.chars().peekable();
+	let mut single_quoted = false;
+	let mut double_quoted = false;
+	while let Some(c) = chars.next() {
+		match c {
+			'\'' if !double_quoted => single_quoted = !single_quoted,
+			_ if single_quoted => {},
+			'"' => double_quoted = !double_quoted,
+			'\\' => {
+				chars.next();
+			},
+			'`' => return true,
+			'$' if chars.peek() == Some(&'(') => {
+				chars.next();
+				if chars.peek() != Some(&'(') {
+					return true;
+				}
+			},
+			_ => {},
+		}
+	}
+	false
+}
+
+#[cfg(test)]
+mod tests {
+	use brush_core::{ShellValue, ShellVariable};
+
+	use super::*;
+	use crate::shell::{ShellConfig, create_session};
+
+	#[test]
+	fn test_needs_substitution() {
+		assert!(needs_substitution("$(whoami)"));
+		assert!(needs_substitution("\"x`date`\""));
+		assert!(needs_substitution("${x:-$(id)}"));
+		assert!(!needs_substitution("'$(literal)'"));
+		assert!(!needs_substitution("\\$(escaped)"));
+		assert!(!needs_substitution("$((1 + 2))"));
+		assert!(!needs_substitution("$HOME/bin"));
+	}
+
+	#[tokio::test]
+	async fn test_preview_command() {
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		session
+			.shell
+			.aliases
+			.insert("ll".to_owned(), "ls -l".to_owned());
+		session
+			.shell
+			.aliases
+			.insert("lsd".to_owned(), "ls \"my dir\"".to_owned());
+		let mut var = ShellVariable::new(ShellValue::String("a b".to_owned()));
+		var.export();
+		session.shell.env.set_global("DIRS", var).unwrap();
+		// Command substitutions stored in values, run by `@P` and by the
+		// subscript of an indirect reference.
+		let dir = std::env::temp_dir().join(format!("pi-preview-{}", std::process::id()));
+		let (p1, p3) = (dir.join("p1"), dir.join("p3"));
+		for (name, value) in [
+			("p1", format!("$(touch {})", p1.display())),
+			("p3", format!("arr[$(touch {})]", p3.display())),
+		] {
+			let var = ShellVariable::new(ShellValue::String(value));
+			session.shell.env.set_global(name, var).unwrap();
+		}
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let source = concat!(
+			"ll $DIRS | cd /; f() { rm -rf /; }; f $((1 + 2)); ",
+			"echo \"$(id)\" <(true); FOO=1 ./run.sh ${x:=1} >out; ",
+			"lsd; echo ${p1@P}; echo ${!p3}"
+		);
+		let previews = preview_command(&session.shell, source).await.unwrap();
+		let summary: Vec<(Vec<&str>, &[u32], &str)> = previews
+			.iter()
+			.map(|p| {
+				(p.argv.iter().map(String::as_str).collect(), p.unevaluated.as_slice(), p.kind.as_str())
+			})
+			.collect();
+		assert_eq!(summary, [
+			(vec!["ls", "-l", "a", "b"], &[][..], "external"),
+			(vec!["cd", "/"], &[][..], "builtin"),
+			(vec!["f", "3"], &[][..], "function"),
+			(vec!["echo", "\"$(id)\"", "<( true )"], &[1, 2][..], "builtin"),
+			(vec!["./run.sh", "1"], &[][..], "external"),
+			(vec!["ls", "my dir"], &[][..], "external"),
+			(vec!["echo", "${p1@P}"], &[1][..], "builtin"),
+			(vec!["echo", "${!p3}"], &[1][..], "builtin"),
+		]);
+		assert!(!p1.exists() && !p3.exists());
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert!(
+			previews[0]
+				.path
+				.as_deref()
+				.is_some_and(|path| path.ends_with("ls"))
+		);
+		// Expansion side effects stay in the preview's copy of the shell.
+		assert!(session.shell.env.get("x").is_none());
+	}
+}
@@ -0,0 +1,127 @@
+//! Reaping of exited children that nothing waited on.
+//!
+//! Only pids recorded when the session spawned them are ever waited on, and
+//! they are forgotten as soon as brush collects them, so a pid reused by
+//! other code in the process is never stolen. A child is reaped only after it
+//! has been seen exited on an earlier pass, which leaves time for whatever
+//! spawned it (brush, or tokio's orphan queue) to collect its status first.
+
+use std::{
+	collections::HashSet,
+	sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Children spawned by a session, and how many of them had to be reaped.
+#[derive(Default)]
+pub struct ChildTracker {
+	state:  parking_lot::Mutex<TrackerState>,
+	reaped: AtomicU32,
+}
+
+#[derive(Default)]
+struct TrackerState {
+	/// Spawned children not yet known to be waited on.
+	pids:   HashSet<i32>,
+	/// Children seen exited but unreaped on the last pass.
+	exited: HashSet<i32>,
+}
+
+impl ChildTracker {
+	/// Record a child the session spawned.
+	pub fn track(&self, pid: i32) {
+		self.state.lock().pids.insert(pid);
+	}
+
+	/// Forget a child brush has waited on, whose pid may now be reused.
+	pub fn untrack(&self, pid: i32) {
+		let mut state = self.state.lock();
+		state.pids.remove(&pid);
+		state.exited.remove(&pid);
+	}
+
+	/// Reap tracked children that exited before the previous pass and are
+	/// still unwaited, leaving `waited` (pids brush still waits on) alone.
+	/// Returns how many tracked children remain.
+	pub fn reap(&self, waited: &HashSet<i32>) -> u32 {
+		let mut state = self.state.lock();
+		let TrackerState { pids, exited } = &mut *state;
+		let mut now_exited = HashSet::new();
+		pids.retain(|&pid| {
+			if waited.contains(&pid) {
+				return true;
+			}
+			match peek_exited(pid) {
+				Some(false) => true,
+				Some(true) if !exited.contains(&pid) => {
+					now_exited.insert(pid);
+					true
+				},
+				Some(true) => {
+					let mut status = 0;
+					// SAFETY: `pid` is an exited child of this session; WNOHANG
+					// never blocks.
+					if unsafe { libc::waitpid(pid, &raw mut status, libc::WNOHANG) } == pid {
+						self.reaped.fetch_add(1, Ordering::Relaxed);
+					}
+					false
+				},
+				// Already waited on elsewhere.
+				None => false,
+			}
+		});
+		*exited = now_exited;
+		pids.len() as u32
+	}
+
+	/// Number of tracked children, without reaping any.
+	pub fn tracked(&self) -> u32 {
+		self.state.lock().pids.len() as u32
+	}
+
+	/// Number of children reaped so far.
+	pub fn reaped(&self) -> u32 {
+		self.reaped.load(Ordering::Relaxed)
+	}
+}
+
+/// Whether child `pid` has exited, without collecting its status. `None`
+/// when it is no longer our child to wait on.
+fn peek_exited(pid: i32) -> Option<bool> {
+	// SAFETY: siginfo_t is plain data; all-zero is a valid value.
+	let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+	// SAFETY: `info` is live for the call; WNOWAIT leaves the child unreaped.
+	let res = unsafe {
+		libc::waitid(
+			libc::P_PID,
+			pid as libc::id_t,
+			&raw mut info,
+			libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+		)
+	};
+	// SAFETY: waitid filled `info`, and si_pid is set for child events.
+	(res == 0).then(|| unsafe { info.si_pid() } == pid)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::process::Command;
+
+	use super::*;
+
+	#[test]
+	fn test_reap_leaked_child() {
+		let tracker = ChildTracker::default();
+		let child = Command::new("true").spawn().unwrap();
+		let pid = child.id() as i32;
+		// Dropping a std Child never waits, leaving a zombie.
+		drop(child);
+		tracker.track(pid);
+		std::thread::sleep(std::time::Duration::from_millis(200));
+
+		assert_eq!(tracker.reap(&HashSet::new()), 1);
+		assert_eq!(tracker.reaped(), 0);
+		assert_eq!(tracker.reap(&HashSet::new()), 0);
+		assert_eq!(tracker.reaped(), 1);
+		assert_eq!(tracker.tracked(), 0);
+	}
+}
@@ -0,0 +1,307 @@
+use std::{
+	fmt::Write as _,
+	io::{self, BufReader},
+	path::Path,
+	process::Stdio,
+};
+
+use brush_parser::{Parser, ParserOptions, SourceInfo};
+use napi::{Error, Result};
+use tokio::{fs, process::Command};
+
+use super::{SnapshotInfo, should_skip_env_var};
+use crate::task;
+
+/// Record separating stray output from the rc files from the dump.
+const START_MARKER: &str = "__PI_SNAPSHOT__";
+/// Record ending the exported variables and starting the functions.
+const FUNCTIONS_MARKER: &str = "__PI_FUNCTIONS__";
+/// Record ending the functions and starting the aliases.
+const ALIASES_MARKER: &str = "__PI_ALIASES__";
+
+/// Dump run by bash (and other shells with `compgen`): every record ends in a
+/// NUL, so multi-line values and definitions survive.
+const BASH_DUMP: &str = r#"printf '\0__PI_SNAPSHOT__\0'
+env -0
+printf '__PI_FUNCTIONS__\0'
+for __pi_name in $(compgen -A function); do typeset -f -- "$__pi_name"; printf '\0'; done
+printf '__PI_ALIASES__\0'
+for __pi_name in $(compgen -a); do alias -- "$__pi_name"; printf '\0'; done
+"#;
+
+/// Same dump for zsh, which lists names through its special arrays.
+const ZSH_DUMP: &str = r#"printf '\0__PI_SNAPSHOT__\0'
+env -0
+printf '__PI_FUNCTIONS__\0'
+for __pi_name in ${(k)functions}; do typeset -f -- "$__pi_name"; printf '\0'; done
+printf '__PI_ALIASES__\0'
+for __pi_name in ${(k)aliases}; do alias -L -- "$__pi_name"; printf '\0'; done
+"#;
+
+/// Run `shell` as an interactive login shell, dump its exported variables,
+/// functions, and aliases, and write those brush can source to
+/// `output_path`. Variables [`should_skip_env_var`] rejects, and functions or
+/// aliases that don't parse, are left out and listed as skipped.
+///
+/// The shell runs in a session of its own, without a controlling terminal,
+/// and the whole session is killed on cancellation.
+pub async fn create_snapshot(
+	shell: &str,
+	output_path: &str,
+	ct: task::CancelToken,
+) -> Result<SnapshotInfo> {
+	let is_zsh = Path::new(shell)
+		.file_stem()
+		.is_some_and(|name| name.eq_ignore_ascii_case("zsh"));
+	let dump = if is_zsh { ZSH_DUMP } else { BASH_DUMP };
+	let mut command = Command::new(shell);
+	command
+		.args(["-i", "-l", "-c", dump])
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.kill_on_drop(true);
+	#[cfg(unix)]
+	// SAFETY: `setsid` is async-signal-safe.
+	unsafe {
+		command.pre_exec(|| {
+			if libc::setsid() < 0 {
+				return Err(io::Error::last_os_error());
+			}
+			Ok(())
+		});
+	}
+	let child = command
+		.spawn()
+		.map_err(|err| Error::from_reason(format!("Failed to launch {shell}: {err}")))?;
+	#[cfg(unix)]
+	let session = child.id().map(|pid| pid as i32);
+
+	let output = tokio::select! {
+		output = child.wait_with_output() => output
+			.map_err(|err| Error::from_reason(format!("Failed to read {shell} output: {err}")))?,
+		reason = ct.wait() => {
+			// Also stop whatever the rc files started in the background.
+			#[cfg(unix)]
+			if let Some(pgid) = session {
+				crate::ps::kill_process_group(pgid, libc::SIGKILL);
+			}
+			return Err(Error::from_reason(format!("Aborted: {reason:?}")));
+		},
+	};
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	let (snapshot, mut info) = build_snapshot(shell, &stdout).ok_or_else(|| {
+		Error::from_reason(format!(
+			"{shell} exited with {} before printing its environment",
+			output.status
+		))
+	})?;
+
+	write_private(Path::new(output_path), snapshot.as_bytes())
+		.await
+		.map_err(|err| Error::from_reason(format!("Failed to write snapshot: {err}")))?;
+	info.path = output_path.to_owned();
+	Ok(info)
+}
+
+/// Write `contents` to `path` readable only by the owner, through a temporary
+/// file renamed into place so readers never see a partial snapshot.
+async fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+	let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+	temp_name.push(format!(".{}.tmp", std::process::id()));
+	let temp = path.with_file_name(temp_name);
+
+	// A leftover from an earlier attempt is replaced, never written through.
+	let _ = fs::remove_file(&temp).await;
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	options.mode(0o600);
+	let written = async {
+		let mut file = options.open(&temp).await?;
+		tokio::io::AsyncWriteExt::write_all(&mut file, contents).await?;
+		file.sync_all().await?;
+		drop(file);
+		fs::rename(&temp, path).await
+	}
+	.await;
+	if written.is_err() {
+		let _ = fs::remove_file(&temp).await;
+	}
+	written
+}
+
+/// Turn the dump printed by [`BASH_DUMP`] or [`ZSH_DUMP`] into a snapshot
+/// script, or `None` if the dump never started.
+fn build_snapshot(shell: &str, dump: &str) -> Option<(String, SnapshotInfo)> {
+	let (_, dump) = dump.split_once(&format!("\0{START_MARKER}\0"))?;
+	let mut records = dump.split('\0');
+	let mut info = SnapshotInfo { shell: shell.to_owned(), ..SnapshotInfo::default() };
+	let mut script = format!("# Login environment of {shell}\n");
+
+	for record in records
+		.by_ref()
+		.take_while(|&record| record != FUNCTIONS_MARKER)
+	{
+		let Some((name, value)) = record.split_once('=') else {
+			continue;
+		};
+		// `_` is `env`'s own path, not part of the environment.
+		if !is_valid_name(name) || name == "_" || should_skip_env_var(name) {
+			info.skipped_variables.push(name.to_owned());
+			continue;
+		}
+		let _ = writeln!(script, "export {name}={}", single_quote(value));
+		info.variables += 1;
+	}
+
+	for record in records
+		.by_ref()
+		.take_while(|&record| record != ALIASES_MARKER)
+	{
+		let definition = record.trim();
+		if definition.is_empty() {
+			continue;
+		}
+		if parses(definition) {
+			script.push_str(definition);
+			script.push('\n');
+			info.functions += 1;
+		} else {
+			info.skipped_functions.push(function_name(definition));
+		}
+	}
+
+	for record in records {
+		let definition = record.trim();
+		if definition.is_empty() {
+			continue;
+		}
+		// zsh prints `alias -g`/`alias -s` for global and suffix aliases, which
+		// brush has no equivalent for.
+		if definition.starts_with("alias ")
+			&& !definition.starts_with("alias -")
+			&& parses(definition)
+		{
+			script.push_str(definition);
+			script.push('\n');
+			info.aliases += 1;
+		} else {
+			info.skipped_aliases.push(alias_name(definition));
+		}
+	}
+	Some((script, info))
+}
+
+/// Whether `name` can be assigned by a shell script.
+fn is_valid_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	chars
+		.next()
+		.is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+		&& chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// Whether brush can parse `script`.
+fn parses(script: &str) -> bool {
+	let source = SourceInfo { source: String::from("snapshot") };
+	Parser::new(BufReader::new(script.as_bytes()), &ParserOptions::default(), &source)
+		.parse_program()
+		.is_ok()
+}
+
+/// `value` in single quotes, with embedded quotes escaped.
+fn single_quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Name from a `name () { ... }` definition.
+fn function_name(definition: &str) -> String {
+	let definition = definition.trim_start_matches("function ");
+	definition
+		.split(['(', ' ', '\n'])
+		.next()
+		.unwrap_or_default()
+		.to_owned()
+}
+
+/// Name from an `alias [-g] name='value'` line.
+fn alias_name(definition: &str) -> String {
+	let head = definition.split('=').next().unwrap_or_default();
+	head
+		.split_whitespace()
+		.last()
+		.unwrap_or_default()
+		.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_build_snapshot() {
+		let dump = concat!(
+			"Welcome!\n\0__PI_SNAPSHOT__\0",
+			"EDITOR=vim\0MULTI=a\nb's\0PS1=$ \0SHLVL=2\0bad-name=x\0",
+			"__PI_FUNCTIONS__\0",
+			"greet () \n{ \n    echo hi\n}\n\0broken () \n{ \n    if true\n}\n\0",
+			"__PI_ALIASES__\0",
+			"alias ll='ls -l'\n\0alias -g G='| grep'\n\0"
+		);
+		let (script, info) = build_snapshot("/bin/bash", dump).unwrap();
+		assert_eq!((info.variables, info.functions, info.aliases), (2, 1, 1));
+		assert_eq!(info.skipped_variables, ["PS1", "SHLVL", "bad-name"]);
+		assert_eq!(info.skipped_functions, ["broken"]);
+		assert_eq!(info.skipped_aliases, ["G"]);
+		assert!(script.contains("export MULTI='a\nb'\\''s'\n"));
+		assert!(script.contains("greet () \n{ \n    echo hi\n}\n"));
+		assert!(script.ends_with("alias ll='ls -l'\n"));
+		assert!(!script.contains("Welcome"));
+		assert!(parses(&script));
+
+		// Nothing was printed past the rc files.
+		assert!(build_snapshot("/bin/bash", "Welcome!\n").is_none());
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_create_snapshot() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = std::env::temp_dir().join(format!("pi-natives-snapshot-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir(&dir).unwrap();
+		// Stands in for a login shell: bash without the user's rc files, then
+		// a fixed environment in their place. The dump is the fourth argument.
+		let shell = dir.join("shell");
+		std::fs::write(
+			&shell,
+			"#!/bin/sh\nexec bash --noprofile --norc -c 'export PI_RC=1; alias ll=\"ls -l\"; greet() \
+			 { echo hi; }; eval \"$1\"' bash \"$4\"\n",
+		)
+		.unwrap();
+		std::fs::set_permissions(&shell, std::fs::Permissions::from_mode(0o755)).unwrap();
+		let shell = shell.to_string_lossy();
+		let path = dir.join("snapshot.sh");
+		let output = path.to_string_lossy();
+
+		let info = create_snapshot(&shell, &output, task::CancelToken::new(Some(10_000), None))
+			.await
+			.unwrap();
+		assert_eq!((info.functions, info.aliases), (1, 1));
+		let script = std::fs::read_to_string(&path).unwrap();
+		assert!(script.contains("export PI_RC='1'\n"));
+		assert!(script.contains("alias ll='ls -l'\n"));
+		assert!(parses(&script));
+		let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o600);
+		assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+		// An expired timeout stops the shell instead of waiting for it.
+		let expired = task::CancelToken::new(Some(0), None);
+		let result = create_snapshot(&shell, &output, expired).await;
+		assert!(result.is_err_and(|err| err.reason == "Aborted: Timeout"));
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+}
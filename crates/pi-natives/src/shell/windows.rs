@@ -1,16 +1,389 @@
 use std::{
-	collections::HashSet,
+	collections::BTreeMap,
 	env,
-	path::{Path, PathBuf},
+	ffi::OsString,
+	path::{Component, Path, PathBuf},
 	process::Command,
+	sync::{
+		Arc,
+		atomic::{AtomicU32, Ordering},
+	},
 };
 
-use brush_core::{Shell as BrushShell, ShellValue, ShellVariable};
-use napi::{Error, Result};
-use winreg::{RegKey, enums::HKEY_LOCAL_MACHINE};
+use brush_core::{
+	ExecutionContext, ExecutionResult, Shell as BrushShell, ShellValue, ShellVariable, builtins,
+	sys::fs::to_shell_path,
+};
+use clap::Parser;
+use napi::{Either, Error, Result};
+use parking_lot::RwLock;
+use winreg::{
+	HKEY, RegKey,
+	enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE},
+};
+
+use super::WindowsPathOverride;
+
+/// Environment variable naming the Unix tool directory to use instead of
+/// discovering one. Validated like the `unixTools` option: without `ls.exe`
+/// discovery runs as usual and the rejection is reported as a warning.
+const UNIX_TOOLS_ENV: &str = "PI_SHELL_UNIX_TOOLS";
+
+/// A distribution of Unix tools for Windows. Only one is ever added to PATH:
+/// their `msys-2.0.dll`/`cygwin1.dll` runtimes conflict when mixed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnixProvider {
+	/// Git for Windows (`usr\bin`, `mingw64\bin`, and the `git` launchers).
+	Git,
+	/// MSYS2 (`usr\bin`).
+	Msys2,
+	/// Cygwin (`bin`).
+	Cygwin,
+}
+
+/// Providers in the order tried when `windowsPathAugmentation` doesn't say.
+pub const DEFAULT_PROVIDERS: [UnixProvider; 3] =
+	[UnixProvider::Git, UnixProvider::Msys2, UnixProvider::Cygwin];
+
+impl UnixProvider {
+	pub const fn as_str(self) -> &'static str {
+		match self {
+			Self::Git => "git",
+			Self::Msys2 => "msys2",
+			Self::Cygwin => "cygwin",
+		}
+	}
+
+	fn parse(name: &str) -> Result<Self> {
+		match name {
+			"git" => Ok(Self::Git),
+			"msys2" => Ok(Self::Msys2),
+			"cygwin" => Ok(Self::Cygwin),
+			_ => Err(Error::from_reason(format!(
+				"windowsPathAugmentation.providers entries must be \"git\", \"msys2\", or \"cygwin\", \
+				 got \"{name}\""
+			))),
+		}
+	}
+
+	/// Directory holding the coreutils under an install root, if `ls.exe` is
+	/// there.
+	fn tools_dir(self, install_root: &Path) -> Option<PathBuf> {
+		match self {
+			Self::Git | Self::Msys2 => git_usr_bin_with_ls(install_root),
+			Self::Cygwin => {
+				let bin = install_root.join("bin");
+				bin.join("ls.exe").is_file().then_some(bin)
+			},
+		}
+	}
+
+	/// PATH entries for an accepted install root, in priority order.
+	fn paths_for_install_root(self, install_root: &str) -> Vec<String> {
+		match self {
+			Self::Git => git_paths_for_install_root(install_root),
+			Self::Msys2 | Self::Cygwin => self
+				.tools_dir(Path::new(install_root))
+				.into_iter()
+				.map(|dir| dir.to_string_lossy().to_string())
+				.collect(),
+		}
+	}
+}
+
+/// A Unix tools directory named by an override (`unixTools` or
+/// `PI_SHELL_UNIX_TOOLS`) that passed [`validate_unix_tools_dir`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnixToolsDir {
+	pub path:     String,
+	/// Provider identified from the directory's contents, if any (e.g. not
+	/// for busybox).
+	pub provider: Option<UnixProvider>,
+}
+
+/// How a session augments PATH, from `windowsPathAugmentation`.
+#[derive(Clone)]
+pub enum PathAugmentation {
+	/// Use `PI_SHELL_UNIX_TOOLS`, else discover Unix tools from the providers
+	/// in this order.
+	Auto(Vec<UnixProvider>),
+	/// Leave PATH alone; `PI_SHELL_UNIX_TOOLS` is ignored too.
+	Off,
+	/// Append this directory (`unixTools`) instead of consulting
+	/// `PI_SHELL_UNIX_TOOLS` or discovering one.
+	UnixTools(UnixToolsDir),
+}
+
+impl PathAugmentation {
+	/// Parse `windowsPathAugmentation`. A `unixTools` directory without
+	/// `ls.exe` falls back to discovery in the default order and is returned
+	/// as a warning instead of failing.
+	pub fn from_option(
+		option: Option<Either<String, WindowsPathOverride>>,
+	) -> Result<(Self, Option<String>)> {
+		match option {
+			None => Ok((Self::Auto(DEFAULT_PROVIDERS.to_vec()), None)),
+			Some(Either::A(mode)) => match mode.as_str() {
+				"auto" => Ok((Self::Auto(DEFAULT_PROVIDERS.to_vec()), None)),
+				"off" => Ok((Self::Off, None)),
+				_ => Err(Error::from_reason(format!(
+					"windowsPathAugmentation must be \"auto\", \"off\", or {{ unixTools }} or {{ \
+					 providers }}, got \"{mode}\""
+				))),
+			},
+			Some(Either::B(custom)) => match (custom.unix_tools, custom.providers) {
+				(Some(_), Some(_)) => Err(Error::from_reason(
+					"windowsPathAugmentation takes unixTools or providers, not both",
+				)),
+				(Some(dir), None) => {
+					match validate_unix_tools_dir(&dir, "windowsPathAugmentation.unixTools") {
+						Ok(dir) => Ok((Self::UnixTools(dir), None)),
+						Err(warning) => Ok((Self::Auto(DEFAULT_PROVIDERS.to_vec()), Some(warning))),
+					}
+				},
+				(None, names) => {
+					let providers = match names {
+						Some(names) => names
+							.iter()
+							.map(|name| UnixProvider::parse(name))
+							.collect::<Result<Vec<_>>>()?,
+						None => DEFAULT_PROVIDERS.to_vec(),
+					};
+					Ok((Self::Auto(providers), None))
+				},
+			},
+		}
+	}
+}
+
+/// Where a Unix tools install was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnixToolsSource {
+	/// The `unixTools` option names the directory directly.
+	Option,
+	/// `PI_SHELL_UNIX_TOOLS` names the directory directly.
+	Environment,
+	/// `GitForWindows` key in HKLM (machine-wide installer).
+	Registry,
+	/// `GitForWindows` key in HKCU (per-user installer).
+	UserRegistry,
+	/// `where git` on the parent PATH.
+	Where,
+	/// `scoop install git`.
+	Scoop,
+	/// `%LOCALAPPDATA%\Programs\Git` (winget or per-user installer).
+	UserInstall,
+	/// `%ProgramFiles%\Git` (chocolatey and default installs).
+	ProgramFiles,
+	/// MSYS2's uninstall registry entry, or `C:\msys64`.
+	Msys2,
+	/// Cygwin's `setup` registry key, or `C:\cygwin64`.
+	Cygwin,
+}
+
+impl UnixToolsSource {
+	pub const fn as_str(self) -> &'static str {
+		match self {
+			Self::Option => "option",
+			Self::Environment => "env",
+			Self::Registry => "registry",
+			Self::UserRegistry => "registry-user",
+			Self::Where => "where",
+			Self::Scoop => "scoop",
+			Self::UserInstall => "user-install",
+			Self::ProgramFiles => "program-files",
+			Self::Msys2 => "msys2",
+			Self::Cygwin => "cygwin",
+		}
+	}
+
+	/// Provider an install found this way belongs to.
+	pub const fn provider(self) -> UnixProvider {
+		match self {
+			Self::Msys2 => UnixProvider::Msys2,
+			Self::Cygwin => UnixProvider::Cygwin,
+			_ => UnixProvider::Git,
+		}
+	}
+}
+
+/// One probe made while locating Unix tools, in probing order.
+pub struct UnixToolsProbe {
+	pub source:       UnixToolsSource,
+	/// Install root the probe pointed at, if any.
+	pub install_root: Option<String>,
+	/// Whether the root passed validation (its tools directory holds
+	/// `ls.exe`).
+	pub accepted:     bool,
+}
+
+/// Outcome of Unix tools discovery: the PATH entries to add, and every probe
+/// made for diagnostics.
+#[derive(Default)]
+pub struct UnixToolsDiscovery {
+	pub paths:     Vec<String>,
+	pub source:    Option<UnixToolsSource>,
+	pub provider:  Option<UnixProvider>,
+	pub probes:    Vec<UnixToolsProbe>,
+	/// Provider order the discovery ran with.
+	pub providers: Vec<UnixProvider>,
+	/// Overrides rejected before falling back to probing.
+	pub warnings:  Vec<String>,
+}
+
+impl UnixToolsDiscovery {
+	/// Whether this discovery ran with `providers` and the directories found
+	/// are still there.
+	fn is_current(&self, providers: &[UnixProvider]) -> bool {
+		self.providers == providers && self.paths.iter().all(|path| Path::new(path).is_dir())
+	}
+}
 
-pub fn configure_windows_path(shell: &mut BrushShell) -> Result<()> {
-	let git_paths = find_git_paths();
+/// Discoveries shared by every session, keyed by provider order, so the
+/// registry and `where git` are only consulted once per process and order.
+static UNIX_TOOLS_DISCOVERY: RwLock<BTreeMap<Vec<UnixProvider>, Arc<UnixToolsDiscovery>>> =
+	RwLock::new(BTreeMap::new());
+
+/// Cached discovery for `providers`; probes again only after
+/// [`invalidate_unix_tools_discovery`] or when a directory it found has been
+/// removed.
+pub fn cached_unix_tools_discovery(providers: &[UnixProvider]) -> Arc<UnixToolsDiscovery> {
+	// Checked outside the lock: `is_current` touches the filesystem.
+	let cached = UNIX_TOOLS_DISCOVERY.read().get(providers).cloned();
+	if let Some(discovery) = cached.filter(|d| d.is_current(providers)) {
+		return discovery;
+	}
+	let mut cache = UNIX_TOOLS_DISCOVERY.write();
+	if let Some(discovery) = cache.get(providers).filter(|d| d.is_current(providers)) {
+		return discovery.clone();
+	}
+	let discovery = Arc::new(discover_unix_tools(providers));
+	cache.insert(providers.to_vec(), discovery.clone());
+	discovery
+}
+
+/// Drop the cached discoveries, e.g. after Git was installed mid-process.
+pub fn invalidate_unix_tools_discovery() {
+	UNIX_TOOLS_DISCOVERY.write().clear();
+}
+
+/// Check a Unix tools override (`name` says which, for the warning): the
+/// directory must hold `ls.exe`. Returns it with the provider its runtime
+/// identifies, else the warning explaining why it was ignored.
+fn validate_unix_tools_dir(dir: &str, name: &str) -> std::result::Result<UnixToolsDir, String> {
+	let path = Path::new(dir);
+	if !path.join("ls.exe").is_file() {
+		return Err(format!("{name} ignored: {dir} does not contain ls.exe"));
+	}
+	let provider = if path.join("cygwin1.dll").is_file() {
+		Some(UnixProvider::Cygwin)
+	} else if path.join("msys-2.0.dll").is_file() {
+		// Git for Windows ships the MSYS2 runtime in `usr\bin`, with its
+		// launchers in `cmd` next to `usr`.
+		let root = path.parent().and_then(Path::parent);
+		if root.is_some_and(|root| has_git_command(&root.join("cmd"))) {
+			Some(UnixProvider::Git)
+		} else {
+			Some(UnixProvider::Msys2)
+		}
+	} else {
+		None
+	};
+	Ok(UnixToolsDir { path: dir.to_string(), provider })
+}
+
+/// Looks up a candidate install root.
+type UnixToolsProbeFn = fn() -> Option<String>;
+
+/// Number of times [`discover_unix_tools`] has run, to check the cache in
+/// tests.
+#[cfg(test)]
+static DISCOVERY_RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// Probe for a Unix tools install, provider by provider in `providers` order,
+/// stopping at the first whose tools directory holds the coreutils.
+pub fn discover_unix_tools(providers: &[UnixProvider]) -> UnixToolsDiscovery {
+	#[cfg(test)]
+	DISCOVERY_RUNS.fetch_add(1, Ordering::Relaxed);
+	let probes: [(UnixToolsSource, UnixToolsProbeFn); 8] = [
+		(UnixToolsSource::Registry, || query_git_install_path_from_registry(HKEY_LOCAL_MACHINE)),
+		(UnixToolsSource::UserRegistry, || query_git_install_path_from_registry(HKEY_CURRENT_USER)),
+		(UnixToolsSource::Where, query_git_install_path_from_where),
+		(UnixToolsSource::Scoop, query_git_install_path_from_scoop),
+		(UnixToolsSource::UserInstall, || {
+			install_dir_under(env::var_os("LOCALAPPDATA"), &["Programs", "Git"])
+		}),
+		(UnixToolsSource::ProgramFiles, || {
+			let program_files =
+				env::var_os("ProgramFiles").or_else(|| Some(OsString::from("C:\\Program Files")));
+			install_dir_under(program_files, &["Git"])
+		}),
+		(UnixToolsSource::Msys2, query_msys2_install_path),
+		(UnixToolsSource::Cygwin, query_cygwin_install_path),
+	];
+
+	let mut discovery = UnixToolsDiscovery { providers: providers.to_vec(), ..Default::default() };
+	if let Some(dir) = env::var(UNIX_TOOLS_ENV).ok().filter(|dir| !dir.is_empty()) {
+		let accepted = match validate_unix_tools_dir(&dir, UNIX_TOOLS_ENV) {
+			Ok(tools) => {
+				discovery.paths = vec![tools.path];
+				discovery.source = Some(UnixToolsSource::Environment);
+				discovery.provider = tools.provider;
+				true
+			},
+			Err(warning) => {
+				discovery.warnings.push(warning);
+				false
+			},
+		};
+		discovery.probes.push(UnixToolsProbe {
+			source: UnixToolsSource::Environment,
+			install_root: Some(dir),
+			accepted,
+		});
+		if accepted {
+			return discovery;
+		}
+	}
+	for &provider in providers {
+		for &(source, probe) in probes
+			.iter()
+			.filter(|(source, _)| source.provider() == provider)
+		{
+			let install_root = probe();
+			let accepted = install_root
+				.as_deref()
+				.is_some_and(|root| provider.tools_dir(Path::new(root)).is_some());
+			if accepted && let Some(root) = install_root.as_deref() {
+				discovery.paths = provider.paths_for_install_root(root);
+				discovery.source = Some(source);
+				discovery.provider = Some(provider);
+			}
+			discovery
+				.probes
+				.push(UnixToolsProbe { source, install_root, accepted });
+			if accepted {
+				return discovery;
+			}
+		}
+	}
+	discovery
+}
+
+/// Add Unix tools to the session's PATH, as `augmentation` says.
+pub fn configure_windows_path(
+	shell: &mut BrushShell,
+	augmentation: &PathAugmentation,
+) -> Result<()> {
+	let discovery;
+	let git_paths = match augmentation {
+		PathAugmentation::Off => return Ok(()),
+		PathAugmentation::UnixTools(dir) => std::slice::from_ref(&dir.path),
+		PathAugmentation::Auto(providers) => {
+			discovery = cached_unix_tools_discovery(providers);
+			&discovery.paths[..]
+		},
+	};
 	if git_paths.is_empty() {
 		return Ok(());
 	}
@@ -24,34 +397,213 @@ pub fn configure_windows_path(shell: &mut BrushShell) -> Result<()> {
 		})
 		.unwrap_or_default();
 
-	let mut updated_path = existing_path.clone();
-	for git_path in git_paths {
-		if !Path::new(&git_path).is_dir() {
+	let updated_path = append_path_entries(&existing_path, git_paths);
+	if updated_path == existing_path {
+		return Ok(());
+	}
+
+	let mut var = ShellVariable::new(ShellValue::String(updated_path));
+	var.export();
+	shell
+		.env
+		.set_global("PATH", var)
+		.map_err(|err| Error::from_reason(format!("Failed to set PATH: {err}")))?;
+
+	Ok(())
+}
+
+/// Append each existing directory in `entries` to `path_value`, in order,
+/// skipping those already present.
+fn append_path_entries(path_value: &str, entries: &[String]) -> String {
+	let mut updated_path = path_value.to_string();
+	for entry in entries {
+		if !Path::new(entry).is_dir() {
 			continue;
 		}
-		if path_contains_entry(&updated_path, &git_path) {
+		if path_contains_entry(&updated_path, entry) {
 			continue;
 		}
 		if !updated_path.is_empty() && !updated_path.ends_with(';') {
 			updated_path.push(';');
 		}
-		updated_path.push_str(&git_path);
+		updated_path.push_str(&strip_extended_length_prefix(entry));
 	}
+	updated_path
+}
 
-	if updated_path == existing_path {
-		return Ok(());
+/// Paths this long need the `\\?\` prefix: `CreateDirectoryW` stops at
+/// `MAX_PATH` (260) less room for an 8.3 file name.
+const LONG_PATH_THRESHOLD: usize = 248;
+
+/// An absolute path in a form Win32 accepts at any length: separators
+/// backslashed, `.` and `..` resolved (the prefix turns off that
+/// normalization), and `\\?\` or `\\?\UNC\` prepended once it reaches
+/// [`LONG_PATH_THRESHOLD`]. Relative and already-prefixed paths are returned
+/// as is.
+pub fn extended_length_path(path: &Path) -> PathBuf {
+	let raw = path.to_string_lossy();
+	if raw.starts_with("\\\\?\\") || !path.is_absolute() {
+		return path.to_path_buf();
 	}
 
-	let mut var = ShellVariable::new(ShellValue::String(updated_path));
-	var.export();
+	let mut normalized = PathBuf::new();
+	for component in Path::new(&raw.replace('/', "\\")).components() {
+		match component {
+			Component::CurDir => {},
+			Component::ParentDir => {
+				normalized.pop();
+			},
+			component => normalized.push(component),
+		}
+	}
+
+	let normalized = normalized.to_string_lossy().into_owned();
+	if normalized.len() < LONG_PATH_THRESHOLD {
+		PathBuf::from(normalized)
+	} else if let Some(unc) = normalized.strip_prefix("\\\\") {
+		PathBuf::from(format!("\\\\?\\UNC\\{unc}"))
+	} else {
+		PathBuf::from(format!("\\\\?\\{normalized}"))
+	}
+}
+
+/// The inverse of [`extended_length_path`] for paths shown to scripts (`$PWD`,
+/// PATH entries), which tools expect in plain `C:\...` or `\\server\...` form.
+pub fn strip_extended_length_prefix(path: &str) -> String {
+	if let Some(unc) = path.strip_prefix("\\\\?\\UNC\\") {
+		format!("\\\\{unc}")
+	} else {
+		path.strip_prefix("\\\\?\\").unwrap_or(path).to_string()
+	}
+}
+
+/// Change the session's directory to `cwd`, reaching it through the
+/// extended-length form if needed. brush-core shows it to scripts as `$PWD`
+/// in forward-slash form (`C:/Users/me/project`).
+pub fn set_working_dir(shell: &mut BrushShell, cwd: &str) -> Result<()> {
+	let target = extended_length_path(&shell.absolute_path(cwd));
 	shell
-		.env
-		.set_global("PATH", var)
-		.map_err(|err| Error::from_reason(format!("Failed to set PATH: {err}")))?;
+		.set_working_dir(&target)
+		.map_err(|err| Error::from_reason(format!("Failed to set cwd: {err}")))
+}
+
+/// `path` as Windows programs expect it: backslashes, no `\\?\` prefix.
+pub fn to_native_path(path: &Path) -> String {
+	strip_extended_length_prefix(&path.to_string_lossy().replace('/', "\\"))
+}
+
+/// `D:` and the other drive builtins: switch to the drive's last working
+/// directory, as `cmd.exe` does.
+#[derive(Parser)]
+#[command(disable_help_flag = true)]
+struct DriveCommand {}
+
+impl builtins::Command for DriveCommand {
+	type Error = brush_core::Error;
+
+	fn execute(
+		&self,
+		context: ExecutionContext<'_>,
+	) -> impl Future<Output = std::result::Result<ExecutionResult, brush_core::Error>> + Send {
+		let result = context.shell.set_working_dir(&context.command_name);
+		std::future::ready(result.map(|()| ExecutionResult::success()))
+	}
+}
+
+/// Register `A:` through `Z:` (either case) as drive-switching builtins.
+pub fn register_drive_builtins(shell: &mut BrushShell) {
+	for letter in 'A'..='Z' {
+		for name in [format!("{letter}:"), format!("{}:", letter.to_ascii_lowercase())] {
+			shell.register_builtin(name, builtins::builtin::<DriveCommand>());
+		}
+	}
+}
+
+/// Variables naming a directory for POSIX tools, which expect forward
+/// slashes.
+const SHELL_PATH_VARS: [&str; 2] = ["HOME", "TMPDIR"];
+
+/// `value` for `key` as the shell should see it: absolute paths in
+/// [`SHELL_PATH_VARS`] use the forward-slash form of `$PWD`.
+pub fn shell_env_value(key: &str, value: String) -> String {
+	if SHELL_PATH_VARS.contains(&key) && Path::new(&value).is_absolute() {
+		to_shell_path(Path::new(&value))
+	} else {
+		value
+	}
+}
+
+/// Python's switches for UTF-8 pipes, which it otherwise writes in the ANSI
+/// code page.
+const PYTHON_UTF8_ENV: [(&str, &str); 2] = [("PYTHONIOENCODING", "utf-8"), ("PYTHONUTF8", "1")];
+
+/// Ask child processes for UTF-8 output instead of the OEM code page: MSYS
+/// tools follow the locale, so `LANG` and `LC_ALL` become `C.UTF-8` unless the
+/// effective locale already is UTF-8, and unset Python variables are filled
+/// in.
+pub fn set_utf8_env(shell: &mut BrushShell) -> Result<()> {
+	let value = |key: &str| {
+		shell
+			.env
+			.get_str(key, shell)
+			.map(|value| value.to_ascii_lowercase())
+			.filter(|value| !value.is_empty())
+	};
+	let is_utf8 = |locale: String| locale.contains("utf-8") || locale.contains("utf8");
+	let utf8_locale = match value("LC_ALL") {
+		Some(locale) => is_utf8(locale),
+		None => value("LANG").is_some_and(is_utf8),
+	};
 
+	let mut vars: Vec<(&str, &str)> = PYTHON_UTF8_ENV
+		.into_iter()
+		.filter(|(key, _)| value(key).is_none())
+		.collect();
+	if !utf8_locale {
+		vars.extend([("LANG", "C.UTF-8"), ("LC_ALL", "C.UTF-8")]);
+	}
+	for (key, value) in vars {
+		let mut var = ShellVariable::new(ShellValue::String(value.to_owned()));
+		var.export();
+		shell
+			.env
+			.set_global(key, var)
+			.map_err(|err| Error::from_reason(format!("Failed to set env: {err}")))?;
+	}
 	Ok(())
 }
 
+/// Copy of a script with CRLF line endings rewritten to LF, in the temp
+/// directory, or `None` when it has none (or can't be read, leaving `source`
+/// to report that). Sourced as is, every value and function body would keep
+/// a trailing `\r`.
+pub fn lf_script_copy(path: &Path) -> Result<Option<PathBuf>> {
+	static COPIES: AtomicU32 = AtomicU32::new(0);
+
+	let Ok(contents) = std::fs::read(path) else {
+		return Ok(None);
+	};
+	if !contents.windows(2).any(|pair| pair == b"\r\n") {
+		return Ok(None);
+	}
+	let mut normalized = Vec::with_capacity(contents.len());
+	let mut bytes = contents.iter().peekable();
+	while let Some(&byte) = bytes.next() {
+		if byte != b'\r' || bytes.peek() != Some(&&b'\n') {
+			normalized.push(byte);
+		}
+	}
+
+	let copy = env::temp_dir().join(format!(
+		"pi-script-{}-{}.sh",
+		std::process::id(),
+		COPIES.fetch_add(1, Ordering::Relaxed)
+	));
+	std::fs::write(&copy, normalized)
+		.map_err(|err| Error::from_reason(format!("Failed to normalize line endings: {err}")))?;
+	Ok(Some(copy))
+}
+
 fn path_contains_entry(path_value: &str, entry: &str) -> bool {
 	let entry_normalized = normalize_path(Path::new(entry));
 	if entry_normalized.is_empty() {
@@ -85,34 +637,32 @@ fn normalize_path(path: &Path) -> String {
 	normalized.to_string_lossy().to_string()
 }
 
-fn find_git_paths() -> Vec<String> {
-	let mut paths = Vec::new();
-	let mut seen = HashSet::new();
+/// `usr\bin` of an install root, if it holds the coreutils.
+fn git_usr_bin_with_ls(install_root: &Path) -> Option<PathBuf> {
+	let usr_bin = install_root.join("usr").join("bin");
+	usr_bin.join("ls.exe").is_file().then_some(usr_bin)
+}
 
-	for install_path in [query_git_install_path_from_registry(), query_git_install_path_from_where()]
-		.into_iter()
-		.flatten()
-	{
-		for path in git_paths_for_install_root(&install_path) {
-			let normalized = normalize_path(Path::new(&path));
-			if normalized.is_empty() {
-				continue;
-			}
-			if seen.insert(normalized) {
-				paths.push(path);
-			}
-		}
-	}
+/// `base` joined with `rel`, if that directory exists.
+fn install_dir_under(base: Option<OsString>, rel: &[&str]) -> Option<String> {
+	let mut dir = PathBuf::from(base.filter(|base| !base.is_empty())?);
+	dir.extend(rel);
+	dir.is_dir().then(|| dir.to_string_lossy().to_string())
+}
 
-	paths
+fn query_git_install_path_from_scoop() -> Option<String> {
+	let scoop = env::var_os("SCOOP").or_else(|| {
+		env::var_os("USERPROFILE").map(|profile| Path::new(&profile).join("scoop").into_os_string())
+	});
+	install_dir_under(scoop, &["apps", "git", "current"])
 }
 
-fn query_git_install_path_from_registry() -> Option<String> {
-	let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+fn query_git_install_path_from_registry(hive: HKEY) -> Option<String> {
+	let root = RegKey::predef(hive);
 	let key_paths = ["SOFTWARE\\GitForWindows", "SOFTWARE\\WOW6432Node\\GitForWindows"];
 
 	for key_path in key_paths {
-		if let Ok(key) = hklm.open_subkey(key_path) {
+		if let Ok(key) = root.open_subkey(key_path) {
 			if let Ok(path) = key.get_value::<String, _>("InstallPath") {
 				if !path.is_empty() {
 					return Some(path);
@@ -124,6 +674,58 @@ fn query_git_install_path_from_registry() -> Option<String> {
 	None
 }
 
+/// MSYS2's install location from its uninstall entry, else the installer's
+/// default `C:\msys64` (or `C:\msys32`).
+fn query_msys2_install_path() -> Option<String> {
+	let uninstall = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
+	for hive in [HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE] {
+		let Ok(key) = RegKey::predef(hive).open_subkey(uninstall) else {
+			continue;
+		};
+		for name in key.enum_keys().flatten() {
+			let Ok(entry) = key.open_subkey(&name) else {
+				continue;
+			};
+			let is_msys2 = entry
+				.get_value::<String, _>("DisplayName")
+				.is_ok_and(|display| display.starts_with("MSYS2"));
+			if !is_msys2 {
+				continue;
+			}
+			if let Ok(path) = entry.get_value::<String, _>("InstallLocation")
+				&& !path.is_empty()
+			{
+				return Some(path);
+			}
+		}
+	}
+	["C:\\msys64", "C:\\msys32"]
+		.into_iter()
+		.find(|root| Path::new(root).is_dir())
+		.map(str::to_string)
+}
+
+/// Cygwin's root from the key its `setup` writes, else the default
+/// `C:\cygwin64` (or `C:\cygwin`).
+fn query_cygwin_install_path() -> Option<String> {
+	for hive in [HKEY_LOCAL_MACHINE, HKEY_CURRENT_USER] {
+		if let Ok(key) = RegKey::predef(hive).open_subkey("SOFTWARE\\Cygwin\\setup")
+			&& let Ok(path) = key.get_value::<String, _>("rootdir")
+			&& !path.is_empty()
+		{
+			return Some(path);
+		}
+	}
+	["C:\\cygwin64", "C:\\cygwin"]
+		.into_iter()
+		.find(|root| Path::new(root).is_dir())
+		.map(str::to_string)
+}
+
+/// Install root from `where git`, trying every hit in order and resolving
+/// scoop shims and `.cmd`/`.bat` wrappers to the `git` they launch. Returns
+/// the first root whose `usr\bin` holds the coreutils, else the first root
+/// found so diagnostics can show why it was rejected.
 fn query_git_install_path_from_where() -> Option<String> {
 	let output = Command::new("where").arg("git").output().ok()?;
 	if !output.status.success() {
@@ -131,14 +733,89 @@ fn query_git_install_path_from_where() -> Option<String> {
 	}
 
 	let stdout = String::from_utf8_lossy(&output.stdout);
-	let line = stdout.lines().next()?.trim();
-	if line.is_empty() {
+	let roots = git_install_roots_from_where_output(&stdout, resolve_git_shim);
+	roots
+		.iter()
+		.find(|root| git_usr_bin_with_ls(root).is_some())
+		.or_else(|| roots.first())
+		.map(|root| root.to_string_lossy().to_string())
+}
+
+/// Candidate install roots for each line of `where git` output, in order,
+/// after passing each hit through `resolve` (shim resolution).
+fn git_install_roots_from_where_output(
+	stdout: &str,
+	resolve: impl Fn(&Path) -> Option<PathBuf>,
+) -> Vec<PathBuf> {
+	let mut roots: Vec<PathBuf> = Vec::new();
+	for line in stdout
+		.lines()
+		.map(str::trim)
+		.filter(|line| !line.is_empty())
+	{
+		let Some(root) = resolve(Path::new(line))
+			.as_deref()
+			.and_then(git_install_root_from_path)
+		else {
+			continue;
+		};
+		if !roots.contains(&root) {
+			roots.push(root);
+		}
+	}
+	roots
+}
+
+/// The `git` a `where` hit actually runs: the target of a scoop shim or a
+/// `.cmd`/`.bat` wrapper, the path itself for a real `git.exe`, or `None`
+/// for shims that can't be followed (e.g. chocolatey's).
+fn resolve_git_shim(git_path: &Path) -> Option<PathBuf> {
+	let dir_name = git_path
+		.parent()?
+		.file_name()?
+		.to_string_lossy()
+		.to_ascii_lowercase();
+	let is_batch = git_path
+		.extension()
+		.is_some_and(|ext| ext.eq_ignore_ascii_case("cmd") || ext.eq_ignore_ascii_case("bat"));
+
+	if dir_name == "shims" {
+		let metadata = std::fs::read_to_string(git_path.with_extension("shim")).ok()?;
+		return scoop_shim_target(&metadata);
+	}
+	if is_batch && dir_name != "cmd" {
+		let script = std::fs::read_to_string(git_path).ok()?;
+		return batch_wrapper_target(&script);
+	}
+	let grandparent = git_path.parent()?.parent().and_then(Path::file_name);
+	if grandparent.is_some_and(|name| name.eq_ignore_ascii_case("chocolatey")) {
 		return None;
 	}
+	Some(git_path.to_path_buf())
+}
 
-	let git_path = Path::new(line);
-	let install_root = git_install_root_from_path(git_path)?;
-	Some(install_root.to_string_lossy().to_string())
+/// Target of a scoop shim from its `.shim` file (`path = "C:\...\git.exe"`).
+fn scoop_shim_target(metadata: &str) -> Option<PathBuf> {
+	metadata.lines().find_map(|line| {
+		let (key, value) = line.split_once('=')?;
+		let value = value.trim().trim_matches('"');
+		(key.trim() == "path" && !value.is_empty()).then(|| PathBuf::from(value))
+	})
+}
+
+/// The absolute path to `git.exe` a batch wrapper launches, if it names one.
+fn batch_wrapper_target(script: &str) -> Option<PathBuf> {
+	script.lines().find_map(|line| {
+		let end = line.to_ascii_lowercase().find("git.exe")? + "git.exe".len();
+		let head = &line[..end];
+		// Quoted (`"C:\Program Files\...\git.exe" %*`) or a bare token.
+		let start = head
+			.rfind('"')
+			.or_else(|| head.rfind(char::is_whitespace))
+			.map_or(0, |idx| idx + 1);
+		let target = Path::new(head[start..].trim_start_matches('@'));
+		target.is_absolute().then(|| target.to_path_buf())
+	})
 }
 
 fn git_install_root_from_path(git_path: &Path) -> Option<PathBuf> {
@@ -166,10 +843,23 @@ fn git_install_root_from_path(git_path: &Path) -> Option<PathBuf> {
 	parent.parent().map(Path::to_path_buf)
 }
 
+/// PATH entries for a Git for Windows install, in priority order: `usr/bin`
+/// first so the MSYS coreutils win name collisions, then `mingw64/bin`
+/// (curl, openssl, ...), then the `git` launchers.
 fn git_paths_for_install_root(install_root: &str) -> Vec<String> {
 	let root = Path::new(install_root);
 	let mut paths = Vec::new();
 
+	let usr_bin = root.join("usr").join("bin");
+	if has_git_command(&usr_bin) || usr_bin.join("ls.exe").is_file() {
+		paths.push(usr_bin.to_string_lossy().to_string());
+	}
+
+	let mingw_bin = root.join("mingw64").join("bin");
+	if mingw_bin.is_dir() {
+		paths.push(mingw_bin.to_string_lossy().to_string());
+	}
+
 	let cmd = root.join("cmd");
 	if has_git_command(&cmd) {
 		paths.push(cmd.to_string_lossy().to_string());
@@ -180,11 +870,6 @@ fn git_paths_for_install_root(install_root: &str) -> Vec<String> {
 		paths.push(bin.to_string_lossy().to_string());
 	}
 
-	let usr_bin = root.join("usr").join("bin");
-	if has_git_command(&usr_bin) || usr_bin.join("ls.exe").is_file() {
-		paths.push(usr_bin.to_string_lossy().to_string());
-	}
-
 	paths
 }
 
@@ -197,3 +882,663 @@ fn has_git_command(dir: &Path) -> bool {
 		.iter()
 		.any(|name| dir.join(name).is_file())
 }
+
+/// Job object holding every process a session spawns, so aborting kills whole
+/// process trees: Windows has no process groups to signal, and grandchildren
+/// (node under npm under a script) would otherwise outlive the abort.
+pub struct ProcessJob {
+	handle:     Handle,
+	/// Processes killed by [`ProcessJob::terminate`] so far.
+	terminated: AtomicU32,
+}
+
+type Handle = *mut std::ffi::c_void;
+
+const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x2000;
+const JOB_OBJECT_BASIC_ACCOUNTING_INFORMATION: i32 = 1;
+const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: i32 = 9;
+const PROCESS_SET_QUOTA: u32 = 0x0100;
+const PROCESS_TERMINATE: u32 = 0x0001;
+
+/// `JOBOBJECT_EXTENDED_LIMIT_INFORMATION`.
+#[repr(C)]
+#[derive(Default)]
+struct JobExtendedLimits {
+	per_process_user_time_limit: i64,
+	per_job_user_time_limit:     i64,
+	limit_flags:                 u32,
+	minimum_working_set_size:    usize,
+	maximum_working_set_size:    usize,
+	active_process_limit:        u32,
+	affinity:                    usize,
+	priority_class:              u32,
+	scheduling_class:            u32,
+	io_counters:                 [u64; 6],
+	process_memory_limit:        usize,
+	job_memory_limit:            usize,
+	peak_process_memory_used:    usize,
+	peak_job_memory_used:        usize,
+}
+
+/// `JOBOBJECT_BASIC_ACCOUNTING_INFORMATION`.
+#[repr(C)]
+#[derive(Default)]
+struct JobAccounting {
+	total_user_time:               i64,
+	total_kernel_time:             i64,
+	this_period_total_user_time:   i64,
+	this_period_total_kernel_time: i64,
+	total_page_fault_count:        u32,
+	total_processes:               u32,
+	active_processes:              u32,
+	total_terminated_processes:    u32,
+}
+
+#[link(name = "kernel32")]
+unsafe extern "system" {
+	fn CreateJobObjectW(attributes: *const std::ffi::c_void, name: *const u16) -> Handle;
+	fn SetInformationJobObject(
+		job: Handle,
+		class: i32,
+		info: *const std::ffi::c_void,
+		len: u32,
+	) -> i32;
+	fn QueryInformationJobObject(
+		job: Handle,
+		class: i32,
+		info: *mut std::ffi::c_void,
+		len: u32,
+		returned: *mut u32,
+	) -> i32;
+	fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+	fn TerminateJobObject(job: Handle, exit_code: u32) -> i32;
+	fn OpenProcess(access: u32, inherit: i32, pid: u32) -> Handle;
+	fn CloseHandle(handle: Handle) -> i32;
+}
+
+// SAFETY: job object handles may be used from any thread.
+unsafe impl Send for ProcessJob {}
+// SAFETY: as above; the job APIs synchronize internally.
+unsafe impl Sync for ProcessJob {}
+
+impl ProcessJob {
+	/// A job whose processes are killed when it is dropped, or `None` if
+	/// Windows refuses to create one.
+	pub fn new() -> Option<Self> {
+		// SAFETY: null attributes and name create an anonymous job.
+		let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+		if handle.is_null() {
+			return None;
+		}
+		let job = Self { handle, terminated: AtomicU32::new(0) };
+		let limits = JobExtendedLimits {
+			limit_flags: JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+			..JobExtendedLimits::default()
+		};
+		// SAFETY: `limits` is a live `JOBOBJECT_EXTENDED_LIMIT_INFORMATION` of
+		// the size passed.
+		let ok = unsafe {
+			SetInformationJobObject(
+				job.handle,
+				JOB_OBJECT_EXTENDED_LIMIT_INFORMATION,
+				(&raw const limits).cast(),
+				size_of::<JobExtendedLimits>() as u32,
+			)
+		};
+		(ok != 0).then_some(job)
+	}
+
+	/// Put a spawned process (and so everything it spawns later) in the job.
+	pub fn assign(&self, pid: u32) -> bool {
+		// SAFETY: OpenProcess accepts any pid and returns null on failure.
+		let process = unsafe { OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, 0, pid) };
+		if process.is_null() {
+			return false;
+		}
+		// SAFETY: both handles are open; the process handle is closed after.
+		unsafe {
+			let assigned = AssignProcessToJobObject(self.handle, process) != 0;
+			CloseHandle(process);
+			assigned
+		}
+	}
+
+	/// Number of processes currently in the job.
+	pub fn active_processes(&self) -> u32 {
+		let mut accounting = JobAccounting::default();
+		// SAFETY: `accounting` is a live `JOBOBJECT_BASIC_ACCOUNTING_INFORMATION`
+		// of the size passed.
+		let ok = unsafe {
+			QueryInformationJobObject(
+				self.handle,
+				JOB_OBJECT_BASIC_ACCOUNTING_INFORMATION,
+				(&raw mut accounting).cast(),
+				size_of::<JobAccounting>() as u32,
+				std::ptr::null_mut(),
+			)
+		};
+		if ok == 0 {
+			0
+		} else {
+			accounting.active_processes
+		}
+	}
+
+	/// Kill every process in the job at once. Returns the number killed by
+	/// all calls so far.
+	pub fn terminate(&self) -> u32 {
+		let active = self.active_processes();
+		// SAFETY: the job handle is open until drop.
+		if active > 0 && unsafe { TerminateJobObject(self.handle, 1) } != 0 {
+			self.terminated.fetch_add(active, Ordering::Relaxed);
+		}
+		self.terminated.load(Ordering::Relaxed)
+	}
+}
+
+impl Drop for ProcessJob {
+	fn drop(&mut self) {
+		// SAFETY: the handle is open and not used after this.
+		unsafe { CloseHandle(self.handle) };
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::HashMap, fs};
+
+	use tokio_util::sync::CancellationToken;
+
+	use super::*;
+	use crate::shell::{ShellConfig, ShellRunConfig, create_session, exit_code, run_shell_command};
+
+	/// Fresh, empty directory for a test.
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = env::temp_dir().join(format!("pi-natives-{name}-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	/// Directory laid out like a tool install, with empty `files`.
+	fn fake_install(name: &str, files: &[&str]) -> PathBuf {
+		let root = temp_dir(name);
+		for file in files {
+			let path = root.join(file);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, b"").unwrap();
+		}
+		root
+	}
+
+	#[test]
+	fn test_git_install_validation() {
+		let root = fake_install("git-validate", &["usr/bin/ls.exe", "cmd/git.exe"]);
+		assert_eq!(git_usr_bin_with_ls(&root), Some(root.join("usr").join("bin")));
+		assert_eq!(git_usr_bin_with_ls(&root.join("cmd")), None);
+
+		// Conventional locations resolve only when the directory exists.
+		let base = Some(root.clone().into_os_string());
+		let found = install_dir_under(base.clone(), &["usr", "bin"]);
+		assert_eq!(found, Some(root.join("usr").join("bin").to_string_lossy().to_string()));
+		assert_eq!(install_dir_under(base, &["apps", "git", "current"]), None);
+		assert_eq!(install_dir_under(None, &["Git"]), None);
+		assert_eq!(install_dir_under(Some(OsString::new()), &["Git"]), None);
+		let _ = fs::remove_dir_all(&root);
+	}
+
+	#[tokio::test]
+	async fn test_unix_tools_discovery_cached() {
+		invalidate_unix_tools_discovery();
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let runs = DISCOVERY_RUNS.load(Ordering::Relaxed);
+		create_session(&config).await.unwrap();
+		// A second session reuses the result instead of probing again.
+		create_session(&config).await.unwrap();
+		assert_eq!(DISCOVERY_RUNS.load(Ordering::Relaxed), runs + 1);
+
+		let first = cached_unix_tools_discovery(&DEFAULT_PROVIDERS);
+		assert!(Arc::ptr_eq(&first, &cached_unix_tools_discovery(&DEFAULT_PROVIDERS)));
+		invalidate_unix_tools_discovery();
+		let second = cached_unix_tools_discovery(&DEFAULT_PROVIDERS);
+		assert!(!Arc::ptr_eq(&first, &second));
+		// Another provider order probes again, without evicting the first.
+		let runs = DISCOVERY_RUNS.load(Ordering::Relaxed);
+		let cygwin = cached_unix_tools_discovery(&[UnixProvider::Cygwin]);
+		assert!(!Arc::ptr_eq(&second, &cygwin));
+		assert!(Arc::ptr_eq(&second, &cached_unix_tools_discovery(&DEFAULT_PROVIDERS)));
+		assert!(Arc::ptr_eq(&cygwin, &cached_unix_tools_discovery(&[UnixProvider::Cygwin])));
+		assert_eq!(DISCOVERY_RUNS.load(Ordering::Relaxed), runs + 1);
+
+		// A discovery whose directories vanished is stale.
+		let root = fake_install("git-stale", &["usr/bin/ls.exe"]);
+		let stale = UnixToolsDiscovery {
+			paths: git_paths_for_install_root(&root.to_string_lossy()),
+			providers: DEFAULT_PROVIDERS.to_vec(),
+			..UnixToolsDiscovery::default()
+		};
+		assert!(stale.is_current(&DEFAULT_PROVIDERS));
+		let _ = fs::remove_dir_all(&root);
+		assert!(!stale.is_current(&DEFAULT_PROVIDERS));
+	}
+
+	#[test]
+	fn test_git_paths_order() {
+		let root = fake_install("git-order", &[
+			"usr/bin/ls.exe",
+			"mingw64/bin/curl.exe",
+			"cmd/git.exe",
+			"bin/git.exe",
+		]);
+		let paths = git_paths_for_install_root(&root.to_string_lossy());
+		let expected: Vec<String> = ["usr/bin", "mingw64/bin", "cmd", "bin"]
+			.iter()
+			.map(|dir| root.join(dir).to_string_lossy().to_string())
+			.collect();
+		assert_eq!(paths, expected);
+
+		// Entries already on PATH are not appended again.
+		let appended = append_path_entries(&expected[2], &paths);
+		let entries: Vec<PathBuf> = env::split_paths(&appended).collect();
+		assert_eq!(entries.len(), 4);
+		assert_eq!(entries[0], root.join("cmd"));
+		assert_eq!(entries[1], root.join("usr/bin"));
+		assert_eq!(entries[2], root.join("mingw64/bin"));
+
+		// Missing directories are skipped.
+		let _ = fs::remove_dir_all(root.join("mingw64"));
+		assert!(!append_path_entries("", &paths).contains("mingw64"));
+		let _ = fs::remove_dir_all(&root);
+	}
+
+	#[test]
+	fn test_path_augmentation_option() {
+		assert!(matches!(
+			PathAugmentation::from_option(None),
+			Ok((PathAugmentation::Auto(providers), None)) if providers == DEFAULT_PROVIDERS
+		));
+		assert!(matches!(
+			PathAugmentation::from_option(Some(Either::A("off".to_owned()))),
+			Ok((PathAugmentation::Off, None))
+		));
+		assert!(PathAugmentation::from_option(Some(Either::A("never".to_owned()))).is_err());
+		let providers = Some(vec!["msys2".to_owned(), "git".to_owned()]);
+		let option = WindowsPathOverride { unix_tools: None, providers };
+		assert!(matches!(
+			PathAugmentation::from_option(Some(Either::B(option))),
+			Ok((PathAugmentation::Auto(order), None))
+				if order == [UnixProvider::Msys2, UnixProvider::Git]
+		));
+		let providers = Some(vec!["wsl".to_owned()]);
+		let option = WindowsPathOverride { unix_tools: None, providers };
+		assert!(PathAugmentation::from_option(Some(Either::B(option))).is_err());
+
+		// A directory replaces discovery, so it can't come with an order.
+		let option = WindowsPathOverride {
+			unix_tools: Some(env::temp_dir().to_string_lossy().into_owned()),
+			providers:  Some(vec!["git".to_owned()]),
+		};
+		assert!(PathAugmentation::from_option(Some(Either::B(option))).is_err());
+	}
+
+	#[test]
+	fn test_pathext_resolves_cmd_shims() {
+		let dir = temp_dir("pathext");
+		for file in ["mytool.cmd", "tool.v2.bat", "plain"] {
+			fs::write(dir.join(file), b"").unwrap();
+		}
+		let search = |name: &str| {
+			brush_core::pathsearch::search_for_executable(
+				std::iter::once(dir.to_string_lossy().into_owned()),
+				name,
+			)
+			.next()
+		};
+		assert_eq!(search("mytool"), Some(dir.join("mytool.cmd")));
+		assert_eq!(search("mytool.cmd"), Some(dir.join("mytool.cmd")));
+		assert_eq!(search("tool.v2"), Some(dir.join("tool.v2.bat")));
+		assert_eq!(search("plain"), None);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn test_unix_provider_layouts() {
+		let msys2 = fake_install("msys2", &["usr/bin/ls.exe", "mingw64/bin/gcc.exe"]);
+		let cygwin = fake_install("cygwin", &["bin/ls.exe"]);
+		let msys2_bin = msys2.join("usr").join("bin");
+		assert_eq!(UnixProvider::Msys2.tools_dir(&msys2), Some(msys2_bin.clone()));
+		assert_eq!(UnixProvider::Cygwin.tools_dir(&cygwin), Some(cygwin.join("bin")));
+		assert_eq!(UnixProvider::Cygwin.tools_dir(&msys2), None);
+		// MSYS2 contributes only its `usr\bin`, never a toolchain directory.
+		assert_eq!(UnixProvider::Msys2.paths_for_install_root(&msys2.to_string_lossy()), [msys2_bin
+			.to_string_lossy()
+			.to_string()]);
+		assert_eq!(UnixToolsSource::Cygwin.provider(), UnixProvider::Cygwin);
+		assert_eq!(UnixToolsSource::Scoop.provider(), UnixProvider::Git);
+		let _ = fs::remove_dir_all(&msys2);
+		let _ = fs::remove_dir_all(&cygwin);
+	}
+
+	#[test]
+	fn test_unix_tools_dir_validation() {
+		let root = fake_install("unix-tools-validate", &[
+			"busybox/ls.exe",
+			"git/usr/bin/ls.exe",
+			"git/usr/bin/msys-2.0.dll",
+			"git/cmd/git.exe",
+			"msys64/usr/bin/ls.exe",
+			"msys64/usr/bin/msys-2.0.dll",
+			"cygwin64/bin/ls.exe",
+			"cygwin64/bin/cygwin1.dll",
+			"empty/cat.exe",
+		]);
+		let provider = |dir: &str| {
+			let dir = root.join(dir).to_string_lossy().into_owned();
+			validate_unix_tools_dir(&dir, "unixTools").map(|tools| tools.provider)
+		};
+		assert_eq!(provider("busybox"), Ok(None));
+		assert_eq!(provider("git/usr/bin"), Ok(Some(UnixProvider::Git)));
+		assert_eq!(provider("msys64/usr/bin"), Ok(Some(UnixProvider::Msys2)));
+		assert_eq!(provider("cygwin64/bin"), Ok(Some(UnixProvider::Cygwin)));
+		let empty = root.join("empty").to_string_lossy().into_owned();
+		assert_eq!(
+			validate_unix_tools_dir(&empty, UNIX_TOOLS_ENV),
+			Err(format!("PI_SHELL_UNIX_TOOLS ignored: {empty} does not contain ls.exe"))
+		);
+		let _ = fs::remove_dir_all(&root);
+	}
+
+	#[tokio::test]
+	async fn test_unix_tools_override() {
+		let root = fake_install("unix-tools", &["tools/ls.exe", "empty/cat.exe"]);
+		let tools = root.join("tools").to_string_lossy().into_owned();
+		let empty = root.join("empty").to_string_lossy().into_owned();
+		let option = |dir: &str| {
+			Some(Either::B(WindowsPathOverride { unix_tools: Some(dir.to_owned()), providers: None }))
+		};
+
+		// A valid override is used verbatim; an invalid one is only a warning,
+		// kept with the shell that set it.
+		let config = ShellConfig::new(None, None, option(&tools), None).unwrap();
+		assert_eq!(config.path_warnings, Vec::<String>::new());
+		let session = create_session(&config).await.unwrap();
+		let path = session
+			.shell
+			.env
+			.get("PATH")
+			.and_then(|(_, var)| match var.value() {
+				ShellValue::String(value) => Some(value.clone()),
+				_ => None,
+			})
+			.unwrap_or_default();
+		assert!(env::split_paths(&path).any(|entry| entry == Path::new(&tools)));
+		let config = ShellConfig::new(None, None, option(&empty), None).unwrap();
+		assert!(matches!(config.path_augmentation, PathAugmentation::Auto(_)));
+		assert_eq!(config.path_warnings, [format!(
+			"windowsPathAugmentation.unixTools ignored: {empty} does not contain ls.exe"
+		)]);
+		assert!(create_session(&config).await.is_ok());
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		assert_eq!(config.path_warnings, Vec::<String>::new());
+		let _ = fs::remove_dir_all(&root);
+	}
+
+	#[test]
+	fn test_extended_length_paths() {
+		let short = Path::new("C:/work/./repo/../project");
+		assert_eq!(extended_length_path(short), Path::new("C:\\work\\project"));
+		assert_eq!(extended_length_path(Path::new("src/lib")), Path::new("src/lib"));
+
+		let long_dir = format!("C:\\{}", ["segment"; 40].join("\\"));
+		let long = extended_length_path(Path::new(&long_dir));
+		assert_eq!(long, Path::new(&format!("\\\\?\\{long_dir}")));
+		assert_eq!(strip_extended_length_prefix(&long.to_string_lossy()), long_dir);
+
+		let share = format!("\\\\server\\share\\{}", ["segment"; 40].join("\\"));
+		let unc = extended_length_path(Path::new(&share))
+			.to_string_lossy()
+			.into_owned();
+		assert!(unc.starts_with("\\\\?\\UNC\\server\\share\\"));
+		assert_eq!(strip_extended_length_prefix(&unc), share);
+	}
+
+	#[tokio::test]
+	async fn test_long_cwd() {
+		let root = env::temp_dir().join(format!("pi-natives-long-{}", std::process::id()));
+		let mut dir = root.clone();
+		while dir.as_os_str().len() <= 300 {
+			dir.push("a-directory-name-of-some-length");
+		}
+		let long = extended_length_path(&dir);
+		fs::create_dir_all(&long).unwrap();
+		fs::write(long.join("marker.txt"), b"").unwrap();
+
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		let expected = to_shell_path(&dir).replace('\'', "'\\''");
+		let options = ShellRunConfig {
+			command: format!("[ \"$PWD\" = '{expected}' ] && ls marker.txt"),
+			cwd: Some(dir.to_string_lossy().into_owned()),
+			..Default::default()
+		};
+		let result =
+			run_shell_command(&mut session, &options, None, CancellationToken::new(), &Arc::default())
+				.await
+				.unwrap();
+		assert_eq!(exit_code(&result), 0);
+		let _ = fs::remove_dir_all(extended_length_path(&root));
+	}
+
+	#[test]
+	fn test_shell_path_form() {
+		assert_eq!(to_shell_path(Path::new("c:\\Users\\me\\project\\")), "C:/Users/me/project");
+		assert_eq!(to_shell_path(Path::new("C:\\")), "C:/");
+		assert_eq!(to_shell_path(Path::new("\\\\?\\UNC\\server\\share\\x")), "//server/share/x");
+		assert_eq!(to_native_path(Path::new("C:/Users/me")), "C:\\Users\\me");
+		assert_eq!(shell_env_value("HOME", "c:\\Users\\me".to_owned()), "C:/Users/me");
+		assert_eq!(shell_env_value("APPDATA", "C:\\x".to_owned()), "C:\\x");
+	}
+
+	#[tokio::test]
+	async fn test_cwd_round_trip() {
+		let dir = temp_dir("cwd-round-trip");
+		fs::create_dir(dir.join("sub")).unwrap();
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		let expected = to_shell_path(&dir).replace('\'', "'\\''");
+		// A lowercase drive letter and a trailing backslash, as JS callers pass.
+		let mut native = dir.to_string_lossy().into_owned();
+		native[..1].make_ascii_lowercase();
+		let options = ShellRunConfig {
+			command: format!(
+				"[ \"$PWD\" = '{expected}' ] && cd \"$PWD\" && [ \"$(pwd)\" = \"$PWD\" ] && cd sub && \
+				 [ \"$(pwd)\" = '{expected}/sub' ]"
+			),
+			cwd: Some(format!("{native}\\")),
+			..Default::default()
+		};
+		let result =
+			run_shell_command(&mut session, &options, None, CancellationToken::new(), &Arc::default())
+				.await
+				.unwrap();
+		assert_eq!(exit_code(&result), 0);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn test_where_git_parsing() {
+		let stdout = concat!(
+			"C:\\Users\\me\\scoop\\shims\\git.exe\r\n",
+			"\r\n",
+			"C:\\Program Files\\Git\\cmd\\git.exe\r\n",
+			"C:\\Program Files\\Git\\mingw64\\bin\\git.exe\r\n",
+		);
+		let scoop = PathBuf::from("C:\\Users\\me\\scoop\\apps\\git\\current\\cmd\\git.exe");
+		let resolve = |path: &Path| {
+			if path.parent()?.ends_with("shims") {
+				Some(scoop.clone())
+			} else {
+				Some(path.to_path_buf())
+			}
+		};
+		// Every hit is considered, shims resolve to their target, and hits in the
+		// same install collapse to one root.
+		assert_eq!(git_install_roots_from_where_output(stdout, resolve), [
+			PathBuf::from("C:\\Users\\me\\scoop\\apps\\git\\current"),
+			PathBuf::from("C:\\Program Files\\Git"),
+		]);
+		assert_eq!(git_install_roots_from_where_output("\r\n", |_| None), Vec::<PathBuf>::new());
+
+		let shim = "path = \"C:\\Users\\me\\scoop\\apps\\git\\current\\cmd\\git.exe\"\r\nargs =\r\n";
+		assert_eq!(scoop_shim_target(shim), Some(scoop.clone()));
+		assert_eq!(scoop_shim_target("args = --version"), None);
+
+		let wrapper = "@echo off\r\n\"C:\\Program Files\\Git\\cmd\\git.exe\" %*\r\n";
+		let git_exe = PathBuf::from("C:\\Program Files\\Git\\cmd\\git.exe");
+		assert_eq!(batch_wrapper_target(wrapper), Some(git_exe));
+		assert_eq!(
+			batch_wrapper_target("@D:\\Tools\\Git\\bin\\git.exe %*"),
+			Some(PathBuf::from("D:\\Tools\\Git\\bin\\git.exe"))
+		);
+		assert_eq!(batch_wrapper_target("@echo off\r\ngit.exe %*"), None);
+	}
+
+	#[test]
+	fn test_resolve_git_shims() {
+		let root = fake_install("where-shims", &["Git/usr/bin/ls.exe", "Git/cmd/git.exe"]);
+		let git_exe = root.join("Git").join("cmd").join("git.exe");
+		let shims = root.join("scoop").join("shims");
+		fs::create_dir_all(&shims).unwrap();
+		fs::write(shims.join("git.shim"), format!("path = \"{}\"", git_exe.display())).unwrap();
+		fs::write(root.join("git.cmd"), format!("@\"{}\" %*", git_exe.display())).unwrap();
+
+		assert_eq!(resolve_git_shim(&shims.join("git.exe")), Some(git_exe.clone()));
+		assert_eq!(resolve_git_shim(&root.join("git.cmd")), Some(git_exe.clone()));
+		assert_eq!(resolve_git_shim(&git_exe), Some(git_exe));
+		let choco = Path::new("C:\\ProgramData\\chocolatey\\bin\\git.exe");
+		assert_eq!(resolve_git_shim(choco), None);
+		let _ = fs::remove_dir_all(&root);
+	}
+
+	#[tokio::test]
+	async fn test_drive_relative_paths() {
+		let dir = temp_dir("drive-relative");
+		fs::create_dir(dir.join("sub")).unwrap();
+		fs::write(dir.join("sub").join("marker.txt"), b"").unwrap();
+		let config = ShellConfig::new(None, None, None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+		set_working_dir(&mut session.shell, &dir.to_string_lossy()).unwrap();
+
+		// `C:marker.txt` resolves against the working directory on its own drive.
+		let drive = dir.to_string_lossy()[..2].to_ascii_lowercase();
+		let relative = session
+			.shell
+			.absolute_path(format!("{drive}sub\\marker.txt"));
+		assert_eq!(relative, dir.join("sub").join("marker.txt"));
+
+		// With a second drive (`subst`), `X:` switches drives and back again
+		// lands in the last directory used on each.
+		let free = ('P'..='Z').find(|letter| !Path::new(&format!("{letter}:\\")).exists());
+		let Some(letter) = free else {
+			return;
+		};
+		let sub = dir.join("sub");
+		let mapped = Command::new("subst")
+			.arg(format!("{letter}:"))
+			.arg(&sub)
+			.status();
+		if !mapped.is_ok_and(|status| status.success()) {
+			return;
+		}
+		let expected = to_shell_path(&dir).replace('\'', "'\\''");
+		let options = ShellRunConfig {
+			command: format!(
+				"{letter}: && [ \"$PWD\" = '{letter}:/' ] && [ -f marker.txt ] && {drive} && [ \
+				 \"$PWD\" = '{expected}' ] && [ -f {letter}:marker.txt ]"
+			),
+			..Default::default()
+		};
+		let result =
+			run_shell_command(&mut session, &options, None, CancellationToken::new(), &Arc::default())
+				.await;
+		let _ = Command::new("subst")
+			.arg(format!("{letter}:"))
+			.arg("/d")
+			.status();
+		assert_eq!(exit_code(&result.unwrap()), 0);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn test_process_job_kills_tree() {
+		let job = ProcessJob::new().unwrap();
+		let mut child = Command::new("cmd")
+			.args(["/d", "/c", "ping -n 30 127.0.0.1 >nul"])
+			.spawn()
+			.unwrap();
+		assert!(job.assign(child.id()));
+		// Let cmd start ping, which inherits the job.
+		std::thread::sleep(std::time::Duration::from_millis(500));
+
+		let terminated = job.terminate();
+		assert!(terminated >= 2, "cmd and ping should both die, got {terminated}");
+		let status = child.wait().unwrap();
+		assert!(!status.success());
+		// Nothing left to kill; the running total is unchanged.
+		assert_eq!(job.terminate(), terminated);
+	}
+
+	#[tokio::test]
+	async fn test_crlf_snapshot() {
+		let dir = temp_dir("crlf-snapshot");
+		let snapshot = dir.join("snapshot.sh");
+		fs::write(&snapshot, "export FOO=bar\r\nbaz() {\r\n\techo qux\r\n}\r\n").unwrap();
+		let config =
+			ShellConfig::new(None, Some(snapshot.to_string_lossy().into_owned()), None, None).unwrap();
+		let mut session = create_session(&config).await.unwrap();
+
+		let foo = session
+			.shell
+			.env
+			.get_str("FOO", &session.shell)
+			.map(|value| value.into_owned());
+		assert_eq!(foo.as_deref(), Some("bar"));
+		let options =
+			ShellRunConfig { command: "[ \"$(baz)\" = qux ]".to_owned(), ..Default::default() };
+		let result =
+			run_shell_command(&mut session, &options, None, CancellationToken::new(), &Arc::default())
+				.await;
+		assert_eq!(exit_code(&result.unwrap()), 0);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[tokio::test]
+	async fn test_utf8_env() {
+		let locale = HashMap::from([("LANG".to_owned(), "de_DE.UTF-8".to_owned())]);
+		let config = ShellConfig::new(Some(locale), None, None, None).unwrap();
+		let session = create_session(&config).await.unwrap();
+		let value = |key: &str| {
+			session
+				.shell
+				.env
+				.get_str(key, &session.shell)
+				.map(|value| value.into_owned())
+		};
+		assert_eq!(value("PYTHONIOENCODING").as_deref(), Some("utf-8"));
+		if std::env::var_os("LC_ALL").is_none() {
+			assert_eq!(value("LANG").as_deref(), Some("de_DE.UTF-8"));
+		}
+
+		let off = ShellConfig::new(None, None, None, Some(false)).unwrap();
+		let mut session = create_session(&off).await.unwrap();
+		let _ = session.shell.env.unset("LC_ALL");
+		let _ = session.shell.env.unset("LANG");
+		set_utf8_env(&mut session.shell).unwrap();
+		let lang = session
+			.shell
+			.env
+			.get_str("LANG", &session.shell)
+			.map(|value| value.into_owned());
+		assert_eq!(lang.as_deref(), Some("C.UTF-8"));
+	}
+}
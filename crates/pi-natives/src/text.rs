@@ -7,17 +7,29 @@
 //! - Width checks early-exit
 //! - Ellipsis decoded lazily
 //! - truncateToWidth returns the original `JsString` when possible
+//! - UTF-8 Buffer variants share the same scanners through `CodeUnit`
 
-use std::cell::RefCell;
+use std::{
+	borrow::Cow,
+	cell::RefCell,
+	collections::{HashMap, VecDeque},
+	ops::Range,
+	sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 use napi::{JsString, bindgen_prelude::*};
 use napi_derive::napi;
+use parking_lot::RwLock;
 use smallvec::{SmallVec, smallvec};
 use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const TAB_WIDTH: usize = 3;
 const ESC: u16 = 0x1b;
+const CR: u16 = 0x0d;
+const BS: u16 = 0x08;
+/// Zero-width joiner.
+const ZWJ: char = '\u{200D}';
 
 fn build_utf16_string(mut data: Vec<u16>) -> Utf16String {
 	while data.last() == Some(&0) {
@@ -32,25 +44,67 @@ fn build_utf16_string(mut data: Vec<u16>) -> Utf16String {
 // ============================================================================
 
 #[napi(object)]
-pub struct SliceResult {
-	/// UTF-16 slice containing the selected text.
-	pub text:  Utf16String,
+pub struct SliceResult<'a> {
+	/// UTF-16 slice containing the selected text; the input itself when a
+	/// slice from column 0 is wider than the line.
+	pub text:             Either<JsString<'a>, Utf16String>,
 	/// Visible width of the slice in terminal cells.
-	pub width: u32,
+	pub width:            u32,
+	/// SGR restore sequence for the state active at the end of the slice
+	/// (only when requested; empty when no style is active).
+	#[napi(js_name = "carriedStyle")]
+	pub carried_style:    Option<Utf16String>,
+	/// A wide grapheme straddling the start column was excluded.
+	#[napi(js_name = "clippedAtStart")]
+	pub clipped_at_start: bool,
+	/// A wide grapheme straddling the end column was excluded (strict mode).
+	#[napi(js_name = "clippedAtEnd")]
+	pub clipped_at_end:   bool,
+}
+
+#[napi(object)]
+pub struct TruncateResult<'a> {
+	/// Truncated (and optionally padded) text; the input itself when
+	/// unchanged.
+	pub text:          Either<JsString<'a>, Utf16String>,
+	/// Visible width of `text`, including the ellipsis and padding.
+	pub width:         u32,
+	/// Content was dropped.
+	pub truncated:     bool,
+	/// SGR restore sequence for the state active at the end of the kept text
+	/// (only when requested; empty when no style is active).
+	#[napi(js_name = "carriedStyle")]
+	pub carried_style: Option<Utf16String>,
 }
 
 #[napi(object)]
 pub struct ExtractSegmentsResult {
 	/// UTF-16 content before the overlay region.
-	pub before:       Utf16String,
+	pub before:                  Utf16String,
 	#[napi(js_name = "beforeWidth")]
 	/// Visible width of the `before` segment.
-	pub before_width: u32,
+	pub before_width:            u32,
 	/// UTF-16 content after the overlay region.
-	pub after:        Utf16String,
+	pub after:                   Utf16String,
 	#[napi(js_name = "afterWidth")]
 	/// Visible width of the `after` segment.
-	pub after_width:  u32,
+	pub after_width:             u32,
+	/// A wide grapheme straddling the start of the `before` segment was
+	/// excluded.
+	#[napi(js_name = "beforeClippedAtStart")]
+	pub before_clipped_at_start: bool,
+	/// A wide grapheme straddling the end of the `before` segment was excluded
+	/// (`strictBefore`).
+	#[napi(js_name = "beforeClippedAtEnd")]
+	pub before_clipped_at_end:   bool,
+	/// A wide grapheme straddling the start of the `after` segment was
+	/// excluded.
+	#[napi(js_name = "afterClippedAtStart")]
+	pub after_clipped_at_start:  bool,
+	/// A wide grapheme straddling the end of the `after` segment was excluded
+	/// (strict mode).
+	#[napi(js_name = "afterClippedAtEnd")]
+	pub after_clipped_at_end:    bool,
 }
 
 #[inline]
@@ -58,6 +112,162 @@ fn clamp_u32(x: usize) -> u32 {
 	x.min(u32::MAX as usize) as u32
 }
 
+// ============================================================================
+// Width Options
+// ============================================================================
+
+const MAX_TAB_WIDTH: u32 = 16;
+
+#[napi(object)]
+#[derive(Default)]
+pub struct WidthOptions {
+	/// Cells per tab character (1-16, default 3).
+	#[napi(js_name = "tabWidth")]
+	pub tab_width:            Option<u32>,
+	/// Advance tabs to the next multiple of `tabWidth` instead of a fixed
+	/// width.
+	#[napi(js_name = "accurateTabs")]
+	pub accurate_tabs:        Option<bool>,
+	/// Emoji width mode: "presentation" (default) measures emoji presentation
+	/// sequences (VS16) at 2 cells; "spec" sums per-codepoint widths.
+	#[napi(js_name = "emojiWidth")]
+	pub emoji_width:          Option<String>,
+	/// Carriage return / backspace handling: "zero" (default) measures them
+	/// as 0 cells, "interpret" renders the line first (`\r` returns to
+	/// column 0, `\b` steps back one column), "replace" shows them as 1-cell
+	/// placeholders (`␍`, `␈`).
+	#[napi(js_name = "controlChars")]
+	pub control_chars:        Option<String>,
+	/// Cells taken by an unpaired surrogate, which is otherwise measured as
+	/// U+FFFD (0 or 1, default 1). Also applies to invalid UTF-8 sequences in
+	/// the Buffer variants.
+	#[napi(js_name = "loneSurrogateWidth")]
+	pub lone_surrogate_width: Option<u32>,
+	/// Screen column the text starts at, e.g. for the continuation of a
+	/// wrapped line; seeds tab stops with `accurateTabs` (default 0).
+	#[napi(js_name = "startColumn")]
+	pub start_column:         Option<u32>,
+}
+
+fn check_tab_width(value: u32, name: &str) -> Result<usize> {
+	if !(1..=MAX_TAB_WIDTH).contains(&value) {
+		return Err(Error::from_reason(format!("{name} must be between 1 and {MAX_TAB_WIDTH}")));
+	}
+	Ok(value as usize)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum EmojiWidth {
+	/// Measure as modern terminals render emoji presentation (2 cells).
+	Presentation,
+	/// Sum per-codepoint widths (wcwidth semantics).
+	Spec,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ControlChars {
+	/// `\r` and `\b` are zero-width.
+	Zero,
+	/// Render overstrikes before measuring, like a terminal would.
+	Interpret,
+	/// Substitute visible 1-cell placeholders.
+	Replace,
+}
+
+const INTERPRET_INDEX_ERROR: &str =
+	"controlChars \"interpret\" is not supported by functions that return indices";
+
+/// Resolved width settings threaded through measurement and slicing.
+#[derive(Clone, Copy)]
+struct WidthConfig {
+	tab_width:            usize,
+	accurate_tabs:        bool,
+	emoji_width:          EmojiWidth,
+	control_chars:        ControlChars,
+	lone_surrogate_width: usize,
+	/// Screen column of column 0, for tab stops.
+	tab_origin:           usize,
+}
+
+impl WidthConfig {
+	const DEFAULT: Self = Self {
+		tab_width:            TAB_WIDTH,
+		accurate_tabs:        false,
+		emoji_width:          EmojiWidth::Presentation,
+		control_chars:        ControlChars::Zero,
+		lone_surrogate_width: 1,
+		tab_origin:           0,
+	};
+
+	fn from_options(options: Option<&WidthOptions>) -> Result<Self> {
+		let mut cfg = Self::DEFAULT;
+		let Some(options) = options else {
+			return Ok(cfg);
+		};
+		if let Some(tab_width) = options.tab_width {
+			cfg.tab_width = check_tab_width(tab_width, "tabWidth")?;
+		}
+		cfg.accurate_tabs = options.accurate_tabs.unwrap_or(false);
+		cfg.emoji_width = match options.emoji_width.as_deref() {
+			None | Some("presentation") => EmojiWidth::Presentation,
+			Some("spec") => EmojiWidth::Spec,
+			Some(_) => {
+				return Err(Error::from_reason("emojiWidth must be \"presentation\" or \"spec\""));
+			},
+		};
+		cfg.control_chars = match options.control_chars.as_deref() {
+			None | Some("zero") => ControlChars::Zero,
+			Some("interpret") => ControlChars::Interpret,
+			Some("replace") => ControlChars::Replace,
+			Some(_) => {
+				return Err(Error::from_reason(
+					"controlChars must be \"zero\", \"interpret\", or \"replace\"",
+				));
+			},
+		};
+		cfg.lone_surrogate_width = match options.lone_surrogate_width {
+			None => 1,
+			Some(width @ 0..=1) => width as usize,
+			Some(_) => return Err(Error::from_reason("loneSurrogateWidth must be 0 or 1")),
+		};
+		cfg.tab_origin = options.start_column.unwrap_or(0) as usize;
+		Ok(cfg)
+	}
+
+	/// Apply the `controlChars` policy to input text; borrows when there is
+	/// nothing to do.
+	fn prepare_u16(self, data: &[u16]) -> Cow<'_, [u16]> {
+		if !data.iter().any(|&u| u == CR || u == BS) {
+			return Cow::Borrowed(data);
+		}
+		match self.control_chars {
+			ControlChars::Zero => Cow::Borrowed(data),
+			ControlChars::Interpret => Cow::Owned(render_control_chars_u16(data, self)),
+			ControlChars::Replace => Cow::Owned(replace_control_chars_u16(data)),
+		}
+	}
+
+	/// [`Self::prepare_u16`] for functions that report UTF-16 indices, which
+	/// must point into the caller's string. `"interpret"` rewrites the text, so
+	/// it is rejected; `"replace"` keeps every index in place.
+	fn prepare_indexed_u16(self, data: &[u16]) -> Result<Cow<'_, [u16]>> {
+		if self.control_chars == ControlChars::Interpret {
+			return Err(Error::from_reason(INTERPRET_INDEX_ERROR));
+		}
+		Ok(self.prepare_u16(data))
+	}
+
+	/// Cells taken by a tab starting at column `col`.
+	#[inline]
+	const fn tab_cells(self, col: usize) -> usize {
+		if self.accurate_tabs {
+			self.tab_width - (self.tab_origin + col) % self.tab_width
+		} else {
+			self.tab_width
+		}
+	}
+}
+
 // ============================================================================
 // ANSI State Tracking - Zero Allocation
 // ============================================================================
@@ -74,7 +284,7 @@ const ATTR_STRIKE: u16 = 1 << 8;
 type ColorVal = u32;
 const COLOR_NONE: ColorVal = 0;
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 struct AnsiState {
 	attrs: u16,
 	fg:    ColorVal,
@@ -97,7 +307,7 @@ impl AnsiState {
 		*self = Self::new();
 	}
 
-	fn apply_sgr_u16(&mut self, params: &[u16]) {
+	fn apply_sgr<U: CodeUnit>(&mut self, params: &[U]) {
 		if params.is_empty() {
 			self.reset();
 			return;
@@ -105,7 +315,7 @@ impl AnsiState {
 
 		let mut i = 0;
 		while i < params.len() {
-			let (code, next_i) = parse_sgr_num_u16(params, i);
+			let (code, next_i) = parse_sgr_num(params, i);
 			i = next_i;
 
 			match code {
@@ -119,7 +329,8 @@ impl AnsiState {
 				8 => self.attrs |= ATTR_HIDDEN,
 				9 => self.attrs |= ATTR_STRIKE,
 
-				21 => self.attrs &= !ATTR_BOLD,
+				// 21 is double underline on most terminals (ECMA-48) but bold-off
+				// on some, so it is left unmodeled.
 				22 => self.attrs &= !(ATTR_BOLD | ATTR_DIM),
 				23 => self.attrs &= !ATTR_ITALIC,
 				24 => self.attrs &= !ATTR_UNDERLINE,
@@ -136,19 +347,19 @@ impl AnsiState {
 				100..=107 => self.bg = (code - 91) as ColorVal,
 
 				38 | 48 => {
-					let (mode, ni) = parse_sgr_num_u16(params, i);
+					let (mode, ni) = parse_sgr_num(params, i);
 					i = ni;
 
 					let color = match mode {
 						5 => {
-							let (idx, ni) = parse_sgr_num_u16(params, i);
+							let (idx, ni) = parse_sgr_num(params, i);
 							i = ni;
 							0x100 | (idx as ColorVal & 0xff)
 						},
 						2 => {
-							let (r, ni) = parse_sgr_num_u16(params, i);
-							let (g, ni) = parse_sgr_num_u16(params, ni);
-							let (b, ni) = parse_sgr_num_u16(params, ni);
+							let (r, ni) = parse_sgr_num(params, i);
+							let (g, ni) = parse_sgr_num(params, ni);
+							let (b, ni) = parse_sgr_num(params, ni);
 							i = ni;
 							0x1000000
 								| ((r as ColorVal & 0xff) << 16)
@@ -251,22 +462,20 @@ fn write_color_u16(out: &mut Vec<u16>, color: ColorVal, base: u32, first: &mut b
 }
 
 #[inline]
-fn parse_sgr_num_u16(params: &[u16], mut i: usize) -> (u32, usize) {
-	while i < params.len() && params[i] == b';' as u16 {
+fn parse_sgr_num<U: CodeUnit>(params: &[U], mut i: usize) -> (u32, usize) {
+	while i < params.len() && params[i].get() == b';' as u32 {
 		i += 1;
 	}
 
 	let mut val: u32 = 0;
 	while i < params.len() {
-		let b = params[i];
-		if b == b';' as u16 {
+		let b = params[i].get();
+		if b == b';' as u32 {
 			i += 1;
 			break;
 		}
-		if (b'0' as u16..=b'9' as u16).contains(&b) {
-			val = val
-				.saturating_mul(10)
-				.saturating_add((b - b'0' as u16) as u32);
+		if (b'0' as u32..=b'9' as u32).contains(&b) {
+			val = val.saturating_mul(10).saturating_add(b - b'0' as u32);
 		}
 		i += 1;
 	}
@@ -288,23 +497,159 @@ fn write_u32_u16(out: &mut Vec<u16>, mut val: u32) {
 }
 
 // ============================================================================
-// ANSI Sequence Detection - UTF-16
+// ANSI Sequence Detection
 // ============================================================================
 
+/// A code unit of UTF-16 (JS strings) or UTF-8 (Buffers) text.
+///
+/// Escapes and their terminators are ASCII, which both encodings store as a
+/// single unit, so scanning, measuring, and slicing run on either encoding
+/// without transcoding; only grapheme segmentation needs to decode.
+trait CodeUnit: Copy + Eq {
+	const ESC: Self;
+	/// Units packed into a word by [`CodeUnit::pack`].
+	const LANES: usize;
+	/// A word with 1 in every lane.
+	const LANE_ONES: u64;
+	/// A word with the top bit of every lane set.
+	const LANE_HIGH: u64;
+
+	/// Pack `LANES` units into a word, one per lane.
+	fn pack(chunk: &[Self]) -> u64;
+
+	/// The unit's value, for comparing against ASCII.
+	fn get(self) -> u32;
+
+	/// An ASCII byte as a unit.
+	fn ascii(b: u8) -> Self;
+
+	/// Iterate the graphemes of a non-ASCII segment starting at column `col`.
+	///
+	/// Callback returns `true` to continue, `false` to stop early.
+	fn for_each_grapheme<F>(segment: &[Self], col: usize, cfg: WidthConfig, f: F) -> bool
+	where
+		F: FnMut(&[Self], usize) -> bool;
+}
+
+impl CodeUnit for u16 {
+	const ESC: Self = ESC;
+	const LANES: usize = 4;
+	const LANE_HIGH: u64 = 0x8000_8000_8000_8000;
+	const LANE_ONES: u64 = 0x0001_0001_0001_0001;
+
+	#[inline]
+	fn pack(chunk: &[Self]) -> u64 {
+		chunk
+			.iter()
+			.rev()
+			.fold(0, |word, &u| (word << 16) | u as u64)
+	}
+
+	#[inline]
+	fn get(self) -> u32 {
+		self as u32
+	}
+
+	#[inline]
+	fn ascii(b: u8) -> Self {
+		b as Self
+	}
+
+	#[inline]
+	fn for_each_grapheme<F>(segment: &[Self], col: usize, cfg: WidthConfig, f: F) -> bool
+	where
+		F: FnMut(&[Self], usize) -> bool,
+	{
+		for_each_grapheme_u16_slow(segment, col, cfg, f)
+	}
+}
+
+impl CodeUnit for u8 {
+	const ESC: Self = 0x1b;
+	const LANES: usize = 8;
+	const LANE_HIGH: u64 = 0x8080_8080_8080_8080;
+	const LANE_ONES: u64 = 0x0101_0101_0101_0101;
+
+	#[inline]
+	fn pack(chunk: &[Self]) -> u64 {
+		u64::from_le_bytes(chunk.try_into().unwrap())
+	}
+
+	#[inline]
+	fn get(self) -> u32 {
+		self as u32
+	}
+
+	#[inline]
+	fn ascii(b: u8) -> Self {
+		b
+	}
+
+	#[inline]
+	fn for_each_grapheme<F>(segment: &[Self], col: usize, cfg: WidthConfig, f: F) -> bool
+	where
+		F: FnMut(&[Self], usize) -> bool,
+	{
+		for_each_grapheme_utf8_slow(segment, col, cfg, f)
+	}
+}
+
+/// Units in the leading whole words of `data` that are all ASCII and contain
+/// no ESC, i.e. can be skipped when looking for the next escape.
+#[inline]
+fn plain_ascii_prefix<U: CodeUnit>(data: &[U]) -> usize {
+	let mut n = 0usize;
+	for chunk in data.chunks_exact(U::LANES) {
+		let word = U::pack(chunk);
+		if word & !(U::LANE_ONES * 0x7f) != 0 {
+			break;
+		}
+		// Every lane is below 0x80, so a zero lane after the XOR is the only
+		// way to borrow into a lane's top bit.
+		let esc = word ^ (U::LANE_ONES * 0x1b);
+		if esc.wrapping_sub(U::LANE_ONES) & !esc & U::LANE_HIGH != 0 {
+			break;
+		}
+		n += U::LANES;
+	}
+	n
+}
+
+/// Units in the leading whole words of `data` that are all printable ASCII
+/// (`0x20..=0x7e`), each one cell wide.
+#[inline]
+fn printable_ascii_prefix<U: CodeUnit>(data: &[U]) -> usize {
+	let bit7 = U::LANE_ONES * 0x80;
+	let mut n = 0usize;
+	for chunk in data.chunks_exact(U::LANES) {
+		let word = U::pack(chunk);
+		// Lanes below 0x80 can't carry out of the low byte: `+ 0x60` sets bit 7
+		// exactly for lanes >= 0x20, `+ 1` exactly for 0x7f.
+		if word & !(U::LANE_ONES * 0x7f) != 0
+			|| word.wrapping_add(U::LANE_ONES * 0x60) & bit7 != bit7
+			|| word.wrapping_add(U::LANE_ONES) & bit7 != 0
+		{
+			break;
+		}
+		n += U::LANES;
+	}
+	n
+}
+
 #[inline]
-fn ansi_seq_len_u16(data: &[u16], pos: usize) -> Option<usize> {
-	if pos >= data.len() || data[pos] != ESC {
+fn ansi_seq_len<U: CodeUnit>(data: &[U], pos: usize) -> Option<usize> {
+	if pos >= data.len() || data[pos] != U::ESC {
 		return None;
 	}
 	if pos + 1 >= data.len() {
 		return None;
 	}
 
-	match data[pos + 1] {
+	match data[pos + 1].get() {
 		0x5b => {
 			// '[' CSI
 			for (i, b) in data[pos + 2..].iter().enumerate() {
-				if (0x40..=0x7e).contains(b) {
+				if (0x40..=0x7e).contains(&b.get()) {
 					return Some(i + 3);
 				}
 			}
@@ -313,22 +658,55 @@ fn ansi_seq_len_u16(data: &[u16], pos: usize) -> Option<usize> {
 		0x5d => {
 			// ']' OSC
 			for (i, &b) in data[pos + 2..].iter().enumerate() {
-				if b == 0x07 {
+				if b.get() == 0x07 {
 					return Some(i + 3);
 				}
-				if b == ESC && data.get(pos + 2 + i + 1) == Some(&0x5c) {
+				if b == U::ESC && data.get(pos + 2 + i + 1).map(|u| u.get()) == Some(0x5c) {
 					return Some(i + 4);
 				}
 			}
 			None
 		},
+		0x50 | 0x58 | 0x5e | 0x5f => {
+			// 'P' DCS, 'X' SOS, '^' PM, '_' APC: terminated by ST (ESC \)
+			data[pos + 2..]
+				.windows(2)
+				.position(|w| w[0] == U::ESC && w[1].get() == 0x5c)
+				.map(|i| i + 4)
+		},
+		0x20..=0x2f => {
+			// nF: intermediate bytes, then a final byte (e.g. ESC ( B)
+			let rest = &data[pos + 2..];
+			let n = rest
+				.iter()
+				.position(|u| !(0x20..=0x2f).contains(&u.get()))?;
+			(0x30..=0x7e).contains(&rest[n].get()).then_some(n + 3)
+		},
+		// Fp/Fe/Fs: a single final byte (e.g. ESC 7, ESC =, ESC M, ESC c)
+		0x30..=0x7e => Some(2),
 		_ => None,
 	}
 }
 
+/// Whether the escape at `pos`, for which [`ansi_seq_len`] found no complete
+/// sequence, is a valid prefix cut off by the end of `data` (rather than
+/// malformed), so more input could complete it.
+fn ansi_seq_incomplete<U: CodeUnit>(data: &[U], pos: usize) -> bool {
+	if data.get(pos) != Some(&U::ESC) {
+		return false;
+	}
+	match data.get(pos + 1).map(|u| u.get()) {
+		None | Some(0x5b | 0x5d | 0x50 | 0x58 | 0x5e | 0x5f) => true,
+		Some(0x20..=0x2f) => data[pos + 2..]
+			.iter()
+			.all(|u| (0x20..=0x2f).contains(&u.get())),
+		Some(_) => false,
+	}
+}
+
 #[inline]
-fn is_sgr_u16(seq: &[u16]) -> bool {
-	seq.len() >= 3 && seq[1] == b'[' as u16 && *seq.last().unwrap() == b'm' as u16
+fn is_sgr<U: CodeUnit>(seq: &[U]) -> bool {
+	seq.len() >= 3 && seq[1].get() == b'[' as u32 && seq[seq.len() - 1].get() == b'm' as u32
 }
 
 // ============================================================================
@@ -336,39 +714,228 @@ fn is_sgr_u16(seq: &[u16]) -> bool {
 // ============================================================================
 
 #[inline]
-const fn ascii_cell_width_u16(u: u16) -> usize {
-	let b = u as u8;
-	match b {
-		b'\t' => TAB_WIDTH,
+fn ascii_cell_width<U: CodeUnit>(u: U, col: usize, cfg: WidthConfig) -> usize {
+	match u.get() as u8 {
+		b'\t' => cfg.tab_cells(col),
 		0x20..=0x7e => 1,
 		_ => 0,
 	}
 }
 
+/// A width override for a range of code points.
+#[napi(object)]
+pub struct WidthOverride {
+	/// First code point of the range.
+	pub start: u32,
+	/// Last code point of the range (inclusive).
+	pub end:   u32,
+	/// Width in terminal cells (0-2).
+	pub width: u8,
+}
+
+/// Sorted, non-overlapping `(start, end, width)` overrides.
+static WIDTH_OVERRIDES: RwLock<Vec<(u32, u32, u8)>> = RwLock::new(Vec::new());
+/// Set while `WIDTH_OVERRIDES` is non-empty, so the common case skips the
+/// lock.
+static HAS_WIDTH_OVERRIDES: AtomicBool = AtomicBool::new(false);
+/// Bumped whenever `WIDTH_OVERRIDES` changes, invalidating cached segment
+/// layouts.
+static WIDTH_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Validate overrides into the sorted table form.
+fn width_override_table(overrides: &[WidthOverride]) -> Result<Vec<(u32, u32, u8)>> {
+	let mut table: Vec<_> = overrides
+		.iter()
+		.map(|o| (o.start, o.end, o.width))
+		.collect();
+	table.sort_unstable();
+	for &(start, end, width) in &table {
+		if start < 0x80 || start > end || end > char::MAX as u32 {
+			return Err(Error::from_reason(format!(
+				"invalid width override range {start:#x}..={end:#x} (must be non-ASCII code points)"
+			)));
+		}
+		if width > 2 {
+			return Err(Error::from_reason(format!("width override must be 0-2, got {width}")));
+		}
+	}
+	if let Some(pair) = table.windows(2).find(|pair| pair[1].0 <= pair[0].1) {
+		return Err(Error::from_reason(format!(
+			"width override ranges {:#x}..={:#x} and {:#x}..={:#x} overlap",
+			pair[0].0, pair[0].1, pair[1].0, pair[1].1
+		)));
+	}
+	Ok(table)
+}
+
+/// Width `table` (as built by `width_override_table`) gives `c`, if any.
+fn lookup_width_override(table: &[(u32, u32, u8)], c: char) -> Option<usize> {
+	let c = c as u32;
+	let idx = table.partition_point(|&(_, end, _)| end < c);
+	table
+		.get(idx)
+		.filter(|&&(start, ..)| start <= c)
+		.map(|&(.., width)| width as usize)
+}
+
+/// Overridden width of `c`, if any.
+#[inline]
+fn width_override(c: char) -> Option<usize> {
+	if c.is_ascii() || !HAS_WIDTH_OVERRIDES.load(Ordering::Acquire) {
+		return None;
+	}
+	lookup_width_override(&WIDTH_OVERRIDES.read(), c)
+}
+
+/// Replace the process-wide table of code point width overrides.
+///
+/// A grapheme whose first code point falls in a range takes that width in
+/// every measuring, slicing, and truncation function, ahead of the Unicode
+/// tables (e.g. Nerd Font glyphs in the private use area rendered 2 cells
+/// wide). Ranges are inclusive, must not overlap or include ASCII, and widths
+/// are 0-2. Pass an empty list to clear.
+#[napi(js_name = "setWidthOverrides")]
+pub fn set_width_overrides(overrides: Vec<WidthOverride>) -> Result<()> {
+	let table = width_override_table(&overrides)?;
+	let mut current = WIDTH_OVERRIDES.write();
+	HAS_WIDTH_OVERRIDES.store(!table.is_empty(), Ordering::Release);
+	*current = table;
+	WIDTH_GENERATION.fetch_add(1, Ordering::Release);
+	Ok(())
+}
+
 #[inline]
-fn grapheme_width_str(g: &str) -> usize {
+fn grapheme_width_str(g: &str, col: usize, cfg: WidthConfig) -> usize {
 	if g == "\t" {
-		return TAB_WIDTH;
+		return cfg.tab_cells(col);
 	}
 	let mut it = g.chars();
 	let Some(c0) = it.next() else {
 		return 0;
 	};
+	if let Some(width) = width_override(c0) {
+		return width;
+	}
 	if it.next().is_none() {
 		return UnicodeWidthChar::width(c0).unwrap_or(0);
 	}
-	UnicodeWidthStr::width(g)
+	match cfg.emoji_width {
+		// ZWJ sequences and skin-tone modifiers render as one joined glyph.
+		EmojiWidth::Presentation if g.chars().any(is_emoji_joiner) => {
+			UnicodeWidthStr::width(g).min(2)
+		},
+		// VS16 widens only Emoji=Yes bases (those with an emoji presentation
+		// sequence), so `"a\u{FE0F}"` stays 1 cell.
+		EmojiWidth::Presentation => UnicodeWidthStr::width(g),
+		EmojiWidth::Spec => g
+			.chars()
+			.map(|c| UnicodeWidthChar::width(c).unwrap_or(0))
+			.sum(),
+	}
+}
+
+#[inline]
+const fn is_emoji_joiner(c: char) -> bool {
+	matches!(c, ZWJ | '\u{1F3FB}'..='\u{1F3FF}')
+}
+
+/// Longest segment whose grapheme layout is cached.
+const SEGMENT_CACHE_MAX_UNITS: usize = 32;
+/// Segments held by each generation of [`SegmentCache`].
+const SEGMENT_CACHE_CAPACITY: usize = 256;
+
+/// Code units, emoji width mode, and lone surrogate width of a cached
+/// segment.
+type SegmentKey = (SmallVec<[u16; SEGMENT_CACHE_MAX_UNITS]>, EmojiWidth, usize);
+/// `(units, width)` of each grapheme in a cached segment.
+type SegmentLayout = SmallVec<[(u8, u8); 8]>;
+
+/// Grapheme layouts of short non-ASCII segments (spinner frames, status
+/// icons, CJK labels), so repeated measurements skip segmentation.
+///
+/// Approximates LRU with two generations: hits in `cold` move to `hot`, and a
+/// full `hot` replaces `cold`, dropping whatever wasn't used since.
+struct SegmentCache {
+	generation: usize,
+	hot:        HashMap<SegmentKey, SegmentLayout>,
+	cold:       HashMap<SegmentKey, SegmentLayout>,
+}
+
+impl SegmentCache {
+	fn new() -> Self {
+		Self { generation: 0, hot: HashMap::new(), cold: HashMap::new() }
+	}
+
+	fn get(&mut self, key: &SegmentKey) -> Option<SegmentLayout> {
+		let generation = WIDTH_GENERATION.load(Ordering::Acquire);
+		if generation != self.generation {
+			self.hot.clear();
+			self.cold.clear();
+			self.generation = generation;
+			return None;
+		}
+		if let Some(layout) = self.hot.get(key) {
+			return Some(layout.clone());
+		}
+		let (key, layout) = self.cold.remove_entry(key)?;
+		self.insert(key, layout.clone());
+		Some(layout)
+	}
+
+	fn insert(&mut self, key: SegmentKey, layout: SegmentLayout) {
+		if self.hot.len() >= SEGMENT_CACHE_CAPACITY {
+			self.cold = std::mem::take(&mut self.hot);
+		}
+		self.hot.insert(key, layout);
+	}
 }
 
 thread_local! {
   static SCRATCH: RefCell<String> = const { RefCell::new(String::new()) };
+  static SEGMENT_CACHE: RefCell<SegmentCache> = RefCell::new(SegmentCache::new());
 }
 
-/// Iterate graphemes in a non-ASCII UTF-16 segment.
+/// Iterate graphemes in a non-ASCII UTF-16 segment starting at column `col`.
 ///
-/// Callback returns `true` to continue, `false` to stop early.
+/// Short segments without tabs (whose widths don't depend on `col`) go
+/// through [`SegmentCache`]. Callback returns `true` to continue, `false` to
+/// stop early.
 #[inline]
-fn for_each_grapheme_u16_slow<F>(segment: &[u16], mut f: F) -> bool
+fn for_each_grapheme_u16_slow<F>(segment: &[u16], col: usize, cfg: WidthConfig, mut f: F) -> bool
+where
+	F: FnMut(&[u16], usize) -> bool,
+{
+	if segment.len() > SEGMENT_CACHE_MAX_UNITS || segment.contains(&(b'\t' as u16)) {
+		return segment_graphemes_u16(segment, col, cfg, f);
+	}
+
+	let key = (SmallVec::from_slice(segment), cfg.emoji_width, cfg.lone_surrogate_width);
+	let layout = SEGMENT_CACHE.with_borrow_mut(|cache| {
+		if let Some(layout) = cache.get(&key) {
+			return layout;
+		}
+		let mut layout = SegmentLayout::new();
+		segment_graphemes_u16(segment, col, cfg, |g, w| {
+			layout.push((g.len() as u8, w as u8));
+			true
+		});
+		cache.insert(key, layout.clone());
+		layout
+	});
+
+	let mut pos = 0usize;
+	for (len, width) in layout {
+		let len = len as usize;
+		if !f(&segment[pos..pos + len], width as usize) {
+			return false;
+		}
+		pos += len;
+	}
+	true
+}
+
+/// Segment `segment` into graphemes and measure them, uncached.
+fn segment_graphemes_u16<F>(segment: &[u16], mut col: usize, cfg: WidthConfig, mut f: F) -> bool
 where
 	F: FnMut(&[u16], usize) -> bool,
 {
@@ -384,14 +951,21 @@ where
 			scratch.push(r.unwrap_or('\u{FFFD}'));
 		}
 
+		// A lone surrogate decodes to one U+FFFD, which is also one UTF-16
+		// unit, so grapheme lengths map straight back onto `segment`.
 		let mut utf16_pos = 0usize;
 		for g in scratch.graphemes(true) {
-			let w = grapheme_width_str(g);
-
 			let g_u16_len: usize = g.chars().map(|c| c.len_utf16()).sum();
 			let u16_slice = &segment[utf16_pos..utf16_pos + g_u16_len];
 			utf16_pos += g_u16_len;
 
+			let w = if g.starts_with('\u{FFFD}') && u16_slice[0] != 0xfffd {
+				cfg.lone_surrogate_width
+			} else {
+				grapheme_width_str(g, col, cfg)
+			};
+			col += w;
+
 			if !f(u16_slice, w) {
 				return false;
 			}
@@ -401,15 +975,62 @@ where
 	})
 }
 
+/// Iterate graphemes in a non-ASCII UTF-8 segment starting at column `col`.
+///
+/// Each invalid byte sequence is one grapheme measured like a lone surrogate
+/// in UTF-16.
+fn for_each_grapheme_utf8_slow<F>(
+	segment: &[u8],
+	mut col: usize,
+	cfg: WidthConfig,
+	mut f: F,
+) -> bool
+where
+	F: FnMut(&[u8], usize) -> bool,
+{
+	let mut pos = 0usize;
+	for chunk in segment.utf8_chunks() {
+		let valid = chunk.valid();
+		for (offset, g) in valid.grapheme_indices(true) {
+			let w = grapheme_width_str(g, col, cfg);
+			col += w;
+			if !f(&segment[pos + offset..pos + offset + g.len()], w) {
+				return false;
+			}
+		}
+		pos += valid.len();
+
+		let invalid = chunk.invalid();
+		if !invalid.is_empty() {
+			let w = cfg.lone_surrogate_width;
+			col += w;
+			if !f(invalid, w) {
+				return false;
+			}
+			pos += invalid.len();
+		}
+	}
+	true
+}
+
+#[inline]
+fn for_each_grapheme_slow<U, F>(segment: &[U], col: usize, cfg: WidthConfig, f: F) -> bool
+where
+	U: CodeUnit,
+	F: FnMut(&[U], usize) -> bool,
+{
+	U::for_each_grapheme(segment, col, cfg, f)
+}
+
 /// Visible width, with early-exit if width exceeds `limit`.
-fn visible_width_u16_up_to(data: &[u16], limit: usize) -> (usize, bool) {
+fn visible_width_up_to<U: CodeUnit>(data: &[U], limit: usize, cfg: WidthConfig) -> (usize, bool) {
 	let mut width = 0usize;
 	let mut i = 0usize;
 	let len = data.len();
 
 	while i < len {
-		if data[i] == ESC {
-			if let Some(seq_len) = ansi_seq_len_u16(data, i) {
+		if data[i] == U::ESC {
+			if let Some(seq_len) = ansi_seq_len(data, i) {
 				i += seq_len;
 				continue;
 			}
@@ -418,9 +1039,10 @@ fn visible_width_u16_up_to(data: &[u16], limit: usize) -> (usize, bool) {
 		}
 
 		let start = i;
+		i += plain_ascii_prefix(&data[i..]);
 		let mut is_ascii = true;
-		while i < len && data[i] != ESC {
-			if data[i] > 0x7f {
+		while i < len && data[i] != U::ESC {
+			if data[i].get() > 0x7f {
 				is_ascii = false;
 			}
 			i += 1;
@@ -428,14 +1050,26 @@ fn visible_width_u16_up_to(data: &[u16], limit: usize) -> (usize, bool) {
 		let seg = &data[start..i];
 
 		if is_ascii {
-			for &u in seg {
-				width += ascii_cell_width_u16(u);
+			let mut k = 0usize;
+			while k < seg.len() {
+				let run = printable_ascii_prefix(&seg[k..]);
+				if run > 0 {
+					// One cell each: the per-unit loop would stop at `limit + 1`.
+					if run > limit - width {
+						return (limit + 1, true);
+					}
+					width += run;
+					k += run;
+					continue;
+				}
+				width += ascii_cell_width(seg[k], width, cfg);
 				if width > limit {
 					return (width, true);
 				}
+				k += 1;
 			}
 		} else {
-			let ok = for_each_grapheme_u16_slow(seg, |_, w| {
+			let ok = for_each_grapheme_slow(seg, width, cfg, |_, w| {
 				width += w;
 				width <= limit
 			});
@@ -448,73 +1082,392 @@ fn visible_width_u16_up_to(data: &[u16], limit: usize) -> (usize, bool) {
 	(width, width > limit)
 }
 
-fn visible_width_u16(data: &[u16]) -> usize {
-	visible_width_u16_up_to(data, usize::MAX).0
+fn visible_width_u16(data: &[u16], cfg: WidthConfig) -> usize {
+	visible_width_up_to(data, usize::MAX, cfg).0
 }
 
-// ============================================================================
-// wrapTextWithAnsi
-// ============================================================================
+/// A visible grapheme within a line.
+#[derive(Clone, Copy)]
+struct GraphemeSpan {
+	/// UTF-16 index of the first code unit.
+	start: usize,
+	/// UTF-16 index just past the last code unit.
+	end:   usize,
+	/// Column the grapheme starts at.
+	col:   usize,
+	/// Width in terminal cells.
+	width: usize,
+}
 
-#[inline]
-fn write_active_codes(state: &AnsiState, out: &mut Vec<u16>) {
-	if !state.is_empty() {
-		state.write_restore_u16(out);
+/// Walk the graphemes of `line` with their UTF-16 spans and columns, skipping
+/// ANSI escapes.
+///
+/// Callback returns `true` to continue, `false` to stop early.
+fn walk_graphemes_u16<F>(line: &[u16], cfg: WidthConfig, mut f: F)
+where
+	F: FnMut(GraphemeSpan) -> bool,
+{
+	let mut col = 0usize;
+	let mut i = 0usize;
+	let len = line.len();
+
+	while i < len {
+		if line[i] == ESC {
+			i += ansi_seq_len(line, i).unwrap_or(1);
+			continue;
+		}
+
+		let start = i;
+		let mut is_ascii = true;
+		while i < len && line[i] != ESC {
+			if line[i] > 0x7f {
+				is_ascii = false;
+			}
+			i += 1;
+		}
+		let seg = &line[start..i];
+
+		if is_ascii {
+			for (k, &u) in seg.iter().enumerate() {
+				let width = ascii_cell_width(u, col, cfg);
+				if !f(GraphemeSpan { start: start + k, end: start + k + 1, col, width }) {
+					return;
+				}
+				col += width;
+			}
+		} else {
+			let mut pos = start;
+			let keep_going = for_each_grapheme_slow(seg, col, cfg, |gu16, width| {
+				let span = GraphemeSpan { start: pos, end: pos + gu16.len(), col, width };
+				pos = span.end;
+				col += width;
+				f(span)
+			});
+			if !keep_going {
+				return;
+			}
+		}
 	}
 }
 
-#[inline]
-fn write_line_end_reset(state: &AnsiState, out: &mut Vec<u16>) {
-	if state.attrs & ATTR_UNDERLINE != 0 {
-		out.extend_from_slice(&[ESC, b'[' as u16, b'2' as u16, b'4' as u16, b'm' as u16]);
+/// End of a zero-width grapheme that continues the grapheme ending at `i`
+/// past one or more escape sequences (a combining mark or conjoining jamo
+/// split from its base by an SGR), so boundaries never separate the two.
+fn continuation_end<U: CodeUnit>(line: &[U], mut i: usize, cfg: WidthConfig) -> Option<usize> {
+	let start = i;
+	while i < line.len() && line[i] == U::ESC {
+		i += ansi_seq_len(line, i)?;
+	}
+	if i == start || i == line.len() || line[i].get() <= 0x7f {
+		return None;
 	}
+	let seg_end = line[i..]
+		.iter()
+		.position(|&u| u == U::ESC)
+		.map_or(line.len(), |p| i + p);
+	let mut end = None;
+	for_each_grapheme_slow(&line[i..seg_end], 0, cfg, |g, w| {
+		end = (w == 0).then_some(i + g.len());
+		false
+	});
+	end
 }
 
-fn update_state_from_text(data: &[u16], state: &mut AnsiState) {
-	let mut i = 0usize;
-	while i < data.len() {
-		if data[i] == ESC
-			&& let Some(seq_len) = ansi_seq_len_u16(data, i)
-		{
-			let seq = &data[i..i + seq_len];
-			if is_sgr_u16(seq) {
-				state.apply_sgr_u16(&seq[2..seq_len - 1]);
-			}
-			i += seq_len;
-			continue;
-		}
-		i += 1;
-	}
+// ============================================================================
+// Control Characters
+// ============================================================================
+
+/// Swap `\r` (except in `\r\n`) and `\b` for Control Pictures.
+fn replace_control_chars_u16(data: &[u16]) -> Vec<u16> {
+	data
+		.iter()
+		.enumerate()
+		.map(|(i, &u)| match u {
+			CR if data.get(i + 1) != Some(&(b'\n' as u16)) => 0x240d, // "␍"
+			BS => 0x2408,                                             // "␈"
+			_ => u,
+		})
+		.collect()
 }
 
-fn token_is_whitespace(token: &[u16]) -> bool {
-	let mut i = 0usize;
-	while i < token.len() {
-		if token[i] == ESC
-			&& let Some(seq_len) = ansi_seq_len_u16(token, i)
-		{
-			i += seq_len;
-			continue;
-		}
-		if token[i] != b' ' as u16 {
-			return false;
+/// One terminal cell of a line being rendered.
+struct RenderCell {
+	/// Grapheme drawn here; empty for the trailing cells of a wide grapheme.
+	text:   Vec<u16>,
+	/// SGR state the cell was drawn in.
+	state:  AnsiState,
+	/// Non-SGR sequences and zero-width text emitted before this cell.
+	prefix: Vec<u16>,
+}
+
+/// Draw a grapheme at `*col`, overwriting what is there.
+fn put_render_cell(
+	cells: &mut Vec<RenderCell>,
+	col: &mut usize,
+	pending: &mut Vec<u16>,
+	g: &[u16],
+	width: usize,
+	state: AnsiState,
+) {
+	if width == 0 {
+		pending.extend_from_slice(g);
+		return;
+	}
+	let blank = |cell: &mut RenderCell| cell.text = vec![b' ' as u16];
+	let start = *col;
+	let end = start + width;
+
+	// Wide graphemes cut by this one leave blanks behind.
+	if start < cells.len() && cells[start].text.is_empty() {
+		let mut k = start;
+		while k > 0 && cells[k].text.is_empty() {
+			k -= 1;
 		}
-		i += 1;
+		cells[k..start].iter_mut().for_each(blank);
+	}
+	let mut k = end;
+	while k < cells.len() && cells[k].text.is_empty() {
+		blank(&mut cells[k]);
+		k += 1;
 	}
-	true
-}
 
-fn trim_end_spaces_in_place(line: &mut Vec<u16>) {
-	while let Some(&last) = line.last() {
-		if last == b' ' as u16 {
-			line.pop();
-		} else {
-			break;
+	let mut prefix = std::mem::take(pending);
+	for k in start..end {
+		let text = if k == start { g.to_vec() } else { Vec::new() };
+		let cell = RenderCell { text, state, prefix: std::mem::take(&mut prefix) };
+		match cells.get_mut(k) {
+			Some(old) => {
+				let mut old_prefix = std::mem::replace(old, cell).prefix;
+				if !old_prefix.is_empty() {
+					old_prefix.append(&mut cells[start].prefix);
+					cells[start].prefix = old_prefix;
+				}
+			},
+			None => cells.push(cell),
 		}
 	}
+	*col = end;
 }
 
-fn split_into_tokens_with_ansi(line: &[u16]) -> SmallVec<[Vec<u16>; 4]> {
+/// Render `\r` and `\b` the way a terminal would: `\r` returns to column 0,
+/// `\b` steps back one column, and later text overwrites earlier cells.
+///
+/// SGR state is tracked per cell and re-emitted minimally; other sequences
+/// are kept before the cell that follows them.
+fn render_control_chars_u16(data: &[u16], cfg: WidthConfig) -> Vec<u16> {
+	let mut out = Vec::with_capacity(data.len());
+	let mut cells: Vec<RenderCell> = Vec::new();
+	let mut pending = Vec::new();
+	let mut col = 0usize;
+	let mut state = AnsiState::new();
+	let mut out_state = AnsiState::new();
+	let mut i = 0usize;
+	let len = data.len();
+
+	let mut flush = |cells: &mut Vec<RenderCell>, pending: &mut Vec<u16>, out: &mut Vec<u16>| {
+		for cell in cells.drain(..) {
+			out.extend_from_slice(&cell.prefix);
+			write_transition_u16(out_state, cell.state, out);
+			out_state = cell.state;
+			out.extend_from_slice(&cell.text);
+		}
+		out.append(pending);
+	};
+
+	while i < len {
+		match data[i] {
+			ESC => {
+				let seq_len = ansi_seq_len(data, i).unwrap_or(1);
+				let seq = &data[i..i + seq_len];
+				if is_sgr(seq) {
+					state.apply_sgr(&seq[2..seq_len - 1]);
+				} else {
+					pending.extend_from_slice(seq);
+				}
+				i += seq_len;
+				continue;
+			},
+			CR => {
+				col = 0;
+				i += 1;
+				continue;
+			},
+			BS => {
+				col = col.saturating_sub(1);
+				i += 1;
+				continue;
+			},
+			0x0a => {
+				flush(&mut cells, &mut pending, &mut out);
+				col = 0;
+				i += 1;
+				out.push(b'\n' as u16);
+				continue;
+			},
+			_ => {},
+		}
+
+		let start = i;
+		let mut is_ascii = true;
+		while i < len && !matches!(data[i], ESC | CR | BS | 0x0a) {
+			if data[i] > 0x7f {
+				is_ascii = false;
+			}
+			i += 1;
+		}
+		let seg = &data[start..i];
+
+		if is_ascii {
+			for k in 0..seg.len() {
+				let width = ascii_cell_width(seg[k], col, cfg);
+				put_render_cell(&mut cells, &mut col, &mut pending, &seg[k..=k], width, state);
+			}
+		} else {
+			for_each_grapheme_slow(seg, col, cfg, |g, width| {
+				put_render_cell(&mut cells, &mut col, &mut pending, g, width, state);
+				true
+			});
+		}
+	}
+	flush(&mut cells, &mut pending, &mut out);
+	write_transition_u16(out_state, state, &mut out);
+
+	out
+}
+
+// ============================================================================
+// wrapTextWithAnsi
+// ============================================================================
+
+#[inline]
+fn write_active_codes(state: &AnsiState, out: &mut Vec<u16>) {
+	if !state.is_empty() {
+		state.write_restore_u16(out);
+	}
+}
+
+#[inline]
+fn write_line_end_reset(state: &AnsiState, out: &mut Vec<u16>) {
+	if state.attrs & ATTR_UNDERLINE != 0 {
+		out.extend_from_slice(&[ESC, b'[' as u16, b'2' as u16, b'4' as u16, b'm' as u16]);
+	}
+}
+
+fn update_state_from_text(data: &[u16], state: &mut AnsiState) {
+	let mut i = 0usize;
+	while i < data.len() {
+		if data[i] == ESC
+			&& let Some(seq_len) = ansi_seq_len(data, i)
+		{
+			let seq = &data[i..i + seq_len];
+			if is_sgr(seq) {
+				state.apply_sgr(&seq[2..seq_len - 1]);
+			}
+			i += seq_len;
+			continue;
+		}
+		i += 1;
+	}
+}
+
+fn token_is_whitespace(token: &[u16]) -> bool {
+	let mut i = 0usize;
+	while i < token.len() {
+		if token[i] == ESC
+			&& let Some(seq_len) = ansi_seq_len(token, i)
+		{
+			i += seq_len;
+			continue;
+		}
+		if token[i] != b' ' as u16 {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
+
+fn trim_end_spaces_in_place(line: &mut Vec<u16>) {
+	while let Some(&last) = line.last() {
+		if last == b' ' as u16 {
+			line.pop();
+		} else {
+			break;
+		}
+	}
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct WrapOptions {
+	/// Characters after which a line may also break, in addition to spaces
+	/// (e.g. `"/-."` for paths and URLs). The character stays on the first
+	/// line.
+	#[napi(js_name = "breakChars")]
+	pub break_chars:         Option<String>,
+	/// Characters a `breakChars` break may not come before (e.g. `"/"`, so
+	/// `https://` never breaks between its slashes).
+	#[napi(js_name = "noBreakChars")]
+	pub no_break_chars:      Option<String>,
+	/// Keep every space: runs are split across lines as needed and count
+	/// toward the width, and lines are not trimmed (default false).
+	#[napi(js_name = "preserveWhitespace")]
+	pub preserve_whitespace: Option<bool>,
+	/// Widen the gaps between words so every wrapped line except the last of
+	/// each paragraph fills the width exactly (default false). Lines with a
+	/// single word are left as they are.
+	pub justify:             Option<bool>,
+	/// Width measurement options.
+	pub width:               Option<WidthOptions>,
+}
+
+/// Break opportunities besides spaces, whitespace handling, and
+/// justification, from [`WrapOptions`].
+#[derive(Default)]
+struct BreakRules {
+	after:               Vec<char>,
+	not_before:          Vec<char>,
+	preserve_whitespace: bool,
+	justify:             bool,
+}
+
+impl BreakRules {
+	fn from_options(options: Option<&WrapOptions>) -> Self {
+		let chars = |s: &Option<String>| s.as_deref().unwrap_or_default().chars().collect();
+		options.map_or_else(Self::default, |options| Self {
+			after:               chars(&options.break_chars),
+			not_before:          chars(&options.no_break_chars),
+			preserve_whitespace: options.preserve_whitespace.unwrap_or(false),
+			justify:             options.justify.unwrap_or(false),
+		})
+	}
+
+	/// Whether a word may break between `ch` and the visible text at `next`
+	/// (escapes skipped): never inside a grapheme cluster.
+	fn breaks_between(&self, ch: char, line: &[u16], mut next: usize) -> bool {
+		if !self.after.contains(&ch) {
+			return false;
+		}
+		while next < line.len() && line[next] == ESC {
+			match ansi_seq_len(line, next) {
+				Some(seq_len) => next += seq_len,
+				None => break,
+			}
+		}
+		let Some(Ok(following)) = char::decode_utf16(line[next..].iter().copied()).next() else {
+			return false;
+		};
+		if following == ' ' || self.not_before.contains(&following) {
+			return false;
+		}
+		let mut pair = String::with_capacity(8);
+		pair.push(ch);
+		pair.push(following);
+		pair.graphemes(true).nth(1).is_some()
+	}
+}
+
+fn split_into_tokens_with_ansi(line: &[u16], breaks: &BreakRules) -> SmallVec<[Vec<u16>; 4]> {
 	let mut tokens = SmallVec::<[Vec<u16>; 4]>::new();
 	let mut current = Vec::<u16>::new();
 	let mut pending_ansi = SmallVec::<[u16; 32]>::new();
@@ -523,7 +1476,7 @@ fn split_into_tokens_with_ansi(line: &[u16]) -> SmallVec<[Vec<u16>; 4]> {
 
 	while i < line.len() {
 		if line[i] == ESC
-			&& let Some(seq_len) = ansi_seq_len_u16(line, i)
+			&& let Some(seq_len) = ansi_seq_len(line, i)
 		{
 			pending_ansi.extend_from_slice(&line[i..i + seq_len]);
 			i += seq_len;
@@ -545,6 +1498,19 @@ fn split_into_tokens_with_ansi(line: &[u16]) -> SmallVec<[Vec<u16>; 4]> {
 		in_whitespace = char_is_space;
 		current.push(ch);
 		i += 1;
+
+		if !breaks.after.is_empty()
+			&& !char_is_space
+			&& let Some(Ok(c)) = char::decode_utf16(line[i - 1..].iter().copied()).next()
+		{
+			if c.len_utf16() == 2 {
+				current.push(line[i]);
+				i += 1;
+			}
+			if breaks.breaks_between(c, line, i) {
+				tokens.push(std::mem::take(&mut current));
+			}
+		}
 	}
 
 	if !pending_ansi.is_empty() {
@@ -558,7 +1524,12 @@ fn split_into_tokens_with_ansi(line: &[u16]) -> SmallVec<[Vec<u16>; 4]> {
 	tokens
 }
 
-fn break_long_word(word: &[u16], width: usize, state: &mut AnsiState) -> SmallVec<[Vec<u16>; 4]> {
+fn break_long_word(
+	word: &[u16],
+	width: usize,
+	state: &mut AnsiState,
+	cfg: WidthConfig,
+) -> SmallVec<[Vec<u16>; 4]> {
 	let mut lines = SmallVec::<[Vec<u16>; 4]>::new();
 	let mut current_line = Vec::<u16>::new();
 	write_active_codes(state, &mut current_line);
@@ -567,12 +1538,12 @@ fn break_long_word(word: &[u16], width: usize, state: &mut AnsiState) -> SmallVe
 
 	while i < word.len() {
 		if word[i] == ESC
-			&& let Some(seq_len) = ansi_seq_len_u16(word, i)
+			&& let Some(seq_len) = ansi_seq_len(word, i)
 		{
 			let seq = &word[i..i + seq_len];
 			current_line.extend_from_slice(seq);
-			if is_sgr_u16(seq) {
-				state.apply_sgr_u16(&seq[2..seq_len - 1]);
+			if is_sgr(seq) {
+				state.apply_sgr(&seq[2..seq_len - 1]);
 			}
 			i += seq_len;
 			continue;
@@ -590,7 +1561,7 @@ fn break_long_word(word: &[u16], width: usize, state: &mut AnsiState) -> SmallVe
 
 		if is_ascii {
 			for &u in seg {
-				let gw = ascii_cell_width_u16(u);
+				let gw = ascii_cell_width(u, current_width, cfg);
 				if current_width + gw > width {
 					write_line_end_reset(state, &mut current_line);
 					lines.push(current_line);
@@ -602,7 +1573,7 @@ fn break_long_word(word: &[u16], width: usize, state: &mut AnsiState) -> SmallVe
 				current_width += gw;
 			}
 		} else {
-			let _ = for_each_grapheme_u16_slow(seg, |gu16, gw| {
+			let _ = for_each_grapheme_slow(seg, current_width, cfg, |gu16, gw| {
 				if current_width + gw > width {
 					write_line_end_reset(state, &mut current_line);
 					lines.push(std::mem::take(&mut current_line));
@@ -623,25 +1594,71 @@ fn break_long_word(word: &[u16], width: usize, state: &mut AnsiState) -> SmallVe
 	lines
 }
 
-fn wrap_single_line(line: &[u16], width: usize) -> SmallVec<[Vec<u16>; 4]> {
+/// Append a whitespace token to the line being wrapped, starting new lines
+/// whenever it is full (`preserveWhitespace`).
+fn fill_whitespace(
+	token: &[u16],
+	width: usize,
+	state: &mut AnsiState,
+	current_line: &mut Vec<u16>,
+	current_width: &mut usize,
+	wrapped: &mut SmallVec<[Vec<u16>; 4]>,
+) {
+	let mut i = 0usize;
+	while i < token.len() {
+		if token[i] == ESC
+			&& let Some(seq_len) = ansi_seq_len(token, i)
+		{
+			let seq = &token[i..i + seq_len];
+			current_line.extend_from_slice(seq);
+			if is_sgr(seq) {
+				state.apply_sgr(&seq[2..seq_len - 1]);
+			}
+			i += seq_len;
+			continue;
+		}
+		if *current_width >= width {
+			write_line_end_reset(state, current_line);
+			wrapped.push(std::mem::take(current_line));
+			write_active_codes(state, current_line);
+			*current_width = 0;
+		}
+		current_line.push(token[i]);
+		*current_width += 1;
+		i += 1;
+	}
+}
+
+fn wrap_single_line(
+	line: &[u16],
+	width: usize,
+	breaks: &BreakRules,
+	cfg: WidthConfig,
+) -> SmallVec<[Vec<u16>; 4]> {
 	if line.is_empty() {
 		return smallvec![Vec::new()];
 	}
 
-	if visible_width_u16(line) <= width {
+	if visible_width_u16(line, cfg) <= width {
 		return smallvec![line.to_vec()];
 	}
 
-	let tokens = split_into_tokens_with_ansi(line);
+	let tokens = split_into_tokens_with_ansi(line, breaks);
 	let mut wrapped = SmallVec::<[Vec<u16>; 4]>::new();
 	let mut current_line = Vec::<u16>::new();
 	let mut current_width = 0usize;
 	let mut state = AnsiState::new();
 
 	for token in tokens {
-		let token_width = visible_width_u16(&token);
+		let token_width = visible_width_u16(&token, cfg);
 		let is_whitespace = token_is_whitespace(&token);
 
+		if is_whitespace && breaks.preserve_whitespace {
+			let (line, cols) = (&mut current_line, &mut current_width);
+			fill_whitespace(&token, width, &mut state, line, cols, &mut wrapped);
+			continue;
+		}
+
 		if token_width > width && !is_whitespace {
 			if !current_line.is_empty() {
 				write_line_end_reset(&state, &mut current_line);
@@ -650,11 +1667,11 @@ fn wrap_single_line(line: &[u16], width: usize) -> SmallVec<[Vec<u16>; 4]> {
 				current_width = 0;
 			}
 
-			let mut broken = break_long_word(&token, width, &mut state);
+			let mut broken = break_long_word(&token, width, &mut state, cfg);
 			if let Some(last) = broken.pop() {
 				wrapped.extend(broken);
 				current_line = last;
-				current_width = visible_width_u16(&current_line);
+				current_width = visible_width_u16(&current_line, cfg);
 			}
 			continue;
 		}
@@ -662,7 +1679,9 @@ fn wrap_single_line(line: &[u16], width: usize) -> SmallVec<[Vec<u16>; 4]> {
 		let total_needed = current_width + token_width;
 		if total_needed > width && current_width > 0 {
 			let mut line_to_wrap = current_line;
-			trim_end_spaces_in_place(&mut line_to_wrap);
+			if !breaks.preserve_whitespace {
+				trim_end_spaces_in_place(&mut line_to_wrap);
+			}
 			write_line_end_reset(&state, &mut line_to_wrap);
 			wrapped.push(line_to_wrap);
 
@@ -686,18 +1705,81 @@ fn wrap_single_line(line: &[u16], width: usize) -> SmallVec<[Vec<u16>; 4]> {
 		wrapped.push(current_line);
 	}
 
-	for line in &mut wrapped {
-		trim_end_spaces_in_place(line);
+	if !breaks.preserve_whitespace {
+		for line in &mut wrapped {
+			trim_end_spaces_in_place(line);
+		}
 	}
 
 	if wrapped.is_empty() {
 		wrapped.push(Vec::new());
 	}
 
+	if breaks.justify {
+		let last = wrapped.len() - 1;
+		for line in &mut wrapped[..last] {
+			justify_line_u16(line, width, cfg);
+		}
+	}
+
 	wrapped
 }
 
-fn wrap_text_with_ansi_impl(text: &[u16], width: usize) -> SmallVec<[Vec<u16>; 4]> {
+/// Pad the gaps between words in `line` until it is `width` cells wide,
+/// spreading the extra spaces evenly and giving the leftmost gaps one more
+/// when they don't divide. Spaces are added after each gap's existing run,
+/// so escape sequences are never split; leading indentation is not a gap.
+fn justify_line_u16(line: &mut Vec<u16>, width: usize, cfg: WidthConfig) {
+	let line_width = visible_width_u16(line, cfg);
+	if line_width >= width {
+		return;
+	}
+
+	// End of each space run with visible text on both sides.
+	let mut gaps = SmallVec::<[usize; 16]>::new();
+	let mut seen_text = false;
+	let mut run_end = None;
+	let mut i = 0usize;
+	while i < line.len() {
+		if line[i] == ESC
+			&& let Some(seq_len) = ansi_seq_len(line, i)
+		{
+			i += seq_len;
+			continue;
+		}
+		if line[i] == b' ' as u16 {
+			if seen_text {
+				run_end = Some(i + 1);
+			}
+		} else {
+			gaps.extend(run_end.take());
+			seen_text = true;
+		}
+		i += 1;
+	}
+	if gaps.is_empty() {
+		return;
+	}
+
+	let extra = width - line_width;
+	let (each, rest) = (extra / gaps.len(), extra % gaps.len());
+	let mut out = Vec::with_capacity(line.len() + extra);
+	let mut copied = 0usize;
+	for (k, &end) in gaps.iter().enumerate() {
+		out.extend_from_slice(&line[copied..end]);
+		out.resize(out.len() + each + usize::from(k < rest), b' ' as u16);
+		copied = end;
+	}
+	out.extend_from_slice(&line[copied..]);
+	*line = out;
+}
+
+fn wrap_text_with_ansi_impl(
+	text: &[u16],
+	width: usize,
+	breaks: &BreakRules,
+	cfg: WidthConfig,
+) -> SmallVec<[Vec<u16>; 4]> {
 	if text.is_empty() {
 		return smallvec![Vec::new()];
 	}
@@ -715,7 +1797,7 @@ fn wrap_text_with_ansi_impl(text: &[u16], width: usize) -> SmallVec<[Vec<u16>; 4
 			}
 			line_with_prefix.extend_from_slice(line);
 
-			let wrapped = wrap_single_line(&line_with_prefix, width);
+			let wrapped = wrap_single_line(&line_with_prefix, width, breaks, cfg);
 			result.extend(wrapped);
 			update_state_from_text(line, &mut state);
 			line_start = i + 1;
@@ -733,86 +1815,238 @@ fn wrap_text_with_ansi_impl(text: &[u16], width: usize) -> SmallVec<[Vec<u16>; 4
 /// breaks.
 ///
 /// Returns UTF-16 lines with active SGR codes carried across line boundaries.
+/// Lines break at spaces, and with `breakChars` also after those characters,
+/// preferring the rightmost break that fits. Spaces at breaks are dropped
+/// unless `preserveWhitespace` is set. With `justify`, lines that end a
+/// paragraph (before a newline or at the end) stay ragged.
 #[napi(js_name = "wrapTextWithAnsi")]
-pub fn wrap_text_with_ansi(text: JsString, width: u32) -> Result<Vec<Utf16String>> {
+pub fn wrap_text_with_ansi(
+	text: JsString,
+	width: u32,
+	options: Option<WrapOptions>,
+) -> Result<Vec<Utf16String>> {
+	let cfg = WidthConfig::from_options(options.as_ref().and_then(|o| o.width.as_ref()))?;
 	let text_u16 = text.into_utf16()?;
-	let lines = wrap_text_with_ansi_impl(text_u16.as_slice(), width as usize);
+	let breaks = BreakRules::from_options(options.as_ref());
+	let lines =
+		wrap_text_with_ansi_impl(&cfg.prepare_u16(text_u16.as_slice()), width as usize, &breaks, cfg);
 	Ok(lines.into_iter().map(build_utf16_string).collect())
 }
 
+// ============================================================================
+// reflow
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct ReflowOptions {
+	/// Width the lines were wrapped at (default: the widest line).
+	#[napi(js_name = "oldWidth")]
+	pub old_width:   Option<u32>,
+	/// Per line, whether it ends in a hard break; overrides the guess for the
+	/// lines it covers.
+	#[napi(js_name = "hardBreaks")]
+	pub hard_breaks: Option<Vec<bool>>,
+}
+
+/// Last visible code unit of `line`, skipping ANSI escapes.
+fn last_visible_unit(line: &[u16]) -> Option<u16> {
+	let mut last = None;
+	let mut i = 0usize;
+	while i < line.len() {
+		if line[i] == ESC
+			&& let Some(seq_len) = ansi_seq_len(line, i)
+		{
+			i += seq_len;
+			continue;
+		}
+		last = Some(line[i]);
+		i += 1;
+	}
+	last
+}
+
+/// Join wrapped lines back into paragraphs and wrap them at `new_width`.
+///
+/// A line continues into the next one unless it is empty, the next one is
+/// empty, or the next line's first word would have fit after it at
+/// `old_width` (a greedy wrapper would have kept it there). Continuations are
+/// joined with a space, or directly after a break character or when the line
+/// was cut mid-word (a single word filling `old_width` exactly).
+fn reflow_impl(
+	lines: &[Vec<u16>],
+	new_width: usize,
+	old_width: Option<usize>,
+	hard_breaks: Option<&[bool]>,
+	breaks: &BreakRules,
+	cfg: WidthConfig,
+) -> SmallVec<[Vec<u16>; 4]> {
+	let widths: Vec<usize> = lines
+		.iter()
+		.map(|line| visible_width_u16(line, cfg))
+		.collect();
+	let old_width = old_width.unwrap_or_else(|| widths.iter().copied().max().unwrap_or(0));
+
+	let mut text = Vec::with_capacity(lines.iter().map(|line| line.len() + 1).sum());
+	for (i, line) in lines.iter().enumerate() {
+		text.extend_from_slice(line);
+		let Some(next) = lines.get(i + 1) else {
+			break;
+		};
+		let after_break_char = last_visible_unit(line)
+			.and_then(|u| char::from_u32(u32::from(u)))
+			.is_some_and(|c| breaks.after.contains(&c));
+		let soft = match hard_breaks.and_then(|hard| hard.get(i)) {
+			Some(&hard) => !hard,
+			None if widths[i] == 0 || widths[i + 1] == 0 => false,
+			None => {
+				let first_word = split_into_tokens_with_ansi(next, breaks)
+					.first()
+					.map_or(0, |token| visible_width_u16(token, cfg));
+				let gap = usize::from(!after_break_char);
+				widths[i] + gap + first_word > old_width
+			},
+		};
+		if soft {
+			let mid_word = widths[i] == old_width && !line.contains(&(b' ' as u16));
+			if !mid_word && !after_break_char {
+				text.push(b' ' as u16);
+			}
+		} else {
+			text.push(b'\n' as u16);
+		}
+	}
+
+	wrap_text_with_ansi_impl(&text, new_width, breaks, cfg)
+}
+
+/// Re-wrap previously wrapped lines to a new width.
+///
+/// Lines belonging to the same paragraph are joined first (see `oldWidth` and
+/// `hardBreaks`), so the old break points don't survive; empty lines separate
+/// paragraphs. ANSI state is carried across the new line breaks like
+/// `wrapTextWithAnsi`.
+#[napi(js_name = "reflow")]
+pub fn reflow(
+	lines: Vec<JsString>,
+	new_width: u32,
+	options: Option<ReflowOptions>,
+	wrap: Option<WrapOptions>,
+) -> Result<Vec<Utf16String>> {
+	let options = options.unwrap_or_default();
+	let cfg = WidthConfig::from_options(wrap.as_ref().and_then(|w| w.width.as_ref()))?;
+	let lines = lines
+		.into_iter()
+		.map(|line| Ok(cfg.prepare_u16(line.into_utf16()?.as_slice()).into_owned()))
+		.collect::<Result<Vec<_>>>()?;
+	let breaks = BreakRules::from_options(wrap.as_ref());
+	let out = reflow_impl(
+		&lines,
+		new_width as usize,
+		options.old_width.map(|w| w as usize),
+		options.hard_breaks.as_deref(),
+		&breaks,
+		cfg,
+	);
+	Ok(out.into_iter().map(build_utf16_string).collect())
+}
+
 // ============================================================================
 // truncateToWidth
 // ============================================================================
 
-/// Truncate text to a visible width, preserving ANSI codes.
+const ELLIPSIS_UNICODE: &[u16] = &[0x2026]; // "…"
+const ELLIPSIS_ASCII: &[u16] = &[0x2e, 0x2e, 0x2e]; // "..."
+const ELLIPSIS_OMIT: &[u16] = &[];
+
+/// Map an ellipsis kind (0 = "…", 1 = "...", 2 = omit) to UTF-16 data.
+const fn ellipsis_for_kind(kind: u8) -> &'static [u16] {
+	match kind {
+		1 => ELLIPSIS_ASCII,
+		2 => ELLIPSIS_OMIT,
+		_ => ELLIPSIS_UNICODE, // Default to Unicode for invalid values
+	}
+}
+
+/// Resolve the JS-facing ellipsis argument (kind or custom string).
+fn resolve_ellipsis(ellipsis: &Either<u8, Utf16String>) -> &[u16] {
+	match ellipsis {
+		Either::A(kind) => ellipsis_for_kind(*kind),
+		Either::B(custom) => custom,
+	}
+}
+
+/// Truncate `text` to `max_width` cells, appending `ellipsis` when content is
+/// dropped.
 ///
-/// `ellipsis_kind`: 0 = "…", 1 = "...", 2 = "" (omit); pads with spaces when
-/// requested.
-#[napi(js_name = "truncateToWidth")]
-pub fn truncate_to_width(
-	text: JsString<'_>,
-	max_width: u32,
-	ellipsis_kind: u8,
+/// Returns `None` when the text can be used unchanged.
+fn truncate_to_width_impl<U: CodeUnit>(
+	text: &[U],
+	max_width: usize,
+	ellipsis: &[U],
 	pad: bool,
-) -> Result<Either<JsString<'_>, Utf16String>> {
-	let max_width = max_width as usize;
+	cfg: WidthConfig,
+) -> Option<Vec<U>> {
+	truncate_to_width_carry(text, max_width, ellipsis, pad, None, cfg)
+}
 
-	// Keep original handle so we can return it without allocating.
-	let original = text;
+/// [`truncate_to_width_impl`], additionally applying every SGR kept in the
+/// output to `carry` (the state is left untouched when `None` is returned).
+fn truncate_to_width_carry<U: CodeUnit>(
+	text: &[U],
+	max_width: usize,
+	ellipsis: &[U],
+	pad: bool,
+	carry: Option<&mut AnsiState>,
+	cfg: WidthConfig,
+) -> Option<Vec<U>> {
+	truncate_to_width_measured(text, max_width, ellipsis, pad, carry, cfg).0
+}
 
-	let text_u16 = text.into_utf16()?;
-	let text = text_u16.as_slice();
+/// Width of a truncation's output, known without rescanning it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TruncationInfo {
+	/// Visible width, including the ellipsis and padding.
+	width:     usize,
+	/// Content was dropped.
+	truncated: bool,
+}
 
+/// [`truncate_to_width_carry`], also reporting the output width.
+fn truncate_to_width_measured<U: CodeUnit>(
+	text: &[U],
+	max_width: usize,
+	ellipsis: &[U],
+	pad: bool,
+	mut carry: Option<&mut AnsiState>,
+	cfg: WidthConfig,
+) -> (Option<Vec<U>>, TruncationInfo) {
 	// Fast path: early-exit width check
-	let (text_w, exceeded) = visible_width_u16_up_to(text, max_width);
+	let (text_w, exceeded) = visible_width_up_to(text, max_width, cfg);
 	if !exceeded {
-		if !pad {
-			// Return original JsString handle: zero output allocation.
-			return Ok(Either::A(original));
-		}
-
-		if text_w < max_width {
+		if pad && text_w < max_width {
 			let mut out = Vec::with_capacity(text.len() + (max_width - text_w));
 			out.extend_from_slice(text);
-			out.resize(out.len() + (max_width - text_w), b' ' as u16);
-			return Ok(Either::B(build_utf16_string(out)));
+			out.resize(out.len() + (max_width - text_w), U::ascii(b' '));
+			return (Some(out), TruncationInfo { width: max_width, truncated: false });
 		}
-
-		// Exactly fits and padding requested: return original is still fine.
-		return Ok(Either::A(original));
+		return (None, TruncationInfo { width: text_w, truncated: false });
 	}
 
-	// Map ellipsis kind to UTF-16 data and width
-	const ELLIPSIS_UNICODE: &[u16] = &[0x2026]; // "…"
-	const ELLIPSIS_ASCII: &[u16] = &[0x2e, 0x2e, 0x2e]; // "..."
-	const ELLIPSIS_OMIT: &[u16] = &[];
-
-	let (ellipsis, ellipsis_w): (&[u16], usize) = match ellipsis_kind {
-		0 => (ELLIPSIS_UNICODE, 1),
-		1 => (ELLIPSIS_ASCII, 3),
-		2 => (ELLIPSIS_OMIT, 0),
-		_ => (ELLIPSIS_UNICODE, 1), // Default to Unicode for invalid values
-	};
-
+	let ellipsis_w = visible_width_up_to(ellipsis, usize::MAX, cfg).0;
 	let target_w = max_width.saturating_sub(ellipsis_w);
 
 	// If ellipsis alone doesn't fit, return ellipsis cut to max_width
 	if target_w == 0 {
-		let mut out = Vec::with_capacity(ellipsis.len().min(max_width * 2));
-		let mut w = 0usize;
-		let _ = for_each_grapheme_u16_slow(ellipsis, |gu16, gw| {
-			if w + gw > max_width {
-				return false;
-			}
-			out.extend_from_slice(gu16);
-			w += gw;
-			true
-		});
-
-		if pad && w < max_width {
-			out.resize(out.len() + (max_width - w), b' ' as u16);
-		}
-		return Ok(Either::B(build_utf16_string(out)));
+		let SliceOutput { text: mut out, width: w, .. } =
+			slice_with_width_impl(ellipsis, 0, max_width, true, cfg);
+		let width = if pad && w < max_width {
+			out.resize(out.len() + (max_width - w), U::ascii(b' '));
+			max_width
+		} else {
+			w
+		};
+		return (Some(out), TruncationInfo { width, truncated: true });
 	}
 
 	// Main truncation
@@ -824,25 +2058,28 @@ pub fn truncate_to_width(
 	let mut saw_sgr = false;
 
 	while i < text_len {
-		if text[i] == ESC {
-			if let Some(seq_len) = ansi_seq_len_u16(text, i) {
+		if text[i] == U::ESC {
+			if let Some(seq_len) = ansi_seq_len(text, i) {
 				let seq = &text[i..i + seq_len];
 				out.extend_from_slice(seq);
-				if is_sgr_u16(seq) {
+				if is_sgr(seq) {
 					saw_sgr = true;
+					if let Some(state) = carry.as_deref_mut() {
+						state.apply_sgr(&seq[2..seq_len - 1]);
+					}
 				}
 				i += seq_len;
 				continue;
 			}
-			out.push(ESC);
+			out.push(U::ESC);
 			i += 1;
 			continue;
 		}
 
 		let start = i;
 		let mut is_ascii = true;
-		while i < text_len && text[i] != ESC {
-			if text[i] > 0x7f {
+		while i < text_len && text[i] != U::ESC {
+			if text[i].get() > 0x7f {
 				is_ascii = false;
 			}
 			i += 1;
@@ -850,19 +2087,22 @@ pub fn truncate_to_width(
 		let seg = &text[start..i];
 
 		if is_ascii {
+			let mut kept = 0usize;
 			for &u in seg {
-				let gw = ascii_cell_width_u16(u);
+				let gw = ascii_cell_width(u, w, cfg);
 				if w + gw > target_w {
 					break;
 				}
 				out.push(u);
 				w += gw;
+				kept += 1;
 			}
-			if w >= target_w {
+			// Keep going only for a mark that combines with the last character.
+			if w >= target_w && (kept < seg.len() || continuation_end(text, i, cfg).is_none()) {
 				break;
 			}
 		} else {
-			let keep_going = for_each_grapheme_u16_slow(seg, |gu16, gw| {
+			let keep_going = for_each_grapheme_slow(seg, w, cfg, |gu16, gw| {
 				if w + gw > target_w {
 					return false;
 				}
@@ -878,54 +2118,404 @@ pub fn truncate_to_width(
 
 	// Only reset if we actually copied SGR codes into the output.
 	if saw_sgr {
-		out.extend_from_slice(&[ESC, b'[' as u16, b'0' as u16, b'm' as u16]);
+		out.extend(b"\x1b[0m".map(U::ascii));
 	}
 	out.extend_from_slice(ellipsis);
 
-	if pad {
-		let out_w = w + ellipsis_w;
-		if out_w < max_width {
-			out.resize(out.len() + (max_width - out_w), b' ' as u16);
-		}
+	let mut width = w + ellipsis_w;
+	if pad && width < max_width {
+		out.resize(out.len() + (max_width - width), U::ascii(b' '));
+		width = max_width;
 	}
 
-	Ok(Either::B(build_utf16_string(out)))
+	(Some(out), TruncationInfo { width, truncated: true })
 }
 
-// ============================================================================
-// sliceWithWidth
-// ============================================================================
+/// Truncate text to a visible width, preserving ANSI codes.
+///
+/// `ellipsis` is either a kind (0 = "…", 1 = "...", 2 = "" (omit)) or a custom
+/// string whose ANSI codes don't count toward width; pads with spaces when
+/// requested, or with `fill` (see `padToWidth`).
+#[napi(js_name = "truncateToWidth")]
+pub fn truncate_to_width(
+	text: JsString<'_>,
+	max_width: u32,
+	ellipsis: Either<u8, Utf16String>,
+	pad: bool,
+	options: Option<WidthOptions>,
+	fill: Option<PadOptions>,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	// Keep original handle so we can return it without allocating.
+	let original = text;
 
-fn slice_with_width_impl(
-	line: &[u16],
-	start_col: usize,
-	length: usize,
-	strict: bool,
-) -> (Vec<u16>, usize) {
-	let end_col = start_col.saturating_add(length);
+	let text_u16 = text.into_utf16()?;
+	let text = cfg.prepare_u16(text_u16.as_slice());
+	if let Some(fill) = fill.filter(|_| pad) {
+		let fill = Fill::from_options(&fill, cfg)?;
+		let ellipsis = resolve_ellipsis(&ellipsis);
+		let (out, ..) = truncate_to_width_fill(&text, max_width as usize, ellipsis, &fill, cfg);
+		return Ok(Either::B(build_utf16_string(out)));
+	}
+	match truncate_to_width_impl(&text, max_width as usize, resolve_ellipsis(&ellipsis), pad, cfg) {
+		// Return original JsString handle: zero output allocation.
+		None if matches!(text, Cow::Borrowed(_)) => Ok(Either::A(original)),
+		None => Ok(Either::B(build_utf16_string(text.into_owned()))),
+		Some(out) => Ok(Either::B(build_utf16_string(out))),
+	}
+}
 
-	let mut out = Vec::with_capacity(length * 2);
-	let mut out_w = 0usize;
+/// Truncate text to a visible width like `truncateToWidth`, returning a
+/// result object with the output's visible width, so callers need not
+/// measure it again.
+///
+/// With `carryStyle`, also reports the SGR restore sequence for the state
+/// active at the end of the kept text (before the reset and ellipsis).
+#[napi(js_name = "truncateToWidthEx")]
+pub fn truncate_to_width_ex(
+	text: JsString<'_>,
+	max_width: u32,
+	ellipsis: Either<u8, Utf16String>,
+	pad: bool,
+	carry_style: Option<bool>,
+	options: Option<WidthOptions>,
+	fill: Option<PadOptions>,
+) -> Result<TruncateResult<'_>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let original = text;
+	let text_u16 = text.into_utf16()?;
+	let text = cfg.prepare_u16(text_u16.as_slice());
+	let carry_style = carry_style.unwrap_or(false);
+	let restore = |state: AnsiState| {
+		let mut style = Vec::new();
+		state.write_restore_u16(&mut style);
+		build_utf16_string(style)
+	};
 
-	let mut current_col = 0usize;
-	let mut i = 0usize;
-	let line_len = line.len();
+	if let Some(fill) = fill.filter(|_| pad) {
+		let fill = Fill::from_options(&fill, cfg)?;
+		let ellipsis = resolve_ellipsis(&ellipsis);
+		let (out, state, info) =
+			truncate_to_width_fill(&text, max_width as usize, ellipsis, &fill, cfg);
+		return Ok(TruncateResult {
+			text:          Either::B(build_utf16_string(out)),
+			width:         clamp_u32(info.width),
+			truncated:     info.truncated,
+			carried_style: carry_style.then(|| restore(state)),
+		});
+	}
 
-	// Store pending ANSI ranges (pos, len) to avoid copying until needed
-	let mut pending_ansi: SmallVec<[(usize, usize); 4]> = SmallVec::new();
+	let mut state = AnsiState::new();
+	let (out, info) = truncate_to_width_measured(
+		&text,
+		max_width as usize,
+		resolve_ellipsis(&ellipsis),
+		pad,
+		carry_style.then_some(&mut state),
+		cfg,
+	);
+	let (text, carried_style) = if let Some(out) = out {
+		(Either::B(build_utf16_string(out)), carry_style.then(|| restore(state)))
+	} else {
+		let carried = carry_style.then(|| carried_style_u16(&text));
+		match text {
+			// Return original JsString handle: zero output allocation.
+			Cow::Borrowed(_) => (Either::A(original), carried),
+			Cow::Owned(text) => (Either::B(build_utf16_string(text)), carried),
+		}
+	};
 
-	while i < line_len && current_col < end_col {
-		if line[i] == ESC {
-			if let Some(seq_len) = ansi_seq_len_u16(line, i) {
-				if current_col >= start_col {
-					out.extend_from_slice(&line[i..i + seq_len]);
-				} else {
-					pending_ansi.push((i, seq_len));
+	Ok(TruncateResult {
+		text,
+		width: clamp_u32(info.width),
+		truncated: info.truncated,
+		carried_style,
+	})
+}
+
+// ============================================================================
+// padToWidth
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct PadOptions {
+	/// Single grapheme of width 1 used for padding (default: space).
+	#[napi(js_name = "fillChar")]
+	pub fill_char:   Option<String>,
+	/// Draw the fill in the style active at the end of the content instead of
+	/// after a reset (default: false).
+	#[napi(js_name = "styledFill")]
+	pub styled_fill: Option<bool>,
+}
+
+/// Resolved padding fill.
+struct Fill {
+	/// UTF-16 fill grapheme.
+	unit:   Vec<u16>,
+	/// Keep the content's style active over the fill.
+	styled: bool,
+}
+
+impl Fill {
+	/// Unstyled spaces.
+	fn space() -> Self {
+		Self { unit: vec![b' ' as u16], styled: false }
+	}
+
+	fn from_options(options: &PadOptions, cfg: WidthConfig) -> Result<Self> {
+		let unit: Vec<u16> = match &options.fill_char {
+			Some(fill) => fill.encode_utf16().collect(),
+			None => vec![b' ' as u16],
+		};
+		let is_control = unit.iter().any(|&u| u < 0x20 || u == 0x7f);
+		if is_control || grapheme_count_u16(&unit) != 1 || visible_width_u16(&unit, cfg) != 1 {
+			return Err(Error::from_reason(format!(
+				"fillChar must be a single grapheme of width 1, got {:?}",
+				String::from_utf16_lossy(&unit)
+			)));
+		}
+		Ok(Self { unit, styled: options.styled_fill.unwrap_or(false) })
+	}
+}
+
+/// Append `gap` fill graphemes to `out`, whose content ends in `content`
+/// style.
+///
+/// An unstyled fill follows a reset; a styled fill re-establishes `content`
+/// if the output no longer ends in it (e.g. after a truncation reset).
+fn append_fill(out: &mut Vec<u16>, content: AnsiState, gap: usize, fill: &Fill) {
+	let mut end = AnsiState::new();
+	update_state_from_text(out, &mut end);
+	let restore = fill.styled && end != content;
+	if !end.is_empty() && (restore || !fill.styled) {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	if restore {
+		content.write_restore_u16(out);
+	}
+	out.reserve(gap * fill.unit.len() + SGR_RESET.len());
+	for _ in 0..gap {
+		out.extend_from_slice(&fill.unit);
+	}
+	if restore && !content.is_empty() {
+		out.extend_from_slice(&SGR_RESET);
+	}
+}
+
+/// [`truncate_to_width_carry`] padding with `fill`; also returns the style
+/// active at the end of the kept text and the output width.
+fn truncate_to_width_fill(
+	text: &[u16],
+	max_width: usize,
+	ellipsis: &[u16],
+	fill: &Fill,
+	cfg: WidthConfig,
+) -> (Vec<u16>, AnsiState, TruncationInfo) {
+	let mut state = AnsiState::new();
+	let (out, mut info) =
+		truncate_to_width_measured(text, max_width, ellipsis, false, Some(&mut state), cfg);
+	let mut out = out.unwrap_or_else(|| {
+		update_state_from_text(text, &mut state);
+		text.to_vec()
+	});
+	if info.width < max_width {
+		append_fill(&mut out, state, max_width - info.width, fill);
+		info.width = max_width;
+	}
+	(out, state, info)
+}
+
+/// Pad `text` with `fill` to `width` cells; `None` when already wide enough.
+fn pad_to_width_impl(
+	text: &[u16],
+	width: usize,
+	fill: &Fill,
+	cfg: WidthConfig,
+) -> Option<Vec<u16>> {
+	let (text_w, exceeded) = visible_width_up_to(text, width, cfg);
+	if exceeded || text_w >= width {
+		return None;
+	}
+	let mut state = AnsiState::new();
+	update_state_from_text(text, &mut state);
+	let mut out = text.to_vec();
+	append_fill(&mut out, state, width - text_w, fill);
+	Some(out)
+}
+
+/// Pad text to a visible width without truncating.
+///
+/// The fill defaults to spaces emitted after a reset, so the content's style
+/// does not bleed into the padding unless `styledFill` is set. Text already at
+/// least `width` wide is returned unchanged.
+#[napi(js_name = "padToWidth")]
+pub fn pad_to_width(
+	text: JsString<'_>,
+	width: u32,
+	fill: Option<PadOptions>,
+	options: Option<WidthOptions>,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let fill = Fill::from_options(&fill.unwrap_or_default(), cfg)?;
+	let original = text;
+
+	let text_u16 = text.into_utf16()?;
+	let text = cfg.prepare_u16(text_u16.as_slice());
+	match pad_to_width_impl(&text, width as usize, &fill, cfg) {
+		None if matches!(text, Cow::Borrowed(_)) => Ok(Either::A(original)),
+		None => Ok(Either::B(build_utf16_string(text.into_owned()))),
+		Some(out) => Ok(Either::B(build_utf16_string(out))),
+	}
+}
+
+// ============================================================================
+// truncateLines
+// ============================================================================
+
+/// Default indicator for dropped lines; `{n}` is the number dropped.
+const MORE_LINES_INDICATOR: &str = "… (+{n} more lines)";
+
+/// Truncate each line of `text` to `max_width` and keep at most `max_lines`
+/// lines, the last of which is `indicator` when lines were dropped.
+///
+/// Each line restores the SGR state carried from earlier lines and ends with a
+/// reset, so truncating one line never changes the style of the next.
+fn truncate_lines_impl(
+	text: &[u16],
+	max_width: usize,
+	max_lines: usize,
+	ellipsis: &[u16],
+	pad: bool,
+	indicator: &str,
+	cfg: WidthConfig,
+) -> Vec<u16> {
+	let lines: Vec<&[u16]> = split_lines_u16(text).collect();
+	let keep = if lines.len() > max_lines && !indicator.is_empty() {
+		max_lines.saturating_sub(1)
+	} else {
+		lines.len().min(max_lines)
+	};
+
+	let mut out = Vec::with_capacity(text.len().min(max_lines * (max_width * 2 + 1)) + 16);
+	let mut first = true;
+	let mut push_line = |line: &[u16]| {
+		if !first {
+			out.push(b'\n' as u16);
+		}
+		first = false;
+		match truncate_to_width_impl(line, max_width, ellipsis, pad, cfg) {
+			Some(truncated) => out.extend_from_slice(&truncated),
+			None => out.extend_from_slice(line),
+		}
+	};
+
+	let mut state = AnsiState::new();
+	for line in &lines[..keep] {
+		let mut styled = Vec::with_capacity(line.len() + 16);
+		state.write_restore_u16(&mut styled);
+		styled.extend_from_slice(line);
+		update_state_from_text(line, &mut state);
+		if !state.is_empty() {
+			styled.extend_from_slice(&SGR_RESET);
+		}
+		push_line(&styled);
+	}
+
+	let dropped = lines.len() - keep;
+	if dropped > 0 && max_lines > 0 && !indicator.is_empty() {
+		let indicator: Vec<u16> = indicator
+			.replace("{n}", &dropped.to_string())
+			.encode_utf16()
+			.collect();
+		push_line(&indicator);
+	}
+	out
+}
+
+/// Truncate multi-line text to a visible width per line and a maximum number
+/// of lines.
+///
+/// Lines are split as in `measureLines` and truncated independently like
+/// `truncateToWidth`, each self-contained in style. When lines are dropped,
+/// the last kept slot shows `indicator` (`{n}` is replaced by the number of
+/// dropped lines; default `"… (+{n} more lines)"`, empty to omit).
+#[napi(js_name = "truncateLines")]
+pub fn truncate_lines(
+	text: JsString,
+	max_width: u32,
+	max_lines: u32,
+	ellipsis: Either<u8, Utf16String>,
+	pad: bool,
+	indicator: Option<String>,
+	options: Option<WidthOptions>,
+) -> Result<Utf16String> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let text_u16 = text.into_utf16()?;
+	let text = cfg.prepare_u16(text_u16.as_slice());
+	Ok(build_utf16_string(truncate_lines_impl(
+		&text,
+		max_width as usize,
+		max_lines as usize,
+		resolve_ellipsis(&ellipsis),
+		pad,
+		indicator.as_deref().unwrap_or(MORE_LINES_INDICATOR),
+		cfg,
+	)))
+}
+
+// ============================================================================
+// truncateStart
+// ============================================================================
+
+/// Truncate `text` to `max_width` cells by dropping leading content and
+/// prepending `ellipsis`.
+///
+/// The ANSI state active at the cut point is restored after the ellipsis, and
+/// a wide grapheme straddling the cut is dropped entirely. Returns `None` when
+/// the text can be used unchanged.
+fn truncate_start_impl(
+	text: &[u16],
+	max_width: usize,
+	ellipsis: &[u16],
+	pad: bool,
+	cfg: WidthConfig,
+) -> Option<Vec<u16>> {
+	let total_w = visible_width_u16(text, cfg);
+	if total_w <= max_width {
+		return truncate_to_width_impl(text, max_width, ellipsis, pad, cfg);
+	}
+
+	let ellipsis_w = visible_width_u16(ellipsis, cfg);
+	let target_w = max_width.saturating_sub(ellipsis_w);
+	if target_w == 0 {
+		return truncate_to_width_impl(text, max_width, ellipsis, pad, cfg);
+	}
+
+	let skip_w = total_w - target_w;
+	let mut out = Vec::with_capacity(text.len().min(max_width * 2) + ellipsis.len() + 16);
+	out.extend_from_slice(ellipsis);
+
+	let mut state = AnsiState::new();
+	let mut col = 0usize;
+	let mut kept_w = 0usize;
+	let mut cut = false;
+	let mut i = 0usize;
+	let len = text.len();
+
+	while i < len {
+		if text[i] == ESC {
+			if let Some(seq_len) = ansi_seq_len(text, i) {
+				let seq = &text[i..i + seq_len];
+				if cut {
+					out.extend_from_slice(seq);
+				} else if is_sgr(seq) {
+					state.apply_sgr(&seq[2..seq_len - 1]);
 				}
 				i += seq_len;
 				continue;
 			}
-			if current_col >= start_col {
+			if cut {
 				out.push(ESC);
 			}
 			i += 1;
@@ -934,345 +2524,7529 @@ fn slice_with_width_impl(
 
 		let start = i;
 		let mut is_ascii = true;
-		while i < line_len && line[i] != ESC {
-			if line[i] > 0x7f {
+		while i < len && text[i] != ESC {
+			if text[i] > 0x7f {
 				is_ascii = false;
 			}
 			i += 1;
 		}
-		let seg = &line[start..i];
+		let seg = &text[start..i];
+
+		if cut {
+			out.extend_from_slice(seg);
+			continue;
+		}
+
+		let mut on_grapheme = |gu16: &[u16], col: usize| {
+			if cut {
+				out.extend_from_slice(gu16);
+			} else if col >= skip_w {
+				cut = true;
+				kept_w = total_w - col;
+				state.write_restore_u16(&mut out);
+				out.extend_from_slice(gu16);
+			}
+		};
 
+		// Tabs are measured at their column in the input, as for `total_w`.
 		if is_ascii {
 			for &u in seg {
-				if current_col >= end_col {
-					break;
+				on_grapheme(std::slice::from_ref(&u), col);
+				col += ascii_cell_width(u, col, cfg);
+			}
+		} else {
+			let _ = for_each_grapheme_slow(seg, col, cfg, |gu16, gw| {
+				on_grapheme(gu16, col);
+				col += gw;
+				true
+			});
+		}
+	}
+
+	if pad {
+		let out_w = kept_w + ellipsis_w;
+		if out_w < max_width {
+			out.resize(out.len() + (max_width - out_w), b' ' as u16);
+		}
+	}
+
+	Some(out)
+}
+
+/// Truncate text to a visible width by dropping leading content.
+///
+/// Prepends the ellipsis and restores the ANSI state active at the cut so the
+/// kept tail renders with its original styling.
+#[napi(js_name = "truncateStart")]
+pub fn truncate_start(
+	text: JsString<'_>,
+	max_width: u32,
+	ellipsis: Either<u8, Utf16String>,
+	pad: bool,
+	options: Option<WidthOptions>,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let original = text;
+
+	let text_u16 = text.into_utf16()?;
+	let text = cfg.prepare_u16(text_u16.as_slice());
+	match truncate_start_impl(&text, max_width as usize, resolve_ellipsis(&ellipsis), pad, cfg) {
+		None if matches!(text, Cow::Borrowed(_)) => Ok(Either::A(original)),
+		None => Ok(Either::B(build_utf16_string(text.into_owned()))),
+		Some(out) => Ok(Either::B(build_utf16_string(out))),
+	}
+}
+
+// ============================================================================
+// truncateMiddle
+// ============================================================================
+
+/// Terminate the kept head of a middle truncation and append the ellipsis.
+fn close_head(out: &mut Vec<u16>, saw_sgr: bool, ellipsis: &[u16]) {
+	if saw_sgr {
+		out.extend_from_slice(&[ESC, b'[' as u16, b'0' as u16, b'm' as u16]);
+	}
+	out.extend_from_slice(ellipsis);
+}
+
+/// Truncate `text` to `max_width` cells by eliding the middle.
+///
+/// `head_bias` is the fraction of the remaining budget given to the head
+/// (0.5 splits evenly, rounding in favor of the head). A reset precedes the
+/// ellipsis only if SGR codes were kept in the head, and the tail gets a
+/// restore of the ANSI state active at its start. Returns `None` when the
+/// text can be used unchanged.
+fn truncate_middle_impl(
+	text: &[u16],
+	max_width: usize,
+	ellipsis: &[u16],
+	pad: bool,
+	head_bias: f64,
+	cfg: WidthConfig,
+) -> Option<Vec<u16>> {
+	let total_w = visible_width_u16(text, cfg);
+	if total_w <= max_width {
+		return truncate_to_width_impl(text, max_width, ellipsis, pad, cfg);
+	}
+
+	let ellipsis_w = visible_width_u16(ellipsis, cfg);
+	let target_w = max_width.saturating_sub(ellipsis_w);
+	if target_w == 0 {
+		return truncate_to_width_impl(text, max_width, ellipsis, pad, cfg);
+	}
+
+	let head_budget = ((target_w as f64) * head_bias.clamp(0.0, 1.0)).round() as usize;
+	let tail_start = total_w - (target_w - head_budget);
+
+	let mut out = Vec::with_capacity(text.len().min(max_width * 2) + ellipsis.len() + 16);
+	let mut state = AnsiState::new();
+	let mut col = 0usize;
+	let mut head_w = 0usize;
+	let mut tail_w = 0usize;
+	let mut head_open = true;
+	let mut saw_sgr = false;
+	let mut in_tail = false;
+	let mut i = 0usize;
+	let len = text.len();
+
+	while i < len {
+		if text[i] == ESC {
+			if let Some(seq_len) = ansi_seq_len(text, i) {
+				let seq = &text[i..i + seq_len];
+				let sgr = is_sgr(seq);
+				if head_open || in_tail {
+					out.extend_from_slice(seq);
 				}
-				let gw = ascii_cell_width_u16(u);
-				let in_range = current_col >= start_col;
-				let fits = !strict || current_col + gw <= end_col;
+				if sgr && !in_tail {
+					saw_sgr |= head_open;
+					state.apply_sgr(&seq[2..seq_len - 1]);
+				}
+				i += seq_len;
+				continue;
+			}
+			if head_open || in_tail {
+				out.push(ESC);
+			}
+			i += 1;
+			continue;
+		}
+
+		let start = i;
+		let mut is_ascii = true;
+		while i < len && text[i] != ESC {
+			if text[i] > 0x7f {
+				is_ascii = false;
+			}
+			i += 1;
+		}
+		let seg = &text[start..i];
+
+		if in_tail {
+			out.extend_from_slice(seg);
+			continue;
+		}
+
+		let mut on_grapheme = |gu16: &[u16], col: usize, gw: usize| {
+			if head_open && head_w + gw <= head_budget {
+				out.extend_from_slice(gu16);
+				head_w += gw;
+			} else if in_tail {
+				out.extend_from_slice(gu16);
+			} else {
+				if head_open {
+					head_open = false;
+					close_head(&mut out, saw_sgr, ellipsis);
+				}
+				if col >= tail_start {
+					in_tail = true;
+					tail_w = total_w - col;
+					state.write_restore_u16(&mut out);
+					out.extend_from_slice(gu16);
+				}
+			}
+		};
+
+		// Tabs are measured at their column in the input, as for `total_w`.
+		if is_ascii {
+			for &u in seg {
+				let gw = ascii_cell_width(u, col, cfg);
+				on_grapheme(std::slice::from_ref(&u), col, gw);
+				col += gw;
+			}
+		} else {
+			let _ = for_each_grapheme_slow(seg, col, cfg, |gu16, gw| {
+				on_grapheme(gu16, col, gw);
+				col += gw;
+				true
+			});
+		}
+	}
+
+	if head_open {
+		close_head(&mut out, saw_sgr, ellipsis);
+	}
+
+	if pad {
+		let out_w = head_w + ellipsis_w + tail_w;
+		if out_w < max_width {
+			out.resize(out.len() + (max_width - out_w), b' ' as u16);
+		}
+	}
+
+	Some(out)
+}
+
+/// Truncate text to a visible width by eliding the middle.
+///
+/// `headBias` (0..1, default 0.5) controls how much of the budget goes to the
+/// head. The tail is prefixed with the ANSI state active at its start.
+#[napi(js_name = "truncateMiddle")]
+pub fn truncate_middle(
+	text: JsString<'_>,
+	max_width: u32,
+	ellipsis: Either<u8, Utf16String>,
+	pad: bool,
+	head_bias: Option<f64>,
+	options: Option<WidthOptions>,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let original = text;
+
+	let text_u16 = text.into_utf16()?;
+	let text = cfg.prepare_u16(text_u16.as_slice());
+	match truncate_middle_impl(
+		&text,
+		max_width as usize,
+		resolve_ellipsis(&ellipsis),
+		pad,
+		head_bias.unwrap_or(0.5),
+		cfg,
+	) {
+		None if matches!(text, Cow::Borrowed(_)) => Ok(Either::A(original)),
+		None => Ok(Either::B(build_utf16_string(text.into_owned()))),
+		Some(out) => Ok(Either::B(build_utf16_string(out))),
+	}
+}
+
+// ============================================================================
+// truncatePath
+// ============================================================================
+
+/// Build `[head sep] … sep tail` for path truncation.
+fn join_elided_path(head: Option<(&[u16], u16)>, tail_sep: u16, tail: &[u16]) -> Vec<u16> {
+	let mut out = Vec::with_capacity(tail.len() + head.map_or(0, |(h, _)| h.len()) + 4);
+	if let Some((head, sep)) = head {
+		out.extend_from_slice(head);
+		out.push(sep);
+	}
+	out.extend_from_slice(ELLIPSIS_UNICODE);
+	out.push(tail_sep);
+	out.extend_from_slice(tail);
+	out
+}
+
+/// Truncate a path to `max_width` cells, keeping the first component and as
+/// many trailing components as fit.
+///
+/// Whole middle components are replaced by a single "…" component. Falls back
+/// to `…/basename` without the head, then to start-truncating the basename
+/// behind a leading "…". Trailing separators are dropped once truncating.
+fn truncate_path_impl(path: &[u16], max_width: usize, seps: &[u16], cfg: WidthConfig) -> Vec<u16> {
+	if visible_width_u16(path, cfg) <= max_width {
+		return path.to_vec();
+	}
+
+	let is_sep = |u: &u16| seps.contains(u);
+	let path = match path.iter().rposition(|u| !is_sep(u)) {
+		Some(last) => &path[..=last],
+		None => path,
+	};
+	if visible_width_u16(path, cfg) <= max_width {
+		return path.to_vec();
+	}
+	let sep_positions: SmallVec<[usize; 16]> = path
+		.iter()
+		.enumerate()
+		.filter(|(_, u)| is_sep(u))
+		.map(|(i, _)| i)
+		.collect();
+
+	// Head: leading separators plus the first name.
+	let name_start = path.iter().position(|u| !is_sep(u)).unwrap_or(path.len());
+	let head_end = path[name_start..]
+		.iter()
+		.position(is_sep)
+		.map_or(path.len(), |p| name_start + p);
+
+	// Candidate tail starts (after a separator, right to left), skipping the
+	// first separator so at least one middle component is elided.
+	let tail_seps = || {
+		sep_positions
+			.iter()
+			.rev()
+			.copied()
+			.filter(|&p| p > head_end)
+	};
+
+	let best_fit = |head: Option<(&[u16], u16)>| {
+		let mut best = None;
+		for p in tail_seps() {
+			let candidate = join_elided_path(head, path[p], &path[p + 1..]);
+			if visible_width_u16(&candidate, cfg) > max_width {
+				break;
+			}
+			best = Some(candidate);
+		}
+		best
+	};
+
+	if head_end < path.len()
+		&& let Some(out) = best_fit(Some((&path[..head_end], path[head_end])))
+	{
+		return out;
+	}
+
+	let no_head = sep_positions.last().and_then(|&p| {
+		let candidate = join_elided_path(None, path[p], &path[p + 1..]);
+		(visible_width_u16(&candidate, cfg) <= max_width).then_some(candidate)
+	});
+	if let Some(out) = no_head {
+		return out;
+	}
+
+	// `…/basename` is too wide, so start-truncating the whole path keeps only
+	// part of the basename, and the "…" still shows something was elided even
+	// when the basename alone would fit.
+	truncate_start_impl(path, max_width, ELLIPSIS_UNICODE, false, cfg)
+		.unwrap_or_else(|| path.to_vec())
+}
+
+/// Truncate a path to a visible width, preserving the basename.
+///
+/// Keeps the first component and as many trailing components as fit,
+/// replacing the elided middle with a single "…" component. `separator` lists
+/// the characters to split on (default "/"; pass "/\\" for Windows paths).
+#[napi(js_name = "truncatePath")]
+pub fn truncate_path(
+	path: JsString,
+	max_width: u32,
+	separator: Option<Utf16String>,
+	options: Option<WidthOptions>,
+) -> Result<Utf16String> {
+	const DEFAULT_SEPS: &[u16] = &[b'/' as u16];
+
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let path_u16 = path.into_utf16()?;
+	let seps = separator
+		.as_deref()
+		.filter(|s| !s.is_empty())
+		.unwrap_or(DEFAULT_SEPS);
+	Ok(build_utf16_string(truncate_path_impl(
+		&cfg.prepare_u16(path_u16.as_slice()),
+		max_width as usize,
+		seps,
+		cfg,
+	)))
+}
+
+// ============================================================================
+// layoutColumns
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct ColumnSpec {
+	/// Minimum width in cells (default 0).
+	pub min:    Option<u32>,
+	/// Maximum width in cells (default unbounded).
+	pub max:    Option<u32>,
+	/// Share of the space beyond `min` (default 1; 0 sizes the column to its
+	/// content and never stretches it).
+	pub weight: Option<f64>,
+}
+
+/// Split `budget` cells in proportion to `weights`, giving no column more
+/// than its cap. Leftover cells from rounding go to the largest remainders.
+fn distribute_by_weight(budget: usize, caps: &[usize], weights: &[f64]) -> Vec<usize> {
+	let mut out = vec![0usize; caps.len()];
+	let mut active: Vec<usize> = (0..caps.len())
+		.filter(|&i| caps[i] > 0 && weights[i] > 0.0)
+		.collect();
+	let mut budget = budget;
+
+	while budget > 0 && !active.is_empty() {
+		let total: f64 = active.iter().map(|&i| weights[i]).sum();
+		let shares: Vec<f64> = active
+			.iter()
+			.map(|&i| budget as f64 * weights[i] / total)
+			.collect();
+
+		// Columns whose share reaches their cap are filled and leave the pool.
+		let capped: Vec<usize> = active
+			.iter()
+			.zip(&shares)
+			.filter(|&(&i, &share)| share >= caps[i] as f64)
+			.map(|(&i, _)| i)
+			.collect();
+		if !capped.is_empty() {
+			for &i in &capped {
+				out[i] = caps[i];
+				budget -= caps[i];
+			}
+			active.retain(|i| !capped.contains(i));
+			continue;
+		}
+
+		for (&i, &share) in active.iter().zip(&shares) {
+			out[i] = share.floor() as usize;
+			budget -= out[i];
+		}
+		let mut by_remainder: Vec<(usize, f64)> = active
+			.iter()
+			.zip(&shares)
+			.map(|(&i, &share)| (i, share.fract()))
+			.collect();
+		by_remainder.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+		for &(i, _) in by_remainder.iter().take(budget) {
+			out[i] += 1;
+		}
+		break;
+	}
+
+	out
+}
+
+/// Allocate column widths: minimums first, then space toward each cell's
+/// natural width (content-sized columns first, then the rest by weight), then
+/// any surplus up to the maximums by weight.
+fn allocate_columns(natural: &[usize], specs: &[ColumnSpec], total: usize) -> Vec<usize> {
+	let maxes: Vec<usize> = specs
+		.iter()
+		.map(|s| s.max.map_or(usize::MAX, |m| m as usize))
+		.collect();
+	let weights: Vec<f64> = specs.iter().map(|s| s.weight.unwrap_or(1.0)).collect();
+
+	// Minimums, then content-sized columns, are served left to right while
+	// the total lasts.
+	let mut budget = total;
+	let mut alloc: Vec<usize> = specs
+		.iter()
+		.map(|s| {
+			let w = (s.min.unwrap_or(0) as usize).min(budget);
+			budget -= w;
+			w
+		})
+		.collect();
+	let natural: Vec<usize> = natural
+		.iter()
+		.zip(&maxes)
+		.zip(&alloc)
+		.map(|((&n, &max), &a)| n.min(max).max(a))
+		.collect();
+	for i in (0..alloc.len()).filter(|&i| weights[i] == 0.0) {
+		let extra = (natural[i] - alloc[i]).min(budget);
+		alloc[i] += extra;
+		budget -= extra;
+	}
+
+	for target in [&natural, &maxes] {
+		let caps: Vec<usize> = target
+			.iter()
+			.zip(&alloc)
+			.map(|(&t, &a)| t.saturating_sub(a))
+			.collect();
+		let extra = distribute_by_weight(budget, &caps, &weights);
+		for (a, e) in alloc.iter_mut().zip(extra) {
+			*a += e;
+			budget -= e;
+		}
+	}
+
+	alloc
+}
+
+/// Fit `cell` to exactly `width` cells: truncated with an ellipsis or padded
+/// with spaces, with a reset so its style cannot leak into the next cell.
+fn layout_cell_u16(cell: &[u16], width: usize, cfg: WidthConfig) -> Vec<u16> {
+	let (mut out, ..) = truncate_to_width_fill(cell, width, ELLIPSIS_UNICODE, &Fill::space(), cfg);
+	let mut state = AnsiState::new();
+	update_state_from_text(&out, &mut state);
+	if !state.is_empty() {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	out
+}
+
+/// Lay out a table row: allocate widths summing to `totalWidth` and fit each
+/// cell to its width.
+///
+/// Each column first gets its `min` (left to right while space lasts), then
+/// space toward its content width (columns with `weight` 0 first) and finally
+/// any surplus up to `max`, split by `weight`. The row is narrower than
+/// `totalWidth` only when every column is capped. Cells are truncated with "…"
+/// or padded with spaces and end with a reset when styled, so each returned
+/// string is exactly its column's width.
+#[napi(js_name = "layoutColumns")]
+pub fn layout_columns(
+	cells: Vec<JsString>,
+	total_width: u32,
+	spec: Vec<ColumnSpec>,
+	options: Option<WidthOptions>,
+) -> Result<Vec<Utf16String>> {
+	if cells.len() != spec.len() {
+		return Err(Error::from_reason(format!(
+			"expected {} column specs, got {}",
+			cells.len(),
+			spec.len()
+		)));
+	}
+	for column in &spec {
+		if column.min.unwrap_or(0) > column.max.unwrap_or(u32::MAX) {
+			return Err(Error::from_reason("column min must not exceed max"));
+		}
+		if column.weight.is_some_and(|w| !w.is_finite() || w < 0.0) {
+			return Err(Error::from_reason("column weight must be a non-negative number"));
+		}
+	}
+
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let cells = cells
+		.into_iter()
+		.map(|cell| Ok(cfg.prepare_u16(cell.into_utf16()?.as_slice()).into_owned()))
+		.collect::<Result<Vec<_>>>()?;
+	let natural: Vec<usize> = cells
+		.iter()
+		.map(|cell| visible_width_u16(cell, cfg))
+		.collect();
+	let widths = allocate_columns(&natural, &spec, total_width as usize);
+
+	Ok(cells
+		.iter()
+		.zip(widths)
+		.map(|(cell, width)| build_utf16_string(layout_cell_u16(cell, width, cfg)))
+		.collect())
+}
+
+// ============================================================================
+// fitBudget
+// ============================================================================
+
+/// One string for `fitBudget`.
+#[napi(object, object_to_js = false)]
+pub struct BudgetPart {
+	/// Text with optional ANSI escapes.
+	pub text:      Utf16String,
+	/// Width kept until every part has shrunk to its minimum (default 0).
+	#[napi(js_name = "minWidth")]
+	pub min_width: Option<u32>,
+	/// Parts with higher priority shrink later (default 0).
+	pub priority:  Option<u32>,
+}
+
+/// Cut up to `over` cells from the parts in `group`, widest first, without
+/// taking any below its floor. Widths are leveled down together; a remainder
+/// that can't be split evenly is cut from the last parts. Returns the number
+/// of cells cut.
+fn shrink_widest(alloc: &mut [usize], floors: &[usize], group: &[usize], over: usize) -> usize {
+	let excess = |level: usize| -> usize {
+		group
+			.iter()
+			.map(|&i| alloc[i].saturating_sub(level.max(floors[i])))
+			.sum()
+	};
+	let available = excess(0);
+	if available <= over {
+		for &i in group {
+			alloc[i] = alloc[i].min(floors[i]);
+		}
+		return available;
+	}
+
+	// Lowest level cutting at most `over`; one level lower cuts too much.
+	let (mut lo, mut hi) = (1, group.iter().map(|&i| alloc[i]).max().unwrap_or(0));
+	while lo < hi {
+		let mid = lo.midpoint(hi);
+		if excess(mid) <= over {
+			hi = mid;
+		} else {
+			lo = mid + 1;
+		}
+	}
+	let mut remaining = over - excess(lo);
+	for &i in group {
+		alloc[i] = alloc[i].min(lo.max(floors[i]));
+	}
+	for &i in group.iter().rev() {
+		if remaining == 0 {
+			break;
+		}
+		if alloc[i] == lo && floors[i] < lo {
+			alloc[i] -= 1;
+			remaining -= 1;
+		}
+	}
+	over
+}
+
+/// Widths for parts measuring `natural`, summing to at most `total`.
+///
+/// Nothing shrinks when the parts fit. Otherwise priority levels are visited
+/// from lowest to highest, each shrinking its widest parts first down to their
+/// minimums until the excess is gone. If the minimums alone exceed `total`, a
+/// second pass in the same order shrinks parts below their minimums.
+fn allocate_budget(
+	natural: &[usize],
+	mins: &[usize],
+	priorities: &[u32],
+	total: usize,
+) -> Vec<usize> {
+	let mut alloc = natural.to_vec();
+	let mut over = natural.iter().sum::<usize>().saturating_sub(total);
+	let mut levels = priorities.to_vec();
+	levels.sort_unstable();
+	levels.dedup();
+
+	let floors: Vec<usize> = mins.iter().zip(natural).map(|(&m, &n)| m.min(n)).collect();
+	let none = vec![0; natural.len()];
+	for floors in [&floors, &none] {
+		for &level in &levels {
+			if over == 0 {
+				return alloc;
+			}
+			let group: Vec<usize> = (0..alloc.len())
+				.filter(|&i| priorities[i] == level)
+				.collect();
+			over -= shrink_widest(&mut alloc, floors, &group, over);
+		}
+	}
+	alloc
+}
+
+/// Fit `parts` (text, minimum width, priority) into `total` cells, truncating
+/// each to its share with `ellipsis`.
+fn fit_budget_impl(
+	parts: &[(&[u16], usize, u32)],
+	total: usize,
+	ellipsis: &[u16],
+	cfg: WidthConfig,
+) -> Vec<Vec<u16>> {
+	let texts: Vec<Cow<'_, [u16]>> = parts
+		.iter()
+		.map(|&(text, ..)| cfg.prepare_u16(text))
+		.collect();
+	let natural: Vec<usize> = texts
+		.iter()
+		.map(|text| visible_width_u16(text, cfg))
+		.collect();
+	let mins: Vec<usize> = parts.iter().map(|&(_, min, _)| min).collect();
+	let priorities: Vec<u32> = parts.iter().map(|&(.., priority)| priority).collect();
+	let widths = allocate_budget(&natural, &mins, &priorities, total);
+
+	texts
+		.into_iter()
+		.zip(widths)
+		.map(|(text, width)| {
+			truncate_to_width_impl(&text, width, ellipsis, false, cfg)
+				.unwrap_or_else(|| text.into_owned())
+		})
+		.collect()
+}
+
+/// Truncate several strings so their visible widths sum to at most
+/// `totalWidth`, e.g. the parts of `user@host:path (branch)` in a status bar.
+///
+/// When the parts don't fit, the lowest `priority` shrinks first, and within a
+/// priority the widest parts are cut first, leveled down together (an uneven
+/// remainder comes off the last parts). No part goes below its `minWidth`
+/// until every part is at its minimum; then the same order repeats ignoring
+/// minimums. Each part is truncated to its share like `truncateToWidth`.
+#[napi(js_name = "fitBudget")]
+pub fn fit_budget(
+	parts: Vec<BudgetPart>,
+	total_width: u32,
+	ellipsis: Either<u8, Utf16String>,
+	options: Option<WidthOptions>,
+) -> Result<Vec<Utf16String>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let parts: Vec<(&[u16], usize, u32)> = parts
+		.iter()
+		.map(|part| (&*part.text, part.min_width.unwrap_or(0) as usize, part.priority.unwrap_or(0)))
+		.collect();
+	Ok(fit_budget_impl(&parts, total_width as usize, resolve_ellipsis(&ellipsis), cfg)
+		.into_iter()
+		.map(build_utf16_string)
+		.collect())
+}
+
+// ============================================================================
+// alignBlock
+// ============================================================================
+
+/// Horizontal alignment of a line within a width.
+#[derive(Clone, Copy)]
+enum Align {
+	Left,
+	Center,
+	Right,
+}
+
+impl Align {
+	fn parse(align: &str) -> Result<Self> {
+		match align {
+			"left" => Ok(Self::Left),
+			"center" => Ok(Self::Center),
+			"right" => Ok(Self::Right),
+			other => Err(Error::from_reason(format!(
+				"align must be \"left\", \"center\", or \"right\", got \"{other}\""
+			))),
+		}
+	}
+}
+
+/// Align `line` within exactly `width` cells; lines that are too wide are
+/// truncated with an ellipsis. Padding is never styled by the line.
+fn align_line_u16(line: &[u16], width: usize, align: Align, cfg: WidthConfig) -> Vec<u16> {
+	let (line_w, exceeded) = visible_width_up_to(line, width, cfg);
+	if exceeded {
+		return layout_cell_u16(line, width, cfg);
+	}
+
+	let gap = width - line_w;
+	let left = match align {
+		Align::Left => 0,
+		Align::Center => gap / 2,
+		Align::Right => gap,
+	};
+	let mut out = Vec::with_capacity(line.len() + gap + SGR_RESET.len());
+	out.resize(left, b' ' as u16);
+	out.extend_from_slice(line);
+	let mut state = AnsiState::new();
+	update_state_from_text(line, &mut state);
+	if !state.is_empty() {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	out.resize(out.len() + gap - left, b' ' as u16);
+	out
+}
+
+/// Align each line within exactly `width` visible columns.
+///
+/// `align` is "left", "center" (an odd extra cell goes to the right), or
+/// "right". Lines wider than `width` are truncated with "…"; styled lines end
+/// with a reset before the right padding.
+#[napi(js_name = "alignBlock")]
+pub fn align_block(
+	lines: Vec<JsString>,
+	width: u32,
+	align: String,
+	options: Option<WidthOptions>,
+) -> Result<Vec<Utf16String>> {
+	let align = Align::parse(&align)?;
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	lines
+		.into_iter()
+		.map(|line| {
+			let line_u16 = line.into_utf16()?;
+			let line = cfg.prepare_u16(line_u16.as_slice());
+			Ok(build_utf16_string(align_line_u16(&line, width as usize, align, cfg)))
+		})
+		.collect()
+}
+
+/// Align every line of `text` like `alignBlock`, splitting on newlines as in
+/// `measureLines` and joining the result with `\n`.
+#[napi(js_name = "alignText")]
+pub fn align_text(
+	text: JsString,
+	width: u32,
+	align: String,
+	options: Option<WidthOptions>,
+) -> Result<Utf16String> {
+	let align = Align::parse(&align)?;
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let text_u16 = text.into_utf16()?;
+	let text = cfg.prepare_u16(text_u16.as_slice());
+
+	let mut out = Vec::with_capacity(text.len() + 16);
+	for (k, line) in split_lines_u16(&text).enumerate() {
+		if k > 0 {
+			out.push(b'\n' as u16);
+		}
+		out.extend_from_slice(&align_line_u16(line, width as usize, align, cfg));
+	}
+	Ok(build_utf16_string(out))
+}
+
+// ============================================================================
+// repeatToWidth
+// ============================================================================
+
+fn repeat_to_width_u16(pattern: &[u16], width: usize, cfg: WidthConfig) -> Vec<u16> {
+	// Pattern as alternating escape runs (width 0) and graphemes.
+	let mut items: Vec<(Range<usize>, usize)> = Vec::new();
+	let mut last = 0usize;
+	walk_graphemes_u16(pattern, cfg, |span| {
+		if span.start > last {
+			items.push((last..span.start, 0));
+		}
+		items.push((span.start..span.end, span.width));
+		last = span.end;
+		true
+	});
+	if last < pattern.len() {
+		items.push((last..pattern.len(), 0));
+	}
+
+	let mut out = Vec::with_capacity(width + pattern.len());
+	let mut w = 0usize;
+	if items.iter().any(|(_, gw)| *gw > 0) {
+		'tile: loop {
+			for (range, gw) in &items {
+				// Stop when full, or when a wide grapheme would overshoot (its
+				// cells are filled with spaces below).
+				if w == width || w + gw > width {
+					break 'tile;
+				}
+				out.extend_from_slice(&pattern[range.clone()]);
+				w += gw;
+			}
+		}
+	}
+	if contains_sgr_u16(&out) {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	out.resize(out.len() + (width - w), b' ' as u16);
+	out
+}
+
+/// Tile a pattern to exactly `width` visible columns.
+///
+/// The pattern's graphemes repeat until the width is reached; the last
+/// repetition is cut on a grapheme boundary, and a wide grapheme that would
+/// overshoot is replaced by a space. ANSI codes in the pattern are replayed
+/// in every repetition and closed with one trailing reset. A pattern without
+/// visible graphemes yields spaces.
+#[napi(js_name = "repeatToWidth")]
+pub fn repeat_to_width(pattern: JsString, width: u32) -> Result<Utf16String> {
+	let pattern_u16 = pattern.into_utf16()?;
+	Ok(build_utf16_string(repeat_to_width_u16(
+		pattern_u16.as_slice(),
+		width as usize,
+		WidthConfig::DEFAULT,
+	)))
+}
+
+// ============================================================================
+// sliceWithWidth
+// ============================================================================
+
+struct SliceOutput<U = u16> {
+	/// Sliced text.
+	text:          Vec<U>,
+	/// Visible width of `text`.
+	width:         usize,
+	/// Index just past the last consumed grapheme or trailing ANSI sequence.
+	end:           usize,
+	/// A wide grapheme straddling the start column was excluded.
+	clipped_start: bool,
+	/// A wide grapheme straddling the end column was excluded (strict mode).
+	clipped_end:   bool,
+}
+
+fn slice_with_width_impl<U: CodeUnit>(
+	line: &[U],
+	start_col: usize,
+	length: usize,
+	strict: bool,
+	cfg: WidthConfig,
+) -> SliceOutput<U> {
+	slice_with_width_at(line, 0, start_col, length, strict, false, cfg)
+}
+
+/// Slice `line` as if it started at column `base_col` (so tab stops line up
+/// with the original line); `start_col` is absolute.
+///
+/// With `pad_clipped`, the cells of a wide grapheme clipped at either
+/// boundary are filled with spaces so the width is exact.
+fn slice_with_width_at<U: CodeUnit>(
+	line: &[U],
+	base_col: usize,
+	start_col: usize,
+	length: usize,
+	strict: bool,
+	pad_clipped: bool,
+	cfg: WidthConfig,
+) -> SliceOutput<U> {
+	let end_col = start_col.saturating_add(length);
+
+	let mut out = Vec::with_capacity(length * 2);
+	let mut out_w = 0usize;
+
+	let mut current_col = base_col;
+	let mut i = 0usize;
+	let mut end = 0usize;
+	let line_len = line.len();
+
+	// Store pending ANSI ranges (pos, len) to avoid copying until needed
+	let mut pending_ansi: SmallVec<[(usize, usize); 4]> = SmallVec::new();
+
+	let mut clipped_start = false;
+	let mut clipped_end = false;
+	// Whether the last visible grapheme was emitted.
+	let mut last_emitted = true;
+
+	macro_rules! flush_pending {
+		() => {
+			if !pending_ansi.is_empty() {
+				for &(p, l) in &pending_ansi {
+					out.extend_from_slice(&line[p..p + l]);
+				}
+				pending_ansi.clear();
+			}
+		};
+	}
+
+	// Record (and optionally pad) a grapheme excluded at a boundary.
+	macro_rules! clip {
+		($gw:expr) => {
+			let cells = if current_col < start_col {
+				clipped_start = true;
+				(current_col + $gw).min(end_col) - start_col
+			} else {
+				clipped_end = true;
+				end_col - current_col
+			};
+			if pad_clipped {
+				flush_pending!();
+				out.resize(out.len() + cells, U::ascii(b' '));
+				out_w += cells;
+			}
+		};
+	}
+
+	while i < line_len && current_col < end_col {
+		if line[i] == U::ESC {
+			if let Some(seq_len) = ansi_seq_len(line, i) {
+				if current_col >= start_col {
+					out.extend_from_slice(&line[i..i + seq_len]);
+				} else {
+					pending_ansi.push((i, seq_len));
+				}
+				i += seq_len;
+				end = i;
+				continue;
+			}
+			if current_col >= start_col {
+				out.push(U::ESC);
+			}
+			i += 1;
+			end = i;
+			continue;
+		}
+
+		let start = i;
+		let mut is_ascii = true;
+		while i < line_len && line[i] != U::ESC {
+			if line[i].get() > 0x7f {
+				is_ascii = false;
+			}
+			i += 1;
+		}
+		let seg = &line[start..i];
+
+		if is_ascii {
+			for (k, &u) in seg.iter().enumerate() {
+				if current_col >= end_col {
+					break;
+				}
+				let gw = ascii_cell_width(u, current_col, cfg);
+				let in_range = current_col >= start_col;
+				let fits = !strict || current_col + gw <= end_col;
+
+				last_emitted = in_range && fits;
+				if last_emitted {
+					flush_pending!();
+					out.push(u);
+					out_w += gw;
+				} else if in_range || current_col + gw > start_col {
+					clip!(gw);
+				}
+				current_col += gw;
+				end = start + k + 1;
+			}
+		} else {
+			let mut pos = start;
+			let _ = for_each_grapheme_slow(seg, current_col, cfg, |gu16, gw| {
+				if current_col >= end_col {
+					return false;
+				}
+
+				let in_range = current_col >= start_col;
+				let fits = !strict || current_col + gw <= end_col;
+				// A mark split from its base by an escape follows the base.
+				let joined = gw == 0 && pos == start && start > 0;
+
+				let emit = in_range && fits && (last_emitted || !joined);
+
+				if emit {
+					flush_pending!();
+					out.extend_from_slice(gu16);
+					out_w += gw;
+				} else if in_range || current_col + gw > start_col {
+					clip!(gw);
+				}
+				if !joined {
+					last_emitted = emit;
+				}
+
+				current_col += gw;
+				pos += gu16.len();
+				end = pos;
+				current_col < end_col
+			});
+		}
+	}
+
+	if last_emitted
+		&& out_w > 0
+		&& let Some(next) = continuation_end(line, end, cfg)
+	{
+		out.extend_from_slice(&line[end..next]);
+		end = next;
+	}
+
+	// Include trailing ANSI sequences (e.g., reset codes) that immediately
+	// follow the scanned text, even when the end of it went unconsumed. They
+	// only count towards `end` when nothing was skipped before them.
+	let mut tail = i.max(end);
+	while tail < line_len {
+		if line[tail] == U::ESC
+			&& let Some(len) = ansi_seq_len(line, tail)
+		{
+			out.extend_from_slice(&line[tail..tail + len]);
+			if tail == end {
+				end += len;
+			}
+			tail += len;
+			continue;
+		}
+		break;
+	}
+
+	SliceOutput { text: out, width: out_w, end, clipped_start, clipped_end }
+}
+
+/// Slice `length` columns starting `from_end` columns before the end of the
+/// line, in a single pass.
+///
+/// Graphemes are buffered in a sliding window of the last `from_end` columns;
+/// the SGR state in effect at the window start is restored at the start of
+/// the output. Lines narrower than the window are kept whole.
+fn slice_from_end_impl(
+	line: &[u16],
+	from_end: usize,
+	length: usize,
+	strict: bool,
+	pad_clipped: bool,
+	cfg: WidthConfig,
+) -> SliceOutput {
+	let mut window: VecDeque<(GraphemeSpan, AnsiState)> = VecDeque::new();
+	let mut window_w = 0usize;
+	let mut window_start = 0usize;
+	let mut window_col = 0usize;
+	let mut window_state = AnsiState::new();
+	let mut state = AnsiState::new();
+	let mut last = 0usize;
+
+	walk_graphemes_u16(line, cfg, |span| {
+		if span.start > last {
+			update_state_from_text(&line[last..span.start], &mut state);
+		}
+		last = span.end;
+		window.push_back((span, state));
+		window_w += span.width;
+
+		// A wide grapheme straddling the window start is dropped whole.
+		while window_w > from_end {
+			let Some((dropped, dropped_state)) = window.pop_front() else {
+				break;
+			};
+			window_w -= dropped.width;
+			window_start = dropped.end;
+			window_col = dropped.col + dropped.width;
+			window_state = dropped_state;
+		}
+		true
+	});
+
+	// Fold SGR sequences at the window start into the restored state.
+	while window_start > 0
+		&& line.get(window_start) == Some(&ESC)
+		&& let Some(seq_len) = ansi_seq_len(line, window_start)
+	{
+		let seq = &line[window_start..window_start + seq_len];
+		if !is_sgr(seq) {
+			break;
+		}
+		window_state.apply_sgr(&seq[2..seq_len - 1]);
+		window_start += seq_len;
+	}
+
+	// Cells of a wide grapheme dropped at the window start.
+	let clipped_cells = window_col - (window_col + window_w).saturating_sub(from_end);
+	let pad = if pad_clipped {
+		clipped_cells.min(length)
+	} else {
+		0
+	};
+
+	let slice = slice_with_width_at(
+		&line[window_start..],
+		window_col,
+		window_col,
+		length - pad,
+		strict,
+		pad_clipped,
+		cfg,
+	);
+	let mut text = Vec::with_capacity(slice.text.len() + pad + 16);
+	window_state.write_restore_u16(&mut text);
+	text.resize(text.len() + pad, b' ' as u16);
+	text.extend_from_slice(&slice.text);
+
+	SliceOutput {
+		text,
+		width: slice.width + pad,
+		end: window_start + slice.end,
+		clipped_start: clipped_cells > 0,
+		clipped_end: slice.clipped_end,
+	}
+}
+
+/// Minimal SGR restore sequence for the state after `data` (empty if none).
+fn carried_style_u16(data: &[u16]) -> Utf16String {
+	let mut state = AnsiState::new();
+	update_state_from_text(data, &mut state);
+	let mut out = Vec::new();
+	state.write_restore_u16(&mut out);
+	build_utf16_string(out)
+}
+
+/// Slice one prepared line for `sliceWithWidth` and `sliceViewport`.
+fn slice_line_u16(
+	line: &[u16],
+	start_col: i32,
+	length: usize,
+	strict: bool,
+	carry_style: bool,
+	pad_clipped: bool,
+	cfg: WidthConfig,
+) -> SliceResult<'static> {
+	let slice = if start_col < 0 {
+		let from_end = start_col.unsigned_abs() as usize;
+		slice_from_end_impl(line, from_end, length, strict, pad_clipped, cfg)
+	} else {
+		slice_with_width_at(line, 0, start_col as usize, length, strict, pad_clipped, cfg)
+	};
+	let carried_style = carry_style.then(|| carried_style_u16(&line[..slice.end]));
+
+	SliceResult {
+		text: Either::B(build_utf16_string(slice.text)),
+		width: clamp_u32(slice.width),
+		carried_style,
+		clipped_at_start: slice.clipped_start,
+		clipped_at_end: slice.clipped_end,
+	}
+}
+
+/// Width of `line` when a slice of `length` columns from `start_col` is the
+/// whole line. The line must be narrower than `length`: at an exact fit,
+/// slicing stops at the end column and drops trailing zero-width units.
+fn whole_line_width(
+	line: &[u16],
+	start_col: i32,
+	length: usize,
+	cfg: WidthConfig,
+) -> Option<usize> {
+	if start_col != 0 {
+		return None;
+	}
+	let (width, exceeded) = visible_width_up_to(line, length, cfg);
+	(!exceeded && width < length).then_some(width)
+}
+
+/// The whole line as a slice from column 0, when it is narrower than
+/// `length` columns: `original` is returned without copying.
+fn whole_line_slice<'a>(
+	original: JsString<'a>,
+	line: &[u16],
+	borrowed: bool,
+	start_col: i32,
+	length: usize,
+	carry_style: bool,
+	cfg: WidthConfig,
+) -> Option<SliceResult<'a>> {
+	if !borrowed {
+		return None;
+	}
+	let width = whole_line_width(line, start_col, length, cfg)?;
+	Some(SliceResult {
+		text:             Either::A(original),
+		width:            clamp_u32(width),
+		carried_style:    carry_style.then(|| carried_style_u16(line)),
+		clipped_at_start: false,
+		clipped_at_end:   false,
+	})
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct SliceOptions {
+	/// Report the SGR restore sequence for the state active at the end of the
+	/// slice as `carriedStyle` (default false).
+	#[napi(js_name = "carryStyle")]
+	pub carry_style: Option<bool>,
+	/// Replace wide graphemes clipped at either boundary with spaces, so the
+	/// slice keeps its full width (default false).
+	#[napi(js_name = "padClipped")]
+	pub pad_clipped: Option<bool>,
+	/// Width measurement options.
+	pub width:       Option<WidthOptions>,
+}
+
+/// Slice a range of visible columns from a line.
+///
+/// Counts terminal cells, skipping ANSI escapes, and optionally enforces strict
+/// width. A negative `startCol` counts from the end of the line, restoring the
+/// SGR state in effect before the slice.
+#[napi(js_name = "sliceWithWidth")]
+pub fn slice_with_width(
+	line: JsString<'_>,
+	start_col: i32,
+	length: u32,
+	strict: bool,
+	options: Option<SliceOptions>,
+) -> Result<SliceResult<'_>> {
+	let options = options.unwrap_or_default();
+	let cfg = WidthConfig::from_options(options.width.as_ref())?;
+	let carry_style = options.carry_style.unwrap_or(false);
+	// Keep original handle so the whole line can be returned without copying.
+	let original = line;
+	let line_u16 = line.into_utf16()?;
+	let line = cfg.prepare_u16(line_u16.as_slice());
+	if let Some(whole) = whole_line_slice(
+		original,
+		&line,
+		matches!(line, Cow::Borrowed(_)),
+		start_col,
+		length as usize,
+		carry_style,
+		cfg,
+	) {
+		return Ok(whole);
+	}
+	Ok(slice_line_u16(
+		&line,
+		start_col,
+		length as usize,
+		strict,
+		carry_style,
+		options.pad_clipped.unwrap_or(false),
+		cfg,
+	))
+}
+
+/// Slice the same column range from many lines in a single call, e.g. the
+/// visible rows of a horizontally scrolled viewport.
+///
+/// Each result is identical to `sliceWithWidth` on that line; the results
+/// align index-for-index with `lines`.
+#[napi(js_name = "sliceViewport")]
+pub fn slice_viewport(
+	lines: Vec<JsString<'_>>,
+	start_col: i32,
+	length: u32,
+	strict: bool,
+	options: Option<SliceOptions>,
+) -> Result<Vec<SliceResult<'_>>> {
+	let options = options.unwrap_or_default();
+	let cfg = WidthConfig::from_options(options.width.as_ref())?;
+	let carry_style = options.carry_style.unwrap_or(false);
+	let pad_clipped = options.pad_clipped.unwrap_or(false);
+	lines
+		.into_iter()
+		.map(|line| {
+			let original = line;
+			let line_u16 = line.into_utf16()?;
+			let line = cfg.prepare_u16(line_u16.as_slice());
+			if let Some(whole) = whole_line_slice(
+				original,
+				&line,
+				matches!(line, Cow::Borrowed(_)),
+				start_col,
+				length as usize,
+				carry_style,
+				cfg,
+			) {
+				return Ok(whole);
+			}
+			Ok(slice_line_u16(
+				&line,
+				start_col,
+				length as usize,
+				strict,
+				carry_style,
+				pad_clipped,
+				cfg,
+			))
+		})
+		.collect()
+}
+
+// ============================================================================
+// UTF-8 Buffers
+// ============================================================================
+
+#[napi(object)]
+pub struct SliceUtf8Result {
+	/// UTF-8 slice containing the selected text.
+	pub text:             Buffer,
+	/// Visible width of the slice in terminal cells.
+	pub width:            u32,
+	/// A wide grapheme straddling the start column was excluded.
+	#[napi(js_name = "clippedAtStart")]
+	pub clipped_at_start: bool,
+	/// A wide grapheme straddling the end column was excluded (strict mode).
+	#[napi(js_name = "clippedAtEnd")]
+	pub clipped_at_end:   bool,
+}
+
+/// Width settings for UTF-8 input, which is measured as-is: `controlChars`
+/// rewriting is only available on strings.
+fn utf8_width_config(options: Option<&WidthOptions>) -> Result<WidthConfig> {
+	let cfg = WidthConfig::from_options(options)?;
+	if cfg.control_chars != ControlChars::Zero {
+		return Err(Error::from_reason("controlChars is not supported for UTF-8 input"));
+	}
+	Ok(cfg)
+}
+
+/// UTF-8 counterpart of [`resolve_ellipsis`].
+const fn resolve_ellipsis_utf8(ellipsis: &Either<u8, String>) -> &[u8] {
+	match ellipsis {
+		Either::A(1) => b"...",
+		Either::A(2) => b"",
+		Either::A(_) => "\u{2026}".as_bytes(),
+		Either::B(custom) => custom.as_bytes(),
+	}
+}
+
+/// `visibleWidth` for UTF-8 bytes, without decoding to a string.
+///
+/// Invalid UTF-8 sequences measure as U+FFFD.
+#[napi(js_name = "visibleWidthUtf8")]
+pub fn visible_width_utf8(buf: Uint8Array, options: Option<WidthOptions>) -> Result<u32> {
+	let cfg = utf8_width_config(options.as_ref())?;
+	Ok(clamp_u32(visible_width_up_to(&buf, usize::MAX, cfg).0))
+}
+
+/// `truncateToWidth` for UTF-8 bytes, without decoding to a string.
+#[napi(js_name = "truncateToWidthUtf8")]
+pub fn truncate_to_width_utf8(
+	buf: Uint8Array,
+	max_width: u32,
+	ellipsis: Either<u8, String>,
+	pad: bool,
+	options: Option<WidthOptions>,
+) -> Result<Buffer> {
+	let cfg = utf8_width_config(options.as_ref())?;
+	let ellipsis = resolve_ellipsis_utf8(&ellipsis);
+	let out = truncate_to_width_impl(&buf, max_width as usize, ellipsis, pad, cfg)
+		.unwrap_or_else(|| buf.to_vec());
+	Ok(out.into())
+}
+
+/// `sliceWithWidth` for UTF-8 bytes, without decoding to a string.
+#[napi(js_name = "sliceWithWidthUtf8")]
+pub fn slice_with_width_utf8(
+	buf: Uint8Array,
+	start_col: u32,
+	length: u32,
+	strict: bool,
+	options: Option<WidthOptions>,
+) -> Result<SliceUtf8Result> {
+	let cfg = utf8_width_config(options.as_ref())?;
+	let slice = slice_with_width_impl(&buf, start_col as usize, length as usize, strict, cfg);
+	Ok(SliceUtf8Result {
+		text:             slice.text.into(),
+		width:            clamp_u32(slice.width),
+		clipped_at_start: slice.clipped_start,
+		clipped_at_end:   slice.clipped_end,
+	})
+}
+
+// ============================================================================
+// MeasuredLine
+// ============================================================================
+
+/// Graphemes between SGR state checkpoints in a [`MeasuredLine`].
+const CHECKPOINT_STRIDE: usize = 64;
+
+/// A line pre-scanned once for repeated slicing and column queries.
+///
+/// Stores grapheme spans with their columns and the SGR state at every
+/// `CHECKPOINT_STRIDE`th grapheme, so each query is a binary search plus a
+/// bounded rescan instead of a walk from the start of the line.
+#[napi]
+pub struct MeasuredLine {
+	/// Prepared UTF-16 line.
+	line:        Vec<u16>,
+	/// Visible graphemes in order.
+	graphemes:   Vec<GraphemeSpan>,
+	/// SGR state before `graphemes[k * CHECKPOINT_STRIDE]`.
+	checkpoints: Vec<AnsiState>,
+	/// Total visible width.
+	width:       usize,
+	cfg:         WidthConfig,
+}
+
+impl MeasuredLine {
+	fn build(line: Vec<u16>, cfg: WidthConfig) -> Self {
+		let mut graphemes = Vec::new();
+		let mut checkpoints = Vec::new();
+		let mut state = AnsiState::new();
+		let mut last = 0usize;
+		let mut width = 0usize;
+		walk_graphemes_u16(&line, cfg, |span| {
+			if span.start > last {
+				update_state_from_text(&line[last..span.start], &mut state);
+			}
+			last = span.end;
+			if graphemes.len() % CHECKPOINT_STRIDE == 0 {
+				checkpoints.push(state);
+			}
+			graphemes.push(span);
+			width = span.col + span.width;
+			true
+		});
+		Self { line, graphemes, checkpoints, width, cfg }
+	}
+
+	/// Index of the first grapheme that ends past `column`.
+	fn grapheme_index_at(&self, column: usize) -> usize {
+		self
+			.graphemes
+			.partition_point(|g| g.col + g.width <= column)
+	}
+
+	/// SGR state after `line[..index]`, where `index` is not inside an escape.
+	fn state_before(&self, index: usize) -> AnsiState {
+		let mut state = AnsiState::new();
+		let before = self.graphemes.partition_point(|g| g.start <= index);
+		let from = match before.checked_sub(1) {
+			Some(g) => {
+				let k = g / CHECKPOINT_STRIDE;
+				state = self.checkpoints[k];
+				self.graphemes[k * CHECKPOINT_STRIDE].start
+			},
+			None => 0,
+		};
+		update_state_from_text(&self.line[from..index], &mut state);
+		state
+	}
+
+	fn slice_impl(&self, start_col: usize, length: usize, strict: bool) -> SliceOutput {
+		// Resume just after the grapheme preceding the slice, so escapes between
+		// it and the slice are replayed by the slicer.
+		let g = self.grapheme_index_at(start_col);
+		let (from, base_col) = match g.checked_sub(1) {
+			Some(prev) => {
+				let span = self.graphemes[prev];
+				(span.end, span.col + span.width)
+			},
+			None => (0, 0),
+		};
+		let slice = slice_with_width_at(
+			&self.line[from..],
+			base_col,
+			start_col,
+			length,
+			strict,
+			false,
+			self.cfg,
+		);
+		if slice.text.is_empty() {
+			return SliceOutput { end: from + slice.end, ..slice };
+		}
+
+		let mut text = Vec::with_capacity(slice.text.len() + 16);
+		self.state_before(from).write_restore_u16(&mut text);
+		text.extend_from_slice(&slice.text);
+		SliceOutput { text, end: from + slice.end, ..slice }
+	}
+}
+
+#[napi]
+impl MeasuredLine {
+	/// Scan `line` once, recording grapheme columns and SGR checkpoints.
+	///
+	/// # Errors
+	/// Returns an error if `options` are invalid.
+	#[napi(constructor)]
+	pub fn new(line: JsString, options: Option<WidthOptions>) -> Result<Self> {
+		let cfg = WidthConfig::from_options(options.as_ref())?;
+		let line_u16 = line.into_utf16()?;
+		let line = cfg.prepare_u16(line_u16.as_slice()).into_owned();
+		Ok(Self::build(line, cfg))
+	}
+
+	/// Visible width of the line in terminal cells.
+	#[napi(js_name = "width")]
+	pub fn width(&self) -> u32 {
+		clamp_u32(self.width)
+	}
+
+	/// Slice a range of visible columns, like `sliceWithWidth`.
+	///
+	/// The SGR state in effect before the slice is restored at its start.
+	#[napi(js_name = "slice")]
+	pub fn slice(&self, start_col: u32, length: u32, strict: bool) -> SliceResult<'static> {
+		let slice = self.slice_impl(start_col as usize, length as usize, strict);
+		SliceResult {
+			text:             Either::B(build_utf16_string(slice.text)),
+			width:            clamp_u32(slice.width),
+			carried_style:    None,
+			clipped_at_start: slice.clipped_start,
+			clipped_at_end:   slice.clipped_end,
+		}
+	}
+
+	/// SGR style in effect at a visible column, like `styleAtColumn`.
+	#[napi(js_name = "styleAt")]
+	pub fn style_at(&self, column: u32) -> StyleInfo {
+		let g = self.grapheme_index_at(column as usize);
+		let index = self
+			.graphemes
+			.get(g)
+			.map_or(self.line.len(), |span| span.start);
+		self.state_before(index).into()
+	}
+
+	/// UTF-16 index of the grapheme occupying a visible column (the line length
+	/// past the end).
+	///
+	/// # Errors
+	/// Returns an error if the line was built with `controlChars: "interpret"`,
+	/// whose indices don't point into the original string.
+	#[napi(js_name = "columnToIndex")]
+	pub fn column_to_index(&self, column: u32) -> Result<u32> {
+		if self.cfg.control_chars == ControlChars::Interpret {
+			return Err(Error::from_reason(INTERPRET_INDEX_ERROR));
+		}
+		let g = self.grapheme_index_at(column as usize);
+		Ok(clamp_u32(
+			self
+				.graphemes
+				.get(g)
+				.map_or(self.line.len(), |span| span.start),
+		))
+	}
+}
+
+// ============================================================================
+// AnsiTracker
+// ============================================================================
+
+/// Longest incomplete sequence held back between chunks; anything longer
+/// (e.g. an OSC that is never terminated) is passed through as text.
+const TRACKER_MAX_PENDING: usize = 4096;
+
+/// Tracks the SGR state of a stream arriving in arbitrary chunks.
+///
+/// An escape sequence split across chunks is held back until it completes,
+/// so each returned piece only contains whole sequences.
+#[napi]
+#[derive(Default)]
+pub struct AnsiTracker {
+	/// Incomplete sequence at the end of the last chunk.
+	pending: Vec<u16>,
+	/// SGR state after everything returned so far.
+	state:   AnsiState,
+}
+
+impl AnsiTracker {
+	fn push_u16(&mut self, chunk: &[u16]) -> Vec<u16> {
+		let mut data = std::mem::take(&mut self.pending);
+		data.extend_from_slice(chunk);
+
+		let mut i = 0usize;
+		while i < data.len() {
+			if data[i] != ESC {
+				i += 1;
+				continue;
+			}
+			match ansi_seq_len(&data, i) {
+				Some(seq_len) => {
+					let seq = &data[i..i + seq_len];
+					if is_sgr(seq) {
+						self.state.apply_sgr(&seq[2..seq_len - 1]);
+					}
+					i += seq_len;
+				},
+				None if ansi_seq_incomplete(&data, i) && data.len() - i <= TRACKER_MAX_PENDING => {
+					self.pending = data.split_off(i);
+					break;
+				},
+				None => i += 1,
+			}
+		}
+		data
+	}
+}
+
+#[napi]
+impl AnsiTracker {
+	#[napi(constructor)]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Feed the next chunk; returns the text up to the last complete escape
+	/// sequence, holding back a sequence split at the end of the chunk.
+	#[napi(js_name = "push")]
+	pub fn push(&mut self, chunk: JsString) -> Result<Utf16String> {
+		let chunk = chunk.into_utf16()?;
+		Ok(build_utf16_string(self.push_u16(chunk.as_slice())))
+	}
+
+	/// Return any held-back partial sequence as-is, e.g. at the end of the
+	/// stream.
+	#[napi(js_name = "flush")]
+	pub fn flush(&mut self) -> Utf16String {
+		build_utf16_string(std::mem::take(&mut self.pending))
+	}
+
+	/// SGR style in effect after the text returned so far.
+	#[napi(js_name = "currentStyle")]
+	pub fn current_style(&self) -> StyleInfo {
+		self.state.into()
+	}
+
+	/// Minimal SGR sequence restoring the current style (empty when unstyled).
+	#[napi(js_name = "currentSgr")]
+	pub fn current_sgr(&self) -> Utf16String {
+		let mut out = Vec::new();
+		self.state.write_restore_u16(&mut out);
+		build_utf16_string(out)
+	}
+
+	/// Forget the style and any held-back sequence.
+	#[napi(js_name = "reset")]
+	pub fn reset(&mut self) {
+		*self = Self::default();
+	}
+}
+
+// ============================================================================
+// extractSegments
+// ============================================================================
+
+struct Segments {
+	before:               Vec<u16>,
+	before_width:         usize,
+	after:                Vec<u16>,
+	after_width:          usize,
+	/// A wide grapheme straddling `before.start` was excluded from `before`.
+	before_clipped_start: bool,
+	/// A wide grapheme straddling `before.end` was excluded (strict before).
+	before_clipped_end:   bool,
+	/// A wide grapheme straddling `after_start` was excluded from `after`.
+	clipped_start:        bool,
+	/// A wide grapheme straddling the end of `after` was excluded.
+	clipped_end:          bool,
+}
+
+/// Handling of wide graphemes crossing the segment boundaries.
+#[derive(Clone, Copy, Default)]
+struct SegmentClip {
+	/// Exclude a grapheme crossing the end of `before`.
+	strict_before: bool,
+	/// Fill the cells of graphemes excluded from `before` with spaces, so its
+	/// width is exactly the window width.
+	pad_before:    bool,
+	/// Exclude a grapheme crossing the end of `after`.
+	strict_after:  bool,
+}
+
+impl SegmentClip {
+	const fn after(strict_after: bool) -> Self {
+		Self { strict_before: false, pad_before: false, strict_after }
+	}
+}
+
+fn extract_segments_impl(
+	line: &[u16],
+	before: Range<usize>,
+	after_start: usize,
+	after_len: usize,
+	clip: SegmentClip,
+	initial: AnsiState,
+	cfg: WidthConfig,
+) -> Segments {
+	let Range { start: before_start, end: before_end } = before;
+	let after_end = after_start.saturating_add(after_len);
+
+	let mut before = Vec::with_capacity(before_end * 2);
+	let mut before_w = 0usize;
+
+	let mut after = Vec::with_capacity(after_len * 2);
+	let mut after_w = 0usize;
+
+	let mut current_col = 0usize;
+	let mut i = 0usize;
+	let line_len = line.len();
+
+	// Store pending ANSI ranges for "before"
+	let mut pending_before_ansi: SmallVec<[(usize, usize); 4]> = SmallVec::new();
+
+	let mut before_started = false;
+	let mut after_started = false;
+	// State at `before_start`, restored at the start of `before`.
+	let mut before_state = initial;
+	let mut state = initial;
+
+	let mut before_clipped_start = false;
+	let mut before_clipped_end = false;
+	let mut clipped_start = false;
+	let mut clipped_end = false;
+
+	let done_col = if after_len == 0 {
+		before_end
+	} else {
+		after_end
+	};
+
+	// Open `before` (restoring its initial state) and flush held escapes.
+	macro_rules! open_before {
+		() => {
+			if !before_started {
+				before_state.write_restore_u16(&mut before);
+				before_started = true;
+			}
+			if !pending_before_ansi.is_empty() {
+				for &(p, l) in &pending_before_ansi {
+					before.extend_from_slice(&line[p..p + l]);
+				}
+				pending_before_ansi.clear();
+			}
+		};
+	}
+
+	macro_rules! pad_before {
+		($cells:expr) => {
+			if clip.pad_before {
+				open_before!();
+				before.resize(before.len() + $cells, b' ' as u16);
+				before_w += $cells;
+			}
+		};
+	}
+
+	macro_rules! visit {
+		($g:expr, $gw:expr) => {
+			let gw = $gw;
+			// Graphemes before the window (including a wide one straddling it)
+			// are skipped.
+			if (before_start..before_end).contains(&current_col) {
+				if clip.strict_before && current_col + gw > before_end {
+					before_clipped_end = true;
+					pad_before!(before_end - current_col);
+				} else {
+					open_before!();
+					before.extend_from_slice($g);
+					before_w += gw;
+				}
+			} else if current_col >= after_start && current_col < after_end {
+				let fits = !clip.strict_after || current_col + gw <= after_end;
+				if fits {
+					if !after_started {
+						state.write_restore_u16(&mut after);
+						after_started = true;
+					}
+					after.extend_from_slice($g);
+					after_w += gw;
+				} else {
+					clipped_end = true;
+				}
+			}
+			if current_col < before_start
+				&& current_col + gw > before_start
+				&& before_end > before_start
+			{
+				before_clipped_start = true;
+				pad_before!((current_col + gw).min(before_end) - before_start);
+			}
+			if current_col < after_start && current_col + gw > after_start && after_len > 0 {
+				clipped_start = true;
+			}
+			current_col += gw;
+		};
+	}
+
+	while i < line_len && current_col < done_col {
+		if line[i] == ESC {
+			if let Some(seq_len) = ansi_seq_len(line, i) {
+				let seq = &line[i..i + seq_len];
+				if is_sgr(seq) {
+					state.apply_sgr(&seq[2..seq_len - 1]);
+				}
+
+				if current_col < before_start {
+					before_state = state;
+				} else if current_col < before_end {
+					pending_before_ansi.push((i, seq_len));
+				} else if current_col >= after_start && current_col < after_end && after_started {
+					after.extend_from_slice(seq);
+				}
+
+				i += seq_len;
+				continue;
+			}
+
+			if (before_start..before_end).contains(&current_col) {
+				before.push(ESC);
+			} else if current_col >= after_start && current_col < after_end && after_started {
+				after.push(ESC);
+			}
+			i += 1;
+			continue;
+		}
+
+		let start = i;
+		let mut is_ascii = true;
+		while i < line_len && line[i] != ESC {
+			if line[i] > 0x7f {
+				is_ascii = false;
+			}
+			i += 1;
+		}
+		let seg = &line[start..i];
+
+		if is_ascii {
+			for u in seg {
+				if current_col >= done_col {
+					break;
+				}
+				visit!(std::slice::from_ref(u), ascii_cell_width(*u, current_col, cfg));
+			}
+		} else {
+			let _ = for_each_grapheme_slow(seg, current_col, cfg, |gu16, gw| {
+				if current_col >= done_col {
+					return false;
+				}
+				visit!(gu16, gw);
+				true
+			});
+		}
+	}
+
+	Segments {
+		before,
+		before_width: before_w,
+		after,
+		after_width: after_w,
+		before_clipped_start,
+		before_clipped_end,
+		clipped_start,
+		clipped_end,
+	}
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct SegmentWindow {
+	/// Column where the `before` segment starts (default 0).
+	#[napi(js_name = "beforeStart")]
+	pub before_start:  Option<u32>,
+	/// SGR sequence(s) for the style already active where the line begins
+	/// (e.g. carried over from a previous wrapped line).
+	#[napi(js_name = "initialState")]
+	pub initial_state: Option<Utf16String>,
+	/// Exclude a wide grapheme crossing the end of `before`, like
+	/// `strictAfter` does for `after` (default false).
+	#[napi(js_name = "strictBefore")]
+	pub strict_before: Option<bool>,
+	/// Fill the cells of wide graphemes excluded from `before` with spaces, so
+	/// `beforeWidth` is exactly the window width (default false).
+	#[napi(js_name = "padBefore")]
+	pub pad_before:    Option<bool>,
+}
+
+/// Extract the before/after slices around an overlay region.
+///
+/// Preserves ANSI state so the `after` segment renders correctly after
+/// truncation. With `window`, `before` may start past column 0 and the line
+/// may begin in an active style; both segments start by restoring the SGR
+/// state in effect at their first column.
+#[napi(js_name = "extractSegments")]
+pub fn extract_segments(
+	line: JsString,
+	before_end: u32,
+	after_start: u32,
+	after_len: u32,
+	strict_after: bool,
+	options: Option<WidthOptions>,
+	window: Option<SegmentWindow>,
+) -> Result<ExtractSegmentsResult> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let line = cfg.prepare_u16(line_u16.as_slice());
+	let line = &*line;
+
+	let window = window.unwrap_or_default();
+	let mut initial = AnsiState::new();
+	if let Some(sgr) = &window.initial_state {
+		update_state_from_text(sgr, &mut initial);
+	}
+
+	let segments = extract_segments_impl(
+		line,
+		window.before_start.unwrap_or(0) as usize..before_end as usize,
+		after_start as usize,
+		after_len as usize,
+		SegmentClip {
+			strict_before: window.strict_before.unwrap_or(false),
+			pad_before: window.pad_before.unwrap_or(false),
+			strict_after,
+		},
+		initial,
+		cfg,
+	);
+
+	Ok(ExtractSegmentsResult {
+		before:                  build_utf16_string(segments.before),
+		before_width:            clamp_u32(segments.before_width),
+		after:                   build_utf16_string(segments.after),
+		after_width:             clamp_u32(segments.after_width),
+		before_clipped_at_start: segments.before_clipped_start,
+		before_clipped_at_end:   segments.before_clipped_end,
+		after_clipped_at_start:  segments.clipped_start,
+		after_clipped_at_end:    segments.clipped_end,
+	})
+}
+
+// ============================================================================
+// overlayAtColumn
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct OverlayOptions {
+	/// Width of the overlay region (default: the overlay's visible width).
+	pub width:       Option<u32>,
+	/// Width of the composed line (default: wide enough for base and overlay).
+	#[napi(js_name = "totalWidth")]
+	pub total_width: Option<u32>,
+	/// Drop wide chars that overflow the base's `after` segment (default true).
+	pub strict:      Option<bool>,
+}
+
+const SGR_RESET: [u16; 4] = [ESC, b'[' as u16, b'0' as u16, b'm' as u16];
+
+fn overlay_at_column_impl(
+	base: &[u16],
+	overlay: &[u16],
+	column: usize,
+	opts: &OverlayOptions,
+	cfg: WidthConfig,
+) -> SliceOutput {
+	let overlay_w = opts
+		.width
+		.map_or_else(|| visible_width_u16(overlay, cfg), |w| w as usize);
+	let after_start = column + overlay_w;
+	let total_w = opts
+		.total_width
+		.map_or_else(|| visible_width_u16(base, cfg).max(after_start), |w| w as usize);
+
+	let Segments { before: mut out, before_width: before_w, after, after_width: after_w, .. } =
+		extract_segments_impl(
+			base,
+			0..column,
+			after_start,
+			total_w.saturating_sub(after_start),
+			SegmentClip::after(opts.strict.unwrap_or(true)),
+			AnsiState::new(),
+			cfg,
+		);
+	let overlay = slice_with_width_impl(overlay, 0, overlay_w, true, cfg);
+
+	if contains_sgr_u16(&out) {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	out.resize(out.len() + column.saturating_sub(before_w), b' ' as u16);
+	out.extend_from_slice(&overlay.text);
+	if contains_sgr_u16(&overlay.text) {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	out.resize(out.len() + overlay_w - overlay.width, b' ' as u16);
+
+	// A wide char straddling the overlay's right edge is dropped from `after`;
+	// fill its leftover cell so the rest of the base keeps its column.
+	let mut after_col = after_start;
+	walk_graphemes_u16(base, cfg, |span| {
+		after_col = span.col;
+		span.col < after_start
+	});
+	let gap = if after_w > 0 {
+		after_col.saturating_sub(after_start)
+	} else {
+		0
+	};
+	out.resize(out.len() + gap, b' ' as u16);
+	out.extend_from_slice(&after);
+	if contains_sgr_u16(&after) {
+		out.extend_from_slice(&SGR_RESET);
+	}
+
+	// A wide char straddling `column` widens the head; keep the overlay whole.
+	let used_w = before_w.max(column) + overlay_w + gap + after_w;
+	if used_w < total_w {
+		out.resize(out.len() + (total_w - used_w), b' ' as u16);
+	} else if used_w > total_w {
+		let slice = slice_with_width_impl(&out, 0, total_w, true, cfg);
+		return SliceOutput {
+			end: out.len(),
+			clipped_end: overlay.clipped_end || slice.clipped_end,
+			..slice
+		};
+	}
+	SliceOutput {
+		width:         total_w,
+		end:           out.len(),
+		text:          out,
+		clipped_start: false,
+		clipped_end:   overlay.clipped_end,
+	}
+}
+
+/// Composite an overlay onto a base line at a visible column.
+///
+/// The overlay is clipped and padded to its region, the base's ANSI state is
+/// restored after it, and the line is padded with spaces when the base is too
+/// short.
+#[napi(js_name = "overlayAtColumn")]
+pub fn overlay_at_column(
+	base: JsString,
+	overlay: JsString,
+	column: u32,
+	options: Option<OverlayOptions>,
+) -> Result<SliceResult<'static>> {
+	let base_u16 = base.into_utf16()?;
+	let overlay_u16 = overlay.into_utf16()?;
+	let out = overlay_at_column_impl(
+		base_u16.as_slice(),
+		overlay_u16.as_slice(),
+		column as usize,
+		&options.unwrap_or_default(),
+		WidthConfig::DEFAULT,
+	);
+	Ok(SliceResult {
+		text:             Either::B(build_utf16_string(out.text)),
+		width:            clamp_u32(out.width),
+		carried_style:    None,
+		clipped_at_start: out.clipped_start,
+		clipped_at_end:   out.clipped_end,
+	})
+}
+
+// ============================================================================
+// visibleWidth
+// ============================================================================
+
+/// Calculate visible width of text, excluding ANSI escape sequences.
+///
+/// Tabs count as `tabWidth` cells (default 3), or advance to the next tab stop
+/// with `accurateTabs`.
+#[napi(js_name = "visibleWidth")]
+pub fn visible_width_napi(text: JsString, options: Option<WidthOptions>) -> Result<u32> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let text_u16 = text.into_utf16()?;
+	Ok(clamp_u32(visible_width_u16(&cfg.prepare_u16(text_u16.as_slice()), cfg)))
+}
+
+/// Calculate visible widths of many lines in a single call.
+///
+/// The result aligns index-for-index with `lines`.
+#[napi(js_name = "visibleWidths")]
+pub fn visible_widths(lines: Vec<JsString>, options: Option<WidthOptions>) -> Result<Vec<u32>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	lines
+		.into_iter()
+		.map(|line| {
+			let line_u16 = line.into_utf16()?;
+			Ok(clamp_u32(visible_width_u16(&cfg.prepare_u16(line_u16.as_slice()), cfg)))
+		})
+		.collect()
+}
+
+/// Whether `data` fits in `max_width` cells, stopping past the limit.
+fn fits_within_u16(data: &[u16], max_width: usize, cfg: WidthConfig) -> bool {
+	!visible_width_up_to(data, max_width, cfg).1
+}
+
+/// Check whether text fits within a visible width.
+///
+/// Stops scanning at the first cell past `maxWidth`, so long lines are cheap
+/// to reject.
+#[napi(js_name = "fitsWithin")]
+pub fn fits_within(text: JsString, max_width: u32, options: Option<WidthOptions>) -> Result<bool> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let text_u16 = text.into_utf16()?;
+	Ok(fits_within_u16(&cfg.prepare_u16(text_u16.as_slice()), max_width as usize, cfg))
+}
+
+/// Check many lines against a visible width in a single call.
+///
+/// The result aligns index-for-index with `lines`.
+#[napi(js_name = "fitsWithinEach")]
+pub fn fits_within_each(
+	lines: Vec<JsString>,
+	max_width: u32,
+	options: Option<WidthOptions>,
+) -> Result<Vec<bool>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	lines
+		.into_iter()
+		.map(|line| {
+			let line_u16 = line.into_utf16()?;
+			Ok(fits_within_u16(&cfg.prepare_u16(line_u16.as_slice()), max_width as usize, cfg))
+		})
+		.collect()
+}
+
+/// Lone carriage return (`\r` not followed by `\n`) handling when splitting
+/// lines; `\r\n` is always a single break.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LoneCr {
+	/// Keep as content, measured per `controlChars` (0 cells by default).
+	Zero,
+	/// Break the line, like `\n`.
+	Break,
+	/// Reject the input.
+	Error,
+}
+
+impl LoneCr {
+	fn parse(mode: Option<&str>) -> Result<Self> {
+		match mode {
+			None | Some("zero") => Ok(Self::Zero),
+			Some("break") => Ok(Self::Break),
+			Some("error") => Ok(Self::Error),
+			Some(_) => Err(Error::from_reason("loneCr must be \"zero\", \"break\", or \"error\"")),
+		}
+	}
+
+	/// Apply the policy ahead of `split_lines_u16`; borrows when there is
+	/// nothing to do.
+	fn prepare_u16(self, text: &[u16]) -> Result<Cow<'_, [u16]>> {
+		let is_lone = |i: usize| text[i] == CR && text.get(i + 1) != Some(&(b'\n' as u16));
+		if self == Self::Zero {
+			return Ok(Cow::Borrowed(text));
+		}
+		let Some(first) = (0..text.len()).find(|&i| is_lone(i)) else {
+			return Ok(Cow::Borrowed(text));
+		};
+		if self == Self::Error {
+			return Err(Error::from_reason(format!("lone carriage return at index {first}")));
+		}
+		Ok(Cow::Owned(
+			(0..text.len())
+				.map(|i| if is_lone(i) { b'\n' as u16 } else { text[i] })
+				.collect(),
+		))
+	}
+}
+
+/// Split on `\n` like `String.prototype.split("\n")`, dropping the `\r` of
+/// `\r\n` pairs. A lone `\r` is kept as zero-width content, not a break.
+fn split_lines_u16(text: &[u16]) -> impl Iterator<Item = &[u16]> {
+	text.split(|&u| u == b'\n' as u16).map(|line| match line {
+		[rest @ .., 0x0d] => rest,
+		_ => line,
+	})
+}
+
+fn line_widths_u16(text: &[u16], cfg: WidthConfig) -> Vec<u32> {
+	split_lines_u16(text)
+		.map(|line| clamp_u32(visible_width_u16(line, cfg)))
+		.collect()
+}
+
+/// Calculate visible widths of each line of `text`.
+///
+/// Lines are split as in `measureLines`.
+#[napi(js_name = "visibleLineWidths")]
+pub fn visible_line_widths(text: JsString, options: Option<WidthOptions>) -> Result<Vec<u32>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let text_u16 = text.into_utf16()?;
+	Ok(line_widths_u16(&cfg.prepare_u16(text_u16.as_slice()), cfg))
+}
+
+#[napi(object)]
+pub struct TrimmedWidth {
+	/// Visible width up to the end of the last non-whitespace grapheme.
+	pub width:                     u32,
+	/// Columns taken by trailing spaces and tabs.
+	#[napi(js_name = "trailingWhitespaceCols")]
+	pub trailing_whitespace_cols:  u32,
+	/// UTF-16 index of the first trailing whitespace character (the line
+	/// length when there is none).
+	#[napi(js_name = "trailingWhitespaceIndex")]
+	pub trailing_whitespace_index: u32,
+}
+
+/// `(width, trailing columns, trailing index)` of `line`, where escapes
+/// after the last non-whitespace grapheme don't count as content.
+fn measure_trimmed_u16(line: &[u16], cfg: WidthConfig) -> (usize, usize, usize) {
+	let mut content = (0usize, None);
+	let mut total = 0usize;
+	walk_graphemes_u16(line, cfg, |span| {
+		total = span.col + span.width;
+		let unit = line[span.start];
+		if span.end - span.start > 1 || (unit != b' ' as u16 && unit != b'\t' as u16) {
+			content = (total, None);
+		} else if content.1.is_none() {
+			content.1 = Some(span.start);
+		}
+		true
+	});
+	let (width, index) = content;
+	(width, total - width, index.unwrap_or(line.len()))
+}
+
+/// Measure a line's visible width without its trailing spaces and tabs.
+///
+/// Also reports the trailing whitespace's columns and UTF-16 start index; a
+/// line of only whitespace has width 0. Escapes (e.g. a reset) after the
+/// whitespace don't make it content.
+#[napi(js_name = "measureTrimmed")]
+pub fn measure_trimmed(line: JsString, options: Option<WidthOptions>) -> Result<TrimmedWidth> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let (width, trailing, index) =
+		measure_trimmed_u16(&cfg.prepare_indexed_u16(line_u16.as_slice())?, cfg);
+	Ok(TrimmedWidth {
+		width:                     clamp_u32(width),
+		trailing_whitespace_cols:  clamp_u32(trailing),
+		trailing_whitespace_index: clamp_u32(index),
+	})
+}
+
+#[napi(object)]
+pub struct LineMetrics {
+	/// Widest visible line in terminal cells.
+	#[napi(js_name = "maxWidth")]
+	pub max_width:   u32,
+	/// Visible width of each line.
+	#[napi(js_name = "lineWidths")]
+	pub line_widths: Vec<u32>,
+	/// Number of lines (a trailing newline adds an empty last line).
+	#[napi(js_name = "lineCount")]
+	pub line_count:  u32,
+}
+
+/// Measure every line of multi-line text in a single pass.
+///
+/// `\n` and `\r\n` break lines. A lone `\r` is zero-width content by
+/// default; `loneCr` can make it a break ("break") or reject it ("error").
+/// Lines follow `String.prototype.split("\n")`, so a trailing newline yields
+/// an empty last line.
+#[napi(js_name = "measureLines")]
+pub fn measure_lines(
+	text: JsString,
+	options: Option<WidthOptions>,
+	lone_cr: Option<String>,
+) -> Result<LineMetrics> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let lone_cr = LoneCr::parse(lone_cr.as_deref())?;
+	let text_u16 = text.into_utf16()?;
+	let text = lone_cr.prepare_u16(text_u16.as_slice())?;
+	let line_widths = line_widths_u16(&cfg.prepare_u16(&text), cfg);
+	Ok(LineMetrics {
+		max_width: line_widths.iter().copied().max().unwrap_or(0),
+		line_count: clamp_u32(line_widths.len()),
+		line_widths,
+	})
+}
+
+// ============================================================================
+// splitAnsiLines
+// ============================================================================
+
+#[napi(object)]
+pub struct SplitLinesResult {
+	/// Lines, each prefixed with the SGR state active at its start.
+	pub lines:  Vec<Utf16String>,
+	/// Visible width of each line.
+	pub widths: Vec<u32>,
+}
+
+fn split_ansi_lines_impl(text: &[u16], cfg: WidthConfig) -> (Vec<Vec<u16>>, Vec<u32>) {
+	let mut lines = Vec::new();
+	let mut widths = Vec::new();
+	let mut state = AnsiState::new();
+
+	for line in split_lines_u16(text) {
+		let mut out = Vec::with_capacity(line.len() + 16);
+		state.write_restore_u16(&mut out);
+		out.extend_from_slice(line);
+		update_state_from_text(line, &mut state);
+		if !state.is_empty() {
+			out.extend_from_slice(&[ESC, b'[' as u16, b'0' as u16, b'm' as u16]);
+		}
+		lines.push(out);
+		widths.push(clamp_u32(visible_width_u16(line, cfg)));
+	}
+
+	(lines, widths)
+}
+
+/// Split text into lines that each render independently.
+///
+/// Lines are split as in `measureLines`, including `loneCr`. Each line starts
+/// with the restore sequence for the SGR state carried over from earlier lines
+/// and ends with a reset when style is still active.
+#[napi(js_name = "splitAnsiLines")]
+pub fn split_ansi_lines(
+	text: JsString,
+	options: Option<WidthOptions>,
+	lone_cr: Option<String>,
+) -> Result<SplitLinesResult> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let lone_cr = LoneCr::parse(lone_cr.as_deref())?;
+	let text_u16 = text.into_utf16()?;
+	let text = lone_cr.prepare_u16(text_u16.as_slice())?;
+	let (lines, widths) = split_ansi_lines_impl(&cfg.prepare_u16(&text), cfg);
+	Ok(SplitLinesResult { lines: lines.into_iter().map(build_utf16_string).collect(), widths })
+}
+
+// ============================================================================
+// normalizeNewlines
+// ============================================================================
+
+/// Rewrite line endings to `\n` or `\r\n`; `None` when already normalized.
+/// Lone `\r` is left alone, matching the default `loneCr` policy.
+fn normalize_newlines_u16(text: &[u16], crlf: bool) -> Option<Vec<u16>> {
+	const LF: u16 = b'\n' as u16;
+	if crlf {
+		let bare = |i: usize| text[i] == LF && (i == 0 || text[i - 1] != CR);
+		if !(0..text.len()).any(bare) {
+			return None;
+		}
+		let mut out = Vec::with_capacity(text.len() + 16);
+		for (i, &u) in text.iter().enumerate() {
+			if bare(i) {
+				out.push(CR);
+			}
+			out.push(u);
+		}
+		Some(out)
+	} else {
+		if !text.windows(2).any(|w| w == [CR, LF]) {
+			return None;
+		}
+		let mut out = Vec::with_capacity(text.len());
+		for (i, &u) in text.iter().enumerate() {
+			if !(u == CR && text.get(i + 1) == Some(&LF)) {
+				out.push(u);
+			}
+		}
+		Some(out)
+	}
+}
+
+/// Convert line endings to `"lf"` (`\r\n` becomes `\n`) or `"crlf"` (bare
+/// `\n` becomes `\r\n`).
+///
+/// Lone `\r` is kept as content, as `measureLines` treats it by default.
+/// Returns the input unchanged when there is nothing to rewrite.
+#[napi(js_name = "normalizeNewlines")]
+pub fn normalize_newlines(
+	text: JsString<'_>,
+	mode: String,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let crlf = match mode.as_str() {
+		"lf" => false,
+		"crlf" => true,
+		_ => return Err(Error::from_reason("mode must be \"lf\" or \"crlf\"")),
+	};
+	let original = text;
+	let text_u16 = text.into_utf16()?;
+	match normalize_newlines_u16(text_u16.as_slice(), crlf) {
+		None => Ok(Either::A(original)),
+		Some(out) => Ok(Either::B(build_utf16_string(out))),
+	}
+}
+
+// ============================================================================
+// expandTabs
+// ============================================================================
+
+fn expand_tabs_impl(text: &[u16], cfg: WidthConfig, start_column: usize) -> Vec<u16> {
+	let cfg = WidthConfig { tab_origin: cfg.tab_origin + start_column, ..cfg };
+	let mut out = Vec::with_capacity(text.len() + 16);
+	let mut last = 0usize;
+	walk_graphemes_u16(text, cfg, |span| {
+		out.extend_from_slice(&text[last..span.start]);
+		if text[span.start..span.end] == [b'\t' as u16] {
+			out.resize(out.len() + span.width, b' ' as u16);
+		} else {
+			out.extend_from_slice(&text[span.start..span.end]);
+		}
+		last = span.end;
+		true
+	});
+	out.extend_from_slice(&text[last..]);
+	out
+}
+
+/// Replace tabs with spaces up to the next tab stop.
+///
+/// Columns are counted from `startColumn` (default 0), skipping ANSI escapes.
+#[napi(js_name = "expandTabs")]
+pub fn expand_tabs(
+	text: JsString,
+	tab_stop: u32,
+	start_column: Option<u32>,
+) -> Result<Utf16String> {
+	let cfg = WidthConfig {
+		tab_width: check_tab_width(tab_stop, "tabStop")?,
+		accurate_tabs: true,
+		..WidthConfig::DEFAULT
+	};
+	let text_u16 = text.into_utf16()?;
+	Ok(build_utf16_string(expand_tabs_impl(
+		text_u16.as_slice(),
+		cfg,
+		start_column.unwrap_or(0) as usize,
+	)))
+}
+
+// ============================================================================
+// graphemeAtColumn
+// ============================================================================
+
+#[napi(object)]
+pub struct GraphemeInfo {
+	/// UTF-16 text of the grapheme.
+	pub text:         Utf16String,
+	/// Width in terminal cells.
+	pub width:        u32,
+	/// UTF-16 index of the first code unit.
+	#[napi(js_name = "startIndex")]
+	pub start_index:  u32,
+	/// UTF-16 index just past the last code unit.
+	#[napi(js_name = "endIndex")]
+	pub end_index:    u32,
+	/// Column the grapheme starts at.
+	#[napi(js_name = "startColumn")]
+	pub start_column: u32,
+}
+
+fn grapheme_at_column_u16(line: &[u16], column: usize, cfg: WidthConfig) -> Option<GraphemeSpan> {
+	let mut found = None;
+	walk_graphemes_u16(line, cfg, |span| {
+		if span.col > column {
+			return false;
+		}
+		if column < span.col + span.width {
+			found = Some(span);
+			return false;
+		}
+		true
+	});
+	found
+}
+
+/// Find the grapheme occupying a visible column.
+///
+/// Every cell of a wide grapheme maps to the same grapheme. Returns `null`
+/// past the end of the line.
+#[napi(js_name = "graphemeAtColumn")]
+pub fn grapheme_at_column(
+	line: JsString,
+	column: u32,
+	options: Option<WidthOptions>,
+) -> Result<Option<GraphemeInfo>> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let line = cfg.prepare_indexed_u16(line_u16.as_slice())?;
+	let line = &*line;
+	Ok(grapheme_at_column_u16(line, column as usize, cfg).map(|span| GraphemeInfo {
+		text:         build_utf16_string(line[span.start..span.end].to_vec()),
+		width:        clamp_u32(span.width),
+		start_index:  clamp_u32(span.start),
+		end_index:    clamp_u32(span.end),
+		start_column: clamp_u32(span.col),
+	}))
+}
+
+#[napi(object)]
+pub struct GraphemeMetrics {
+	/// Width of each grapheme in terminal cells.
+	pub widths:        Uint8Array,
+	/// UTF-16 index of each grapheme's first code unit.
+	#[napi(js_name = "startIndices")]
+	pub start_indices: Uint32Array,
+	/// Column each grapheme starts at.
+	#[napi(js_name = "startColumns")]
+	pub start_columns: Uint32Array,
+}
+
+/// Parallel width, start index, and start column arrays for every grapheme.
+fn measure_graphemes_u16(line: &[u16], cfg: WidthConfig) -> (Vec<u8>, Vec<u32>, Vec<u32>) {
+	let mut widths = Vec::new();
+	let mut starts = Vec::new();
+	let mut cols = Vec::new();
+	walk_graphemes_u16(line, cfg, |span| {
+		widths.push(span.width.min(u8::MAX as usize) as u8);
+		starts.push(clamp_u32(span.start));
+		cols.push(clamp_u32(span.col));
+		true
+	});
+	(widths, starts, cols)
+}
+
+/// Measure every grapheme of a line in one call, as typed arrays.
+///
+/// ANSI escapes are skipped; entry `i` of each array describes the same
+/// grapheme, matching what `graphemeAtColumn` reports for its columns.
+#[napi(js_name = "measureGraphemes")]
+pub fn measure_graphemes(line: JsString, options: Option<WidthOptions>) -> Result<GraphemeMetrics> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let (widths, starts, cols) =
+		measure_graphemes_u16(&cfg.prepare_indexed_u16(line_u16.as_slice())?, cfg);
+	Ok(GraphemeMetrics {
+		widths:        Uint8Array::new(widths),
+		start_indices: Uint32Array::new(starts),
+		start_columns: Uint32Array::new(cols),
+	})
+}
+
+// ============================================================================
+// graphemeCount / grapheme boundaries
+// ============================================================================
+
+fn grapheme_count_u16(text: &[u16]) -> usize {
+	let mut count = 0usize;
+	walk_graphemes_u16(text, WidthConfig::DEFAULT, |_| {
+		count += 1;
+		true
+	});
+	count
+}
+
+/// End of the first grapheme ending after `index`, or `text.len()`.
+fn next_grapheme_boundary_u16(text: &[u16], index: usize) -> usize {
+	let mut next = text.len();
+	walk_graphemes_u16(text, WidthConfig::DEFAULT, |span| {
+		if span.end > index {
+			next = span.end;
+			return false;
+		}
+		true
+	});
+	next
+}
+
+/// Start of the last grapheme starting before `index`, or 0.
+fn prev_grapheme_boundary_u16(text: &[u16], index: usize) -> usize {
+	let mut prev = 0usize;
+	walk_graphemes_u16(text, WidthConfig::DEFAULT, |span| {
+		if span.start >= index {
+			return false;
+		}
+		prev = span.start;
+		true
+	});
+	prev
+}
+
+/// Count grapheme clusters, ignoring ANSI escapes.
+#[napi(js_name = "graphemeCount")]
+pub fn grapheme_count(text: JsString) -> Result<u32> {
+	let text_u16 = text.into_utf16()?;
+	Ok(clamp_u32(grapheme_count_u16(text_u16.as_slice())))
+}
+
+/// UTF-16 index just past the grapheme after `index` (cursor right).
+///
+/// ANSI escapes are stepped over, so the result never lands inside one.
+/// Returns the text length at the end.
+#[napi(js_name = "nextGraphemeBoundary")]
+pub fn next_grapheme_boundary(text: JsString, index: u32) -> Result<u32> {
+	let text_u16 = text.into_utf16()?;
+	Ok(clamp_u32(next_grapheme_boundary_u16(text_u16.as_slice(), index as usize)))
+}
+
+/// UTF-16 index of the start of the grapheme before `index` (cursor left).
+///
+/// ANSI escapes are stepped over, so the result never lands inside one.
+/// Returns 0 at the start.
+#[napi(js_name = "prevGraphemeBoundary")]
+pub fn prev_grapheme_boundary(text: JsString, index: u32) -> Result<u32> {
+	let text_u16 = text.into_utf16()?;
+	Ok(clamp_u32(prev_grapheme_boundary_u16(text_u16.as_slice(), index as usize)))
+}
+
+// ============================================================================
+// substringBalanced
+// ============================================================================
+
+#[napi(object)]
+pub struct BalancedSubstring {
+	/// The slice, opened with the style active at `start` and closed with a
+	/// reset when a style is still active at `end`.
+	pub text:  Utf16String,
+	/// `start` after snapping to a sequence or grapheme boundary.
+	pub start: u32,
+	/// `end` after snapping to a sequence or grapheme boundary.
+	pub end:   u32,
+}
+
+/// Snap `start..end` so neither splits an escape sequence (both move back to
+/// the sequence start) nor a grapheme (both move outward to include it).
+fn snap_substring_range_u16(line: &[u16], start: usize, end: usize) -> (usize, usize) {
+	let mut end = end.min(line.len());
+	let mut start = start.min(end);
+
+	let mut i = 0usize;
+	while i < end {
+		if line[i] != ESC {
+			i += 1;
+			continue;
+		}
+		let seq_len = ansi_seq_len(line, i).unwrap_or(1);
+		if (i + 1..i + seq_len).contains(&start) {
+			start = i;
+		}
+		if (i + 1..i + seq_len).contains(&end) {
+			end = i;
+		}
+		i += seq_len;
+	}
+
+	walk_graphemes_u16(line, WidthConfig::DEFAULT, |span| {
+		if (span.start + 1..span.end).contains(&start) {
+			start = span.start;
+		}
+		if (span.start + 1..span.end).contains(&end) {
+			end = span.end;
+		}
+		span.start < end
+	});
+	(start, end)
+}
+
+fn substring_balanced_u16(line: &[u16], start: usize, end: usize) -> (Vec<u16>, usize, usize) {
+	let (start, end) = snap_substring_range_u16(line, start, end);
+	let mut state = AnsiState::new();
+	update_state_from_text(&line[..start], &mut state);
+
+	let mut out = Vec::with_capacity(end - start + 32);
+	state.write_restore_u16(&mut out);
+	out.extend_from_slice(&line[start..end]);
+	update_state_from_text(&line[start..end], &mut state);
+	if !state.is_empty() {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	(out, start, end)
+}
+
+/// Substring a styled line by raw UTF-16 indices without corrupting its
+/// styles.
+///
+/// Indices inside an escape sequence move back to its start; indices inside a
+/// surrogate pair or grapheme cluster move outward to include it. The slice
+/// opens with the SGR state active at `start` and ends with a reset when a
+/// style is active at `end`. The snapped indices are returned with the text.
+#[napi(js_name = "substringBalanced")]
+pub fn substring_balanced(line: JsString, start: u32, end: u32) -> Result<BalancedSubstring> {
+	let line_u16 = line.into_utf16()?;
+	let (text, start, end) =
+		substring_balanced_u16(line_u16.as_slice(), start as usize, end as usize);
+	Ok(BalancedSubstring {
+		text:  build_utf16_string(text),
+		start: clamp_u32(start),
+		end:   clamp_u32(end),
+	})
+}
+
+// ============================================================================
+// findVisible
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct FindVisibleOptions {
+	/// Compare lowercased text (default false).
+	#[napi(js_name = "caseInsensitive")]
+	pub case_insensitive: Option<bool>,
+	/// Skip matches starting before this visible column (default 0).
+	#[napi(js_name = "startColumn")]
+	pub start_column:     Option<u32>,
+	/// Width measurement options.
+	pub width:            Option<WidthOptions>,
+}
+
+#[napi(object)]
+pub struct VisibleMatch {
+	/// Visible column of the first matched grapheme.
+	#[napi(js_name = "startCol")]
+	pub start_col:   u32,
+	/// Visible column just past the last matched grapheme.
+	#[napi(js_name = "endCol")]
+	pub end_col:     u32,
+	/// UTF-16 index of the match start in the original string.
+	#[napi(js_name = "startIndex")]
+	pub start_index: u32,
+	/// UTF-16 index just past the match in the original string.
+	#[napi(js_name = "endIndex")]
+	pub end_index:   u32,
+}
+
+/// Matches of `needle` starting at or after `start_column`, as their first
+/// and last graphemes.
+fn find_visible_u16(
+	haystack: &[u16],
+	needle: &str,
+	case_insensitive: bool,
+	start_column: usize,
+	cfg: WidthConfig,
+) -> Vec<(GraphemeSpan, GraphemeSpan)> {
+	let fold = |c: char, out: &mut String| {
+		if case_insensitive {
+			out.extend(c.to_lowercase());
+		} else {
+			out.push(c);
+		}
+	};
+
+	// Stripped, folded text; each grapheme's byte range in it.
+	let mut graphemes = Vec::new();
+	let mut bounds: Vec<usize> = vec![0];
+	let mut text = String::with_capacity(haystack.len());
+	walk_graphemes_u16(haystack, cfg, |span| {
+		for c in std::char::decode_utf16(haystack[span.start..span.end].iter().copied()) {
+			fold(c.unwrap_or('\u{FFFD}'), &mut text);
+		}
+		graphemes.push(span);
+		bounds.push(text.len());
+		true
+	});
+
+	let mut pattern = String::with_capacity(needle.len());
+	for c in needle.chars() {
+		fold(c, &mut pattern);
+	}
+	if pattern.is_empty() {
+		return Vec::new();
+	}
+
+	let mut matches = Vec::new();
+	let mut from = bounds[graphemes.partition_point(|g| g.col < start_column)];
+	while let Some(off) = text[from..].find(&pattern) {
+		let start = from + off;
+		let end = start + pattern.len();
+		let first = bounds.binary_search(&start);
+		let last = bounds.binary_search(&end);
+		if let (Ok(first), Ok(last)) = (first, last) {
+			matches.push((graphemes[first], graphemes[last - 1]));
+			from = end;
+		} else {
+			// Starts or ends inside a grapheme: retry one character later.
+			from = start + text[start..].chars().next().map_or(1, char::len_utf8);
+		}
+	}
+	matches
+}
+
+/// Find a plain-text needle in the visible text of a styled string.
+///
+/// The search runs on the ANSI-stripped (and, with `caseInsensitive`,
+/// lowercased) text; matches must cover whole graphemes. Matches are reported
+/// left to right without overlapping, as visible column ranges and as UTF-16
+/// index ranges into the original string that never start or end inside an
+/// escape sequence.
+#[napi(js_name = "findVisible")]
+pub fn find_visible(
+	haystack: JsString,
+	needle: String,
+	options: Option<FindVisibleOptions>,
+) -> Result<Vec<VisibleMatch>> {
+	let options = options.unwrap_or_default();
+	let cfg = WidthConfig::from_options(options.width.as_ref())?;
+	let haystack_u16 = haystack.into_utf16()?;
+	let haystack = cfg.prepare_indexed_u16(haystack_u16.as_slice())?;
+	let start_column = options.start_column.unwrap_or(0) as usize;
+
+	let case_insensitive = options.case_insensitive.unwrap_or(false);
+	let matches = find_visible_u16(&haystack, &needle, case_insensitive, start_column, cfg);
+	Ok(matches
+		.into_iter()
+		.map(|(first, last)| VisibleMatch {
+			start_col:   clamp_u32(first.col),
+			end_col:     clamp_u32(last.col + last.width),
+			start_index: clamp_u32(first.start),
+			end_index:   clamp_u32(last.end),
+		})
+		.collect())
+}
+
+// ============================================================================
+// insertAtColumn
+// ============================================================================
+
+/// Split point for inserting at `column`: the UTF-16 index of the first
+/// grapheme that ends past `column`, and the width before it.
+fn split_at_column_u16(line: &[u16], column: usize, cfg: WidthConfig) -> (usize, usize) {
+	let mut split = None;
+	let mut width = 0usize;
+	walk_graphemes_u16(line, cfg, |span| {
+		if span.col + span.width > column {
+			split = Some((span.start, span.col));
+			return false;
+		}
+		width = span.col + span.width;
+		true
+	});
+	split.unwrap_or((line.len(), width))
+}
+
+fn contains_sgr_u16(data: &[u16]) -> bool {
+	let mut i = 0usize;
+	while i < data.len() {
+		if data[i] == ESC
+			&& let Some(seq_len) = ansi_seq_len(data, i)
+		{
+			if is_sgr(&data[i..i + seq_len]) {
+				return true;
+			}
+			i += seq_len;
+			continue;
+		}
+		i += 1;
+	}
+	false
+}
+
+fn insert_at_column_impl(
+	line: &[u16],
+	column: usize,
+	insert: &[u16],
+	cfg: WidthConfig,
+) -> Vec<u16> {
+	let (split, before_w) = split_at_column_u16(line, column, cfg);
+	let (before, after) = line.split_at(split);
+
+	let mut out =
+		Vec::with_capacity(line.len() + insert.len() + column.saturating_sub(before_w) + 16);
+	out.extend_from_slice(before);
+	out.resize(out.len() + column.saturating_sub(before_w), b' ' as u16);
+	out.extend_from_slice(insert);
+	if contains_sgr_u16(insert) {
+		let mut state = AnsiState::new();
+		update_state_from_text(before, &mut state);
+		out.extend_from_slice(&SGR_RESET);
+		state.write_restore_u16(&mut out);
+	}
+	out.extend_from_slice(after);
+	out
+}
+
+/// Insert text into a line at a visible column.
+///
+/// ANSI codes before the column stay with the head, and the line's style is
+/// restored after `insert` so the remainder renders unchanged. Short lines are
+/// padded with spaces; a wide char under `column` moves after the insertion.
+#[napi(js_name = "insertAtColumn")]
+pub fn insert_at_column(
+	line: JsString,
+	column: u32,
+	insert: JsString,
+	options: Option<WidthOptions>,
+) -> Result<Utf16String> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let insert_u16 = insert.into_utf16()?;
+	Ok(build_utf16_string(insert_at_column_impl(
+		&cfg.prepare_u16(line_u16.as_slice()),
+		column as usize,
+		insert_u16.as_slice(),
+		cfg,
+	)))
+}
+
+// ============================================================================
+// replaceRange
+// ============================================================================
+
+/// UTF-16 bounds of the graphemes touching `[start_col, end_col)`, snapped
+/// outward over wide chars, plus the padding needed when the line ends before
+/// `start_col`.
+fn column_range_u16(
+	line: &[u16],
+	start_col: usize,
+	end_col: usize,
+	cfg: WidthConfig,
+) -> (Range<usize>, usize) {
+	let mut start = None;
+	let mut end = None;
+	let mut width = 0usize;
+	walk_graphemes_u16(line, cfg, |span| {
+		if start.is_none() && span.col + span.width > start_col {
+			start = Some(span.start);
+		}
+		if span.col >= end_col {
+			end = Some(span.start);
+			return false;
+		}
+		width = span.col + span.width;
+		true
+	});
+	let Some(start) = start else {
+		return (line.len()..line.len(), start_col.saturating_sub(width));
+	};
+	let end = if end_col > start_col {
+		end.unwrap_or(line.len()).max(start)
+	} else {
+		start
+	};
+	(start..end, 0)
+}
+
+fn replace_range_impl(
+	line: &[u16],
+	start_col: usize,
+	end_col: usize,
+	replacement: &[u16],
+	cfg: WidthConfig,
+) -> Vec<u16> {
+	let (range, pad) = column_range_u16(line, start_col, end_col, cfg);
+
+	let mut out = Vec::with_capacity(line.len() + replacement.len() + pad + 16);
+	out.extend_from_slice(&line[..range.start]);
+	out.resize(out.len() + pad, b' ' as u16);
+	out.extend_from_slice(replacement);
+
+	let mut before = AnsiState::new();
+	update_state_from_text(&line[..range.start], &mut before);
+	let mut after = before;
+	update_state_from_text(&line[range.clone()], &mut after);
+	if range.end < line.len() && (after != before || contains_sgr_u16(replacement)) {
+		out.extend_from_slice(&SGR_RESET);
+		after.write_restore_u16(&mut out);
+	}
+	out.extend_from_slice(&line[range.end..]);
+	out
+}
+
+/// Replace the visible content in `[startCol, endCol)` of a line.
+///
+/// Everything before `startCol` is kept byte-identical and ranges that cut a
+/// wide char grow to cover it. The line's style at `endCol` is restored after
+/// `replacement`, and the rest of the line shifts by the width difference.
+/// Short lines are padded with spaces up to `startCol`.
+#[napi(js_name = "replaceRange")]
+pub fn replace_range(
+	line: JsString,
+	start_col: u32,
+	end_col: u32,
+	replacement: JsString,
+	options: Option<WidthOptions>,
+) -> Result<Utf16String> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let replacement_u16 = replacement.into_utf16()?;
+	Ok(build_utf16_string(replace_range_impl(
+		&cfg.prepare_indexed_u16(line_u16.as_slice())?,
+		start_col as usize,
+		end_col as usize,
+		replacement_u16.as_slice(),
+		cfg,
+	)))
+}
+
+// ============================================================================
+// applyStyleToRange
+// ============================================================================
+
+fn apply_style_to_range_impl(
+	line: &[u16],
+	start_col: usize,
+	end_col: usize,
+	sgr: &[u16],
+	cfg: WidthConfig,
+) -> Vec<u16> {
+	if start_col >= end_col || sgr.is_empty() {
+		return line.to_vec();
+	}
+
+	let mut out = Vec::with_capacity(line.len() + sgr.len() * 2 + 16);
+	let mut state = AnsiState::new();
+	let mut in_range = false;
+	let mut done = false;
+	let mut last = 0usize;
+
+	// Copy the escapes in `line[range]`, tracking the style they set.
+	let copy_escapes =
+		|range: Range<usize>, in_range: bool, state: &mut AnsiState, out: &mut Vec<u16>| {
+			let mut i = range.start;
+			while i < range.end {
+				let seq_len = ansi_seq_len(line, i).unwrap_or(1);
+				let seq = &line[i..i + seq_len];
+				out.extend_from_slice(seq);
+				if is_sgr(seq) {
+					state.apply_sgr(&seq[2..seq_len - 1]);
+					// Re-assert the highlight so it wins over codes inside the
+					// range.
+					if in_range {
+						out.extend_from_slice(sgr);
+					}
+				}
+				i += seq_len;
+			}
+		};
+
+	walk_graphemes_u16(line, cfg, |span| {
+		copy_escapes(last..span.start, in_range, &mut state, &mut out);
+		last = span.start;
+		// Wide chars straddling either boundary snap outward into the range.
+		if !in_range && span.col + span.width > start_col {
+			out.extend_from_slice(sgr);
+			in_range = true;
+		}
+		if in_range && span.col >= end_col {
+			out.extend_from_slice(&SGR_RESET);
+			state.write_restore_u16(&mut out);
+			in_range = false;
+			done = true;
+			return false;
+		}
+		out.extend_from_slice(&line[span.start..span.end]);
+		last = span.end;
+		true
+	});
+
+	if done {
+		out.extend_from_slice(&line[last..]);
+	} else {
+		copy_escapes(last..line.len(), in_range, &mut state, &mut out);
+	}
+	if in_range {
+		out.extend_from_slice(&SGR_RESET);
+		state.write_restore_u16(&mut out);
+	}
+
+	out
+}
+
+/// Apply an SGR sequence to a range of visible columns.
+///
+/// `sgr` is injected at `start`; at `end` the style originally active there is
+/// restored. SGR codes inside the range are kept but followed by `sgr` again
+/// so the highlight wins. Wide chars straddling a boundary join the range.
+#[napi(js_name = "applyStyleToRange")]
+pub fn apply_style_to_range(
+	line: JsString,
+	start: u32,
+	end: u32,
+	sgr: Utf16String,
+	options: Option<WidthOptions>,
+) -> Result<Utf16String> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	Ok(build_utf16_string(apply_style_to_range_impl(
+		&cfg.prepare_u16(line_u16.as_slice()),
+		start as usize,
+		end as usize,
+		&sgr,
+		cfg,
+	)))
+}
+
+// ============================================================================
+// highlightMatches
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct HighlightOptions {
+	/// Compare lowercased text (default false).
+	#[napi(js_name = "caseInsensitive")]
+	pub case_insensitive: Option<bool>,
+	/// Only highlight matches not adjoined by letters, digits, or `_`
+	/// (default false).
+	#[napi(js_name = "wholeWord")]
+	pub whole_word:       Option<bool>,
+	/// Width measurement options.
+	pub width:            Option<WidthOptions>,
+}
+
+/// UTF-16 index ranges of the matches of `query` to highlight.
+fn highlight_ranges_u16(
+	line: &[u16],
+	query: &str,
+	case_insensitive: bool,
+	whole_word: bool,
+	cfg: WidthConfig,
+) -> Vec<(usize, usize)> {
+	let matches = find_visible_u16(line, query, case_insensitive, 0, cfg);
+	if !whole_word || matches.is_empty() {
+		return matches
+			.iter()
+			.map(|(first, last)| (first.start, last.end))
+			.collect();
+	}
+
+	let mut graphemes = Vec::new();
+	walk_graphemes_u16(line, cfg, |span| {
+		graphemes.push(span);
+		true
+	});
+	let is_word = |span: Option<&GraphemeSpan>| {
+		span
+			.and_then(|g| std::char::decode_utf16(line[g.start..g.end].iter().copied()).next())
+			.and_then(|c| c.ok())
+			.is_some_and(|c| c.is_alphanumeric() || c == '_')
+	};
+	matches
+		.iter()
+		.filter(|(first, last)| {
+			let before = graphemes.partition_point(|g| g.start < first.start);
+			let after = graphemes.partition_point(|g| g.start < last.end);
+			!is_word(before.checked_sub(1).map(|i| &graphemes[i])) && !is_word(graphemes.get(after))
+		})
+		.map(|(first, last)| (first.start, last.end))
+		.collect()
+}
+
+/// Wrap each range of `line` in `sgr`, restoring the style active before it
+/// afterwards. SGR codes inside a range are kept but followed by `sgr` again
+/// so the highlight wins.
+fn highlight_ranges_apply_u16(line: &[u16], ranges: &[(usize, usize)], sgr: &[u16]) -> Vec<u16> {
+	let mut out = Vec::with_capacity(line.len() + ranges.len() * (sgr.len() + 16));
+	let mut state = AnsiState::new();
+	let mut pos = 0usize;
+
+	for &(start, end) in ranges {
+		update_state_from_text(&line[pos..start], &mut state);
+		out.extend_from_slice(&line[pos..start]);
+		out.extend_from_slice(sgr);
+
+		let mut i = start;
+		while i < end {
+			if line[i] != ESC {
+				let run = line[i..end]
+					.iter()
+					.position(|&u| u == ESC)
+					.unwrap_or(end - i);
+				out.extend_from_slice(&line[i..i + run]);
+				i += run;
+				continue;
+			}
+			let seq_len = ansi_seq_len(&line[..end], i).unwrap_or(1);
+			let seq = &line[i..i + seq_len];
+			out.extend_from_slice(seq);
+			if is_sgr(seq) {
+				state.apply_sgr(&seq[2..seq_len - 1]);
+				out.extend_from_slice(sgr);
+			}
+			i += seq_len;
+		}
+
+		out.extend_from_slice(&SGR_RESET);
+		state.write_restore_u16(&mut out);
+		pos = end;
+	}
+	out.extend_from_slice(&line[pos..]);
+	out
+}
+
+/// Highlight every match of a plain-text query in a styled line.
+///
+/// Matches are found like `findVisible` and each is wrapped in `sgr`; after a
+/// match the style originally active there is restored, and SGR codes inside
+/// a match are followed by `sgr` again so the highlight wins. A line without
+/// matches is returned unchanged.
+#[napi(js_name = "highlightMatches")]
+pub fn highlight_matches(
+	line: JsString<'_>,
+	query: String,
+	sgr: Utf16String,
+	options: Option<HighlightOptions>,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let options = options.unwrap_or_default();
+	let cfg = WidthConfig::from_options(options.width.as_ref())?;
+	let original = line;
+	let line_u16 = line.into_utf16()?;
+	let line = cfg.prepare_u16(line_u16.as_slice());
+
+	let ranges = highlight_ranges_u16(
+		&line,
+		&query,
+		options.case_insensitive.unwrap_or(false),
+		options.whole_word.unwrap_or(false),
+		cfg,
+	);
+	match line {
+		Cow::Borrowed(_) if ranges.is_empty() || sgr.is_empty() => Ok(Either::A(original)),
+		line if sgr.is_empty() => Ok(Either::B(build_utf16_string(line.into_owned()))),
+		line => Ok(Either::B(build_utf16_string(highlight_ranges_apply_u16(&line, &ranges, &sgr)))),
+	}
+}
+
+// ============================================================================
+// styleAtColumn / styleToSgr
+// ============================================================================
+
+#[napi(object)]
+pub struct ColorInfo {
+	/// "none", "indexed" (0-255; 0-15 are the basic colors) or "rgb".
+	pub kind:  String,
+	/// Palette index, or `0xRRGGBB` for "rgb".
+	pub value: u32,
+	/// Resolved color, when requested (absent for "none").
+	pub rgb:   Option<RgbColor>,
+}
+
+#[napi(object)]
+pub struct StyleInfo {
+	/// Bold (SGR 1).
+	pub bold:          bool,
+	/// Dim (SGR 2).
+	pub dim:           bool,
+	/// Italic (SGR 3).
+	pub italic:        bool,
+	/// Underline (SGR 4).
+	pub underline:     bool,
+	/// Blink (SGR 5).
+	pub blink:         bool,
+	/// Inverse (SGR 7).
+	pub inverse:       bool,
+	/// Hidden (SGR 8).
+	pub hidden:        bool,
+	/// Strikethrough (SGR 9).
+	pub strikethrough: bool,
+	/// Foreground color.
+	pub fg:            ColorInfo,
+	/// Background color.
+	pub bg:            ColorInfo,
+}
+
+fn color_info(color: ColorVal) -> ColorInfo {
+	let (kind, value) = match color {
+		COLOR_NONE => ("none", 0),
+		1..=16 => ("indexed", color - 1),
+		_ if color < 0x1000000 => ("indexed", color & 0xff),
+		_ => ("rgb", color & 0xffffff),
+	};
+	ColorInfo { kind: kind.to_owned(), value, rgb: None }
+}
+
+fn color_val(info: &ColorInfo) -> Result<ColorVal> {
+	match info.kind.as_str() {
+		"none" => Ok(COLOR_NONE),
+		"indexed" if info.value < 16 => Ok(info.value + 1),
+		"indexed" if info.value < 256 => Ok(0x100 | info.value),
+		"rgb" if info.value <= 0xffffff => Ok(0x1000000 | info.value),
+		"indexed" | "rgb" => Err(Error::from_reason(format!(
+			"color value {} out of range for kind \"{}\"",
+			info.value, info.kind
+		))),
+		kind => Err(Error::from_reason(format!("unknown color kind \"{kind}\""))),
+	}
+}
+
+const STYLE_ATTRS: [u16; 8] = [
+	ATTR_BOLD,
+	ATTR_DIM,
+	ATTR_ITALIC,
+	ATTR_UNDERLINE,
+	ATTR_BLINK,
+	ATTR_INVERSE,
+	ATTR_HIDDEN,
+	ATTR_STRIKE,
+];
+
+impl From<AnsiState> for StyleInfo {
+	fn from(state: AnsiState) -> Self {
+		let has = |attr| state.attrs & attr != 0;
+		Self {
+			bold:          has(ATTR_BOLD),
+			dim:           has(ATTR_DIM),
+			italic:        has(ATTR_ITALIC),
+			underline:     has(ATTR_UNDERLINE),
+			blink:         has(ATTR_BLINK),
+			inverse:       has(ATTR_INVERSE),
+			hidden:        has(ATTR_HIDDEN),
+			strikethrough: has(ATTR_STRIKE),
+			fg:            color_info(state.fg),
+			bg:            color_info(state.bg),
+		}
+	}
+}
+
+impl TryFrom<&StyleInfo> for AnsiState {
+	type Error = Error;
+
+	fn try_from(style: &StyleInfo) -> Result<Self> {
+		let flags = [
+			style.bold,
+			style.dim,
+			style.italic,
+			style.underline,
+			style.blink,
+			style.inverse,
+			style.hidden,
+			style.strikethrough,
+		];
+		let attrs = STYLE_ATTRS
+			.iter()
+			.zip(flags)
+			.filter(|&(_, set)| set)
+			.fold(0, |acc, (&attr, _)| acc | attr);
+		Ok(Self { attrs, fg: color_val(&style.fg)?, bg: color_val(&style.bg)? })
+	}
+}
+
+/// SGR state in effect at a visible column (or at the end of the line).
+fn style_at_column_u16(line: &[u16], column: usize, cfg: WidthConfig) -> AnsiState {
+	let end = grapheme_at_column_u16(line, column, cfg).map_or(line.len(), |span| span.start);
+	let mut state = AnsiState::new();
+	update_state_from_text(&line[..end], &mut state);
+	state
+}
+
+/// Query the SGR style in effect at a visible column.
+///
+/// Past the end of the line, returns the style active after the last
+/// sequence. With `resolveColors`, colors also carry `rgb` resolved against
+/// the default palette.
+#[napi(js_name = "styleAtColumn")]
+pub fn style_at_column(
+	line: JsString,
+	column: u32,
+	options: Option<WidthOptions>,
+	resolve_colors: Option<bool>,
+) -> Result<StyleInfo> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let state = style_at_column_u16(&cfg.prepare_u16(line_u16.as_slice()), column as usize, cfg);
+	Ok(if resolve_colors.unwrap_or(false) {
+		StyleInfo::resolved(state, &DEFAULT_PALETTE.read())
+	} else {
+		state.into()
+	})
+}
+
+/// Serialize a style to the SGR sequence that establishes it.
+///
+/// Returns an empty string for the default style.
+#[napi(js_name = "styleToSgr")]
+pub fn style_to_sgr(style: StyleInfo) -> Result<Utf16String> {
+	let state = AnsiState::try_from(&style)?;
+	let mut out = Vec::new();
+	state.write_restore_u16(&mut out);
+	Ok(build_utf16_string(out))
+}
+
+// ============================================================================
+// resolveColor
+// ============================================================================
+
+#[napi(object)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RgbColor {
+	pub r: u8,
+	pub g: u8,
+	pub b: u8,
+}
+
+impl From<Rgb> for RgbColor {
+	fn from((r, g, b): Rgb) -> Self {
+		Self { r, g, b }
+	}
+}
+
+/// Base 16 colors used to resolve indexed colors when no palette is given.
+static DEFAULT_PALETTE: RwLock<[Rgb; 16]> = RwLock::new(BASIC_PALETTE);
+
+/// Validate a palette of 16 `0xRRGGBB` base colors.
+fn palette_table(colors: &[u32]) -> Result<[Rgb; 16]> {
+	let mut table = [(0, 0, 0); 16];
+	if colors.len() != table.len() {
+		return Err(Error::from_reason(format!("palette must have 16 colors, got {}", colors.len())));
+	}
+	for (slot, &color) in table.iter_mut().zip(colors) {
+		if color > 0xffffff {
+			return Err(Error::from_reason(format!("palette color {color:#x} is not 0xRRGGBB")));
+		}
+		*slot = ((color >> 16) as u8, (color >> 8) as u8, color as u8);
+	}
+	Ok(table)
+}
+
+/// RGB value of a color; `None` for the default color and out-of-range
+/// values.
+fn resolve_color_rgb(color: &ColorInfo, basic: &[Rgb; 16]) -> Result<Option<Rgb>> {
+	match color.kind.as_str() {
+		"indexed" if color.value > 0xff => Ok(None),
+		"rgb" if color.value > 0xffffff => Ok(None),
+		_ => Ok(color_rgb(color_val(color)?, basic)),
+	}
+}
+
+impl StyleInfo {
+	/// Structured style with each color's `rgb` filled from `basic`.
+	fn resolved(state: AnsiState, basic: &[Rgb; 16]) -> Self {
+		let mut info = Self::from(state);
+		info.fg.rgb = color_rgb(state.fg, basic).map(Into::into);
+		info.bg.rgb = color_rgb(state.bg, basic).map(Into::into);
+		info
+	}
+}
+
+/// Replace the process-wide palette used to resolve indexed colors.
+///
+/// `colors` are the 16 base colors as `0xRRGGBB`; the 6x6x6 cube and
+/// grayscale ramp (16-255) are fixed. Pass an empty list to restore the
+/// xterm defaults.
+#[napi(js_name = "setDefaultPalette")]
+pub fn set_default_palette(colors: Vec<u32>) -> Result<()> {
+	let table = if colors.is_empty() {
+		BASIC_PALETTE
+	} else {
+		palette_table(&colors)?
+	};
+	*DEFAULT_PALETTE.write() = table;
+	Ok(())
+}
+
+/// Resolve a color to RGB.
+///
+/// Indexed colors 0-15 come from `palette` (16 `0xRRGGBB` values) or the
+/// default palette. Returns `null` for the default color and out-of-range
+/// values.
+#[napi(js_name = "resolveColor")]
+pub fn resolve_color(color: ColorInfo, palette: Option<Vec<u32>>) -> Result<Option<RgbColor>> {
+	let basic = match palette {
+		Some(colors) => palette_table(&colors)?,
+		None => *DEFAULT_PALETTE.read(),
+	};
+	Ok(resolve_color_rgb(&color, &basic)?.map(Into::into))
+}
+
+// ============================================================================
+// diffVisible
+// ============================================================================
+
+#[napi(object)]
+pub struct VisibleDiff {
+	/// Columns at the start of both lines with identical text and style.
+	#[napi(js_name = "commonPrefixCols")]
+	pub common_prefix_cols: u32,
+	/// Columns at the end of both lines with identical text and style (never
+	/// overlapping the prefix).
+	#[napi(js_name = "commonSuffixCols")]
+	pub common_suffix_cols: u32,
+	/// Whether the lines render differently.
+	pub changed:            bool,
+}
+
+/// Visible graphemes of `line`, each with the SGR state it is drawn in.
+fn styled_graphemes_u16(line: &[u16], cfg: WidthConfig) -> Vec<(GraphemeSpan, AnsiState)> {
+	let mut out = Vec::new();
+	let mut state = AnsiState::new();
+	let mut last = 0usize;
+	walk_graphemes_u16(line, cfg, |span| {
+		if span.start > last {
+			update_state_from_text(&line[last..span.start], &mut state);
+		}
+		last = span.end;
+		out.push((span, state));
+		true
+	});
+	out
+}
+
+fn diff_visible_u16(old: &[u16], new: &[u16], cfg: WidthConfig) -> VisibleDiff {
+	let old_g = styled_graphemes_u16(old, cfg);
+	let new_g = styled_graphemes_u16(new, cfg);
+	let same = |(a, sa): &(GraphemeSpan, AnsiState), (b, sb): &(GraphemeSpan, AnsiState)| {
+		a.width == b.width && sa == sb && old[a.start..a.end] == new[b.start..b.end]
+	};
+
+	let prefix = old_g
+		.iter()
+		.zip(&new_g)
+		.take_while(|(a, b)| same(a, b))
+		.count();
+	let prefix_cols: usize = old_g[..prefix].iter().map(|(span, _)| span.width).sum();
+
+	// The suffix may only use graphemes the prefix hasn't claimed.
+	let suffix = old_g[prefix..]
+		.iter()
+		.rev()
+		.zip(new_g[prefix..].iter().rev())
+		.take_while(|(a, b)| same(a, b))
+		.count();
+	let suffix_cols: usize = old_g[old_g.len() - suffix..]
+		.iter()
+		.map(|(span, _)| span.width)
+		.sum();
+
+	VisibleDiff {
+		common_prefix_cols: clamp_u32(prefix_cols),
+		common_suffix_cols: clamp_u32(suffix_cols),
+		changed:            prefix != old_g.len() || prefix != new_g.len(),
+	}
+}
+
+/// Compare two rendered lines for a minimal redraw.
+///
+/// Regions only match when both the text and the SGR style match; boundaries
+/// always fall between graphemes, so wide characters are never split.
+#[napi(js_name = "diffVisible")]
+pub fn diff_visible(old_line: JsString, new_line: JsString) -> Result<VisibleDiff> {
+	let old_u16 = old_line.into_utf16()?;
+	let new_u16 = new_line.into_utf16()?;
+	Ok(diff_visible_u16(old_u16.as_slice(), new_u16.as_slice(), WidthConfig::DEFAULT))
+}
+
+// ============================================================================
+// parseAnsiSpans / spansToAnsi
+// ============================================================================
+
+#[napi(object)]
+pub struct AnsiSpan {
+	/// Plain text of the span (no escape sequences).
+	pub text:     Utf16String,
+	/// Visible width of `text` in terminal cells.
+	pub width:    u32,
+	/// SGR style applied to the span.
+	pub style:    StyleInfo,
+	/// OSC 8 hyperlink target, if the span is inside a link.
+	pub link:     Option<String>,
+	/// Raw non-SGR control sequence (only with `keepSequences`); such spans
+	/// carry no text.
+	pub sequence: Option<Utf16String>,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct ParseSpansOptions {
+	/// Surface non-SGR sequences (cursor movement, ...) as `sequence` spans
+	/// instead of dropping them (default false).
+	#[napi(js_name = "keepSequences")]
+	pub keep_sequences: Option<bool>,
+	/// Fill each color's `rgb` from the default palette (default false).
+	#[napi(js_name = "resolveColors")]
+	pub resolve_colors: Option<bool>,
+	/// Width measurement options.
+	pub width:          Option<WidthOptions>,
+}
+
+/// URI of an OSC 8 hyperlink sequence (empty when the link is closed).
+fn osc8_uri_u16(seq: &[u16]) -> Option<&[u16]> {
+	let body = seq.strip_prefix(&[ESC, b']' as u16, b'8' as u16, b';' as u16])?;
+	let body = match body {
+		[rest @ .., 0x07] => rest,
+		[rest @ .., ESC, 0x5c] => rest,
+		_ => return None,
+	};
+	let params_end = body.iter().position(|&u| u == b';' as u16)?;
+	Some(&body[params_end + 1..])
+}
+
+/// Intermediate span produced by the parser, before napi conversion.
+struct RawSpan {
+	text:     Vec<u16>,
+	width:    usize,
+	style:    AnsiState,
+	link:     Option<Vec<u16>>,
+	sequence: Option<Vec<u16>>,
+}
+
+fn parse_ansi_spans_u16(line: &[u16], keep_sequences: bool, cfg: WidthConfig) -> Vec<RawSpan> {
+	let mut spans = Vec::new();
+	let mut state = AnsiState::new();
+	let mut link: Option<Vec<u16>> = None;
+	let mut text = Vec::new();
+	let mut i = 0usize;
+	let len = line.len();
+
+	macro_rules! flush {
+		() => {
+			if !text.is_empty() {
+				let text = std::mem::take(&mut text);
+				let width = visible_width_u16(&text, cfg);
+				spans.push(RawSpan { text, width, style: state, link: link.clone(), sequence: None });
+			}
+		};
+	}
+
+	while i < len {
+		if line[i] != ESC {
+			text.push(line[i]);
+			i += 1;
+			continue;
+		}
+		let Some(seq_len) = ansi_seq_len(line, i) else {
+			// Lone or unterminated ESC: dropped.
+			i += 1;
+			continue;
+		};
+		let seq = &line[i..i + seq_len];
+		i += seq_len;
+
+		if is_sgr(seq) {
+			let mut next = state;
+			next.apply_sgr(&seq[2..seq_len - 1]);
+			if next != state {
+				flush!();
+				state = next;
+			}
+		} else if let Some(uri) = osc8_uri_u16(seq) {
+			let next = (!uri.is_empty()).then(|| uri.to_vec());
+			if next != link {
+				flush!();
+				link = next;
+			}
+		} else if keep_sequences {
+			flush!();
+			spans.push(RawSpan {
+				text:     Vec::new(),
+				width:    0,
+				style:    state,
+				link:     link.clone(),
+				sequence: Some(seq.to_vec()),
+			});
+		}
+	}
+	flush!();
+
+	spans
+}
+
+fn write_osc8_u16(out: &mut Vec<u16>, uri: &[u16]) {
+	out.extend_from_slice(&[ESC, b']' as u16, b'8' as u16, b';' as u16, b';' as u16]);
+	out.extend_from_slice(uri);
+	out.push(0x07);
+}
+
+fn spans_to_ansi_u16(spans: &[AnsiSpan]) -> Result<Vec<u16>> {
+	let mut out = Vec::new();
+	let mut state = AnsiState::new();
+	let mut link: Option<&str> = None;
+
+	for span in spans {
+		let next_link = span.link.as_deref().filter(|l| !l.is_empty());
+		if next_link != link {
+			let uri: Vec<u16> = next_link.unwrap_or("").encode_utf16().collect();
+			write_osc8_u16(&mut out, &uri);
+			link = next_link;
+		}
+
+		let next = AnsiState::try_from(&span.style)?;
+		if next != state {
+			if !state.is_empty() {
+				out.extend_from_slice(&SGR_RESET);
+			}
+			next.write_restore_u16(&mut out);
+			state = next;
+		}
+
+		if let Some(seq) = &span.sequence {
+			out.extend_from_slice(seq);
+		}
+		out.extend_from_slice(&span.text);
+	}
+
+	if !state.is_empty() {
+		out.extend_from_slice(&SGR_RESET);
+	}
+	if link.is_some() {
+		write_osc8_u16(&mut out, &[]);
+	}
+
+	Ok(out)
+}
+
+/// Parse a styled line into spans of plain text with structured styles.
+///
+/// A new span starts whenever the SGR style or OSC 8 link changes. Other
+/// control sequences are dropped unless `keepSequences` is set.
+#[napi(js_name = "parseAnsiSpans")]
+pub fn parse_ansi_spans(
+	line: JsString,
+	options: Option<ParseSpansOptions>,
+) -> Result<Vec<AnsiSpan>> {
+	let options = options.unwrap_or_default();
+	let cfg = WidthConfig::from_options(options.width.as_ref())?;
+	let line_u16 = line.into_utf16()?;
+	let spans = parse_ansi_spans_u16(
+		&cfg.prepare_u16(line_u16.as_slice()),
+		options.keep_sequences.unwrap_or(false),
+		cfg,
+	);
+	let palette = options
+		.resolve_colors
+		.unwrap_or(false)
+		.then(|| *DEFAULT_PALETTE.read());
+	Ok(spans
+		.into_iter()
+		.map(|span| AnsiSpan {
+			text:     build_utf16_string(span.text),
+			width:    clamp_u32(span.width),
+			style:    match &palette {
+				Some(basic) => StyleInfo::resolved(span.style, basic),
+				None => span.style.into(),
+			},
+			link:     span.link.map(|uri| String::from_utf16_lossy(&uri)),
+			sequence: span.sequence.map(build_utf16_string),
+		})
+		.collect())
+}
+
+/// Serialize spans back to an ANSI string.
+///
+/// The visible result matches the line the spans were parsed from; escape
+/// sequences are re-emitted minimally.
+#[napi(js_name = "spansToAnsi")]
+pub fn spans_to_ansi(spans: Vec<AnsiSpan>) -> Result<Utf16String> {
+	Ok(build_utf16_string(spans_to_ansi_u16(&spans)?))
+}
+
+// ============================================================================
+// extractLinks
+// ============================================================================
+
+#[napi(object)]
+pub struct LinkRange {
+	/// Target of the OSC 8 hyperlink.
+	pub uri:         String,
+	/// Column where the link's visible text starts.
+	#[napi(js_name = "startCol")]
+	pub start_col:   u32,
+	/// Column just past the link's visible text.
+	#[napi(js_name = "endCol")]
+	pub end_col:     u32,
+	/// UTF-16 index just past the opening sequence.
+	#[napi(js_name = "startIndex")]
+	pub start_index: u32,
+	/// UTF-16 index of the sequence ending the link, or the line length.
+	#[napi(js_name = "endIndex")]
+	pub end_index:   u32,
+}
+
+/// OSC 8 links in `line` as (URI, UTF-16 range, column range). A link ends at
+/// a close, at the next open (which replaces it), or at the end of the line;
+/// links covering no visible text are skipped.
+fn extract_links_u16<'a>(line: &'a [u16]) -> Vec<(&'a [u16], Range<usize>, Range<usize>)> {
+	let mut starts = Vec::new();
+	let mut width = 0usize;
+	walk_graphemes_u16(line, WidthConfig::DEFAULT, |span| {
+		starts.push((span.start, span.col));
+		width = span.col + span.width;
+		true
+	});
+	let col_at = |index: usize| {
+		let k = starts.partition_point(|&(start, _)| start < index);
+		starts.get(k).map_or(width, |&(_, col)| col)
+	};
+
+	let mut links = Vec::new();
+	let mut open = None;
+	let mut push = |(uri, start): (&'a [u16], usize), end: usize| {
+		let cols = col_at(start)..col_at(end);
+		if !cols.is_empty() {
+			links.push((uri, start..end, cols));
+		}
+	};
+	let mut i = 0usize;
+	while let Some(off) = line[i..].iter().position(|&u| u == ESC) {
+		i += off;
+		let Some(seq_len) = ansi_seq_len(line, i) else {
+			i += 1;
+			continue;
+		};
+		if let Some(uri) = osc8_uri_u16(&line[i..i + seq_len]) {
+			if let Some(link) = open.take() {
+				push(link, i);
+			}
+			if !uri.is_empty() {
+				open = Some((uri, i + seq_len));
+			}
+		}
+		i += seq_len;
+	}
+	if let Some(link) = open {
+		push(link, line.len());
+	}
+
+	links
+}
+
+/// Find the OSC 8 hyperlinks in a line with the columns and UTF-16 range of
+/// the text each covers, for building a click map.
+///
+/// Both BEL- and ST-terminated sequences are recognized, and styling inside a
+/// link doesn't split it. As in terminals, opening a link while another is
+/// open ends the first; a link still open at the end of the line extends to
+/// it. Links with no visible text are omitted.
+#[napi(js_name = "extractLinks")]
+pub fn extract_links(line: JsString) -> Result<Vec<LinkRange>> {
+	let line_u16 = line.into_utf16()?;
+	Ok(extract_links_u16(line_u16.as_slice())
+		.into_iter()
+		.map(|(uri, range, cols)| LinkRange {
+			uri:         String::from_utf16_lossy(uri),
+			start_col:   clamp_u32(cols.start),
+			end_col:     clamp_u32(cols.end),
+			start_index: clamp_u32(range.start),
+			end_index:   clamp_u32(range.end),
+		})
+		.collect())
+}
+
+// ============================================================================
+// concatStyled
+// ============================================================================
+
+/// Whether `data` leaves any SGR attributes active.
+fn ends_styled_u16(data: &[u16]) -> bool {
+	let mut state = AnsiState::new();
+	update_state_from_text(data, &mut state);
+	!state.is_empty()
+}
+
+fn concat_styled_impl<'a>(
+	parts: impl IntoIterator<Item = &'a [u16]>,
+	separator: Option<&[u16]>,
+) -> Vec<u16> {
+	let mut out = Vec::new();
+	let mut styled = false;
+	for (i, part) in parts.into_iter().enumerate() {
+		if i > 0 {
+			if styled {
+				out.extend_from_slice(&SGR_RESET);
+			}
+			if let Some(sep) = separator {
+				out.extend_from_slice(sep);
+				if ends_styled_u16(sep) {
+					out.extend_from_slice(&SGR_RESET);
+				}
+			}
+		}
+		out.extend_from_slice(part);
+		styled = ends_styled_u16(part);
+	}
+	out
+}
+
+/// Join styled fragments so no fragment's style bleeds into the next.
+///
+/// A reset is inserted between parts only when the preceding part leaves
+/// attributes active; the separator is written unstyled. The final part's
+/// trailing style is left as-is.
+#[napi(js_name = "concatStyled")]
+pub fn concat_styled(parts: Vec<JsString>, separator: Option<JsString>) -> Result<Utf16String> {
+	let parts = parts
+		.into_iter()
+		.map(|part| part.into_utf16())
+		.collect::<Result<Vec<_>>>()?;
+	let separator = separator.map(|sep| sep.into_utf16()).transpose()?;
+	Ok(build_utf16_string(concat_styled_impl(
+		parts.iter().map(|part| part.as_slice()),
+		separator.as_ref().map(|sep| sep.as_slice()),
+	)))
+}
+
+// ============================================================================
+// ansiToHtml
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct AnsiToHtmlOptions {
+	/// Colors for the 16 basic colors and inverse defaults: "dark" (default,
+	/// xterm) or "light".
+	pub palette:      Option<String>,
+	/// Emit classes (`{prefix}bold`, `{prefix}fg-1`, ...) for attributes and
+	/// basic colors instead of inline styles; 256-color and truecolor values
+	/// stay inline. Inverse swaps the colors rather than adding a class.
+	#[napi(js_name = "classPrefix")]
+	pub class_prefix: Option<String>,
+}
+
+type Rgb = (u8, u8, u8);
+
+struct HtmlPalette {
+	basic: [Rgb; 16],
+	/// Default foreground, used for inverse text without a foreground.
+	fg:    Rgb,
+	/// Default background, used for inverse text without a background.
+	bg:    Rgb,
+}
+
+const DARK_HTML_PALETTE: HtmlPalette =
+	HtmlPalette { basic: BASIC_PALETTE, fg: BASIC_PALETTE[7], bg: BASIC_PALETTE[0] };
+
+const LIGHT_HTML_PALETTE: HtmlPalette = HtmlPalette {
+	basic: [
+		(0, 0, 0),
+		(205, 49, 49),
+		(0, 188, 0),
+		(148, 152, 0),
+		(4, 81, 165),
+		(188, 5, 188),
+		(5, 152, 188),
+		(85, 85, 85),
+		(102, 102, 102),
+		(205, 49, 49),
+		(20, 206, 20),
+		(181, 186, 0),
+		(4, 81, 165),
+		(188, 5, 188),
+		(5, 152, 188),
+		(165, 165, 165),
+	],
+	fg:    (0, 0, 0),
+	bg:    (255, 255, 255),
+};
+
+/// Link schemes emitted as `<a href>`; other links render as plain text.
+const HTML_LINK_SCHEMES: [&str; 4] = ["http:", "https:", "mailto:", "file:"];
+
+fn push_html_escaped(out: &mut String, text: &str) {
+	for c in text.chars() {
+		match c {
+			'&' => out.push_str("&amp;"),
+			'<' => out.push_str("&lt;"),
+			'>' => out.push_str("&gt;"),
+			'"' => out.push_str("&quot;"),
+			'\'' => out.push_str("&#39;"),
+			_ => out.push(c),
+		}
+	}
+}
+
+/// Palette index (0-15) of a basic color, from either SGR form.
+const fn basic_color_index(color: ColorVal) -> Option<u8> {
+	match color {
+		1..=16 => Some((color - 1) as u8),
+		0x100..0x110 => Some((color & 0xff) as u8),
+		_ => None,
+	}
+}
+
+const fn color_rgb(color: ColorVal, basic: &[Rgb; 16]) -> Option<Rgb> {
+	if color == COLOR_NONE {
+		return None;
+	}
+	if let Some(idx) = basic_color_index(color) {
+		return Some(basic[idx as usize]);
+	}
+	if color < 0x1000000 {
+		return Some(palette_rgb((color & 0xff) as u8));
+	}
+	Some(((color >> 16) as u8, (color >> 8) as u8, color as u8))
+}
+
+/// Open a `<span>` for `style`; returns `false` (writing nothing) for the
+/// default style.
+fn write_html_span_open(
+	out: &mut String,
+	style: AnsiState,
+	palette: &HtmlPalette,
+	prefix: Option<&str>,
+) -> bool {
+	let mut classes: SmallVec<[String; 4]> = SmallVec::new();
+	let mut css: SmallVec<[String; 4]> = SmallVec::new();
+
+	// Inverse is rendered by swapping the colors in both modes, never as a
+	// class as well, which would invert the text back.
+	let inverse = style.attrs & ATTR_INVERSE != 0;
+	let (fg, bg) = if inverse {
+		(style.bg, style.fg)
+	} else {
+		(style.fg, style.bg)
+	};
+	for (color, kind, prop, inverse_default) in
+		[(fg, "fg", "color", palette.bg), (bg, "bg", "background-color", palette.fg)]
+	{
+		if let (Some(p), Some(idx)) = (prefix, basic_color_index(color)) {
+			classes.push(format!("{p}{kind}-{idx}"));
+			continue;
+		}
+		let rgb = match color_rgb(color, &palette.basic) {
+			Some(rgb) => rgb,
+			None if inverse => inverse_default,
+			None => continue,
+		};
+		css.push(format!("{prop}:#{:02x}{:02x}{:02x}", rgb.0, rgb.1, rgb.2));
+	}
+
+	let mut decorations: SmallVec<[&str; 2]> = SmallVec::new();
+	for (attr, name, rule) in [
+		(ATTR_BOLD, "bold", Some("font-weight:bold")),
+		(ATTR_DIM, "dim", Some("opacity:0.5")),
+		(ATTR_ITALIC, "italic", Some("font-style:italic")),
+		(ATTR_UNDERLINE, "underline", None),
+		(ATTR_BLINK, "blink", None),
+		(ATTR_HIDDEN, "hidden", Some("visibility:hidden")),
+		(ATTR_STRIKE, "strikethrough", None),
+	] {
+		if style.attrs & attr == 0 {
+			continue;
+		}
+		if let Some(p) = prefix {
+			classes.push(format!("{p}{name}"));
+		} else if let Some(rule) = rule {
+			css.push(rule.to_owned());
+		} else if attr == ATTR_UNDERLINE {
+			decorations.push("underline");
+		} else if attr == ATTR_STRIKE {
+			decorations.push("line-through");
+		}
+	}
+	if !decorations.is_empty() {
+		css.push(format!("text-decoration:{}", decorations.join(" ")));
+	}
+
+	if classes.is_empty() && css.is_empty() {
+		return false;
+	}
+	out.push_str("<span");
+	if !classes.is_empty() {
+		out.push_str(" class=\"");
+		push_html_escaped(out, &classes.join(" "));
+		out.push('"');
+	}
+	if !css.is_empty() {
+		out.push_str(" style=\"");
+		out.push_str(&css.join(";"));
+		out.push('"');
+	}
+	out.push('>');
+	true
+}
+
+fn ansi_to_html_impl(text: &[u16], palette: &HtmlPalette, prefix: Option<&str>) -> String {
+	let spans = parse_ansi_spans_u16(text, false, WidthConfig::DEFAULT);
+	let mut out = String::with_capacity(text.len() + spans.len() * 32);
+	let mut link: Option<&[u16]> = None;
+	let mut link_open = false;
+
+	for span in &spans {
+		let next_link = span.link.as_deref();
+		if next_link != link {
+			if link_open {
+				out.push_str("</a>");
+				link_open = false;
+			}
+			if let Some(uri) = next_link {
+				let uri = String::from_utf16_lossy(uri);
+				let lower = uri.to_ascii_lowercase();
+				if HTML_LINK_SCHEMES
+					.iter()
+					.any(|scheme| lower.starts_with(scheme))
+				{
+					out.push_str("<a href=\"");
+					push_html_escaped(&mut out, &uri);
+					out.push_str("\">");
+					link_open = true;
+				}
+			}
+			link = next_link;
+		}
+
+		let opened = write_html_span_open(&mut out, span.style, palette, prefix);
+		push_html_escaped(&mut out, &String::from_utf16_lossy(&span.text));
+		if opened {
+			out.push_str("</span>");
+		}
+	}
+	if link_open {
+		out.push_str("</a>");
+	}
+	out
+}
+
+/// Render ANSI-styled text as HTML.
+///
+/// SGR runs become `<span>`s with inline styles (or classes with
+/// `classPrefix`), text is HTML-escaped, OSC 8 links with http(s), mailto, or
+/// file targets become `<a href>`, and other control sequences are dropped.
+/// Newlines are kept, so the result is meant for a `<pre>` block.
+#[napi(js_name = "ansiToHtml")]
+pub fn ansi_to_html(text: JsString, options: Option<AnsiToHtmlOptions>) -> Result<String> {
+	let options = options.unwrap_or_default();
+	let palette = match options.palette.as_deref() {
+		None | Some("dark") => &DARK_HTML_PALETTE,
+		Some("light") => &LIGHT_HTML_PALETTE,
+		Some(_) => return Err(Error::from_reason("palette must be \"dark\" or \"light\"")),
+	};
+	let text_u16 = text.into_utf16()?;
+	Ok(ansi_to_html_impl(text_u16.as_slice(), palette, options.class_prefix.as_deref()))
+}
+
+// ============================================================================
+// analyzeText
+// ============================================================================
+
+#[napi(object)]
+pub struct TextAnalysis {
+	/// Visible width in terminal cells.
+	#[napi(js_name = "visibleWidth")]
+	pub visible_width:  u32,
+	/// Total length in UTF-16 code units (including escapes).
+	#[napi(js_name = "utf16Length")]
+	pub utf16_length:   u32,
+	/// Number of complete ANSI sequences.
+	#[napi(js_name = "ansiCount")]
+	pub ansi_count:     u32,
+	/// Whether any non-SGR sequence (cursor movement, OSC, ...) is present.
+	#[napi(js_name = "hasNonSgr")]
+	pub has_non_sgr:    bool,
+	/// Whether any grapheme is wider than one cell.
+	#[napi(js_name = "hasWideChars")]
+	pub has_wide_chars: bool,
+	/// Whether the text contains tabs.
+	#[napi(js_name = "hasTabs")]
+	pub has_tabs:       bool,
+}
+
+fn analyze_text_u16(data: &[u16], cfg: WidthConfig) -> TextAnalysis {
+	let mut width = 0usize;
+	let mut ansi_count = 0usize;
+	let mut has_non_sgr = false;
+	let mut has_wide_chars = false;
+	let mut has_tabs = false;
+	let mut i = 0usize;
+	let len = data.len();
+
+	while i < len {
+		if data[i] == ESC {
+			if let Some(seq_len) = ansi_seq_len(data, i) {
+				ansi_count += 1;
+				if !is_sgr(&data[i..i + seq_len]) {
+					has_non_sgr = true;
+				}
+				i += seq_len;
+				continue;
+			}
+			i += 1;
+			continue;
+		}
+
+		let start = i;
+		let mut is_ascii = true;
+		while i < len && data[i] != ESC {
+			if data[i] > 0x7f {
+				is_ascii = false;
+			}
+			i += 1;
+		}
+		let seg = &data[start..i];
+
+		if is_ascii {
+			for &u in seg {
+				if u == b'\t' as u16 {
+					has_tabs = true;
+				}
+				width += ascii_cell_width(u, width, cfg);
+			}
+		} else {
+			let _ = for_each_grapheme_slow(seg, width, cfg, |gu16, w| {
+				if w > 1 {
+					has_wide_chars = true;
+				}
+				if gu16 == [b'\t' as u16] {
+					has_tabs = true;
+				}
+				width += w;
+				true
+			});
+		}
+	}
+
+	TextAnalysis {
+		visible_width: clamp_u32(width),
+		utf16_length: clamp_u32(len),
+		ansi_count: clamp_u32(ansi_count),
+		has_non_sgr,
+		has_wide_chars,
+		has_tabs,
+	}
+}
+
+/// Analyze text in a single pass: visible width, UTF-16 length, ANSI
+/// sequence count, and presence of non-SGR sequences, wide chars, and tabs.
+///
+/// Everything but the length is measured after `controlChars` is applied.
+#[napi(js_name = "analyzeText")]
+pub fn analyze_text(text: JsString, options: Option<WidthOptions>) -> Result<TextAnalysis> {
+	let cfg = WidthConfig::from_options(options.as_ref())?;
+	let text_u16 = text.into_utf16()?;
+	let mut analysis = analyze_text_u16(&cfg.prepare_u16(text_u16.as_slice()), cfg);
+	analysis.utf16_length = clamp_u32(text_u16.len());
+	Ok(analysis)
+}
+
+// ============================================================================
+// stripAnsi
+// ============================================================================
+
+fn strip_ansi_u16(data: &[u16]) -> Vec<u16> {
+	let mut out = Vec::with_capacity(data.len());
+	let mut i = 0usize;
+	let len = data.len();
+
+	while i < len {
+		let Some(off) = data[i..].iter().position(|&u| u == ESC) else {
+			out.extend_from_slice(&data[i..]);
+			break;
+		};
+		out.extend_from_slice(&data[i..i + off]);
+		i += off;
+
+		if let Some(seq_len) = ansi_seq_len(data, i) {
+			i += seq_len;
+			continue;
+		}
+
+		// Unterminated CSI/OSC/DCS runs to end-of-string: drop the remainder.
+		if matches!(data.get(i + 1), None | Some(&(0x5b | 0x5d | 0x50 | 0x58 | 0x5e | 0x5f))) {
+			break;
+		}
+		// Lone ESC: drop the byte itself, keep what follows.
+		i += 1;
+	}
+
+	out
+}
+
+/// Remove ANSI escape sequences (CSI, OSC, DCS, and short `ESC x` forms)
+/// from text.
+///
+/// Returns the original string when it contains no ESC. Truncated sequences
+/// at end-of-string are dropped.
+#[napi(js_name = "stripAnsi")]
+pub fn strip_ansi(text: JsString<'_>) -> Result<Either<JsString<'_>, Utf16String>> {
+	let original = text;
+	let text_u16 = text.into_utf16()?;
+	let data = text_u16.as_slice();
+
+	if !data.contains(&ESC) {
+		return Ok(Either::A(original));
+	}
+
+	Ok(Either::B(build_utf16_string(strip_ansi_u16(data))))
+}
+
+// ============================================================================
+// splitAnsiSegments
+// ============================================================================
+
+#[napi(object)]
+pub struct AnsiSegment {
+	/// "text", "csi", "osc", "esc" (other escape sequences, including DCS and
+	/// APC strings), or "incomplete" (a sequence cut off by the end of the
+	/// line).
+	pub kind:  String,
+	/// UTF-16 index of the first code unit.
+	pub start: u32,
+	/// UTF-16 index just past the last code unit.
+	pub end:   u32,
+}
+
+/// Split `line` into text runs and escape sequences without interpreting
+/// them. An ESC that starts no valid sequence is part of the text.
+fn split_ansi_segments_u16(line: &[u16]) -> Vec<(&'static str, Range<usize>)> {
+	let mut segments = Vec::new();
+	let mut text_start = 0usize;
+	let mut i = 0usize;
+
+	while let Some(off) = line[i..].iter().position(|&u| u == ESC) {
+		i += off;
+		let (kind, end) = match ansi_seq_len(line, i) {
+			Some(seq_len) => {
+				let kind = match line[i + 1] {
+					0x5b => "csi",
+					0x5d => "osc",
+					_ => "esc",
+				};
+				(kind, i + seq_len)
+			},
+			None if ansi_seq_incomplete(line, i) => ("incomplete", line.len()),
+			None => {
+				i += 1;
+				continue;
+			},
+		};
+		if text_start < i {
+			segments.push(("text", text_start..i));
+		}
+		segments.push((kind, i..end));
+		i = end;
+		text_start = end;
+	}
+	if text_start < line.len() {
+		segments.push(("text", text_start..line.len()));
+	}
+
+	segments
+}
+
+/// Split a line into plain-text runs and escape sequences, as UTF-16 index
+/// ranges for slicing the original string.
+///
+/// A sequence cut off by the end of the line is reported as "incomplete".
+#[napi(js_name = "splitAnsiSegments")]
+pub fn split_ansi_segments(line: JsString) -> Result<Vec<AnsiSegment>> {
+	let line_u16 = line.into_utf16()?;
+	Ok(split_ansi_segments_u16(line_u16.as_slice())
+		.into_iter()
+		.map(|(kind, range)| AnsiSegment {
+			kind:  kind.to_owned(),
+			start: clamp_u32(range.start),
+			end:   clamp_u32(range.end),
+		})
+		.collect())
+}
+
+// ============================================================================
+// sanitizeAnsi
+// ============================================================================
+
+/// What to do with control sequences other than SGR and OSC 8.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SanitizePolicy {
+	/// Remove the sequence.
+	Drop,
+	/// Replace control characters with visible Control Pictures (`␛[2K`).
+	Escape,
+	/// Leave the sequence untouched.
+	Keep,
+}
+
+impl SanitizePolicy {
+	fn parse(policy: Option<&str>) -> Result<Self> {
+		match policy {
+			None | Some("drop") => Ok(Self::Drop),
+			Some("escape") => Ok(Self::Escape),
+			Some("keep") => Ok(Self::Keep),
+			Some(other) => Err(Error::from_reason(format!(
+				"policy must be \"drop\", \"escape\", or \"keep\", got \"{other}\""
+			))),
+		}
+	}
+}
+
+/// Length of the escape sequence at `pos` (which must be ESC).
+///
+/// Malformed or unterminated sequences are bounded so the text after them
+/// survives; only an unterminated OSC runs to the end of `data`. A lone ESC
+/// is 1.
+fn control_seq_len_u16(data: &[u16], pos: usize) -> usize {
+	if let Some(len) = ansi_seq_len(data, pos) {
+		return len;
+	}
+	let rest = data.len() - pos;
+	match data.get(pos + 1) {
+		None => 1,
+		// CSI without a final byte: the parameter and intermediate bytes.
+		Some(&0x5b) => data[pos + 2..]
+			.iter()
+			.position(|u| !(0x20..=0x3f).contains(u))
+			.map_or(rest, |i| i + 2),
+		Some(&0x5d) => rest,
+		// DCS, SOS, PM, APC without ST: an ESC that doesn't start ST aborts
+		// the string, as it does in a terminal.
+		Some(&(0x50 | 0x58 | 0x5e | 0x5f)) => data[pos + 2..]
+			.iter()
+			.position(|&u| u == ESC)
+			.map_or(rest, |i| i + 2),
+		// Character set designation and other intermediates: ESC I F.
+		Some(&(0x20..=0x2f)) => rest.min(3),
+		// Lone ESC.
+		Some(_) => 1,
+	}
+}
+
+/// Rewrite control sequences other than SGR and OSC 8 according to
+/// `policy`. Truncated sequences at end-of-string can't be verified and are
+/// treated as foreign.
+pub(crate) fn sanitize_ansi_u16(data: &[u16], policy: SanitizePolicy) -> Vec<u16> {
+	let mut out = Vec::with_capacity(data.len());
+	let mut i = 0usize;
+	let len = data.len();
+
+	while i < len {
+		let Some(off) = data[i..].iter().position(|&u| u == ESC) else {
+			out.extend_from_slice(&data[i..]);
+			break;
+		};
+		out.extend_from_slice(&data[i..i + off]);
+		i += off;
+
+		let seq_len = control_seq_len_u16(data, i);
+		let seq = &data[i..i + seq_len];
+		i += seq_len;
+
+		let allowed =
+			ansi_seq_len(seq, 0) == Some(seq_len) && (is_sgr(seq) || osc8_uri_u16(seq).is_some());
+		match policy {
+			_ if allowed => out.extend_from_slice(seq),
+			SanitizePolicy::Keep => out.extend_from_slice(seq),
+			SanitizePolicy::Drop => {},
+			SanitizePolicy::Escape => {
+				out.extend(seq.iter().map(|&u| match u {
+					0x00..=0x1f => 0x2400 + u,
+					0x7f => 0x2421,
+					_ => u,
+				}));
+			},
+		}
+	}
+
+	out
+}
+
+/// Neutralize control sequences other than SGR and OSC 8 hyperlinks.
+///
+/// `policy` is "drop" (default), "escape" (show them as `␛[2K`-style text),
+/// or "keep". Returns the original string when nothing changes.
+#[napi(js_name = "sanitizeAnsi")]
+pub fn sanitize_ansi(
+	text: JsString<'_>,
+	policy: Option<String>,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let policy = SanitizePolicy::parse(policy.as_deref())?;
+	let original = text;
+	let text_u16 = text.into_utf16()?;
+	let data = text_u16.as_slice();
+
+	if policy == SanitizePolicy::Keep || !data.contains(&ESC) {
+		return Ok(Either::A(original));
+	}
+
+	let out = sanitize_ansi_u16(data, policy);
+	if out == data {
+		return Ok(Either::A(original));
+	}
+	Ok(Either::B(build_utf16_string(out)))
+}
+
+// ============================================================================
+// sanitizeForTerminal
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct TerminalSanitizeOptions {
+	/// "strip" (default), "caret" (`^[`, `^?`), or "pictures" (`␛`, `␀`).
+	pub mode:             Option<String>,
+	/// Keep SGR sequences (default true).
+	#[napi(js_name = "allowSgr")]
+	pub allow_sgr:        Option<bool>,
+	/// Keep OSC 8 hyperlinks (default false).
+	#[napi(js_name = "allowHyperlinks")]
+	pub allow_hyperlinks: Option<bool>,
+	/// Keep OSC 0/1/2 window and icon title sequences (default false).
+	#[napi(js_name = "allowTitle")]
+	pub allow_title:      Option<bool>,
+}
+
+/// How `sanitizeForTerminal` neutralizes a dangerous character.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ControlNotation {
+	/// Remove it.
+	Strip,
+	/// Caret notation (`^[`, `^?`); C1 controls as their 7-bit ESC form.
+	Caret,
+	/// Control Pictures (`␛`, `␡`); C1 controls as their 7-bit ESC form.
+	Pictures,
+}
+
+struct TerminalAllowlist {
+	notation:   ControlNotation,
+	sgr:        bool,
+	hyperlinks: bool,
+	title:      bool,
+}
+
+impl TerminalAllowlist {
+	fn from_options(options: &TerminalSanitizeOptions) -> Result<Self> {
+		let notation = match options.mode.as_deref() {
+			None | Some("strip") => ControlNotation::Strip,
+			Some("caret") => ControlNotation::Caret,
+			Some("pictures") => ControlNotation::Pictures,
+			Some(other) => {
+				return Err(Error::from_reason(format!(
+					"mode must be \"strip\", \"caret\", or \"pictures\", got \"{other}\""
+				)));
+			},
+		};
+		Ok(Self {
+			notation,
+			sgr: options.allow_sgr.unwrap_or(true),
+			hyperlinks: options.allow_hyperlinks.unwrap_or(false),
+			title: options.allow_title.unwrap_or(false),
+		})
+	}
+
+	/// Whether a complete escape sequence may pass through.
+	fn allows(&self, seq: &[u16]) -> bool {
+		if ansi_seq_len(seq, 0) != Some(seq.len()) {
+			return false;
+		}
+		(self.sgr && is_sgr(seq))
+			|| (self.hyperlinks && osc8_uri_u16(seq).is_some())
+			|| (self.title && is_osc_title_u16(seq))
+	}
+}
+
+/// `ESC ] 0;`, `ESC ] 1;`, or `ESC ] 2;` (icon name / window title).
+const fn is_osc_title_u16(seq: &[u16]) -> bool {
+	matches!(seq, [ESC, 0x5d, 0x30..=0x32, 0x3b, ..])
+}
+
+/// C0 controls other than `\t` and `\n`, DEL, and C1 controls.
+const fn is_unsafe_control_u16(u: u16) -> bool {
+	matches!(u, 0x00..=0x08 | 0x0b..=0x1f | 0x7f..=0x9f)
+}
+
+fn push_control_notation_u16(out: &mut Vec<u16>, u: u16, notation: ControlNotation) {
+	match (notation, u) {
+		(ControlNotation::Strip, _) => {},
+		(ControlNotation::Caret, 0x00..=0x1f) => out.extend_from_slice(&[b'^' as u16, u + 0x40]),
+		(ControlNotation::Caret, 0x7f) => out.extend_from_slice(&[b'^' as u16, b'?' as u16]),
+		(ControlNotation::Caret, 0x80..=0x9f) => {
+			out.extend_from_slice(&[b'^' as u16, b'[' as u16, u - 0x40]);
+		},
+		(ControlNotation::Pictures, 0x00..=0x1f) => out.push(0x2400 + u),
+		(ControlNotation::Pictures, 0x7f) => out.push(0x2421),
+		(ControlNotation::Pictures, 0x80..=0x9f) => out.extend_from_slice(&[0x241b, u - 0x40]),
+		_ => out.push(u),
+	}
+}
+
+fn sanitize_for_terminal_u16(data: &[u16], allow: &TerminalAllowlist) -> Vec<u16> {
+	let mut out = Vec::with_capacity(data.len());
+	let mut i = 0usize;
+	let len = data.len();
+
+	while i < len {
+		let u = data[i];
+		if u == ESC {
+			let seq_len = control_seq_len_u16(data, i);
+			let seq = &data[i..i + seq_len];
+			i += seq_len;
+			if allow.allows(seq) {
+				out.extend_from_slice(seq);
+			} else if allow.notation != ControlNotation::Strip {
+				for &u in seq {
+					push_control_notation_u16(&mut out, u, allow.notation);
+				}
+			}
+			continue;
+		}
+		if is_unsafe_control_u16(u) {
+			push_control_notation_u16(&mut out, u, allow.notation);
+		} else {
+			out.push(u);
+		}
+		i += 1;
+	}
+
+	out
+}
+
+/// Neutralize control characters in untrusted text before printing it.
+///
+/// Unlike `stripAnsi` and `sanitizeAnsi`, this targets safety: C0 controls
+/// other than `\t` and `\n`, DEL, C1 controls, lone ESC bytes, and escape
+/// sequences outside the allowlist (SGR by default; OSC 8 links and titles on
+/// request) are stripped or shown in caret or Control Pictures notation.
+/// Returns the original string when nothing changes.
+#[napi(js_name = "sanitizeForTerminal")]
+pub fn sanitize_for_terminal(
+	text: JsString<'_>,
+	options: Option<TerminalSanitizeOptions>,
+) -> Result<Either<JsString<'_>, Utf16String>> {
+	let allow = TerminalAllowlist::from_options(&options.unwrap_or_default())?;
+	let original = text;
+	let text_u16 = text.into_utf16()?;
+	let data = text_u16.as_slice();
+
+	if !data.iter().any(|&u| u == ESC || is_unsafe_control_u16(u)) {
+		return Ok(Either::A(original));
+	}
+
+	let out = sanitize_for_terminal_u16(data, &allow);
+	if out == data {
+		return Ok(Either::A(original));
+	}
+	Ok(Either::B(build_utf16_string(out)))
+}
+
+// ============================================================================
+// convertOverstrike
+// ============================================================================
+
+/// Length of the code point at `i` (2 for a surrogate pair).
+fn code_point_len_u16(data: &[u16], i: usize) -> usize {
+	let is_pair = (0xd800..0xdc00).contains(&data[i])
+		&& data
+			.get(i + 1)
+			.is_some_and(|u| (0xdc00..0xe000).contains(u));
+	if is_pair { 2 } else { 1 }
+}
+
+/// Switch between (bold, underline) overstrike styles with one SGR sequence.
+fn write_overstrike_style_u16(from: (bool, bool), to: (bool, bool), out: &mut Vec<u16>) {
+	if from == to {
+		return;
+	}
+	let mut codes = SmallVec::<[u32; 2]>::new();
+	if from.0 != to.0 {
+		codes.push(if to.0 { 1 } else { 22 });
+	}
+	if from.1 != to.1 {
+		codes.push(if to.1 { 4 } else { 24 });
+	}
+	out.extend_from_slice(&[ESC, b'[' as u16]);
+	for (n, &code) in codes.iter().enumerate() {
+		if n > 0 {
+			out.push(b';' as u16);
+		}
+		write_u32_u16(out, code);
+	}
+	out.push(b'm' as u16);
+}
+
+fn convert_overstrike_u16(data: &[u16]) -> Vec<u16> {
+	const UNDERSCORE: u16 = b'_' as u16;
+	let mut out = Vec::with_capacity(data.len());
+	let mut style = (false, false);
+	let mut i = 0usize;
+	let len = data.len();
+
+	while i < len {
+		if data[i] < 0x20 {
+			// Controls (and escapes) end any run and are copied untouched.
+			let seq_len = if data[i] == ESC {
+				ansi_seq_len(data, i).unwrap_or(1)
+			} else {
+				1
+			};
+			write_overstrike_style_u16(style, (false, false), &mut out);
+			style = (false, false);
+			out.extend_from_slice(&data[i..i + seq_len]);
+			i += seq_len;
+			continue;
+		}
+
+		let mut glyph = &data[i..i + code_point_len_u16(data, i)];
+		let mut next = (false, false);
+		let mut j = i + glyph.len();
+		// Fold every "\b" + code point struck over the glyph.
+		while data.get(j) == Some(&BS) && j + 1 < len && data[j + 1] >= 0x20 {
+			let struck = &data[j + 1..j + 1 + code_point_len_u16(data, j + 1)];
+			if struck == glyph {
+				next.0 = true;
+			} else if glyph == [UNDERSCORE] {
+				next.1 = true;
+				glyph = struck;
+			} else if struck == [UNDERSCORE] {
+				next.1 = true;
+			} else {
+				break;
+			}
+			j += 1 + struck.len();
+		}
+
+		write_overstrike_style_u16(style, next, &mut out);
+		style = next;
+		out.extend_from_slice(glyph);
+		i = j;
+	}
+	write_overstrike_style_u16(style, (false, false), &mut out);
+
+	out
+}
+
+/// Rewrite backspace overstrike (`X\bX` bold, `_\bX` underline), as
+/// produced by `man` and `nroff`, to SGR sequences.
+///
+/// Consecutive overstruck characters share one sequence. Other backspaces
+/// are left alone. Returns the original string when it has no backspace.
+#[napi(js_name = "convertOverstrike")]
+pub fn convert_overstrike(text: JsString<'_>) -> Result<Either<JsString<'_>, Utf16String>> {
+	let original = text;
+	let text_u16 = text.into_utf16()?;
+	let data = text_u16.as_slice();
+
+	if !data.contains(&BS) {
+		return Ok(Either::A(original));
+	}
+
+	Ok(Either::B(build_utf16_string(convert_overstrike_u16(data))))
+}
+
+// ============================================================================
+// optimizeAnsi
+// ============================================================================
+
+/// Whether every code in an SGR parameter list is modeled by `AnsiState`.
+fn sgr_is_modeled_u16(params: &[u16]) -> bool {
+	if params.contains(&(b':' as u16)) {
+		return false;
+	}
+	let mut i = 0usize;
+	while i < params.len() {
+		let (code, next_i) = parse_sgr_num(params, i);
+		i = next_i;
+		match code {
+			0..=5 | 7..=9 | 22..=25 | 27..=37 | 39..=47 | 49 | 90..=97 | 100..=107 => {},
+			38 | 48 => {
+				let (mode, ni) = parse_sgr_num(params, i);
+				i = match mode {
+					5 => parse_sgr_num(params, ni).1,
+					2 => (0..3).fold(ni, |j, _| parse_sgr_num(params, j).1),
+					_ => return false,
+				};
+			},
+			_ => return false,
+		}
+	}
+	true
+}
+
+/// Write the shortest single SGR sequence that moves `from` to `to`.
+fn write_transition_u16(from: AnsiState, to: AnsiState, out: &mut Vec<u16>) {
+	if from == to {
+		return;
+	}
+	if to.is_empty() {
+		out.extend_from_slice(&SGR_RESET);
+		return;
+	}
+
+	let keeps = |from: ColorVal, to: ColorVal| from == to || to != COLOR_NONE;
+	if from.attrs & !to.attrs == 0 && keeps(from.fg, to.fg) && keeps(from.bg, to.bg) {
+		// Only additions: emit just what changed.
+		let changed = |from: ColorVal, to: ColorVal| if from == to { COLOR_NONE } else { to };
+		let diff = AnsiState {
+			attrs: to.attrs & !from.attrs,
+			fg:    changed(from.fg, to.fg),
+			bg:    changed(from.bg, to.bg),
+		};
+		diff.write_restore_u16(out);
+	} else {
+		// Reset and restore in one sequence: "\x1b[0;...m".
+		let start = out.len();
+		to.write_restore_u16(out);
+		out.splice(start + 2..start + 2, [b'0' as u16, b';' as u16]);
+	}
+}
+
+fn optimize_ansi_u16(data: &[u16]) -> Vec<u16> {
+	let mut out = Vec::with_capacity(data.len());
+	// State the output has established vs. the state the input asks for.
+	let mut emitted = AnsiState::new();
+	let mut target = AnsiState::new();
+	let mut i = 0usize;
+	let len = data.len();
+
+	while i < len {
+		if data[i] == ESC
+			&& let Some(seq_len) = ansi_seq_len(data, i)
+		{
+			let seq = &data[i..i + seq_len];
+			i += seq_len;
+			if is_sgr(seq) && sgr_is_modeled_u16(&seq[2..seq_len - 1]) {
+				target.apply_sgr(&seq[2..seq_len - 1]);
+				continue;
+			}
+			// Other sequences (and SGR codes we don't model) may depend on the
+			// current style, so settle it first and keep them verbatim.
+			write_transition_u16(emitted, target, &mut out);
+			out.extend_from_slice(seq);
+			if is_sgr(seq) {
+				target.apply_sgr(&seq[2..seq_len - 1]);
+			}
+			emitted = target;
+			continue;
+		}
+
+		let start = i;
+		i += 1;
+		while i < len && data[i] != ESC {
+			i += 1;
+		}
+		write_transition_u16(emitted, target, &mut out);
+		emitted = target;
+		out.extend_from_slice(&data[start..i]);
+	}
+
+	// Trailing changes style nothing; only keep a final reset so the style
+	// doesn't leak into whatever follows.
+	if target.is_empty() {
+		write_transition_u16(emitted, target, &mut out);
+	}
+
+	out
+}
+
+/// Collapse redundant ANSI escape sequences.
+///
+/// SGR sequences that don't change the style before the next visible text are
+/// removed and adjacent ones merged; trailing style changes are dropped except
+/// a final reset. Returns the original string when nothing changes.
+#[napi(js_name = "optimizeAnsi")]
+pub fn optimize_ansi(text: JsString<'_>) -> Result<Either<JsString<'_>, Utf16String>> {
+	let original = text;
+	let text_u16 = text.into_utf16()?;
+	let data = text_u16.as_slice();
+
+	if !data.contains(&ESC) {
+		return Ok(Either::A(original));
+	}
+
+	let out = optimize_ansi_u16(data);
+	if out == data {
+		return Ok(Either::A(original));
+	}
+	Ok(Either::B(build_utf16_string(out)))
+}
+
+// ============================================================================
+// SGR Rewriting
+// ============================================================================
+
+type SgrCodes = SmallVec<[u32; 8]>;
+
+/// Split SGR codes into units, keeping extended colors (`38;5;n`,
+/// `48;2;r;g;b`, `58;5;n`, ...) together.
+fn sgr_units(codes: &[u32]) -> impl Iterator<Item = &[u32]> {
+	let mut rest = codes;
+	std::iter::from_fn(move || {
+		let len = match rest {
+			[] => return None,
+			[38 | 48 | 58, 5, _, ..] => 3,
+			[38 | 48 | 58, 2, _, _, _, ..] => 5,
+			// A truncated extended color takes the rest, so its mode is not
+			// read as a code of its own (`5` as blink).
+			[38 | 48 | 58, 2 | 5, ..] => rest.len(),
+			_ => 1,
+		};
+		let (unit, tail) = rest.split_at(len);
+		rest = tail;
+		Some(unit)
+	})
+}
+
+/// Rewrite every SGR sequence in `data` through `f`.
+///
+/// `f` receives the parsed codes and returns `None` to keep the sequence
+/// verbatim, or replacement codes (an empty list drops the sequence).
+/// Sequences using `:` sub-parameters are always kept verbatim.
+fn rewrite_sgr_u16<F>(data: &[u16], mut f: F) -> Vec<u16>
+where
+	F: FnMut(&[u32]) -> Option<SgrCodes>,
+{
+	let mut out = Vec::with_capacity(data.len());
+	let mut i = 0usize;
+	let len = data.len();
+
+	while i < len {
+		let Some(off) = data[i..].iter().position(|&u| u == ESC) else {
+			out.extend_from_slice(&data[i..]);
+			break;
+		};
+		out.extend_from_slice(&data[i..i + off]);
+		i += off;
+
+		let Some(seq_len) = ansi_seq_len(data, i) else {
+			out.push(ESC);
+			i += 1;
+			continue;
+		};
+		let seq = &data[i..i + seq_len];
+		i += seq_len;
+
+		let params = &seq[2..seq_len - 1];
+		if !is_sgr(seq) || params.contains(&(b':' as u16)) {
+			out.extend_from_slice(seq);
+			continue;
+		}
+
+		let mut codes = SgrCodes::new();
+		let mut p = 0usize;
+		while p < params.len() {
+			let (code, next_p) = parse_sgr_num(params, p);
+			codes.push(code);
+			p = next_p;
+		}
+
+		match f(&codes) {
+			None => out.extend_from_slice(seq),
+			Some(codes) if codes.is_empty() => {},
+			Some(codes) => {
+				out.extend_from_slice(&[ESC, b'[' as u16]);
+				for (n, &code) in codes.iter().enumerate() {
+					if n > 0 {
+						out.push(b';' as u16);
+					}
+					write_u32_u16(&mut out, code);
+				}
+				out.push(b'm' as u16);
+			},
+		}
+	}
+
+	out
+}
+
+// ============================================================================
+// filterStyles
+// ============================================================================
+
+#[napi(object)]
+#[derive(Default)]
+pub struct FilterStylesOptions {
+	/// Drop background colors (40-49, 100-107, `48;5;n`, `48;2;r;g;b`).
+	#[napi(js_name = "dropBackground")]
+	pub drop_background:   Option<bool>,
+	/// Drop foreground colors (30-39, 90-97, `38;5;n`, `38;2;r;g;b`).
+	#[napi(js_name = "dropForeground")]
+	pub drop_foreground:   Option<bool>,
+	/// Attributes to drop: "bold", "dim", "italic", "underline", "blink",
+	/// "inverse", "hidden", "strikethrough".
+	#[napi(js_name = "dropAttributes")]
+	pub drop_attributes:   Option<Vec<String>>,
+	/// Attributes to keep, dropping every other one, including codes with no
+	/// named attribute (fonts, overline, ...). Underline colors (`58`, `59`)
+	/// follow "underline".
+	#[napi(js_name = "allowAttributes")]
+	pub allow_attributes:  Option<Vec<String>>,
+	/// Keep foreground colors (default true); `false` is `dropForeground`.
+	#[napi(js_name = "allowForeground")]
+	pub allow_foreground:  Option<bool>,
+	/// Keep background colors (default true); `false` is `dropBackground`.
+	#[napi(js_name = "allowBackground")]
+	pub allow_background:  Option<bool>,
+	/// Also downsample the kept colors, as `downsampleColors` with this
+	/// target ("256" or "16"), in the same pass.
+	#[napi(js_name = "downsampleColors")]
+	pub downsample_colors: Option<String>,
+}
+
+/// Every attribute bit a filter can name.
+const ATTR_ALL: u16 = ATTR_BOLD
+	| ATTR_DIM
+	| ATTR_ITALIC
+	| ATTR_UNDERLINE
+	| ATTR_BLINK
+	| ATTR_INVERSE
+	| ATTR_HIDDEN
+	| ATTR_STRIKE;
+
+/// Resolved filter: which SGR codes to drop, and how to downsample the rest.
+struct StyleFilter {
+	background: bool,
+	foreground: bool,
+	attrs:      u16,
+	/// Drop codes that don't map to an attribute bit (allowlist form).
+	unknown:    bool,
+	/// `Some(to_16)` to downsample kept colors.
+	downsample: Option<bool>,
+}
+
+impl StyleFilter {
+	fn from_options(options: &FilterStylesOptions) -> Result<Self> {
+		let attr_bits = |names: Option<&[String]>| -> Result<u16> {
+			let mut attrs = 0;
+			for name in names.into_iter().flatten() {
+				attrs |= match name.as_str() {
+					"bold" => ATTR_BOLD,
+					"dim" => ATTR_DIM,
+					"italic" => ATTR_ITALIC,
+					"underline" => ATTR_UNDERLINE,
+					"blink" => ATTR_BLINK,
+					"inverse" => ATTR_INVERSE,
+					"hidden" => ATTR_HIDDEN,
+					"strikethrough" => ATTR_STRIKE,
+					_ => return Err(Error::from_reason(format!("unknown attribute \"{name}\""))),
+				};
+			}
+			Ok(attrs)
+		};
+		let mut attrs = attr_bits(options.drop_attributes.as_deref())?;
+		if let Some(allowed) = options.allow_attributes.as_deref() {
+			attrs |= ATTR_ALL & !attr_bits(Some(allowed))?;
+		}
+		let downsample = match options.downsample_colors.as_deref() {
+			None => None,
+			Some("256") => Some(false),
+			Some("16") => Some(true),
+			Some(_) => {
+				return Err(Error::from_reason("downsampleColors must be \"256\" or \"16\""));
+			},
+		};
+		Ok(Self {
+			background: options.drop_background.unwrap_or(false)
+				|| options.allow_background == Some(false),
+			foreground: options.drop_foreground.unwrap_or(false)
+				|| options.allow_foreground == Some(false),
+			attrs,
+			unknown: options.allow_attributes.is_some(),
+			downsample,
+		})
+	}
+
+	fn drops(&self, unit: &[u32]) -> bool {
+		let attr = |bits: u16| self.attrs & bits == bits;
+		match unit[0] {
+			0 => false,
+			30..=39 | 90..=97 => self.foreground,
+			40..=49 | 100..=107 => self.background,
+			1 => attr(ATTR_BOLD),
+			2 => attr(ATTR_DIM),
+			// 22 clears both bold and dim.
+			22 => attr(ATTR_BOLD | ATTR_DIM),
+			3 | 23 => attr(ATTR_ITALIC),
+			// 21 is double underline (ECMA-48).
+			4 | 21 | 24 => attr(ATTR_UNDERLINE),
+			5 | 6 | 25 => attr(ATTR_BLINK),
+			7 | 27 => attr(ATTR_INVERSE),
+			8 | 28 => attr(ATTR_HIDDEN),
+			9 | 29 => attr(ATTR_STRIKE),
+			58 | 59 => self.unknown && attr(ATTR_UNDERLINE),
+			_ => self.unknown,
+		}
+	}
+
+	fn filter_codes(&self, codes: &[u32]) -> Option<SgrCodes> {
+		let filtered: Option<SgrCodes> = sgr_units(codes).any(|unit| self.drops(unit)).then(|| {
+			sgr_units(codes)
+				.filter(|unit| !self.drops(unit))
+				.flatten()
+				.copied()
+				.collect()
+		});
+		let Some(to_16) = self.downsample else {
+			return filtered;
+		};
+		downsample_sgr_codes(filtered.as_deref().unwrap_or(codes), to_16).or(filtered)
+	}
+}
+
+fn filter_styles_u16(data: &[u16], filter: &StyleFilter) -> Vec<u16> {
+	rewrite_sgr_u16(data, |codes| filter.filter_codes(codes))
+}
+
+/// Remove selected style categories from SGR sequences, either by naming
+/// what to drop or by allowlisting what to keep.
+///
+/// Combined sequences are rewritten without the dropped codes and sequences
+/// left empty are removed. Resets are always kept. With `downsampleColors`,
+/// the kept colors are downsampled in the same pass.
+#[napi(js_name = "filterStyles")]
+pub fn filter_styles(text: JsString, options: FilterStylesOptions) -> Result<Utf16String> {
+	let filter = StyleFilter::from_options(&options)?;
+	let text_u16 = text.into_utf16()?;
+	Ok(build_utf16_string(filter_styles_u16(text_u16.as_slice(), &filter)))
+}
+
+// ============================================================================
+// downsampleColors
+// ============================================================================
+
+/// xterm's default RGB values for the 16 basic colors.
+const BASIC_PALETTE: [(u8, u8, u8); 16] = [
+	(0, 0, 0),
+	(205, 0, 0),
+	(0, 205, 0),
+	(205, 205, 0),
+	(0, 0, 238),
+	(205, 0, 205),
+	(0, 205, 205),
+	(229, 229, 229),
+	(127, 127, 127),
+	(255, 0, 0),
+	(0, 255, 0),
+	(255, 255, 0),
+	(92, 92, 255),
+	(255, 0, 255),
+	(0, 255, 255),
+	(255, 255, 255),
+];
+
+/// Channel levels of the 6x6x6 color cube (indices 16-231).
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn rgb_distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+	let d = |a: u8, b: u8| (a as i32 - b as i32).unsigned_abs().pow(2);
+	d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+const fn palette_rgb(idx: u8) -> (u8, u8, u8) {
+	match idx {
+		0..=15 => BASIC_PALETTE[idx as usize],
+		16..=231 => {
+			let i = idx - 16;
+			(
+				CUBE_LEVELS[(i / 36) as usize],
+				CUBE_LEVELS[(i / 6 % 6) as usize],
+				CUBE_LEVELS[(i % 6) as usize],
+			)
+		},
+		_ => {
+			let v = 8 + (idx - 232) * 10;
+			(v, v, v)
+		},
+	}
+}
+
+/// Nearest xterm 256-color index (cube or grayscale ramp).
+fn rgb_to_256(rgb: (u8, u8, u8)) -> u8 {
+	let level = |v: u8| match v {
+		0..48 => 0,
+		48..115 => 1,
+		_ => (v - 35) / 40,
+	};
+	let (r, g, b) = rgb;
+	let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+
+	let avg = (r as u32 + g as u32 + b as u32) / 3;
+	let gray = if avg > 238 {
+		255
+	} else {
+		232 + (avg.saturating_sub(3) / 10) as u8
+	};
+
+	if rgb_distance(rgb, palette_rgb(gray)) < rgb_distance(rgb, palette_rgb(cube)) {
+		gray
+	} else {
+		cube
+	}
+}
+
+/// Nearest of the 16 basic colors; bright variants win when they are closer.
+fn rgb_to_16(rgb: (u8, u8, u8)) -> u8 {
+	(0..16u8)
+		.min_by_key(|&idx| rgb_distance(rgb, BASIC_PALETTE[idx as usize]))
+		.unwrap_or(0)
+}
+
+/// Rewrite extended colors in SGR codes. Returns `None` when unchanged.
+fn downsample_sgr_codes(codes: &[u32], to_16: bool) -> Option<SgrCodes> {
+	let mut out = SgrCodes::new();
+	let mut changed = false;
+
+	for unit in sgr_units(codes) {
+		// Extended color as (palette index if indexed, RGB value).
+		let color = match *unit {
+			[code @ (38 | 48), 5, idx] => {
+				let idx = idx.min(255) as u8;
+				Some((code, Some(idx), palette_rgb(idx)))
+			},
+			[code @ (38 | 48), 2, r, g, b] => {
+				let c = |v: u32| v.min(255) as u8;
+				Some((code, None, (c(r), c(g), c(b))))
+			},
+			_ => None,
+		};
+
+		match color {
+			None => out.extend_from_slice(unit),
+			Some((_, Some(idx), _)) if !to_16 || idx < 16 => out.extend_from_slice(unit),
+			Some((code, _, rgb)) if to_16 => {
+				let idx = rgb_to_16(rgb) as u32;
+				let basic = if idx < 8 { 30 + idx } else { 90 + idx - 8 };
+				out.push(if code == 48 { basic + 10 } else { basic });
+				changed = true;
+			},
+			Some((code, _, rgb)) => {
+				out.extend([code, 5, rgb_to_256(rgb) as u32]);
+				changed = true;
+			},
+		}
+	}
+
+	changed.then_some(out)
+}
+
+fn downsample_colors_u16(data: &[u16], to_16: bool) -> Vec<u16> {
+	rewrite_sgr_u16(data, |codes| downsample_sgr_codes(codes, to_16))
+}
+
+/// Rewrite truecolor and 256-color SGR colors to a smaller palette.
+///
+/// `target` is "256" (xterm cube and grayscale ramp) or "16" (nearest basic
+/// color). Other sequences and text are left untouched.
+#[napi(js_name = "downsampleColors")]
+pub fn downsample_colors(text: JsString, target: String) -> Result<Utf16String> {
+	let to_16 = match target.as_str() {
+		"256" => false,
+		"16" => true,
+		_ => return Err(Error::from_reason("target must be \"256\" or \"16\"")),
+	};
+	let text_u16 = text.into_utf16()?;
+	Ok(build_utf16_string(downsample_colors_u16(text_u16.as_slice(), to_16)))
+}
+
+// ============================================================================
+// mapTextPreservingAnsi
+// ============================================================================
+
+/// Append `text` case-mapped to `out`. Runs of valid UTF-16 are mapped as
+/// strings so context-dependent mappings (final sigma) apply; unpaired
+/// surrogates are kept as they are.
+fn push_case_mapped(out: &mut Vec<u16>, text: &[u16], upper: bool) {
+	let mut run = String::new();
+	let flush = |run: &mut String, out: &mut Vec<u16>| {
+		let mapped = if upper {
+			run.to_uppercase()
+		} else {
+			run.to_lowercase()
+		};
+		out.extend(mapped.encode_utf16());
+		run.clear();
+	};
+	for c in char::decode_utf16(text.iter().copied()) {
+		match c {
+			Ok(c) => run.push(c),
+			Err(err) => {
+				flush(&mut run, out);
+				out.push(err.unpaired_surrogate());
+			},
+		}
+	}
+	flush(&mut run, out);
+}
+
+/// Case-map the visible text of `line`, copying escape sequences (including
+/// one cut off at the end) unchanged.
+fn map_text_preserving_ansi_u16(line: &[u16], upper: bool) -> Vec<u16> {
+	let mut out = Vec::with_capacity(line.len() + 8);
+	let mut text_start = 0usize;
+	let mut i = 0usize;
+
+	while let Some(off) = line[i..].iter().position(|&u| u == ESC) {
+		i += off;
+		let end = match ansi_seq_len(line, i) {
+			Some(seq_len) => i + seq_len,
+			None if ansi_seq_incomplete(line, i) => line.len(),
+			None => {
+				i += 1;
+				continue;
+			},
+		};
+		push_case_mapped(&mut out, &line[text_start..i], upper);
+		out.extend_from_slice(&line[i..end]);
+		i = end;
+		text_start = end;
+	}
+	push_case_mapped(&mut out, &line[text_start..], upper);
+
+	out
+}
+
+/// Upper- or lowercase the visible text of a line, leaving ANSI escape
+/// sequences byte-identical.
+///
+/// `op` is "upper" or "lower". Full Unicode mappings are used, so the visible
+/// width can change (`ß` uppercases to `SS`); measure the result rather than
+/// reusing columns computed for the input.
+#[napi(js_name = "mapTextPreservingAnsi")]
+pub fn map_text_preserving_ansi(line: JsString, op: String) -> Result<Utf16String> {
+	let upper = match op.as_str() {
+		"upper" => true,
+		"lower" => false,
+		_ => return Err(Error::from_reason("op must be \"upper\" or \"lower\"")),
+	};
+	let line_u16 = line.into_utf16()?;
+	Ok(build_utf16_string(map_text_preserving_ansi_u16(line_u16.as_slice(), upper)))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fmt::Write as _;
+
+	use super::*;
+
+	const CFG: WidthConfig = WidthConfig::DEFAULT;
+
+	fn to_u16(s: &str) -> Vec<u16> {
+		s.encode_utf16().collect()
+	}
+
+	#[test]
+	fn test_visible_width() {
+		assert_eq!(visible_width_u16(&to_u16("hello"), CFG), 5);
+		assert_eq!(visible_width_u16(&to_u16("\x1b[31mhello\x1b[0m"), CFG), 5);
+		assert_eq!(visible_width_u16(&to_u16("\x1b[38;5;196mred\x1b[0m"), CFG), 3);
+		assert_eq!(visible_width_u16(&to_u16("a\tb"), CFG), 1 + TAB_WIDTH + 1);
+	}
+
+	#[test]
+	fn test_ansi_detection() {
+		let data = to_u16("\x1b[31mred\x1b[0m");
+		assert_eq!(ansi_seq_len(&data, 0), Some(5)); // \x1b[31m
+		assert_eq!(ansi_seq_len(&data, 8), Some(4)); // \x1b[0m
+	}
+
+	#[test]
+	fn test_slice_basic() {
+		let data = to_u16("hello world");
+		let slice = slice_with_width_impl(&data, 0, 5, false, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "hello");
+		assert_eq!(slice.width, 5);
+	}
+
+	#[test]
+	fn test_slice_with_ansi() {
+		let data = to_u16("\x1b[31mhello\x1b[0m world");
+		let slice = slice_with_width_impl(&data, 0, 5, false, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "\x1b[31mhello\x1b[0m");
+		assert_eq!(slice.width, 5);
+	}
+
+	#[test]
+	fn test_carried_style() {
+		let data = to_u16("\x1b[1;31mhel\x1b[0mlo \x1b[38;5;196mworld");
+		let carried = |start, len| {
+			let slice = slice_with_width_impl(&data, start, len, false, CFG);
+			String::from_utf16_lossy(&carried_style_u16(&data[..slice.end]))
+		};
+		assert_eq!(carried(0, 2), "\x1b[1;31m");
+		assert_eq!(carried(0, 3), "");
+		assert_eq!(carried(2, 3), "");
+		assert_eq!(carried(2, 4), "\x1b[38;5;196m");
+		assert_eq!(carried(6, 2), "\x1b[38;5;196m");
+
+		// Sequences after the cut text are still appended, as before carried
+		// styles existed, but the style they set isn't carried.
+		let data = to_u16("\x1b[31mhello\x1b[0m");
+		let slice = slice_with_width_impl(&data, 0, 3, false, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "\x1b[31mhel\x1b[0m");
+		assert_eq!(String::from_utf16_lossy(&carried_style_u16(&data[..slice.end])), "\x1b[31m");
+		let data = to_u16("hello\x1b[31mworld");
+		let slice = slice_with_width_impl(&data, 0, 3, false, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "hel\x1b[31m");
+		assert_eq!(slice.end, 3);
+
+		let mut state = AnsiState::new();
+		let text = to_u16("\x1b[4munderlined text");
+		let out = truncate_to_width_carry(&text, 5, ELLIPSIS_UNICODE, false, Some(&mut state), CFG);
+		assert_eq!(String::from_utf16_lossy(&out.unwrap()), "\x1b[4munde\x1b[0m…");
+		let mut style = Vec::new();
+		state.write_restore_u16(&mut style);
+		assert_eq!(String::from_utf16_lossy(&style), "\x1b[4m");
+	}
+
+	#[test]
+	fn test_ascii_fast_path() {
+		fn is_ascii(seg: &[u16]) -> bool {
+			seg.iter().all(|&u| u <= 0x7f)
+		}
+
+		let ascii = to_u16("hello world 12345");
+		assert!(is_ascii(&ascii));
+
+		let non_ascii = to_u16("hello 世界");
+		assert!(!is_ascii(&non_ascii));
+	}
+
+	#[test]
+	fn test_early_exit() {
+		let data = to_u16(&"a]b".repeat(1000));
+		let (w, exceeded) = visible_width_up_to(&data, 10, CFG);
+		assert!(exceeded);
+		assert!(w > 10);
+	}
+
+	#[test]
+	fn test_wrap_text_with_ansi_preserves_color() {
+		let data = to_u16("\x1b[38;2;156;163;176mhello world\x1b[0m");
+		let lines = wrap_text_with_ansi_impl(&data, 5, &BreakRules::default(), CFG);
+		assert_eq!(lines.len(), 2);
+		let first = String::from_utf16_lossy(&lines[0]);
+		let second = String::from_utf16_lossy(&lines[1]);
+		assert!(first.starts_with("\x1b[38;2;156;163;176m"));
+		assert!(second.starts_with("\x1b[38;2;156;163;176m"));
+		assert!(second.contains("world"));
+	}
+
+	#[test]
+	fn test_strip_ansi() {
+		let strip = |s: &str| String::from_utf16_lossy(&strip_ansi_u16(&to_u16(s)));
+		assert_eq!(strip("\x1b[38;2;255;128;0mtrue\x1b[0mcolor"), "truecolor");
+		assert_eq!(strip("\x1b]8;;https://example.com\x07link\x1b]8;;\x07"), "link");
+		assert_eq!(strip("a\x1b[31"), "a");
+		assert_eq!(strip("a\x1b"), "a");
+		assert_eq!(strip("a\x1b]8;;https://exa"), "a");
+	}
+
+	#[test]
+	fn test_truncate_custom_ellipsis() {
+		let truncate = |s: &str, w: usize, e: &str, pad: bool| {
+			truncate_to_width_impl(&to_u16(s), w, &to_u16(e), pad, CFG)
+				.map(|out| String::from_utf16_lossy(&out))
+		};
+		assert_eq!(truncate("hello world", 11, " [more]", false), None);
+		assert_eq!(truncate("hello world", 9, " [more]", false).unwrap(), "he [more]");
+		assert_eq!(
+			truncate("\x1b[31mhello world", 6, "\x1b[2m…\x1b[22m", false).unwrap(),
+			"\x1b[31mhello\x1b[0m\x1b[2m…\x1b[22m"
+		);
+		// Ellipsis wider than the budget is cut to fit.
+		assert_eq!(truncate("hello world", 3, " [more]", true).unwrap(), " [m");
+		assert_eq!(
+			truncate("hello world", 4, &String::from_utf16_lossy(ELLIPSIS_ASCII), true).unwrap(),
+			"h..."
+		);
+	}
+
+	#[test]
+	fn test_truncate_start() {
+		let truncate = |s: &str, w: usize, pad: bool| {
+			truncate_start_impl(&to_u16(s), w, ELLIPSIS_UNICODE, pad, CFG)
+				.map(|out| String::from_utf16_lossy(&out))
+		};
+		assert_eq!(truncate("src/Button.tsx", 14, false), None);
+		assert_eq!(truncate("src/components/Button.tsx", 11, false).unwrap(), "…Button.tsx");
+		assert_eq!(truncate("\x1b[1;31mred\x1b[0m plain", 4, false).unwrap(), "…ain");
+		assert_eq!(
+			truncate("\x1b[1;31mcolored text\x1b[0m", 5, false).unwrap(),
+			"…\x1b[1;31mtext\x1b[0m"
+		);
+		// Wide char straddling the cut is dropped, padding fills the gap.
+		assert_eq!(truncate("世界ab", 4, false).unwrap(), "…ab");
+		assert_eq!(truncate("世界ab", 4, true).unwrap(), "…ab ");
+		// Text after an escape past the cut counts toward the kept width, so
+		// nothing is padded once the output is full.
+		assert_eq!(truncate("\x1b[31mabcdef\x1b[0mgh", 6, true).unwrap(), "…\x1b[31mdef\x1b[0mgh");
+		assert_eq!(truncate("abcdefgh\x1b[1mij", 8, true).unwrap(), "…defgh\x1b[1mij");
+	}
+
+	#[test]
+	fn test_truncate_middle() {
+		let truncate = |s: &str, w: usize, pad: bool, bias: f64| {
+			truncate_middle_impl(&to_u16(s), w, ELLIPSIS_UNICODE, pad, bias, CFG)
+				.map(|out| String::from_utf16_lossy(&out))
+		};
+		assert_eq!(
+			truncate("downloading verylongfilename.tar.gz (42%)", 18, false, 0.5).unwrap(),
+			"downloadi…gz (42%)"
+		);
+		assert_eq!(truncate("abcdefghij", 5, false, 0.0).unwrap(), "…ghij");
+		assert_eq!(truncate("abcdefghij", 5, false, 1.0).unwrap(), "abcd…");
+		assert_eq!(truncate("short", 10, false, 0.5), None);
+		// Reset only when the head kept SGR; the tail restores state.
+		assert_eq!(
+			truncate("\x1b[31mabcdef\x1b[0m", 5, false, 0.5).unwrap(),
+			"\x1b[31mab\x1b[0m…\x1b[31mef\x1b[0m"
+		);
+		assert_eq!(truncate("abc\x1b[32mdefghi", 5, false, 0.5).unwrap(), "ab…\x1b[32mhi");
+		// Wide chars never get split at either cut.
+		assert_eq!(truncate("世界世界世界", 6, true, 0.5).unwrap(), "世…界 ");
+		// Text after an escape in the tail counts toward its width, so nothing
+		// is padded once the output is full.
+		assert_eq!(truncate("abcdefgh\x1b[1mij", 7, true, 0.5).unwrap(), "abc…h\x1b[1mij");
+	}
+
+	#[test]
+	fn test_truncate_path() {
+		let truncate = |s: &str, w: usize, seps: &str| {
+			String::from_utf16_lossy(&truncate_path_impl(&to_u16(s), w, &to_u16(seps), CFG))
+		};
+		let path = "/Users/me/projects/oh-my-pi/crates/pi-natives/src/shell.rs";
+		assert_eq!(truncate(path, 100, "/"), path);
+		assert_eq!(truncate(path, 32, "/"), "/Users/…/pi-natives/src/shell.rs");
+		assert_eq!(truncate(path, 20, "/"), "/Users/…/shell.rs");
+		assert_eq!(truncate(path, 12, "/"), "…/shell.rs");
+		assert_eq!(truncate(path, 6, "/"), "…ll.rs");
+		// The basename alone fits, but the elided directories still show.
+		assert_eq!(truncate(path, 9, "/"), "…shell.rs");
+		assert_eq!(truncate(path, 8, "/"), "…hell.rs");
+		// Trailing separators don't leave an empty basename.
+		assert_eq!(truncate("/Users/me/projects/oh-my-pi/", 20, "/"), "/Users/…/oh-my-pi");
+		assert_eq!(truncate("/Users/me/projects/oh-my-pi//", 11, "/"), "…/oh-my-pi");
+		assert_eq!(truncate("/Users/me/projects/oh-my-pi/", 6, "/"), "…my-pi");
+		assert_eq!(truncate("/Users/me/", 9, "/"), "/Users/me");
+		assert_eq!(
+			truncate("C:\\Users\\me\\projects\\app\\main.rs", 20, "/\\"),
+			"C:\\…\\app\\main.rs"
+		);
+		assert_eq!(truncate("relative/deeply/nested/file.txt", 20, "/"), "relative/…/file.txt");
+	}
+
+	#[test]
+	fn test_analyze_text() {
+		let a = analyze_text_u16(&to_u16("\x1b[31m世界\x1b[0m\ta\x1b[2K"), CFG);
+		assert_eq!(a.visible_width as usize, 4 + TAB_WIDTH + 1);
+		assert_eq!(a.ansi_count, 3);
+		assert!(a.has_non_sgr);
+		assert!(a.has_wide_chars);
+		assert!(a.has_tabs);
+
+		let a = analyze_text_u16(&to_u16("\x1b[1mplain\x1b[0m"), CFG);
+		assert_eq!(a.visible_width, 5);
+		assert_eq!(a.utf16_length, 13);
+		assert!(!a.has_non_sgr && !a.has_wide_chars && !a.has_tabs);
+	}
+
+	#[test]
+	fn test_tab_width_option() {
+		let cfg = |tab_width| {
+			WidthConfig::from_options(Some(&WidthOptions {
+				tab_width: Some(tab_width),
+				..Default::default()
+			}))
+		};
+		let data = to_u16("a\tb");
+		assert_eq!(visible_width_u16(&data, cfg(4).unwrap()), 6);
+		assert_eq!(visible_width_u16(&data, cfg(8).unwrap()), 10);
+		assert_eq!(slice_with_width_impl(&data, 0, 5, false, cfg(4).unwrap()).width, 5);
+		assert_eq!(
+			truncate_to_width_impl(&data, 5, ELLIPSIS_UNICODE, false, cfg(8).unwrap()),
+			Some(to_u16("a…"))
+		);
+		assert_eq!(
+			truncate_start_impl(&data, 5, ELLIPSIS_UNICODE, false, cfg(8).unwrap()),
+			Some(to_u16("…b"))
+		);
+		assert_eq!(analyze_text_u16(&data, cfg(8).unwrap()).visible_width, 10);
+		let wrapped =
+			wrap_text_with_ansi_impl(&to_u16("a\tb c"), 10, &BreakRules::default(), cfg(8).unwrap());
+		assert_eq!(wrapped.len(), 2);
+		assert!(cfg(0).is_err());
+		assert!(cfg(17).is_err());
+		assert!(cfg(16).is_ok());
+		assert_eq!(WidthConfig::from_options(None).unwrap().tab_width, TAB_WIDTH);
+	}
+
+	#[test]
+	fn test_accurate_tabs() {
+		let cfg = WidthConfig { tab_width: 4, accurate_tabs: true, ..CFG };
+		assert_eq!(visible_width_u16(&to_u16("\t"), cfg), 4);
+		assert_eq!(visible_width_u16(&to_u16("ab\tc"), cfg), 5);
+		assert_eq!(visible_width_u16(&to_u16("abcd\t"), cfg), 8);
+		// Tab right after a wide char (non-ASCII segment path).
+		assert_eq!(visible_width_u16(&to_u16("世\tx"), cfg), 5);
+		assert_eq!(visible_width_u16(&to_u16("a世\tx"), cfg), 5);
+		// ANSI sequences don't advance the column.
+		assert_eq!(visible_width_u16(&to_u16("a\x1b[31m\tb\x1b[0m"), cfg), 5);
+
+		let slice = slice_with_width_impl(&to_u16("ab\tcd"), 2, 3, false, cfg);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "\tc");
+		assert_eq!(slice.width, 3);
+
+		let expand =
+			|s: &str, start| String::from_utf16_lossy(&expand_tabs_impl(&to_u16(s), cfg, start));
+		assert_eq!(expand("a\tb\tc", 0), "a   b   c");
+		assert_eq!(expand("世\tx", 0), "世  x");
+		assert_eq!(expand("\x1b[1mab\x1b[0m\tc", 0), "\x1b[1mab\x1b[0m  c");
+		assert_eq!(expand("\tx", 3), " x");
+		assert_eq!(expand("no tabs", 0), "no tabs");
+
+		// Truncation measures a tab at its column in the input (3 and 2 cells
+		// here, not 4), so the cut lands where `max_width` says.
+		let start = |s: &str, w| {
+			let out = truncate_start_impl(&to_u16(s), w, ELLIPSIS_UNICODE, true, cfg).unwrap();
+			String::from_utf16_lossy(&out)
+		};
+		assert_eq!(start("a\tbcdefgh", 8), "…bcdefgh");
+		let middle = |s: &str, w| {
+			let out = truncate_middle_impl(&to_u16(s), w, ELLIPSIS_UNICODE, true, 0.5, cfg).unwrap();
+			String::from_utf16_lossy(&out)
+		};
+		assert_eq!(middle("ab\tcdefgh", 7), "ab…fgh ");
+	}
+
+	#[test]
+	fn test_emoji_width() {
+		let spec = WidthConfig { emoji_width: EmojiWidth::Spec, ..CFG };
+		let width = |s: &str, cfg| visible_width_u16(&to_u16(s), cfg);
+		// Emoji presentation (VS16) renders at 2 cells.
+		assert_eq!(width("❤\u{FE0F}", CFG), 2);
+		assert_eq!(width("✔\u{FE0F}", CFG), 2);
+		assert_eq!(width("a❤\u{FE0F}b", CFG), 4);
+		assert_eq!(width("1\u{FE0F}\u{20E3}", CFG), 2);
+		// VS16 after a non-emoji base changes nothing.
+		assert_eq!(width("a\u{FE0F}", CFG), 1);
+		assert_eq!(width("xa\u{FE0F}y", CFG), 3);
+		// Text presentation fallbacks without VS16.
+		assert_eq!(width("❤", CFG), 1);
+		assert_eq!(width("✔", CFG), 1);
+		assert_eq!(width("❤", spec), 1);
+		// Spec mode sums codepoint widths (VS16 is zero-width).
+		assert_eq!(width("❤\u{FE0F}", spec), 1);
+		assert_eq!(width("✔\u{FE0F}", spec), 1);
+
+		let out = truncate_to_width_impl(&to_u16("ab❤\u{FE0F}cd"), 4, ELLIPSIS_UNICODE, false, CFG);
+		assert_eq!(out, Some(to_u16("ab…")));
+
+		let opts = |mode: &str| WidthOptions { emoji_width: Some(mode.into()), ..Default::default() };
+		assert!(WidthConfig::from_options(Some(&opts("spec"))).is_ok());
+		assert!(WidthConfig::from_options(Some(&opts("wide"))).is_err());
+	}
+
+	#[test]
+	fn test_emoji_zwj_clusters() {
+		let spec = WidthConfig { emoji_width: EmojiWidth::Spec, ..CFG };
+		let width = |s: &str, cfg| visible_width_u16(&to_u16(s), cfg);
+		let family = "👨\u{200D}👩\u{200D}👧\u{200D}👦";
+		let coder = "👩\u{200D}💻";
+		let astronaut = "🧑🏽\u{200D}🚀";
+		assert_eq!(width(family, CFG), 2);
+		assert_eq!(width(coder, CFG), 2);
+		assert_eq!(width(astronaut, CFG), 2);
+		assert_eq!(width("👍🏽", CFG), 2);
+		assert_eq!(width(coder, spec), 4);
+		assert_eq!(width("👍🏽", spec), 4);
+
+		// Clusters are kept or dropped whole.
+		let text = format!("a{family}b{astronaut}c");
+		let data = to_u16(&text);
+		assert_eq!(width(&text, CFG), 7);
+		let out = truncate_to_width_impl(&data, 4, ELLIPSIS_UNICODE, false, CFG).unwrap();
+		assert_eq!(String::from_utf16_lossy(&out), format!("a{family}…"));
+		let out = truncate_to_width_impl(&data, 3, ELLIPSIS_UNICODE, false, CFG).unwrap();
+		assert_eq!(String::from_utf16_lossy(&out), "a…");
+
+		let slice = slice_with_width_impl(&data, 1, 4, true, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), format!("{family}b"));
+		assert_eq!(slice.width, 3);
+		let slice = slice_with_width_impl(&data, 3, 2, true, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "b");
+	}
+
+	#[test]
+	fn test_line_widths() {
+		let widths = |s: &str| line_widths_u16(&to_u16(s), CFG);
+		assert_eq!(widths("ab\n\x1b[31m世界\x1b[0m\nxyz"), vec![2, 4, 3]);
+		assert_eq!(widths("a\n"), vec![1, 0]);
+		assert_eq!(widths(""), vec![0]);
+		assert_eq!(widths("abc\r\nd\r\n"), vec![3, 1, 0]);
+		assert_eq!(widths("ab\rc"), vec![3]);
+	}
+
+	#[test]
+	fn test_split_ansi_lines() {
+		let (lines, widths) =
+			split_ansi_lines_impl(&to_u16("\x1b[31mred\nstill\x1b[1m\r\nbold\x1b[0m\nplain"), CFG);
+		let lines: Vec<String> = lines.iter().map(|l| String::from_utf16_lossy(l)).collect();
+		assert_eq!(lines, vec![
+			"\x1b[31mred\x1b[0m",
+			"\x1b[31mstill\x1b[1m\x1b[0m",
+			"\x1b[1;31mbold\x1b[0m",
+			"plain",
+		]);
+		assert_eq!(widths, vec![3, 5, 4, 5]);
+	}
+
+	#[test]
+	fn test_grapheme_at_column() {
+		let data = to_u16("a\x1b[31m世\x1b[0mb\te\u{301}");
+		let at =
+			|col| grapheme_at_column_u16(&data, col, CFG).map(|g| (g.start, g.end, g.col, g.width));
+		assert_eq!(at(0), Some((0, 1, 0, 1)));
+		// Both cells of the wide char map to it.
+		assert_eq!(at(1), Some((6, 7, 1, 2)));
+		assert_eq!(at(2), Some((6, 7, 1, 2)));
+		assert_eq!(at(3), Some((11, 12, 3, 1)));
+		assert_eq!(at(5), Some((12, 13, 4, TAB_WIDTH)));
+		assert_eq!(at(7), Some((13, 15, 7, 1)));
+		assert_eq!(at(8), None);
+		assert_eq!(grapheme_at_column_u16(&[], 0, CFG).map(|g| g.start), None);
+	}
+
+	#[test]
+	fn test_insert_at_column() {
+		let insert = |line: &str, col, ins: &str| {
+			String::from_utf16_lossy(&insert_at_column_impl(&to_u16(line), col, &to_u16(ins), CFG))
+		};
+		assert_eq!(insert("hello", 2, "XY"), "heXYllo");
+		assert_eq!(insert("hi", 4, "!"), "hi  !");
+		// Wide char under the column moves after the insertion.
+		assert_eq!(insert("a世b", 2, "|"), "a |世b");
+		assert_eq!(insert("a世b", 1, "|"), "a|世b");
+		// Style is restored after a styled insertion.
+		assert_eq!(
+			insert("\x1b[31mred\x1b[0m", 1, "\x1b[1mB\x1b[22m"),
+			"\x1b[31mr\x1b[1mB\x1b[22m\x1b[0m\x1b[31med\x1b[0m"
+		);
+		assert_eq!(insert("\x1b[31mab", 1, "x"), "\x1b[31maxb");
+	}
+
+	#[test]
+	fn test_replace_range() {
+		let replace = |line: &str, start, end, rep: &str| {
+			String::from_utf16_lossy(&replace_range_impl(&to_u16(line), start, end, &to_u16(rep), CFG))
+		};
+		assert_eq!(replace("hello world", 6, 11, "there!"), "hello there!");
+		assert_eq!(replace("hello", 1, 4, "-"), "h-o");
+		assert_eq!(replace("hello", 2, 2, "XY"), "heXYllo");
+		assert_eq!(replace("hi", 4, 6, "!"), "hi  !");
+		assert_eq!(replace("a世b", 2, 2, "|"), "a|世b");
+		// Wide chars cut by either edge are removed whole.
+		assert_eq!(replace("a世b世c", 2, 5, "_"), "a_c");
+		// The prefix is untouched and the style at the end column is restored.
+		assert_eq!(
+			replace("\x1b[1mab\x1b[31mcd\x1b[0mef", 1, 3, "X"),
+			"\x1b[1maX\x1b[0m\x1b[1;31md\x1b[0mef"
+		);
+		assert_eq!(replace("\x1b[31mabcd", 1, 3, "XY"), "\x1b[31maXYd");
+		assert_eq!(replace("\x1b[31mabcd", 1, 3, "\x1b[1mX"), "\x1b[31ma\x1b[1mX\x1b[0m\x1b[31md");
+	}
+
+	#[test]
+	fn test_overlay_at_column() {
+		let overlay = |base: &str, ov: &str, col, opts: OverlayOptions| {
+			let out = overlay_at_column_impl(&to_u16(base), &to_u16(ov), col, &opts, CFG);
+			(String::from_utf16_lossy(&out.text), out.width)
+		};
+		assert_eq!(
+			overlay("hello world", "XY", 3, OverlayOptions::default()),
+			("helXY world".into(), 11)
+		);
+		// Base shorter than the overlay column.
+		assert_eq!(overlay("ab", "XY", 4, OverlayOptions::default()), ("ab  XY".into(), 6));
+		// Overlay clipped and padded to its region.
+		let opts = || OverlayOptions { width: Some(3), ..Default::default() };
+		assert_eq!(overlay("abcdefgh", "XYZW", 1, opts()), ("aXYZefgh".into(), 8));
+		assert_eq!(overlay("abcdefgh", "X", 1, opts()), ("aX  efgh".into(), 8));
+		// Base style restored after the overlay.
+		assert_eq!(
+			overlay("\x1b[31mabcdef\x1b[0m", "\x1b[1mX\x1b[22m", 2, OverlayOptions::default()),
+			("\x1b[31mab\x1b[0m\x1b[1mX\x1b[22m\x1b[0m\x1b[31mdef\x1b[0m".into(), 6)
+		);
+		// Wide chars in the after segment are dropped when strict.
+		let (text, width) =
+			overlay("a世b", "X", 0, OverlayOptions { width: Some(2), ..Default::default() });
+		assert_eq!((text.as_str(), width), ("X  b", 4));
+		// Total width pads the composed line.
+		let opts = OverlayOptions { total_width: Some(6), ..Default::default() };
+		assert_eq!(overlay("ab", "X", 1, opts), ("aX    ".into(), 6));
+	}
+
+	#[test]
+	fn test_apply_style_to_range() {
+		let style = |line: &str, start, end| {
+			String::from_utf16_lossy(&apply_style_to_range_impl(
+				&to_u16(line),
+				start,
+				end,
+				&to_u16("\x1b[7m"),
+				CFG,
+			))
+		};
+		assert_eq!(style("hello world", 6, 11), "hello \x1b[7mworld\x1b[0m");
+		assert_eq!(style("hello world", 0, 5), "\x1b[7mhello\x1b[0m world");
+		// Original style is restored at the end column.
+		assert_eq!(
+			style("\x1b[31mabcdef\x1b[0m", 1, 3),
+			"\x1b[31ma\x1b[7mbc\x1b[0m\x1b[31mdef\x1b[0m"
+		);
+		// Codes inside the range are followed by the highlight again.
+		assert_eq!(
+			style("ab\x1b[32mcd\x1b[0m", 0, 4),
+			"\x1b[7mab\x1b[32m\x1b[7mcd\x1b[0m\x1b[7m\x1b[0m"
+		);
+		// Wide chars snap outward.
+		assert_eq!(style("a世b世c", 2, 5), "a\x1b[7m世b世\x1b[0mc");
+		assert_eq!(style("a世b世c", 2, 4), "a\x1b[7m世b\x1b[0m世c");
+		assert_eq!(style("abc", 2, 2), "abc");
+	}
+
+	#[test]
+	fn test_style_at_column() {
+		let line = to_u16("a\x1b[1;38;5;208mb\x1b[48;2;10;20;30mc\x1b[22;39md\x1b[0me");
+		let style = |col| StyleInfo::from(style_at_column_u16(&line, col, CFG));
+
+		let s = style(0);
+		assert!(!s.bold && s.fg.kind == "none" && s.bg.kind == "none");
+		let s = style(1);
+		assert!(s.bold);
+		assert_eq!((s.fg.kind.as_str(), s.fg.value), ("indexed", 208));
+		let s = style(2);
+		assert_eq!((s.bg.kind.as_str(), s.bg.value), ("rgb", 0x0a141e));
+		let s = style(3);
+		assert!(!s.bold && s.fg.kind == "none" && s.bg.kind == "rgb");
+		assert_eq!(style(4).bg.kind, "none");
+		assert_eq!(style(9).bg.kind, "none");
+
+		// styleToSgr round-trips through AnsiState.
+		for col in 0..5 {
+			let state = style_at_column_u16(&line, col, CFG);
+			let round = AnsiState::try_from(&StyleInfo::from(state)).unwrap();
+			let (mut a, mut b) = (Vec::new(), Vec::new());
+			state.write_restore_u16(&mut a);
+			round.write_restore_u16(&mut b);
+			assert_eq!(a, b);
+		}
+		let basic = style_at_column_u16(&to_u16("\x1b[91;44mx"), 0, CFG);
+		let info = StyleInfo::from(basic);
+		assert_eq!((info.fg.value, info.bg.value), (9, 4));
+		let mut sgr = Vec::new();
+		AnsiState::try_from(&info)
+			.unwrap()
+			.write_restore_u16(&mut sgr);
+		assert_eq!(String::from_utf16_lossy(&sgr), "\x1b[91;44m");
+
+		let bad = StyleInfo { fg: ColorInfo { kind: "hsl".into(), value: 0, rgb: None }, ..info };
+		assert!(AnsiState::try_from(&bad).is_err());
+	}
+
+	#[test]
+	fn test_ansi_spans() {
+		let line = to_u16(
+			"a\x1b[31mred\x1b[1m\x1b]8;;https://x.dev\x07link\x1b]8;;\x07\x1b[0m\x1b[2Kplain世",
+		);
+		let spans = parse_ansi_spans_u16(&line, false, CFG);
+		let summary: Vec<_> = spans
+			.iter()
+			.map(|s| {
+				let link = s.link.as_deref().map(String::from_utf16_lossy);
+				(String::from_utf16_lossy(&s.text), s.width, s.style.fg, s.style.attrs, link)
+			})
+			.collect();
+		assert_eq!(summary, vec![
+			("a".into(), 1, COLOR_NONE, 0, None),
+			("red".into(), 3, 2, 0, None),
+			("link".into(), 4, 2, ATTR_BOLD, Some("https://x.dev".into())),
+			("plain世".into(), 7, COLOR_NONE, 0, None),
+		]);
+
+		let kept = parse_ansi_spans_u16(&line, true, CFG);
+		assert_eq!(kept.len(), 5);
+		assert_eq!(kept[3].sequence.as_deref(), Some(&to_u16("\x1b[2K")[..]));
+
+		// Round trip keeps text and per-column style.
+		let napi_spans: Vec<AnsiSpan> = spans
+			.into_iter()
+			.map(|s| AnsiSpan {
+				text:     build_utf16_string(s.text),
+				width:    clamp_u32(s.width),
+				style:    s.style.into(),
+				link:     s.link.map(|uri| String::from_utf16_lossy(&uri)),
+				sequence: None,
+			})
+			.collect();
+		let out = spans_to_ansi_u16(&napi_spans).unwrap();
+		assert_eq!(strip_ansi_u16(&out), strip_ansi_u16(&line));
+		for col in 0..visible_width_u16(&line, CFG) {
+			assert!(style_at_column_u16(&out, col, CFG) == style_at_column_u16(&line, col, CFG));
+		}
+		let reparsed = parse_ansi_spans_u16(&out, false, CFG);
+		assert_eq!(reparsed[2].link.as_deref(), Some(&to_u16("https://x.dev")[..]));
+	}
+
+	#[test]
+	fn test_extract_links() {
+		let links = |s: &str| -> Vec<(String, Range<usize>, Range<usize>)> {
+			let line = to_u16(s);
+			extract_links_u16(&line)
+				.into_iter()
+				.map(|(uri, range, cols)| (String::from_utf16_lossy(uri), range, cols))
+				.collect()
+		};
+		let open = |uri: &str| format!("\x1b]8;;{uri}\x07");
+		let close = "\x1b]8;;\x07";
+
+		// BEL and ST terminators, with styling inside the link.
+		let line = format!("see {}\x1b[1mdocs\x1b[22m here{close}.", open("https://a"));
+		assert_eq!(links(&line), [("https://a".to_owned(), 19..37, 4..13)]);
+		let line = "\x1b]8;id=1;file:///x\x1b\\日本\x1b]8;;\x1b\\ ok";
+		assert_eq!(links(line), [("file:///x".to_owned(), 20..22, 0..4)]);
+
+		// Unclosed links run to the end; a new open replaces the current link.
+		let line = format!("{}ab{}cd", open("u1"), open("u2"));
+		assert_eq!(links(&line), [("u1".to_owned(), 8..10, 0..2), ("u2".to_owned(), 18..20, 2..4)]);
+		// Empty links and stray closes are ignored.
+		let line = format!("{}{close}{close}x{}", open("u1"), open("u2"));
+		assert_eq!(links(&line), []);
+		assert_eq!(links("plain \x1b]8;;u"), []);
+	}
+
+	#[test]
+	fn test_optimize_ansi() {
+		let optimize = |s: &str| String::from_utf16_lossy(&optimize_ansi_u16(&to_u16(s)));
+		assert_eq!(optimize("\x1b[0m\x1b[31m\x1b[0m\x1b[31mred\x1b[0m"), "\x1b[31mred\x1b[0m");
+		assert_eq!(optimize("\x1b[1m\x1b[31mx\x1b[31my"), "\x1b[1;31mxy");
+		assert_eq!(optimize("\x1b[1;31ma\x1b[0m\x1b[31mb"), "\x1b[1;31ma\x1b[0;31mb");
+		assert_eq!(optimize("\x1b[31ma\x1b[1mb"), "\x1b[31ma\x1b[1mb");
+		assert_eq!(optimize("plain\x1b[31m"), "plain");
+		assert_eq!(optimize("\x1b[31ma\x1b[32m\x1b[0m"), "\x1b[31ma\x1b[0m");
+		// Unmodeled codes and non-SGR sequences are kept in place.
+		assert_eq!(optimize("\x1b[31m\x1b[4:3mx"), "\x1b[31m\x1b[4:3mx");
+		assert_eq!(optimize("\x1b[41m\x1b[2Kx\x1b[0m"), "\x1b[41m\x1b[2Kx\x1b[0m");
+		// 21 is kept verbatim and does not clear bold.
+		assert_eq!(optimize("\x1b[1ma\x1b[21mb\x1b[1mc"), "\x1b[1ma\x1b[21mbc");
+
+		let samples = [
+			"\x1b[0m\x1b[1m\x1b[31m\x1b[0m\x1b[31mab\x1b[22m\x1b[1mc\x1b[0m\x1b[0md",
+			"\x1b[38;5;208m\x1b[48;2;1;2;3m世\x1b[39m界\x1b[49m\x1b[7m\x1b[27mx\x1b[m",
+			"\x1b[1;2ma\x1b[22;3mb\x1b[23;9mc\x1b[0;4md\x1b[24me",
+		];
+		for sample in samples {
+			let data = to_u16(sample);
+			let out = optimize_ansi_u16(&data);
+			assert!(out.len() <= data.len());
+			assert_eq!(strip_ansi_u16(&out), strip_ansi_u16(&data));
+			for col in 0..visible_width_u16(&data, CFG) {
+				assert!(
+					style_at_column_u16(&out, col, CFG) == style_at_column_u16(&data, col, CFG),
+					"{sample:?} differs at column {col}"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn test_downsample_colors() {
+		// Grayscale ramp to 256 colors.
+		assert_eq!(rgb_to_256((0, 0, 0)), 16);
+		assert_eq!(rgb_to_256((8, 8, 8)), 232);
+		assert_eq!(rgb_to_256((128, 128, 128)), 244);
+		assert_eq!(rgb_to_256((238, 238, 238)), 255);
+		assert_eq!(rgb_to_256((255, 255, 255)), 231);
+		assert_eq!(rgb_to_256((255, 0, 0)), 196);
+		assert_eq!(rgb_to_256((95, 135, 175)), 67);
+		// Grayscale ramp to 16 colors.
+		assert_eq!(rgb_to_16((30, 30, 30)), 0);
+		assert_eq!(rgb_to_16((110, 110, 110)), 8);
+		assert_eq!(rgb_to_16((200, 200, 200)), 7);
+		assert_eq!(rgb_to_16((255, 255, 255)), 15);
+		assert_eq!(rgb_to_16((250, 10, 10)), 9);
+		assert_eq!(rgb_to_16(palette_rgb(244)), 8);
+
+		let down =
+			|s: &str, to_16| String::from_utf16_lossy(&downsample_colors_u16(&to_u16(s), to_16));
+		assert_eq!(down("\x1b[38;2;255;0;0mx\x1b[0m", false), "\x1b[38;5;196mx\x1b[0m");
+		assert_eq!(down("\x1b[1;48;2;128;128;128;4mx", false), "\x1b[1;48;5;244;4mx");
+		assert_eq!(down("\x1b[38;5;196;48;2;0;0;0mx", true), "\x1b[91;40mx");
+		assert_eq!(down("\x1b[38;5;3mx", true), "\x1b[38;5;3mx");
+		assert_eq!(down("\x1b[38;5;244mx", false), "\x1b[38;5;244mx");
+		assert_eq!(down("\x1b[31m\x1b]8;;u\x07x\x1b[2K", true), "\x1b[31m\x1b]8;;u\x07x\x1b[2K");
+	}
+
+	#[test]
+	fn test_substring_balanced() {
+		let sub = |s: &str, start, end| {
+			let (text, start, end) = substring_balanced_u16(&to_u16(s), start, end);
+			(String::from_utf16_lossy(&text), start, end)
+		};
+		// The style active at `start` is restored and closed at `end`.
+		assert_eq!(sub("\x1b[31mhello\x1b[0m", 6, 8), ("\x1b[31mel\x1b[0m".to_owned(), 6, 8));
+		assert_eq!(sub("\x1b[1mab\x1b[22mcd", 5, 12), ("\x1b[1mb\x1b[22mc".to_owned(), 5, 12));
+		assert_eq!(sub("plain", 1, 3), ("la".to_owned(), 1, 3));
+		// Indices inside a sequence move back to its start.
+		assert_eq!(sub("a\x1b[31mb", 3, 7), ("\x1b[31mb\x1b[0m".to_owned(), 1, 7));
+		assert_eq!(sub("ab\x1b[31mc", 0, 4), ("ab".to_owned(), 0, 2));
+		// Surrogate pairs and clusters are kept whole.
+		assert_eq!(sub("a😀b", 2, 3), ("😀".to_owned(), 1, 3));
+		assert_eq!(sub("xe\u{301}y", 0, 2), ("xe\u{301}".to_owned(), 0, 3));
+		assert_eq!(sub("xe\u{301}y", 2, 4), ("e\u{301}y".to_owned(), 1, 4));
+		// Out-of-range and reversed indices are clamped.
+		assert_eq!(sub("abc", 5, 9), (String::new(), 3, 3));
+		assert_eq!(sub("abc", 2, 1), (String::new(), 1, 1));
+	}
+
+	#[test]
+	fn test_filter_styles() {
+		let filter = |s: &str, options: FilterStylesOptions| {
+			let filter = StyleFilter::from_options(&options).unwrap();
+			String::from_utf16_lossy(&filter_styles_u16(&to_u16(s), &filter))
+		};
+		let bg = || FilterStylesOptions { drop_background: Some(true), ..Default::default() };
+		assert_eq!(filter("\x1b[1;41;37mx\x1b[0m", bg()), "\x1b[1;37mx\x1b[0m");
+		assert_eq!(filter("\x1b[48;5;196mx\x1b[49m", bg()), "x");
+		assert_eq!(filter("\x1b[48;2;1;2;3;38;5;4mx\x1b[m", bg()), "\x1b[38;5;4mx\x1b[m");
+		assert_eq!(filter("\x1b[0;44mx", bg()), "\x1b[0mx");
+		assert_eq!(filter("\x1b[31mx\x1b[2K", bg()), "\x1b[31mx\x1b[2K");
+
+		let fg = || FilterStylesOptions { drop_foreground: Some(true), ..Default::default() };
+		assert_eq!(filter("\x1b[38;2;1;2;3;48;5;9mx", fg()), "\x1b[48;5;9mx");
+		// A truncated extended color is dropped whole.
+		assert_eq!(filter("\x1b[1;38;5mx", fg()), "\x1b[1mx");
+		assert_eq!(filter("\x1b[48;2;1;2mx", bg()), "x");
+
+		let attrs = |names: &[&str]| FilterStylesOptions {
+			drop_attributes: Some(names.iter().map(|n| n.to_string()).collect()),
+			..Default::default()
+		};
+		assert_eq!(filter("\x1b[1;3;4mx\x1b[22;23;24m", attrs(&["italic"])), "\x1b[1;4mx\x1b[22;24m");
+		assert_eq!(filter("\x1b[1mx\x1b[22m", attrs(&["bold"])), "x\x1b[22m");
+		assert_eq!(filter("\x1b[1mx\x1b[22m", attrs(&["bold", "dim"])), "x");
+		assert_eq!(filter("\x1b[1;21mx\x1b[24m", attrs(&["bold"])), "\x1b[21mx\x1b[24m");
+		assert_eq!(filter("\x1b[1;21mx\x1b[24m", attrs(&["underline"])), "\x1b[1mx");
+		assert!(StyleFilter::from_options(&attrs(&["sparkly"])).is_err());
+
+		// Allowlist form: only the named attributes survive, resets stay.
+		let allow = |names: &[&str]| FilterStylesOptions {
+			allow_attributes: Some(names.iter().map(|n| n.to_string()).collect()),
+			allow_background: Some(false),
+			..Default::default()
+		};
+		let keep = || allow(&["bold", "underline"]);
+		assert_eq!(filter("\x1b[1;5;7;31;44mx\x1b[0m", keep()), "\x1b[1;31mx\x1b[0m");
+		assert_eq!(filter("\x1b[8mx\x1b[28m\x1b[m", keep()), "x\x1b[m");
+		assert_eq!(filter("\x1b[4;58;5;1;53mx\x1b[24m", keep()), "\x1b[4;58;5;1mx\x1b[24m");
+		assert_eq!(filter("\x1b[4;58;5;1mx", allow(&["bold"])), "x");
+		assert_eq!(filter("\x1b[22;0mx", allow(&[])), "\x1b[0mx");
+		let fg_only = FilterStylesOptions { allow_foreground: Some(false), ..Default::default() };
+		assert_eq!(filter("\x1b[31;42mx", fg_only), "\x1b[42mx");
+
+		// Downsampling composes with filtering.
+		let both = FilterStylesOptions { downsample_colors: Some("16".to_owned()), ..keep() };
+		assert_eq!(filter("\x1b[5;38;2;255;0;0;48;5;4mx", both), "\x1b[91mx");
+		let down =
+			FilterStylesOptions { downsample_colors: Some("256".to_owned()), ..Default::default() };
+		assert_eq!(filter("\x1b[38;2;255;0;0mx", down), "\x1b[38;5;196mx");
+		let bad =
+			FilterStylesOptions { downsample_colors: Some("8".to_owned()), ..Default::default() };
+		assert!(StyleFilter::from_options(&bad).is_err());
+	}
+
+	#[test]
+	fn test_diff_visible() {
+		let diff = |a: &str, b: &str| {
+			let d = diff_visible_u16(&to_u16(a), &to_u16(b), CFG);
+			(d.common_prefix_cols, d.common_suffix_cols, d.changed)
+		};
+		assert_eq!(diff("hello", "hello"), (5, 0, false));
+		// Equal rendering with different escapes.
+		assert_eq!(diff("\x1b[31mhi\x1b[0m", "\x1b[31mh\x1b[31mi\x1b[39m"), (2, 0, false));
+		assert_eq!(diff("\x1b[31mhi", "\x1b[31mh\x1b[39mi"), (1, 0, true));
+		assert_eq!(diff("hello world", "hello there"), (6, 0, true));
+		assert_eq!(diff("abcXdef", "abcYYdef"), (3, 3, true));
+		assert_eq!(diff("aa", "aaa"), (2, 0, true));
+		// Style changes count as differences.
+		assert_eq!(diff("ab\x1b[1mcd", "abcd"), (2, 0, true));
+		assert_eq!(diff("\x1b[1mab\x1b[0mcd", "abcd"), (0, 2, true));
+		// Wide characters are compared whole.
+		assert_eq!(diff("a日本", "a日x"), (3, 0, true));
+		assert_eq!(diff("日本x", "日x"), (2, 1, true));
+	}
+
+	#[test]
+	fn test_grapheme_boundaries() {
+		let count = |s: &str| grapheme_count_u16(&to_u16(s));
+		assert_eq!(count("abc"), 3);
+		assert_eq!(count("\x1b[31mab\x1b[0m"), 2);
+		assert_eq!(count("e\u{301}x"), 2);
+		assert_eq!(count("👩\u{200d}💻👍🏽🇺🇸"), 3);
+		assert_eq!(count("\u{1100}\u{1161}\u{11a8}"), 1);
+		assert_eq!(count("a\u{0308}\u{0323}b"), 2);
+		assert_eq!(count(""), 0);
+
+		// "a" + ZWJ family + "\x1b[1m" + "e" + combining acute.
+		let text = to_u16("a👨\u{200d}👩\u{200d}👧\x1b[1me\u{301}");
+		let next = |i| next_grapheme_boundary_u16(&text, i);
+		let prev = |i| prev_grapheme_boundary_u16(&text, i);
+		assert_eq!(next(0), 1);
+		assert_eq!(next(1), 9);
+		assert_eq!(next(3), 9);
+		assert_eq!(next(9), 15);
+		assert_eq!(next(11), 15);
+		assert_eq!(next(15), 15);
+		assert_eq!(next(100), 15);
+		assert_eq!(prev(15), 13);
+		assert_eq!(prev(13), 1);
+		assert_eq!(prev(11), 1);
+		assert_eq!(prev(1), 0);
+		assert_eq!(prev(0), 0);
+	}
+
+	#[test]
+	fn test_slice_from_end() {
+		let slice = |s: &str, from_end, len| {
+			let out = slice_from_end_impl(&to_u16(s), from_end, len, false, false, CFG);
+			(String::from_utf16_lossy(&out.text), out.width)
+		};
+		assert_eq!(slice("hello world", 5, 5), ("world".to_string(), 5));
+		assert_eq!(slice("hello world", 5, 2), ("wo".to_string(), 2));
+		assert_eq!(slice("abc", 10, 10), ("abc".to_string(), 3));
+		assert_eq!(slice("\x1b[31mabc", 10, 10), ("\x1b[31mabc".to_string(), 3));
+		// State before the window is restored.
+		assert_eq!(
+			slice("\x1b[31mhello \x1b[1mworld\x1b[0m", 5, 5),
+			("\x1b[1;31mworld\x1b[0m".to_string(), 5)
+		);
+		assert_eq!(slice("\x1b[31mab\x1b[0mcd", 2, 2), ("cd".to_string(), 2));
+		// Wide graphemes straddling the window start are dropped.
+		assert_eq!(slice("a日本", 3, 3), ("本".to_string(), 2));
+		assert_eq!(slice("a日本", 4, 4), ("日本".to_string(), 4));
+
+		// Tab stops follow the original line.
+		let cfg = WidthConfig { tab_width: 4, accurate_tabs: true, ..CFG };
+		let out = slice_from_end_impl(&to_u16("abcde\tx"), 4, 4, false, false, cfg);
+		assert_eq!((String::from_utf16_lossy(&out.text), out.width), ("\tx".to_string(), 4));
+	}
+
+	#[test]
+	fn test_slice_clipping() {
+		let slice = |s: &str, start, len, strict, pad| {
+			let out = slice_with_width_at(&to_u16(s), 0, start, len, strict, pad, CFG);
+			(String::from_utf16_lossy(&out.text), out.width, out.clipped_start, out.clipped_end)
+		};
+		assert_eq!(slice("ab日本", 0, 3, true, false), ("ab".to_string(), 2, false, true));
+		assert_eq!(slice("ab日本", 0, 3, true, true), ("ab ".to_string(), 3, false, true));
+		assert_eq!(slice("ab日本", 0, 3, false, false), ("ab日".to_string(), 4, false, false));
+		assert_eq!(slice("ab日本", 3, 3, true, false), ("本".to_string(), 2, true, false));
+		assert_eq!(slice("ab日本", 3, 3, true, true), (" 本".to_string(), 3, true, false));
+		assert_eq!(
+			slice("a\x1b[1m日b", 2, 2, false, true),
+			("\x1b[1m b".to_string(), 2, true, false)
+		);
+		assert_eq!(slice("abcd", 1, 2, true, true), ("bc".to_string(), 2, false, false));
+
+		let out = slice_from_end_impl(&to_u16("a日本"), 3, 3, false, true, CFG);
+		assert_eq!(String::from_utf16_lossy(&out.text), " 本");
+		assert!(out.clipped_start && !out.clipped_end);
+
+		let segments = extract_segments_impl(
+			&to_u16("ab日cd日"),
+			0..2,
+			3,
+			4,
+			SegmentClip::after(true),
+			AnsiState::new(),
+			CFG,
+		);
+		assert_eq!(String::from_utf16_lossy(&segments.after), "cd");
+		assert!(segments.clipped_start && segments.clipped_end);
+	}
+
+	#[test]
+	fn test_sanitize_ansi() {
+		let sanitize =
+			|s: &str, policy| String::from_utf16_lossy(&sanitize_ansi_u16(&to_u16(s), policy));
+		let drop = SanitizePolicy::Drop;
+		let escape = SanitizePolicy::Escape;
+
+		let styled = "\x1b[1;31mred\x1b[0m \x1b]8;;https://x.dev\x07link\x1b]8;;\x07";
+		assert_eq!(sanitize(styled, drop), styled);
+		assert_eq!(sanitize(styled, escape), styled);
+
+		assert_eq!(sanitize("a\x1b[2K\x1b[1Ab", drop), "ab");
+		assert_eq!(sanitize("a\x1b[2Jb", escape), "a␛[2Jb");
+		assert_eq!(sanitize("\x1b]0;title\x07x", drop), "x");
+		assert_eq!(sanitize("\x1b]0;title\x07x", escape), "␛]0;title␇x");
+		assert_eq!(sanitize("\x1bPq#0;2\x1b\\x", drop), "x");
+		assert_eq!(sanitize("\x1b7a\x1b8\x1b(Bb\x1bc", drop), "ab");
+		assert_eq!(sanitize("a\x1b\x01b", escape), "a␛\x01b");
+		assert_eq!(sanitize("a\x1b[2Kb", SanitizePolicy::Keep), "a\x1b[2Kb");
+
+		// Truncated sequences at end-of-string.
+		assert_eq!(sanitize("abc\x1b[31", drop), "abc");
+		assert_eq!(sanitize("abc\x1b[31", escape), "abc␛[31");
+		assert_eq!(sanitize("abc\x1b]8;;http", drop), "abc");
+		assert_eq!(sanitize("abc\x1bP1$r", drop), "abc");
+		assert_eq!(sanitize("abc\x1b(", drop), "abc");
+		assert_eq!(sanitize("abc\x1b", escape), "abc␛");
+
+		// Malformed sequences only swallow their own bytes.
+		assert_eq!(sanitize("a\x1b(\x01bc", drop), "abc");
+		assert_eq!(sanitize("a\x1bPq#0\x1b[31mred", drop), "a\x1b[31mred");
+		assert_eq!(sanitize("a\x1b[1;\u{4e2d}", drop), "a\u{4e2d}");
+	}
 
-				if in_range && fits {
-					if !pending_ansi.is_empty() {
-						for &(p, l) in &pending_ansi {
-							out.extend_from_slice(&line[p..p + l]);
-						}
-						pending_ansi.clear();
-					}
-					out.push(u);
-					out_w += gw;
-				}
-				current_col += gw;
-			}
-		} else {
-			let _ = for_each_grapheme_u16_slow(seg, |gu16, gw| {
-				if current_col >= end_col {
-					return false;
-				}
+	#[test]
+	fn test_control_chars() {
+		let interpret = WidthConfig { control_chars: ControlChars::Interpret, ..CFG };
+		let replace = WidthConfig { control_chars: ControlChars::Replace, ..CFG };
+		let prepared =
+			|s: &str, cfg: WidthConfig| String::from_utf16_lossy(&cfg.prepare_u16(&to_u16(s)));
+		let width = |s: &str, cfg: WidthConfig| visible_width_u16(&cfg.prepare_u16(&to_u16(s)), cfg);
+
+		assert_eq!(width("12345\rab", CFG), 7);
+		assert_eq!(width("12345\rab", interpret), 5);
+		assert_eq!(prepared("12345\rab", interpret), "ab345");
+		assert_eq!(width("12345\rab", replace), 8);
+		assert_eq!(prepared("ab\x08c\r\n", replace), "ab␈c\r\n");
+
+		assert_eq!(prepared("abc\x08\x08X", interpret), "aXc");
+		assert_eq!(prepared("a\x08\x08\x08X", interpret), "X");
+		assert_eq!(prepared("10%\r100%\n", interpret), "100%\n");
+		assert_eq!(prepared("ab\rx\ncd\re", interpret), "xb\ned");
+		// Styles are tracked per cell.
+		assert_eq!(
+			prepared("\x1b[31mabc\x1b[0m\r\x1b[1mX", interpret),
+			"\x1b[1mX\x1b[0;31mbc\x1b[0;1m"
+		);
+		// Overwriting half of a wide grapheme blanks the other half.
+		assert_eq!(prepared("日本\rx", interpret), "x 本");
+		assert_eq!(prepared("a日\r\x08ab", interpret), "ab ");
+		assert_eq!(width("日本\r\x1b[2Kx", interpret), 4);
+
+		// Indices must point into the caller's string: "interpret" is rejected,
+		// "replace" keeps them in place.
+		let line = to_u16("a\x08\tb\x07cd");
+		assert!(interpret.prepare_indexed_u16(&line).is_err());
+		let prepared = replace.prepare_indexed_u16(&line).unwrap();
+		let span = grapheme_at_column_u16(&prepared, 6, replace).unwrap();
+		assert_eq!((span.start, span.end), (5, 6));
+		assert_eq!(line[span.start], u16::from(b'c'));
+		let (first, last) = find_visible_u16(&prepared, "cd", false, 0, replace)[0];
+		assert_eq!((first.start, last.end), (5, 7));
+	}
 
-				let in_range = current_col >= start_col;
-				let fits = !strict || current_col + gw <= end_col;
+	#[test]
+	fn test_convert_overstrike() {
+		let convert = |s: &str| String::from_utf16_lossy(&convert_overstrike_u16(&to_u16(s)));
+
+		// `MAN_KEEP_FORMATTING=1 man ls | head -8`
+		let man =
+			"L\x08LS\x08S(1)                            User Commands                           \
+			 L\x08LS\x08S(1)\n\nN\x08NA\x08AM\x08ME\x08E\n       ls - list directory \
+			 contents\n\nS\x08SY\x08YN\x08NO\x08OP\x08PS\x08SI\x08IS\x08S\n       l\x08ls\x08s \
+			 [_\x08O_\x08P_\x08T_\x08I_\x08O_\x08N]... [_\x08F_\x08I_\x08L_\x08E]...\n";
+		let expected =
+			"\x1b[1mLS\x1b[22m(1)                            User Commands                           \
+			 \x1b[1mLS\x1b[22m(1)\n\n\x1b[1mNAME\x1b[22m\n       ls - list directory \
+			 contents\n\n\x1b[1mSYNOPSIS\x1b[22m\n       \x1b[1mls\x1b[22m \
+			 [\x1b[4mOPTION\x1b[24m]... [\x1b[4mFILE\x1b[24m]...\n";
+		assert_eq!(convert(man), expected);
+		let widths = line_widths_u16(&convert_overstrike_u16(&to_u16(man)), CFG);
+		assert_eq!(widths, [78, 0, 4, 35, 0, 8, 31, 0]);
+
+		// Bold underline, mode switches, and non-ASCII glyphs.
+		assert_eq!(convert("_\x08a\x08ab\x08b"), "\x1b[1;4ma\x1b[24mb\x1b[22m");
+		assert_eq!(convert("\u{2010}\x08\u{2010}x\x08_"), "\x1b[1m\u{2010}\x1b[22;4mx\x1b[24m");
+		assert_eq!(convert("😀\x08😀"), "\x1b[1m😀\x1b[22m");
+		// Unrelated backspaces are kept.
+		assert_eq!(convert("+\x08o a\x08"), "+\x08o a\x08");
+		assert_eq!(convert("ab\x08\x08c"), "ab\x08\x08c");
+	}
 
-				if in_range && fits {
-					if !pending_ansi.is_empty() {
-						for &(p, l) in &pending_ansi {
-							out.extend_from_slice(&line[p..p + l]);
-						}
-						pending_ansi.clear();
-					}
-					out.extend_from_slice(gu16);
-					out_w += gw;
-				}
+	#[test]
+	fn test_short_escape_sequences() {
+		let width = |s: &str| visible_width_u16(&to_u16(s), CFG);
+		assert_eq!(width("\x1b(Babc"), 3);
+		assert_eq!(width("ab\x1b(0q\x1b(Bc"), 4);
+		assert_eq!(width("\x1b7abc\x1b8"), 3);
+		assert_eq!(width("\x1b=x\x1b>"), 1);
+		assert_eq!(width("\x1bMx\x1bc"), 1);
+		assert_eq!(width("\x1b$)Ax"), 1);
+		assert_eq!(width("\x1bPq#0;2;0;0;0\x1b\\ab"), 2);
+		assert_eq!(width("\x1b_Gf=24;AAAA\x1b\\ab"), 2);
+
+		let slice = slice_with_width_impl(&to_u16("\x1b(Babc\x1b7"), 0, 2, false, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "\x1b(Bab\x1b7");
+		let slice = slice_with_width_impl(&to_u16("\x1b(Babc\x1b7"), 1, 2, false, CFG);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "\x1b(Bbc\x1b7");
+		assert_eq!(
+			String::from_utf16_lossy(&strip_ansi_u16(&to_u16("\x1b(Ba\x1b7b\x1bP1$r\x1b\\c\x1bPq"))),
+			"abc"
+		);
+	}
 
-				current_col += gw;
-				current_col < end_col
-			});
+	#[test]
+	fn test_osc_terminators() {
+		for (open, close) in [
+			("\x1b]8;;https://a.dev\x07", "\x1b]8;;\x07"),
+			("\x1b]8;;https://a.dev\x1b\\", "\x1b]8;;\x1b\\"),
+		] {
+			let line = format!("ab {open}link{close} cd");
+			let data = to_u16(&line);
+			let at = line.find('\x1b').unwrap();
+			assert_eq!(ansi_seq_len(&data, at), Some(open.len()));
+			assert_eq!(visible_width_u16(&data, CFG), 10);
+			assert_eq!(String::from_utf16_lossy(&strip_ansi_u16(&data)), "ab link cd");
+
+			let slice = slice_with_width_impl(&data, 3, 4, false, CFG);
+			assert_eq!(String::from_utf16_lossy(&slice.text), format!("{open}link{close}"));
+
+			let spans = parse_ansi_spans_u16(&data, false, CFG);
+			assert_eq!(spans.len(), 3);
+			assert_eq!(spans[1].link.as_deref(), Some(&to_u16("https://a.dev")[..]));
 		}
 	}
 
-	// Include trailing ANSI sequences (e.g., reset codes) that immediately follow
-	while i < line.len() {
-		if line[i] == ESC
-			&& let Some(len) = ansi_seq_len_u16(line, i)
-		{
-			out.extend_from_slice(&line[i..i + len]);
-			i += len;
-			continue;
+	#[test]
+	fn test_measured_line() {
+		let mut line = String::new();
+		for i in 0..40 {
+			let _ = write!(line, "\x1b[3{}mw{i}\x1b[0m 中 ", i % 8);
 		}
-		break;
+		line.push_str("\x1b[1mtail");
+		let data = to_u16(&line);
+		let measured = MeasuredLine::build(data.clone(), CFG);
+		assert_eq!(measured.width, visible_width_u16(&data, CFG));
+		assert!(measured.checkpoints.len() > 1);
+
+		for col in 0..=measured.width + 1 {
+			let expected = style_at_column_u16(&data, col, CFG);
+			let got = measured.state_before(measured.column_to_index(col as u32).unwrap() as usize);
+			assert!(got == expected, "style at {col}");
+			assert_eq!(
+				measured.column_to_index(col as u32).unwrap() as usize,
+				split_at_column_u16(&data, col, CFG).0
+			);
+
+			for strict in [false, true] {
+				let expected = slice_with_width_impl(&data, col, 7, strict, CFG);
+				let got = measured.slice_impl(col, 7, strict);
+				assert_eq!(strip_ansi_u16(&got.text), strip_ansi_u16(&expected.text));
+				assert_eq!(got.width, expected.width);
+				assert_eq!(got.end, expected.end);
+				assert_eq!(got.clipped_start, expected.clipped_start);
+				assert_eq!(got.clipped_end, expected.clipped_end);
+			}
+		}
+
+		// The state before the slice is restored rather than replayed.
+		let measured = MeasuredLine::build(to_u16("\x1b[31mab\x1b[1mcd\x1b[0mef"), CFG);
+		let slice = measured.slice_impl(3, 2, false);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "\x1b[1;31md\x1b[0me");
+		let slice = measured.slice_impl(2, 1, false);
+		assert_eq!(String::from_utf16_lossy(&slice.text), "\x1b[31m\x1b[1mc\x1b[0m");
 	}
 
-	(out, out_w)
-}
+	#[test]
+	fn test_pad_fill() {
+		let fill = |fill_char: &str, styled| {
+			let options =
+				PadOptions { fill_char: Some(fill_char.to_string()), styled_fill: Some(styled) };
+			Fill::from_options(&options, CFG)
+		};
+		let dot = fill("·", false).unwrap();
+		assert!(fill("中", false).is_err());
+		assert!(fill("ab", false).is_err());
+		assert!(fill("\t", false).is_err());
+		assert!(fill("e\u{301}", false).is_ok());
+
+		let pad = |s: &str, w, fill: &Fill| {
+			pad_to_width_impl(&to_u16(s), w, fill, CFG).map(|out| String::from_utf16_lossy(&out))
+		};
+		assert_eq!(pad("ab", 5, &dot).as_deref(), Some("ab···"));
+		assert_eq!(pad("abcde", 5, &dot), None);
+		assert_eq!(pad("\x1b[41mab", 4, &dot).as_deref(), Some("\x1b[41mab\x1b[0m··"));
+		let styled = fill("─", true).unwrap();
+		assert_eq!(pad("\x1b[41mab", 4, &styled).as_deref(), Some("\x1b[41mab──"));
+
+		let truncate = |s: &str, w, fill: &Fill| {
+			let (out, ..) = truncate_to_width_fill(&to_u16(s), w, ELLIPSIS_UNICODE, fill, CFG);
+			String::from_utf16_lossy(&out)
+		};
+		assert_eq!(truncate("ab", 4, &dot), "ab··");
+		assert_eq!(truncate("\x1b[31mab\x1b[0m", 4, &dot), "\x1b[31mab\x1b[0m··");
+		// A styled fill re-establishes the style reset by the truncation.
+		let wide = "\x1b[31mab中中中";
+		assert_eq!(truncate(wide, 6, &dot), "\x1b[31mab中\x1b[0m…·");
+		assert_eq!(truncate(wide, 6, &styled), "\x1b[31mab中\x1b[0m…\x1b[31m─\x1b[0m");
+	}
 
-/// Slice a range of visible columns from a line.
-///
-/// Counts terminal cells, skipping ANSI escapes, and optionally enforces strict
-/// width.
-#[napi(js_name = "sliceWithWidth")]
-pub fn slice_with_width(
-	line: JsString,
-	start_col: u32,
-	length: u32,
-	strict: bool,
-) -> Result<SliceResult> {
-	let line_u16 = line.into_utf16()?;
-	let line = line_u16.as_slice();
+	#[test]
+	fn test_truncate_lines() {
+		let truncate = |s: &str, w, n, pad, indicator| {
+			let out = truncate_lines_impl(&to_u16(s), w, n, ELLIPSIS_UNICODE, pad, indicator, CFG);
+			String::from_utf16_lossy(&out)
+		};
+		let text = "first line\n\nthird\r\nfourth\nfifth";
+		assert_eq!(
+			truncate(text, 6, 10, false, MORE_LINES_INDICATOR),
+			"first…\n\nthird\nfourth\nfifth"
+		);
+		assert_eq!(
+			truncate(text, 20, 3, false, MORE_LINES_INDICATOR),
+			"first line\n\n… (+3 more lines)"
+		);
+		assert_eq!(truncate(text, 8, 3, false, "+{n}"), "first l…\n\n+3");
+		assert_eq!(truncate(text, 8, 2, true, ""), "first l…\n        ");
+		assert_eq!(truncate(text, 8, 0, false, MORE_LINES_INDICATOR), "");
+		assert_eq!(truncate("", 8, 1, false, MORE_LINES_INDICATOR), "");
+
+		// Style carries across lines but each line resets on its own.
+		let styled = "\x1b[31mred line\nstill red\x1b[0m\nplain";
+		assert_eq!(
+			truncate(styled, 5, 3, false, MORE_LINES_INDICATOR),
+			"\x1b[31mred \x1b[0m…\n\x1b[31mstil\x1b[0m…\nplain"
+		);
+	}
 
-	let (out, w) = slice_with_width_impl(line, start_col as usize, length as usize, strict);
+	#[test]
+	fn test_extract_segments_window() {
+		let line = to_u16("\x1b[31mab\x1b[1mcd\x1b[0mef gh");
+		let segments =
+			extract_segments_impl(&line, 3..6, 7, 2, SegmentClip::after(true), AnsiState::new(), CFG);
+		assert_eq!(String::from_utf16_lossy(&segments.before), "\x1b[1;31md\x1b[0mef");
+		assert_eq!(segments.before_width, 3);
+		assert_eq!(String::from_utf16_lossy(&segments.after), "gh");
+
+		// Style active before the line starts is restored in both segments.
+		let mut initial = AnsiState::new();
+		update_state_from_text(&to_u16("\x1b[4m"), &mut initial);
+		let line = to_u16("abc\x1b[32mdef");
+		let segments =
+			extract_segments_impl(&line, 1..2, 4, 2, SegmentClip::after(true), initial, CFG);
+		assert_eq!(String::from_utf16_lossy(&segments.before), "\x1b[4mb");
+		assert_eq!(String::from_utf16_lossy(&segments.after), "\x1b[4;32mef");
+		let segments =
+			extract_segments_impl(&line, 0..1, 1, 1, SegmentClip::after(true), initial, CFG);
+		assert_eq!(String::from_utf16_lossy(&segments.before), "\x1b[4ma");
+
+		// A wide grapheme straddling the window start is dropped.
+		let segments = extract_segments_impl(
+			&to_u16("a日bc"),
+			2..4,
+			4,
+			1,
+			SegmentClip::after(true),
+			AnsiState::new(),
+			CFG,
+		);
+		assert_eq!(String::from_utf16_lossy(&segments.before), "b");
+		assert_eq!(segments.before_width, 1);
+		assert!(segments.before_clipped_start && !segments.before_clipped_end);
+
+		// Strict before drops a wide grapheme crossing its end.
+		let line = to_u16("日a本b");
+		let clip = SegmentClip { strict_before: true, ..SegmentClip::after(true) };
+		let segments = extract_segments_impl(&line, 0..4, 5, 2, clip, AnsiState::new(), CFG);
+		assert_eq!(String::from_utf16_lossy(&segments.before), "日a");
+		assert_eq!(segments.before_width, 3);
+		assert!(segments.before_clipped_end && !segments.before_clipped_start);
+		let segments =
+			extract_segments_impl(&line, 0..4, 5, 1, SegmentClip::after(true), AnsiState::new(), CFG);
+		assert_eq!(String::from_utf16_lossy(&segments.before), "日a本");
+		assert!(!segments.before_clipped_end);
+		let clip = SegmentClip { pad_before: true, ..clip };
+		let segments = extract_segments_impl(&line, 1..4, 5, 1, clip, AnsiState::new(), CFG);
+		assert_eq!(String::from_utf16_lossy(&segments.before), " a ");
+		assert_eq!(segments.before_width, 3);
+		assert!(segments.before_clipped_start && segments.before_clipped_end);
+		assert_eq!(String::from_utf16_lossy(&segments.after), "b");
+	}
 
-	Ok(SliceResult { text: build_utf16_string(out), width: clamp_u32(w) })
-}
+	#[test]
+	fn test_ansi_to_html() {
+		let diff = concat!(
+			"\x1b[1mdiff --git a/main.rs b/main.rs\x1b[m\n",
+			"\x1b[36m@@ -1,2 +1,2 @@\x1b[m\n",
+			" fn main() {\n",
+			"\x1b[31m-    let v: Vec<u8> = vec![];\x1b[m\n",
+			"\x1b[32m+    let v = \"a\" & 'b';\x1b[m\n",
+			"\x1b[38;5;208;48;2;0;0;95m~\x1b[7m!\x1b[0m \x1b]8;;https://a.dev/?q=1&x\x07link\x1b]8;;\x07",
+			"\x1b]8;;javascript:alert(1)\x07bad\x1b]8;;\x07\x1b[2J",
+		);
+		let html = ansi_to_html_impl(&to_u16(diff), &DARK_HTML_PALETTE, None);
+		assert_eq!(
+			html,
+			concat!(
+				"<span style=\"font-weight:bold\">diff --git a/main.rs b/main.rs</span>\n",
+				"<span style=\"color:#00cdcd\">@@ -1,2 +1,2 @@</span>\n",
+				" fn main() {\n",
+				"<span style=\"color:#cd0000\">-    let v: Vec&lt;u8&gt; = vec![];</span>\n",
+				"<span style=\"color:#00cd00\">+    let v = &quot;a&quot; &amp; &#39;b&#39;;</span>\n",
+				"<span style=\"color:#ff8700;background-color:#00005f\">~</span>",
+				"<span style=\"color:#00005f;background-color:#ff8700\">!</span> ",
+				"<a href=\"https://a.dev/?q=1&amp;x\">link</a>bad",
+			)
+		);
+
+		let html = ansi_to_html_impl(
+			&to_u16("\x1b[1;4;31;100mx\x1b[7my"),
+			&LIGHT_HTML_PALETTE,
+			Some("ansi-"),
+		);
+		assert_eq!(
+			html,
+			concat!(
+				"<span class=\"ansi-fg-1 ansi-bg-8 ansi-bold ansi-underline\">x</span>",
+				"<span class=\"ansi-fg-8 ansi-bg-1 ansi-bold ansi-underline\">y</span>",
+			)
+		);
+		let html = ansi_to_html_impl(
+			&to_u16("\x1b[7mz\x1b[32mg\x1b[27mn"),
+			&LIGHT_HTML_PALETTE,
+			Some("ansi-"),
+		);
+		assert_eq!(
+			html,
+			concat!(
+				"<span style=\"color:#ffffff;background-color:#000000\">z</span>",
+				"<span class=\"ansi-bg-2\" style=\"color:#ffffff\">g</span>",
+				"<span class=\"ansi-fg-2\">n</span>",
+			)
+		);
+		let html = ansi_to_html_impl(&to_u16("\x1b[7;9;4mz"), &LIGHT_HTML_PALETTE, None);
+		assert_eq!(
+			html,
+			"<span style=\"color:#ffffff;background-color:#000000;text-decoration:underline \
+			 line-through\">z</span>"
+		);
+	}
 
-// ============================================================================
-// extractSegments
-// ============================================================================
+	#[test]
+	fn test_sanitize_for_terminal() {
+		let sanitize = |s: &str, options: TerminalSanitizeOptions| {
+			let allow = TerminalAllowlist::from_options(&options).unwrap();
+			String::from_utf16_lossy(&sanitize_for_terminal_u16(&to_u16(s), &allow))
+		};
+		let input = "\x1b[31mred\x1b[0m\t\x1b]0;pwned\x07\x1b]8;;https://a.dev\x07x\x1b]8;;\x07\r\n\u{9b}2J\x00\x7f\x1b";
+		assert_eq!(sanitize(input, TerminalSanitizeOptions::default()), "\x1b[31mred\x1b[0m\tx\n2J");
+		assert_eq!(
+			sanitize(input, TerminalSanitizeOptions {
+				mode: Some("caret".into()),
+				allow_sgr: Some(false),
+				allow_hyperlinks: Some(true),
+				..Default::default()
+			}),
+			"^[[31mred^[[0m\t^[]0;pwned^G\x1b]8;;https://a.dev\x07x\x1b]8;;\x07^M\n^[[2J^@^?^["
+		);
+		assert_eq!(
+			sanitize(input, TerminalSanitizeOptions {
+				mode: Some("pictures".into()),
+				allow_title: Some(true),
+				..Default::default()
+			}),
+			"\x1b[31mred\x1b[0m\t\x1b]0;pwned\x07␛]8;;https://a.dev␇x␛]8;;␇␍\n␛[2J␀␡␛"
+		);
+		assert_eq!(
+			sanitize("a\x1b(\x01bc\x1bPq\x1b[1mx", TerminalSanitizeOptions::default()),
+			"abc\x1b[1mx"
+		);
+		assert!(
+			TerminalAllowlist::from_options(&TerminalSanitizeOptions {
+				mode: Some("loud".into()),
+				..Default::default()
+			})
+			.is_err()
+		);
+	}
 
-fn extract_segments_impl(
-	line: &[u16],
-	before_end: usize,
-	after_start: usize,
-	after_len: usize,
-	strict_after: bool,
-) -> (Vec<u16>, usize, Vec<u16>, usize) {
-	let after_end = after_start.saturating_add(after_len);
+	#[test]
+	fn test_width_overrides() {
+		let range = |start, end, width| WidthOverride { start, end, width };
+		assert!(width_override_table(&[range(0x41, 0x100, 2)]).is_err());
+		assert!(width_override_table(&[range(0xe0b0, 0xe0a0, 2)]).is_err());
+		assert!(width_override_table(&[range(0xe0a0, 0xe0b0, 3)]).is_err());
+		assert!(width_override_table(&[range(0xe0b0, 0xe0c0, 2), range(0xe0a0, 0xe0b0, 1)]).is_err());
+
+		// Looked up on a local table: the process-wide one is shared with tests
+		// running in parallel.
+		let table =
+			width_override_table(&[range(0xe0b0, 0xe0b3, 2), range(0xe0a0, 0xe0a2, 0)]).unwrap();
+		assert_eq!(table, [(0xe0a0, 0xe0a2, 0), (0xe0b0, 0xe0b3, 2)]);
+		assert_eq!(lookup_width_override(&table, '\u{e0a0}'), Some(0));
+		assert_eq!(lookup_width_override(&table, '\u{e0a2}'), Some(0));
+		assert_eq!(lookup_width_override(&table, '\u{e0b3}'), Some(2));
+		assert_eq!(lookup_width_override(&table, '\u{e0af}'), None);
+		assert_eq!(lookup_width_override(&table, '\u{e0b4}'), None);
+		assert_eq!(lookup_width_override(&[], '\u{e0b0}'), None);
+	}
 
-	let mut before = Vec::with_capacity(before_end * 2);
-	let mut before_w = 0usize;
+	#[test]
+	fn test_layout_columns() {
+		let spec = |min, max, weight| ColumnSpec { min, max, weight };
+		// Content-sized columns fit; the flexible one takes the rest.
+		let specs = [
+			spec(None, Some(8), Some(0.0)),
+			spec(Some(4), None, None),
+			spec(None, Some(6), Some(0.0)),
+		];
+		assert_eq!(allocate_columns(&[5, 10, 3], &specs, 30), vec![5, 22, 3]);
+		// Too narrow: space beyond the minimums goes toward content by weight.
+		let specs = [spec(Some(2), None, Some(1.0)), spec(Some(2), None, Some(3.0))];
+		assert_eq!(allocate_columns(&[20, 20], &specs, 12), vec![4, 8]);
+		assert_eq!(allocate_columns(&[3, 20], &specs, 12), vec![3, 9]);
+		// Minimums beyond the total are honored left to right.
+		assert_eq!(allocate_columns(&[20, 20], &specs, 3), vec![2, 1]);
+		// Every column capped: the row is narrower than the total.
+		let specs = [spec(None, Some(3), None), spec(None, Some(2), None)];
+		assert_eq!(allocate_columns(&[1, 1], &specs, 10), vec![3, 2]);
+		// Rounding leftovers go to the largest remainders.
+		assert_eq!(distribute_by_weight(10, &[100; 3], &[1.0; 3]), vec![4, 3, 3]);
+
+		let cell = |s: &str, w| String::from_utf16_lossy(&layout_cell_u16(&to_u16(s), w, CFG));
+		assert_eq!(cell("name", 6), "name  ");
+		assert_eq!(cell("description", 6), "descr…");
+		assert_eq!(cell("\x1b[32mok", 4), "\x1b[32mok\x1b[0m  ");
+		assert_eq!(cell("\x1b[32mok", 2), "\x1b[32mok\x1b[0m");
+		assert_eq!(cell("\x1b[31mfailed", 4), "\x1b[31mfai\x1b[0m…");
+		assert_eq!(cell("中文字", 4), "中… ");
+	}
 
-	let mut after = Vec::with_capacity(after_len * 2);
-	let mut after_w = 0usize;
+	#[test]
+	fn test_fit_budget() {
+		// Parts that fit are untouched.
+		assert_eq!(allocate_budget(&[4, 4], &[0, 0], &[0, 0], 10), vec![4, 4]);
+		// The lowest priority shrinks first, down to its minimum.
+		assert_eq!(allocate_budget(&[4, 4, 20, 6], &[0, 0, 5, 0], &[2, 2, 0, 1], 30), vec![
+			4, 4, 16, 6
+		]);
+		assert_eq!(allocate_budget(&[4, 4, 20, 6], &[0, 0, 5, 0], &[2, 2, 0, 1], 15), vec![
+			4, 4, 5, 2
+		]);
+		// Within a priority the widest shrink first; ties come off the last part.
+		assert_eq!(allocate_budget(&[10, 4], &[0, 0], &[0, 0], 10), vec![6, 4]);
+		assert_eq!(allocate_budget(&[10, 4], &[0, 0], &[0, 0], 7), vec![4, 3]);
+		assert_eq!(allocate_budget(&[5, 5, 5], &[0, 0, 0], &[0, 0, 0], 13), vec![5, 4, 4]);
+		// Minimums beyond the budget are given up in the same order.
+		assert_eq!(allocate_budget(&[6, 6], &[5, 5], &[0, 0], 6), vec![3, 3]);
+		assert_eq!(allocate_budget(&[6, 6], &[5, 5], &[0, 1], 6), vec![1, 5]);
+		assert_eq!(allocate_budget(&[6, 6], &[5, 5], &[0, 1], 0), vec![0, 0]);
+		// A minimum above the natural width doesn't grow the part.
+		assert_eq!(allocate_budget(&[2, 8], &[5, 0], &[0, 0], 6), vec![2, 4]);
+
+		let fit = |parts: &[(&str, usize, u32)], total| -> Vec<String> {
+			let parts: Vec<(Vec<u16>, usize, u32)> = parts
+				.iter()
+				.map(|&(s, min, p)| (to_u16(s), min, p))
+				.collect();
+			let parts: Vec<(&[u16], usize, u32)> = parts
+				.iter()
+				.map(|(s, min, p)| (s.as_slice(), *min, *p))
+				.collect();
+			fit_budget_impl(&parts, total, ELLIPSIS_UNICODE, CFG)
+				.iter()
+				.map(|out| String::from_utf16_lossy(out))
+				.collect()
+		};
+		let parts = [
+			("user", 0, 2),
+			("@host:", 0, 2),
+			("\x1b[34m~/src/project\x1b[0m", 4, 0),
+			(" (main)", 0, 1),
+		];
+		assert_eq!(fit(&parts, 40), ["user", "@host:", "\x1b[34m~/src/project\x1b[0m", " (main)"]);
+		assert_eq!(fit(&parts, 21), ["user", "@host:", "\x1b[34m~/s\x1b[0m…", " (main)"]);
+		assert_eq!(fit(&parts, 20), ["user", "@host:", "\x1b[34m~/s\x1b[0m…", " (mai…"]);
+		for total in 0..30 {
+			let width: usize = fit(&parts, total)
+				.iter()
+				.map(|s| visible_width_u16(&to_u16(s), CFG))
+				.sum();
+			assert!(width <= total, "{width} > {total}");
+		}
+		let wide = [("日本語テキスト", 0, 0), ("ab", 0, 0)];
+		let width: usize = fit(&wide, 5)
+			.iter()
+			.map(|s| visible_width_u16(&to_u16(s), CFG))
+			.sum();
+		assert!(width <= 5);
+	}
 
-	let mut current_col = 0usize;
-	let mut i = 0usize;
-	let line_len = line.len();
+	#[test]
+	fn test_align_line() {
+		let align =
+			|s: &str, w, align| String::from_utf16_lossy(&align_line_u16(&to_u16(s), w, align, CFG));
+		assert_eq!(align("ab", 6, Align::Left), "ab    ");
+		assert_eq!(align("ab", 7, Align::Center), "  ab   ");
+		assert_eq!(align("ab", 6, Align::Right), "    ab");
+		assert_eq!(align("中", 5, Align::Center), " 中  ");
+		assert_eq!(align("\x1b[1mtitle", 9, Align::Center), "  \x1b[1mtitle\x1b[0m  ");
+		assert_eq!(align("\x1b[1mtitle\x1b[0m", 5, Align::Right), "\x1b[1mtitle\x1b[0m");
+		assert_eq!(align("too long", 5, Align::Center), "too …");
+		assert!(Align::parse("justify").is_err());
+	}
 
-	// Store pending ANSI ranges for "before"
-	let mut pending_before_ansi: SmallVec<[(usize, usize); 4]> = SmallVec::new();
+	#[test]
+	fn test_repeat_to_width() {
+		let repeat = |s: &str, w| String::from_utf16_lossy(&repeat_to_width_u16(&to_u16(s), w, CFG));
+		assert_eq!(repeat("─", 4), "────");
+		assert_eq!(repeat("═╦", 5), "═╦═╦═");
+		assert_eq!(repeat("ab中", 7), "ab中ab ");
+		assert_eq!(repeat("中", 3), "中 ");
+		assert_eq!(repeat("\x1b[2m-\x1b[22m+", 3), "\x1b[2m-\x1b[22m+\x1b[2m-\x1b[0m");
+		assert_eq!(repeat("\x1b[31m", 2), "  ");
+		assert_eq!(repeat("", 2), "  ");
+		assert_eq!(repeat("ab", 0), "");
+	}
 
-	let mut after_started = false;
-	let mut state = AnsiState::new();
+	#[test]
+	fn test_find_visible() {
+		let find = |haystack: &str, needle, case_insensitive, start| {
+			find_visible_u16(&to_u16(haystack), needle, case_insensitive, start, CFG)
+				.into_iter()
+				.map(|(first, last)| (first.col, last.col + last.width, first.start, last.end))
+				.collect::<Vec<_>>()
+		};
+		let line = "\x1b[1mFoo\x1b[0m 中foo \x1b[31mfo\x1b[0mo";
+		assert_eq!(find(line, "foo", false, 0), vec![(6, 9, 13, 16), (10, 13, 22, 29)]);
+		assert_eq!(find(line, "foo", true, 0), vec![(0, 3, 4, 7), (6, 9, 13, 16), (10, 13, 22, 29)]);
+		assert_eq!(find(line, "foo", true, 1), vec![(6, 9, 13, 16), (10, 13, 22, 29)]);
+		assert_eq!(find(line, "中f", false, 0), vec![(4, 7, 12, 14)]);
+		// Non-overlapping, left to right.
+		assert_eq!(find("aaaa", "aa", false, 0), vec![(0, 2, 0, 2), (2, 4, 2, 4)]);
+		// Matches never split a grapheme.
+		assert_eq!(find("e\u{301}e", "e", false, 0), vec![(1, 2, 2, 3)]);
+		assert_eq!(find("abc", "", false, 0), vec![]);
+	}
 
-	let done_col = if after_len == 0 {
-		before_end
-	} else {
-		after_end
-	};
+	#[test]
+	fn test_concat_styled() {
+		let concat = |parts: &[&str], sep: Option<&str>| {
+			let parts: Vec<Vec<u16>> = parts.iter().map(|p| to_u16(p)).collect();
+			let sep = sep.map(to_u16);
+			String::from_utf16_lossy(&concat_styled_impl(
+				parts.iter().map(Vec::as_slice),
+				sep.as_deref(),
+			))
+		};
+		assert_eq!(concat(&["a", "b"], None), "ab");
+		assert_eq!(concat(&["\x1b[1mA", "b"], None), "\x1b[1mA\x1b[0mb");
+		// A part that already ends in a reset needs nothing extra.
+		assert_eq!(concat(&["\x1b[1mA\x1b[0m", "b"], None), "\x1b[1mA\x1b[0mb");
+		assert_eq!(concat(&["\x1b[1mA\x1b[22m", "b"], None), "\x1b[1mA\x1b[22mb");
+		// The separator is unstyled and never leaks either.
+		assert_eq!(concat(&["\x1b[44mA", "B", "C"], Some(" | ")), "\x1b[44mA\x1b[0m | B | C");
+		assert_eq!(concat(&["A", "B"], Some("\x1b[2m·")), "A\x1b[2m·\x1b[0mB");
+		// The last part's trailing style is left alone.
+		assert_eq!(concat(&["a", "\x1b[31mb"], None), "a\x1b[31mb");
+		assert_eq!(concat(&[], Some(",")), "");
+	}
 
-	while i < line_len && current_col < done_col {
-		if line[i] == ESC {
-			if let Some(seq_len) = ansi_seq_len_u16(line, i) {
-				let seq = &line[i..i + seq_len];
-				if is_sgr_u16(seq) {
-					state.apply_sgr_u16(&seq[2..seq_len - 1]);
-				}
+	#[test]
+	fn test_newlines() {
+		let normalize = |text: &str, crlf| {
+			normalize_newlines_u16(&to_u16(text), crlf).map(|out| String::from_utf16_lossy(&out))
+		};
+		assert_eq!(normalize("a\r\nb\nc\r", false).as_deref(), Some("a\nb\nc\r"));
+		assert_eq!(normalize("a\nb\r", false), None);
+		assert_eq!(normalize("\na\r\nb\rc", true).as_deref(), Some("\r\na\r\nb\rc"));
+		assert_eq!(normalize("a\r\nb", true), None);
+
+		let widths = |text: &str, lone_cr: LoneCr| {
+			lone_cr
+				.prepare_u16(&to_u16(text))
+				.map(|text| line_widths_u16(&text, CFG))
+				.map_err(|err| err.reason.clone())
+		};
+		// Default: `\r\n` breaks, a lone `\r` is zero-width content.
+		assert_eq!(widths("ab\r\ncd\rx\n", LoneCr::Zero), Ok(vec![2, 3, 0]));
+		assert_eq!(widths("ab\r\ncd\rx\n", LoneCr::Break), Ok(vec![2, 2, 1, 0]));
+		assert_eq!(widths("ab\r\ncd", LoneCr::Error), Ok(vec![2, 2]));
+		assert_eq!(
+			widths("ab\r\ncd\r", LoneCr::Error),
+			Err("lone carriage return at index 6".to_string())
+		);
+		assert!(LoneCr::parse(Some("ignore")).is_err());
+	}
 
-				if current_col < before_end {
-					pending_before_ansi.push((i, seq_len));
-				} else if current_col >= after_start && current_col < after_end && after_started {
-					after.extend_from_slice(seq);
-				}
+	#[test]
+	fn test_utf8_matches_utf16() {
+		const PIECES: &[&str] = &[
+			"a",
+			"Z",
+			" ",
+			"\t",
+			"\x1b[1m",
+			"\x1b[38;5;196m",
+			"\x1b[0m",
+			"\x1b]8;;http://x\x07",
+			"中",
+			"é",
+			"e\u{301}",
+			"👍",
+			"👍🏽",
+			"❤\u{FE0F}",
+			"\u{200B}",
+			"\r",
+			"\x1b",
+			"\x1b[",
+			"ｱ",
+		];
+		let mut seed = 0x2545_f491_4f6c_dd1du64;
+		let mut next = move || {
+			seed ^= seed << 13;
+			seed ^= seed >> 7;
+			seed ^= seed << 17;
+			seed as usize
+		};
+
+		for _ in 0..2000 {
+			let text: String = (0..next() % 24)
+				.map(|_| PIECES[next() % PIECES.len()])
+				.collect();
+			let (u8s, u16s) = (text.as_bytes(), to_u16(&text));
+			let (width, cols) = (next() % 16, next() % 8);
+			let strict = next() % 2 == 0;
+
+			assert_eq!(
+				visible_width_up_to(u8s, usize::MAX, CFG),
+				visible_width_up_to(&u16s, usize::MAX, CFG)
+			);
+			assert_eq!(visible_width_up_to(u8s, width, CFG), visible_width_up_to(&u16s, width, CFG));
+
+			let t8 = truncate_to_width_impl(u8s, width, "\u{2026}".as_bytes(), strict, CFG);
+			let t16 = truncate_to_width_impl(&u16s, width, ELLIPSIS_UNICODE, strict, CFG);
+			assert_eq!(
+				t8.map(|out| String::from_utf8(out).unwrap()),
+				t16.map(|out| String::from_utf16(&out).unwrap()),
+				"truncate {text:?} to {width}"
+			);
+
+			let s8 = slice_with_width_impl(u8s, cols, width, strict, CFG);
+			let s16 = slice_with_width_impl(&u16s, cols, width, strict, CFG);
+			assert_eq!(std::str::from_utf8(&s8.text).unwrap(), String::from_utf16(&s16.text).unwrap());
+			assert_eq!(
+				(s8.width, s8.clipped_start, s8.clipped_end),
+				(s16.width, s16.clipped_start, s16.clipped_end)
+			);
+		}
 
-				i += seq_len;
-				continue;
-			}
+		// Invalid UTF-8 measures like the U+FFFD a lone surrogate becomes.
+		assert_eq!(visible_width_up_to(b"a\xff\xfeb", usize::MAX, CFG).0, 4);
+		assert_eq!(visible_width_up_to(&[b'a' as u16, 0xd800, b'b' as u16], usize::MAX, CFG).0, 3);
+	}
 
-			if current_col < before_end {
-				before.push(ESC);
-			} else if current_col >= after_start && current_col < after_end && after_started {
-				after.push(ESC);
+	#[test]
+	fn test_width_word_scan() {
+		// Reference: the per-grapheme walk, stopping once past the limit.
+		let reference = |data: &[u16], limit: usize| {
+			let mut width = 0usize;
+			walk_graphemes_u16(data, CFG, |span| {
+				width = span.col + span.width;
+				width <= limit
+			});
+			(width, width > limit)
+		};
+		const PIECES: &[&[u16]] = &[
+			&[0x61],
+			&[0x7e, 0x20],
+			&[0x7f],
+			&[0x09],
+			&[0x1b, 0x5b, 0x31, 0x6d],
+			&[0x1b],
+			&[0x00],
+			&[0x31, 0xfe0f, 0x20e3],
+			&[0x4e2d],
+			&[0xd800],
+			&[0xdc00, 0x62],
+			&[0xd83d, 0xdc4d],
+			&[0x65, 0x301],
+		];
+		let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+		let mut next = move || {
+			seed ^= seed << 13;
+			seed ^= seed >> 7;
+			seed ^= seed << 17;
+			seed as usize
+		};
+		for _ in 0..5000 {
+			let mut data: Vec<u16> = Vec::new();
+			for _ in 0..next() % 12 {
+				let piece = PIECES[next() % PIECES.len()];
+				for _ in 0..=next() % 9 {
+					data.extend_from_slice(piece);
+				}
 			}
-			i += 1;
-			continue;
+			let limit = next() % 40;
+			assert_eq!(visible_width_up_to(&data, usize::MAX, CFG), reference(&data, usize::MAX));
+			assert_eq!(visible_width_up_to(&data, limit, CFG), reference(&data, limit), "{data:x?}");
+
+			let bytes = String::from_utf16_lossy(&data);
+			assert_eq!(
+				visible_width_up_to(bytes.as_bytes(), limit, CFG),
+				reference(&to_u16(&bytes), limit)
+			);
 		}
+	}
 
-		let start = i;
-		let mut is_ascii = true;
-		while i < line_len && line[i] != ESC {
-			if line[i] > 0x7f {
-				is_ascii = false;
+	#[test]
+	fn test_segment_cache() {
+		let layout = |segment: &str, cfg: WidthConfig, cached: bool| {
+			let mut out = Vec::new();
+			let push = |g: &[u16], w| {
+				out.push((g.len(), w));
+				true
+			};
+			if cached {
+				for_each_grapheme_u16_slow(&to_u16(segment), 2, cfg, push);
+			} else {
+				segment_graphemes_u16(&to_u16(segment), 2, cfg, push);
+			}
+			out
+		};
+		let spec = WidthConfig { emoji_width: EmojiWidth::Spec, ..CFG };
+		for segment in ["⠋", "✅ ok", "👨‍👩‍👧", "⚠\u{FE0F}", "状态", "e\u{301}\t中", &"中".repeat(40)]
+		{
+			for cfg in [CFG, spec] {
+				let expected = layout(segment, cfg, false);
+				// A miss fills the cache; the hit must replay the same layout.
+				assert_eq!(layout(segment, cfg, true), expected, "{segment:?}");
+				assert_eq!(layout(segment, cfg, true), expected, "{segment:?}");
 			}
-			i += 1;
 		}
-		let seg = &line[start..i];
+		assert_eq!(layout("👨‍👩‍👧", CFG, true), vec![(8, 2)]);
+		assert_eq!(layout("👨‍👩‍👧", spec, true), vec![(8, 6)]);
+	}
 
-		if is_ascii {
-			for &u in seg {
-				if current_col >= done_col {
-					break;
-				}
-				let gw = ascii_cell_width_u16(u);
-
-				if current_col < before_end {
-					if !pending_before_ansi.is_empty() {
-						for &(p, l) in &pending_before_ansi {
-							before.extend_from_slice(&line[p..p + l]);
-						}
-						pending_before_ansi.clear();
-					}
-					before.push(u);
-					before_w += gw;
-				} else if current_col >= after_start && current_col < after_end {
-					let fits = !strict_after || current_col + gw <= after_end;
-					if fits {
-						if !after_started {
-							state.write_restore_u16(&mut after);
-							after_started = true;
-						}
-						after.push(u);
-						after_w += gw;
-					}
-				}
-				current_col += gw;
+	#[test]
+	fn test_fuzz_lone_surrogates() {
+		const PIECES: &[&[u16]] = &[
+			&[0x61],
+			&[0x20],
+			&[0x09],
+			&[0x1b, 0x5b, 0x31, 0x6d],
+			&[0x1b, 0x5b, 0x30, 0x6d],
+			&[0xd83d],
+			&[0xdc4d],
+			&[0xd83d, 0xdc4d],
+			&[0x301],
+			&[0x4e2d],
+			&[0xfffd],
+			&[0xd800, 0x301],
+		];
+		let mut seed = 0x853c_49e6_748f_ea9bu64;
+		let mut next = move || {
+			seed ^= seed << 13;
+			seed ^= seed >> 7;
+			seed ^= seed << 17;
+			seed as usize
+		};
+		let width = |data: &[u16]| visible_width_u16(data, CFG);
+		// Valid UTF-16 in must stay valid out: no pair is ever split.
+		let check = |input: &[u16], output: &[u16], what: &str| {
+			if String::from_utf16(input).is_ok() {
+				assert!(
+					String::from_utf16(output).is_ok(),
+					"{what} split a pair: {input:x?} -> {output:x?}"
+				);
 			}
-		} else {
-			let _ = for_each_grapheme_u16_slow(seg, |gu16, gw| {
-				if current_col >= done_col {
-					return false;
-				}
-
-				if current_col < before_end {
-					if !pending_before_ansi.is_empty() {
-						for &(p, l) in &pending_before_ansi {
-							before.extend_from_slice(&line[p..p + l]);
-						}
-						pending_before_ansi.clear();
-					}
-					before.extend_from_slice(gu16);
-					before_w += gw;
-				} else if current_col >= after_start && current_col < after_end {
-					let fits = !strict_after || current_col + gw <= after_end;
-					if fits {
-						if !after_started {
-							state.write_restore_u16(&mut after);
-							after_started = true;
-						}
-						after.extend_from_slice(gu16);
-						after_w += gw;
+		};
+
+		for _ in 0..3000 {
+			let data: Vec<u16> = (0..next() % 16)
+				.flat_map(|_| PIECES[next() % PIECES.len()].iter().copied())
+				.collect();
+			let (a, n) = (next() % 12, next() % 12);
+			let strict = next() % 2 == 0;
+			let ctx = format!("{data:x?} a={a} n={n}");
+
+			let slice = slice_with_width_impl(&data, a, n, strict, CFG);
+			assert_eq!(width(&slice.text), slice.width, "slice {ctx}");
+			if strict {
+				assert!(slice.width <= n, "slice {ctx}");
+			}
+			check(&data, &slice.text, "slice");
+
+			for pad in [false, true] {
+				for out in [
+					truncate_to_width_impl(&data, n, ELLIPSIS_UNICODE, pad, CFG),
+					truncate_start_impl(&data, n, ELLIPSIS_UNICODE, pad, CFG),
+					truncate_middle_impl(&data, n, ELLIPSIS_UNICODE, pad, 0.5, CFG),
+				] {
+					let out = out.unwrap_or_else(|| data.clone());
+					if pad {
+						assert_eq!(width(&out), n, "truncate {ctx}: {out:x?}");
+					} else {
+						assert!(width(&out) <= n, "truncate {ctx}: {out:x?}");
 					}
+					check(&data, &out, "truncate");
 				}
-
-				current_col += gw;
+			}
+			if let Some(out) = pad_to_width_impl(&data, n, &Fill::space(), CFG) {
+				assert_eq!(width(&out), n.max(width(&data)), "pad {ctx}");
+			}
+			for line in wrap_text_with_ansi_impl(&data, n.max(2), &BreakRules::default(), CFG) {
+				assert!(width(&line) <= n.max(2) || data.contains(&0x09), "wrap {ctx}");
+				check(&data, &line, "wrap");
+			}
+			for out in [
+				insert_at_column_impl(&data, a, &[0x7c], CFG),
+				replace_range_impl(&data, a, a + n, &[0x7c], CFG),
+				align_line_u16(&data, n, Align::Center, CFG),
+				layout_cell_u16(&data, n, CFG),
+				repeat_to_width_u16(&data, n, CFG),
+				expand_tabs_impl(&data, CFG, 0),
+				strip_ansi_u16(&data),
+			] {
+				check(&data, &out, "edit");
+			}
+			assert_eq!(width(&align_line_u16(&data, n, Align::Right, CFG)), n, "align {ctx}");
+			assert_eq!(width(&layout_cell_u16(&data, n, CFG)), n, "layout {ctx}");
+
+			let mut last = 0usize;
+			walk_graphemes_u16(&data, CFG, |span| {
+				assert!(span.start >= last && span.end > span.start, "walk {ctx}");
+				check(&data, &data[span.start..span.end], "walk");
+				last = span.end;
 				true
 			});
+			let i = next() % (data.len() + 1);
+			let (prev, nxt) =
+				(prev_grapheme_boundary_u16(&data, i), next_grapheme_boundary_u16(&data, i));
+			check(&data, &data[..prev], "prev boundary");
+			check(&data, &data[..nxt], "next boundary");
 		}
-	}
 
-	(before, before_w, after, after_w)
-}
+		// Lone surrogates are one unit each, 1 cell by default or 0 if asked.
+		let zero = WidthConfig { lone_surrogate_width: 0, ..CFG };
+		let line = [0x61, 0xd83d, 0x62, 0xdc4d, 0xd83d, 0xdc4d, 0xd800, 0x301];
+		assert_eq!(visible_width_u16(&line, CFG), 7);
+		assert_eq!(visible_width_u16(&line, zero), 4);
+		assert_eq!(slice_with_width_impl(&line, 1, 1, true, CFG).text, [0xd83d]);
+		assert_eq!(slice_with_width_impl(&line, 1, 1, true, zero).text, [0xd83d, 0x62]);
+		assert_eq!(visible_width_up_to(b"a\xffb".as_slice(), usize::MAX, zero).0, 2);
+	}
 
-/// Extract the before/after slices around an overlay region.
-///
-/// Preserves ANSI state so the `after` segment renders correctly after
-/// truncation.
-#[napi(js_name = "extractSegments")]
-pub fn extract_segments(
-	line: JsString,
-	before_end: u32,
-	after_start: u32,
-	after_len: u32,
-	strict_after: bool,
-) -> Result<ExtractSegmentsResult> {
-	let line_u16 = line.into_utf16()?;
-	let line = line_u16.as_slice();
+	#[test]
+	fn test_clusters_at_boundaries() {
+		let trunc = |s: &str, w| {
+			let out = truncate_to_width_impl(&to_u16(s), w, &to_u16("~"), false, CFG);
+			out.map_or_else(|| s.to_owned(), |o| String::from_utf16_lossy(&o))
+		};
+		let slice = |s: &str, start, len| {
+			String::from_utf16_lossy(&slice_with_width_impl(&to_u16(s), start, len, true, CFG).text)
+		};
+		// Combining marks stay with their base, also across an SGR.
+		assert_eq!(trunc("abe\u{301}fg", 4), "abe\u{301}~");
+		assert_eq!(trunc("abe\u{301}fg", 3), "ab~");
+		assert_eq!(trunc("abe\x1b[1m\u{301}fg", 4), "abe\x1b[1m\u{301}\x1b[0m~");
+		assert_eq!(trunc("abe\x1b[1m\u{301}fg", 3), "ab~");
+		assert_eq!(slice("abe\u{301}f", 2, 1), "e\u{301}");
+		assert_eq!(slice("abe\x1b[1m\u{301}f", 2, 1), "e\x1b[1m\u{301}");
+		assert_eq!(slice("abe\x1b[1m\u{301}f", 3, 1), "\x1b[1mf");
+		assert_eq!(slice("abe\x1b[1m\u{301}f", 0, 2), "ab\x1b[1m");
+		// Conjoining Hangul jamo (L V T) form one 2-cell syllable.
+		let jamo = "a\u{1100}\u{1161}\u{11a8}b";
+		assert_eq!(visible_width_u16(&to_u16(jamo), CFG), 4);
+		assert_eq!(trunc(jamo, 3), "a~");
+		assert_eq!(trunc(&format!("{jamo}cd"), 4), "a\u{1100}\u{1161}\u{11a8}~");
+		assert_eq!(slice(jamo, 1, 2), "\u{1100}\u{1161}\u{11a8}");
+		assert_eq!(slice(jamo, 2, 2), "b");
+		// Regional indicator pairs are one flag.
+		let flags = "a\u{1f1fa}\u{1f1f8}\u{1f1ef}\u{1f1f5}";
+		assert_eq!(visible_width_u16(&to_u16(flags), CFG), 5);
+		assert_eq!(trunc(flags, 4), "a\u{1f1fa}\u{1f1f8}~");
+		assert_eq!(trunc(flags, 3), "a~");
+		assert_eq!(slice(flags, 3, 2), "\u{1f1ef}\u{1f1f5}");
+		assert_eq!(slice(flags, 2, 2), "");
+	}
 
-	let (before, bw, after, aw) = extract_segments_impl(
-		line,
-		before_end as usize,
-		after_start as usize,
-		after_len as usize,
-		strict_after,
-	);
+	#[test]
+	fn test_truncate_measured() {
+		let measure = |s: &str, w, ellipsis: &str, pad| {
+			let text = to_u16(s);
+			let (out, info) = truncate_to_width_measured(&text, w, &to_u16(ellipsis), pad, None, CFG);
+			let out = out.unwrap_or(text);
+			assert_eq!(info.width, visible_width_u16(&out, CFG), "{s:?} at {w}");
+			(info.width, info.truncated)
+		};
+		assert_eq!(measure("hello", 10, "…", false), (5, false));
+		assert_eq!(measure("hello", 10, "…", true), (10, false));
+		assert_eq!(measure("\x1b[1mhello world", 8, "…", false), (8, true));
+		// A wide character that doesn't fit leaves a gap unless padded.
+		assert_eq!(measure("ab日本", 4, "…", false), (3, true));
+		assert_eq!(measure("ab日本", 4, "…", true), (4, true));
+		// The ellipsis alone is cut to the width.
+		assert_eq!(measure("hello", 2, "...", false), (2, true));
+		assert_eq!(measure("hello", 2, "...", true), (2, true));
+
+		let (out, _, info) =
+			truncate_to_width_fill(&to_u16("ab"), 4, ELLIPSIS_UNICODE, &Fill::space(), CFG);
+		assert_eq!(info, TruncationInfo {
+			width:     visible_width_u16(&out, CFG),
+			truncated: false,
+		});
+	}
 
-	Ok(ExtractSegmentsResult {
-		before:       build_utf16_string(before),
-		before_width: clamp_u32(bw),
-		after:        build_utf16_string(after),
-		after_width:  clamp_u32(aw),
-	})
-}
+	#[test]
+	fn test_wrap_break_chars() {
+		let wrap = |s: &str, w, after: &str, not_before: &str| {
+			let breaks = BreakRules {
+				after: after.chars().collect(),
+				not_before: not_before.chars().collect(),
+				..BreakRules::default()
+			};
+			wrap_text_with_ansi_impl(&to_u16(s), w, &breaks, CFG)
+				.iter()
+				.map(|l| String::from_utf16_lossy(l))
+				.collect::<Vec<_>>()
+		};
+		let path = "see node_modules/@scope/package/index.js";
+		assert_eq!(wrap(path, 20, "", ""), ["see", "node_modules/@scope/", "package/index.js"]);
+		assert_eq!(wrap(path, 20, "/", ""), ["see node_modules/", "@scope/package/", "index.js"]);
+		// The rightmost break that fits wins, and the break char stays put.
+		assert_eq!(wrap("a-b-c-d-e", 5, "-", ""), ["a-b-", "c-d-e"]);
+		assert_eq!(wrap("https://x.dev/a", 9, "/.", "/"), ["https://", "x.dev/a"]);
+		// Never inside a cluster or an escape sequence.
+		assert_eq!(wrap("ab/\u{301}cdef", 4, "/", ""), ["ab/\u{301}c", "def"]);
+		assert_eq!(wrap("ab/\x1b[31mcd", 3, "/", ""), ["ab/", "\x1b[31mcd"]);
+	}
 
-// ============================================================================
-// visibleWidth
-// ============================================================================
+	#[test]
+	fn test_reflow() {
+		let reflow = |lines: &[&str], w, old, hard: Option<&[bool]>| {
+			let lines: Vec<Vec<u16>> = lines.iter().map(|l| to_u16(l)).collect();
+			reflow_impl(&lines, w, old, hard, &BreakRules::default(), CFG)
+				.iter()
+				.map(|l| String::from_utf16_lossy(l))
+				.collect::<Vec<_>>()
+		};
+		let text = "the quick brown fox jumps over the lazy dog";
+		let wrapped: Vec<String> =
+			wrap_text_with_ansi_impl(&to_u16(text), 12, &BreakRules::default(), CFG)
+				.iter()
+				.map(|l| String::from_utf16_lossy(l))
+				.collect();
+		let wrapped: Vec<&str> = wrapped.iter().map(String::as_str).collect();
+		assert_eq!(reflow(&wrapped, 20, Some(12), None), [
+			"the quick brown fox",
+			"jumps over the lazy",
+			"dog"
+		]);
+		assert_eq!(reflow(&wrapped, 100, None, None), [text]);
+		// Short lines and empty lines end paragraphs.
+		assert_eq!(reflow(&["one", "two three", "", "four"], 20, Some(12), None), [
+			"one",
+			"two three",
+			"",
+			"four"
+		]);
+		assert_eq!(reflow(&["one", "two"], 20, Some(12), Some(&[false])), ["one two"]);
+		// A word cut at the old width is rejoined without a space.
+		assert_eq!(reflow(&["abcdef", "gh ij"], 20, Some(6), None), ["abcdefgh ij"]);
+		assert_eq!(reflow(&["aa bb", "cc"], 20, Some(5), None), ["aa bb cc"]);
+		// Style carries across the new breaks.
+		assert_eq!(reflow(&["\x1b[1maa bb", "\x1b[1mcc\x1b[0m"], 3, Some(5), None), [
+			"\x1b[1maa",
+			"\x1b[1mbb",
+			"\x1b[1m\x1b[1mcc\x1b[0m"
+		]);
+	}
 
-/// Calculate visible width of text, excluding ANSI escape sequences.
-///
-/// Tabs count as a fixed-width cell.
-#[napi(js_name = "visibleWidth")]
-pub fn visible_width_napi(text: JsString) -> Result<u32> {
-	let text_u16 = text.into_utf16()?;
-	Ok(clamp_u32(visible_width_u16(text_u16.as_slice())))
-}
+	#[test]
+	fn test_fits_within() {
+		let fits = |s: &str, w| fits_within_u16(&to_u16(s), w, CFG);
+		assert!(fits("hello", 5));
+		assert!(!fits("hello", 4));
+		assert!(fits("", 0));
+		assert!(fits("\x1b[31mhello\x1b[0m", 5));
+		// Exceeding on a wide char, both halves of it.
+		assert!(fits("ab日", 4));
+		assert!(!fits("ab日", 3));
+		assert!(!fits("ab日c", 4));
+		assert!(fits("e\u{301}", 1));
+		// Tabs count their configured width.
+		assert!(fits("a\tb", 2 + TAB_WIDTH));
+		assert!(!fits("a\tb", 1 + TAB_WIDTH));
+		assert!(!fits(&"x".repeat(100_000), 80));
+	}
 
-#[cfg(test)]
-mod tests {
-	use super::*;
+	#[test]
+	fn test_ansi_tracker() {
+		let mut tracker = AnsiTracker::default();
+		let mut push = |s: &str| String::from_utf16_lossy(&tracker.push_u16(&to_u16(s)));
+		assert_eq!(push("plain \x1b[3"), "plain ");
+		assert_eq!(push("1mred"), "\x1b[31mred");
+		assert_eq!(push("\x1b"), "");
+		assert_eq!(push("[1mbold\x1b]8;;http://x\x1b"), "\x1b[1mbold");
+		assert_eq!(push("\\link"), "\x1b]8;;http://x\x1b\\link");
+		// Malformed sequences pass through instead of being held back.
+		assert_eq!(push("\x1b\x01x"), "\x1b\x01x");
+		assert_eq!(push("\x1b(B"), "\x1b(B");
+		let mut sgr = Vec::new();
+		tracker.state.write_restore_u16(&mut sgr);
+		assert_eq!(String::from_utf16_lossy(&sgr), "\x1b[1;31m");
+
+		assert_eq!(String::from_utf16_lossy(&tracker.push_u16(&to_u16("a\x1b[0"))), "a");
+		assert_eq!(tracker.pending, to_u16("\x1b[0"));
+		tracker.reset();
+		assert!(tracker.pending.is_empty() && tracker.state.is_empty());
+
+		assert!(ansi_seq_incomplete(&to_u16("\x1b"), 0));
+		assert!(ansi_seq_incomplete(&to_u16("\x1b(\x20"), 0));
+		assert!(!ansi_seq_incomplete(&to_u16("\x1b(\x01"), 0));
+		assert!(!ansi_seq_incomplete(&to_u16("a"), 0));
+	}
 
-	fn to_u16(s: &str) -> Vec<u16> {
-		s.encode_utf16().collect()
+	#[test]
+	fn test_split_ansi_segments() {
+		let split = |s: &str| {
+			let data = to_u16(s);
+			split_ansi_segments_u16(&data)
+				.into_iter()
+				.map(|(kind, range)| (kind, String::from_utf16_lossy(&data[range])))
+				.collect::<Vec<_>>()
+		};
+		let seg = |kind, s: &str| (kind, s.to_owned());
+		assert_eq!(split(""), []);
+		assert_eq!(split("plain"), [seg("text", "plain")]);
+		assert_eq!(split("\x1b[1mhi\x1b]8;;u\x07x\x1b(B\x1b7"), [
+			seg("csi", "\x1b[1m"),
+			seg("text", "hi"),
+			seg("osc", "\x1b]8;;u\x07"),
+			seg("text", "x"),
+			seg("esc", "\x1b(B"),
+			seg("esc", "\x1b7"),
+		]);
+		assert_eq!(split("a\x1bPq\x1b\\b"), [
+			seg("text", "a"),
+			seg("esc", "\x1bPq\x1b\\"),
+			seg("text", "b")
+		]);
+		// A malformed ESC stays in the text; a truncated sequence is reported.
+		assert_eq!(split("a\x1b\x01b"), [seg("text", "a\x1b\x01b")]);
+		assert_eq!(split("ab\x1b[3"), [seg("text", "ab"), seg("incomplete", "\x1b[3")]);
+		assert_eq!(split("ab\x1b"), [seg("text", "ab"), seg("incomplete", "\x1b")]);
 	}
 
 	#[test]
-	fn test_visible_width() {
-		assert_eq!(visible_width_u16(&to_u16("hello")), 5);
-		assert_eq!(visible_width_u16(&to_u16("\x1b[31mhello\x1b[0m")), 5);
-		assert_eq!(visible_width_u16(&to_u16("\x1b[38;5;196mred\x1b[0m")), 3);
-		assert_eq!(visible_width_u16(&to_u16("a\tb")), 1 + TAB_WIDTH + 1);
+	fn test_measure_trimmed() {
+		let trimmed = |s: &str| measure_trimmed_u16(&to_u16(s), CFG);
+		assert_eq!(trimmed("abc"), (3, 0, 3));
+		assert_eq!(trimmed("abc  "), (3, 2, 3));
+		assert_eq!(trimmed("a\t"), (1, TAB_WIDTH, 1));
+		assert_eq!(trimmed("   "), (0, 3, 0));
+		assert_eq!(trimmed(""), (0, 0, 0));
+		// Escapes around the whitespace don't make it content.
+		assert_eq!(trimmed("\x1b[41mab  \x1b[0m"), (2, 2, 7));
+		assert_eq!(trimmed("ab\x1b[0m \x1b[1m \x1b[0m"), (2, 2, 6));
+		assert_eq!(trimmed("\x1b[1m  \x1b[0m"), (0, 2, 4));
+		assert_eq!(trimmed("日 x "), (4, 1, 3));
 	}
 
 	#[test]
-	fn test_ansi_detection() {
-		let data = to_u16("\x1b[31mred\x1b[0m");
-		assert_eq!(ansi_seq_len_u16(&data, 0), Some(5)); // \x1b[31m
-		assert_eq!(ansi_seq_len_u16(&data, 8), Some(4)); // \x1b[0m
+	fn test_resolve_color() {
+		let color = |kind: &str, value| ColorInfo { kind: kind.into(), value, rgb: None };
+		let resolve = |c: &ColorInfo| resolve_color_rgb(c, &BASIC_PALETTE).unwrap();
+		assert_eq!(resolve(&color("none", 0)), None);
+		assert_eq!(resolve(&color("indexed", 1)), Some(BASIC_PALETTE[1]));
+		assert_eq!(resolve(&color("indexed", 16)), Some((0, 0, 0)));
+		assert_eq!(resolve(&color("indexed", 208)), Some((255, 135, 0)));
+		assert_eq!(resolve(&color("indexed", 232)), Some((8, 8, 8)));
+		assert_eq!(resolve(&color("indexed", 255)), Some((238, 238, 238)));
+		assert_eq!(resolve(&color("rgb", 0x0a141e)), Some((10, 20, 30)));
+		// Out-of-range values resolve to nothing rather than black.
+		assert_eq!(resolve(&color("indexed", 256)), None);
+		assert_eq!(resolve(&color("rgb", 0x1000000)), None);
+		assert!(resolve_color_rgb(&color("hsl", 0), &BASIC_PALETTE).is_err());
+
+		// A custom palette only replaces the 16 base colors.
+		let mut colors = vec![0u32; 16];
+		colors[4] = 0x112233;
+		let custom = palette_table(&colors).unwrap();
+		assert_eq!(
+			resolve_color_rgb(&color("indexed", 4), &custom).unwrap(),
+			Some((0x11, 0x22, 0x33))
+		);
+		assert_eq!(resolve_color_rgb(&color("indexed", 208), &custom).unwrap(), Some((255, 135, 0)));
+		assert!(palette_table(&colors[..15]).is_err());
+		colors[0] = 0x1000000;
+		assert!(palette_table(&colors).is_err());
+
+		let state = style_at_column_u16(&to_u16("\x1b[31;48;5;232mx"), 0, CFG);
+		let info = StyleInfo::resolved(state, &custom);
+		assert_eq!(info.fg.rgb, Some(RgbColor { r: 0, g: 0, b: 0 }));
+		assert_eq!(info.bg.rgb, Some(RgbColor { r: 8, g: 8, b: 8 }));
+		assert_eq!(StyleInfo::from(state).fg.rgb, None);
+		let plain = StyleInfo::resolved(AnsiState::new(), &custom);
+		assert_eq!((plain.fg.rgb, plain.bg.rgb), (None, None));
 	}
 
 	#[test]
-	fn test_slice_basic() {
-		let data = to_u16("hello world");
-		let (out, width) = slice_with_width_impl(&data, 0, 5, false);
-		assert_eq!(String::from_utf16_lossy(&out), "hello");
-		assert_eq!(width, 5);
+	fn test_slice_whole_line() {
+		// `sliceWithWidth` returns the input itself when the line is narrower
+		// than the slice; that must match what slicing would have produced.
+		for line in [
+			"",
+			"abc",
+			"\x1b[31mred\x1b[0m",
+			"\x1b[1mbold",
+			"日本語 e\u{301}",
+			"\x1b]8;;http://x\x07link\x1b]8;;\x07\x1b[0m",
+			"a\tb",
+		] {
+			let line = to_u16(line);
+			let width = whole_line_width(&line, 0, 80, CFG).unwrap();
+			let slice = slice_line_u16(&line, 0, 80, true, true, false, CFG);
+			let Either::B(text) = slice.text else {
+				unreachable!()
+			};
+			assert_eq!(&text[..], &line[..]);
+			assert_eq!(slice.width as usize, width);
+			assert!(!slice.clipped_at_start && !slice.clipped_at_end);
+			let carried = slice.carried_style.unwrap();
+			assert_eq!(&carried[..], &carried_style_u16(&line)[..]);
+		}
+		assert_eq!(whole_line_width(&to_u16("abcd"), 0, 3, CFG), None);
+		assert_eq!(whole_line_width(&to_u16("abc"), 1, 80, CFG), None);
+
+		// An exact fit is sliced, which drops trailing zero-width units.
+		for (line, sliced) in [
+			("ab", "ab"),
+			("ab\u{200b}", "ab"),
+			("ab\x01", "ab"),
+			("ab\x1b", "ab"),
+			("a\u{200b}b\u{200b}", "a\u{200b}b"),
+		] {
+			let line = to_u16(line);
+			assert_eq!(whole_line_width(&line, 0, 2, CFG), None);
+			let slice = slice_line_u16(&line, 0, 2, true, false, false, CFG);
+			let Either::B(text) = slice.text else {
+				unreachable!()
+			};
+			assert_eq!(String::from_utf16_lossy(&text), sliced);
+		}
 	}
 
 	#[test]
-	fn test_slice_with_ansi() {
-		let data = to_u16("\x1b[31mhello\x1b[0m world");
-		let (out, width) = slice_with_width_impl(&data, 0, 5, false);
-		assert_eq!(String::from_utf16_lossy(&out), "\x1b[31mhello\x1b[0m");
-		assert_eq!(width, 5);
+	fn test_highlight_matches() {
+		let hl = |line: &str, query: &str, ci: bool, whole: bool| {
+			let line = to_u16(line);
+			let ranges = highlight_ranges_u16(&line, query, ci, whole, CFG);
+			String::from_utf16_lossy(&highlight_ranges_apply_u16(&line, &ranges, &to_u16("\x1b[43m")))
+		};
+		assert_eq!(
+			hl("a foo b foo", "foo", false, false),
+			"a \x1b[43mfoo\x1b[0m b \x1b[43mfoo\x1b[0m"
+		);
+		assert_eq!(hl("Foo foo", "FOO", true, false), "\x1b[43mFoo\x1b[0m \x1b[43mfoo\x1b[0m");
+		assert_eq!(hl("abc", "x", false, false), "abc");
+		assert_eq!(hl("food foo_ (foo)", "foo", false, true), "food foo_ (\x1b[43mfoo\x1b[0m)");
+		// The surrounding style is restored after the match.
+		assert_eq!(
+			hl("\x1b[7mx foo y\x1b[0m", "foo", false, false),
+			"\x1b[7mx \x1b[43mfoo\x1b[0m\x1b[7m y\x1b[0m"
+		);
+		// Codes inside a match are kept, then the highlight is re-asserted.
+		assert_eq!(
+			hl("f\x1b[31moo bar", "foo", false, false),
+			"\x1b[43mf\x1b[31m\x1b[43moo\x1b[0m\x1b[31m bar"
+		);
+		// Matches span escapes but never split graphemes.
+		assert_eq!(hl("e\u{301}e", "e", false, false), "e\u{301}\x1b[43me\x1b[0m");
 	}
 
 	#[test]
-	fn test_ascii_fast_path() {
-		fn is_ascii(seg: &[u16]) -> bool {
-			seg.iter().all(|&u| u <= 0x7f)
+	fn test_wrap_preserve_whitespace() {
+		let breaks = BreakRules { preserve_whitespace: true, ..BreakRules::default() };
+		let wrap = |s: &str, w| {
+			wrap_text_with_ansi_impl(&to_u16(s), w, &breaks, CFG)
+				.iter()
+				.map(|l| String::from_utf16_lossy(l))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(wrap("hello world foo", 8), ["hello ", "world ", "foo"]);
+		assert_eq!(wrap("ab      cd", 4), ["ab  ", "    ", "cd"]);
+		assert_eq!(wrap("abcdefgh   ", 4), ["abcd", "efgh", "   "]);
+		assert_eq!(wrap("\x1b[41ma    b\x1b[0m", 3), ["\x1b[41ma  ", "\x1b[41m  b\x1b[0m"]);
+
+		// Joining the wrapped lines reproduces the input.
+		for text in
+			["  leading", "trailing   ", "a  b   c    d", "x = 1;  // note  ", "    ", "日本 語  x"]
+		{
+			for w in 1..12 {
+				let lines = wrap(text, w);
+				let strip = |l: &String| String::from_utf16_lossy(&strip_ansi_u16(&to_u16(l)));
+				assert_eq!(lines.iter().map(strip).collect::<String>(), text, "{text:?} at {w}");
+				for line in &lines {
+					assert!(visible_width_u16(&to_u16(line), CFG) <= w.max(2), "{line:?} at {w}");
+				}
+			}
 		}
+	}
 
-		let ascii = to_u16("hello world 12345");
-		assert!(is_ascii(&ascii));
+	#[test]
+	fn test_wrap_justify() {
+		let breaks = BreakRules { justify: true, ..BreakRules::default() };
+		let wrap = |s: &str, w| {
+			wrap_text_with_ansi_impl(&to_u16(s), w, &breaks, CFG)
+				.iter()
+				.map(|l| String::from_utf16_lossy(l))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(wrap("the quick brown fox jumps", 12), ["the    quick", "brown    fox", "jumps"]);
+		// Extra spaces go to the leftmost gaps; existing runs are kept.
+		assert_eq!(wrap("a b c  d efghij", 10), ["a  b  c  d", "efghij"]);
+		assert_eq!(wrap("ab cd ef gh", 9), ["ab  cd ef", "gh"]);
+		// Escapes stay intact and the last line of each paragraph is ragged.
+		assert_eq!(wrap("\x1b[1mab\x1b[22m cd ef\nxy z", 7), [
+			"\x1b[1mab\x1b[22m   cd",
+			"ef",
+			"xy z"
+		]);
+		// Single words and indentation aren't padded.
+		assert_eq!(wrap("abcdefghij k l", 4), ["abcd", "efgh", "ij k", "l"]);
+		assert_eq!(wrap("  ab cd ef", 8), ["  ab  cd", "ef"]);
+
+		for text in [
+			"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod",
+			"日本語 の テキスト を 折り返す ab cd",
+			"\x1b[31mred words\x1b[0m and \x1b]8;;u\x07linked text\x1b]8;;\x07 here  too",
+		] {
+			let strip = |l: &str| String::from_utf16_lossy(&strip_ansi_u16(&to_u16(l)));
+			let visible = |l: &str| strip(l).replace(' ', "");
+			for w in 6..30 {
+				let lines = wrap(text, w);
+				for line in &lines[..lines.len() - 1] {
+					let width = visible_width_u16(&to_u16(line), CFG);
+					if strip(line).split_whitespace().count() > 1 {
+						assert_eq!(width, w, "{line:?} at {w}");
+					} else {
+						assert!(width <= w, "{line:?} at {w}");
+					}
+				}
+				assert_eq!(lines.iter().map(|l| visible(l)).collect::<String>(), visible(text));
+			}
+		}
+	}
 
-		let non_ascii = to_u16("hello 世界");
-		assert!(!is_ascii(&non_ascii));
+	#[test]
+	fn test_measure_graphemes() {
+		let line = to_u16("a\x1b[31m日e\u{301}\x1b[0m\tb");
+		let (widths, starts, cols) = measure_graphemes_u16(&line, CFG);
+		assert_eq!(widths, [1, 2, 1, TAB_WIDTH as u8, 1]);
+		assert_eq!(starts, [0, 6, 7, 13, 14]);
+		assert_eq!(cols, [0, 1, 3, 4, 4 + TAB_WIDTH as u32]);
+		// Consistent with graphemeAtColumn for every column.
+		for (i, (&start, &col)) in starts.iter().zip(&cols).enumerate() {
+			for c in col..col + u32::from(widths[i]) {
+				let span = grapheme_at_column_u16(&line, c as usize, CFG).unwrap();
+				assert_eq!((span.start, span.col), (start as usize, col as usize));
+			}
+		}
+		assert_eq!(measure_graphemes_u16(&to_u16("\x1b[1m"), CFG), (vec![], vec![], vec![]));
 	}
 
 	#[test]
-	fn test_early_exit() {
-		let data = to_u16(&"a]b".repeat(1000));
-		let (w, exceeded) = visible_width_u16_up_to(&data, 10);
-		assert!(exceeded);
-		assert!(w > 10);
+	fn test_tab_start_column() {
+		let cfg = |start| WidthConfig { tab_width: 4, accurate_tabs: true, tab_origin: start, ..CFG };
+		let width = |s: &str, start| visible_width_u16(&to_u16(s), cfg(start));
+		// A fragment starting with a tab advances to the real tab stop.
+		assert_eq!(width("\tx", 0), 5);
+		assert_eq!(width("\tx", 1), 4);
+		assert_eq!(width("\tx", 3), 2);
+		assert_eq!(width("\tx", 37), 4);
+		assert_eq!(width("ab\t世\t", 37), 7);
+		assert_eq!(width("\x1b[31m\t\x1b[0m", 2), 2);
+		// Without accurate tabs the start column has no effect.
+		assert_eq!(
+			visible_width_u16(&to_u16("\tx"), WidthConfig { tab_origin: 3, ..CFG }),
+			TAB_WIDTH + 1
+		);
+
+		let slice = slice_with_width_impl(&to_u16("\tab\tc"), 1, 4, false, cfg(2));
+		assert_eq!(String::from_utf16_lossy(&slice.text), "ab\t");
+		assert_eq!(slice.width, 4);
+		let out = truncate_to_width_impl(&to_u16("\tabcdef"), 5, &[], false, cfg(1));
+		assert_eq!(String::from_utf16_lossy(&out.unwrap()), "\tab");
+
+		let options =
+			WidthOptions { accurate_tabs: Some(true), start_column: Some(6), ..Default::default() };
+		assert_eq!(
+			WidthConfig::from_options(Some(&options))
+				.unwrap()
+				.tab_cells(0),
+			TAB_WIDTH - 6 % TAB_WIDTH
+		);
 	}
 
 	#[test]
-	fn test_wrap_text_with_ansi_preserves_color() {
-		let data = to_u16("\x1b[38;2;156;163;176mhello world\x1b[0m");
-		let lines = wrap_text_with_ansi_impl(&data, 5);
-		assert_eq!(lines.len(), 2);
-		let first = String::from_utf16_lossy(&lines[0]);
-		let second = String::from_utf16_lossy(&lines[1]);
-		assert!(first.starts_with("\x1b[38;2;156;163;176m"));
-		assert!(second.starts_with("\x1b[38;2;156;163;176m"));
-		assert!(second.contains("world"));
+	fn test_map_text_preserving_ansi() {
+		let map = |s: &str, upper| {
+			String::from_utf16_lossy(&map_text_preserving_ansi_u16(&to_u16(s), upper))
+		};
+		assert_eq!(map("\x1b[31mred\x1b[0m", true), "\x1b[31mRED\x1b[0m");
+		assert_eq!(
+			map("\x1b]8;;http://X/\x07Link\x1b]8;;\x07", false),
+			"\x1b]8;;http://X/\x07link\x1b]8;;\x07"
+		);
+		assert_eq!(map("a\x1b(Bb\x1b[1", true), "A\x1b(BB\x1b[1");
+		assert_eq!(map("ΟΔΟΣ", false), "οδος");
+		assert_eq!(map("a\x1b\x01b", true), "A\x1b\x01B");
+		let lone = [u16::from(b'a'), 0xd800, u16::from(b'b')];
+		assert_eq!(map_text_preserving_ansi_u16(&lone, true), [
+			u16::from(b'A'),
+			0xd800,
+			u16::from(b'B')
+		]);
+
+		// Escapes are untouched and the width follows the mapped visible text.
+		for (line, upper) in [
+			("\x1b[1mstraße\x1b[22m ok", true),
+			("\x1b[38;5;33mİstanbul\x1b[m", false),
+			("\x1b[4mﬁne 日本\x1b[24m", true),
+		] {
+			let out = map_text_preserving_ansi_u16(&to_u16(line), upper);
+			let kinds = |data: &[u16]| -> Vec<Vec<u16>> {
+				split_ansi_segments_u16(data)
+					.into_iter()
+					.filter(|(kind, _)| *kind != "text")
+					.map(|(_, range)| data[range].to_vec())
+					.collect()
+			};
+			assert_eq!(kinds(&out), kinds(&to_u16(line)));
+
+			let plain = String::from_utf16_lossy(&strip_ansi_u16(&to_u16(line)));
+			let mapped = if upper {
+				plain.to_uppercase()
+			} else {
+				plain.to_lowercase()
+			};
+			assert_eq!(String::from_utf16_lossy(&strip_ansi_u16(&out)), mapped);
+			assert_eq!(visible_width_u16(&out, CFG), visible_width_u16(&to_u16(&mapped), CFG));
+		}
+		let before = visible_width_u16(&to_u16("\x1b[1mstraße\x1b[22m"), CFG);
+		let after = visible_width_u16(
+			&map_text_preserving_ansi_u16(&to_u16("\x1b[1mstraße\x1b[22m"), true),
+			CFG,
+		);
+		assert_eq!((before, after), (6, 7));
 	}
 }
@@ -0,0 +1,74 @@
+import { sliceViewport, sliceWithWidth, visibleWidth, visibleWidthUtf8 } from "../src/index.js";
+
+const ITERATIONS = 200;
+const SIZE = 1024 * 1024;
+
+interface BenchCase {
+	name: string;
+	text: string;
+}
+
+const cases: BenchCase[] = [
+	{ name: "1MB ASCII line", text: "abcdefghij".repeat(SIZE / 10) },
+	{ name: "1MB ASCII with tabs", text: "abcdefgh\t".repeat(SIZE / 9) },
+	{ name: "1MB styled ASCII", text: "\x1b[1mabcdefghij\x1b[0m".repeat(SIZE / 18) },
+	{ name: "1MB mixed CJK", text: "status 状态 ok ".repeat(SIZE / 14) },
+	{
+		name: "1MB styled emoji",
+		text: "\x1b[32m✅\x1b[0m done \x1b[33m⠋\x1b[0m 👨‍👩‍👧 \x1b[1m状态\x1b[0m ".repeat(SIZE / 48),
+	},
+];
+
+const glyphs = ["⠋", "⠙", "⠹", "✅", "❌", "⚠️", "👨‍👩‍👧", "状态"];
+
+const time = (fn: () => number): { ms: number; width: number } => {
+	let width = fn();
+	const start = Bun.nanoseconds();
+	for (let i = 0; i < ITERATIONS; i++) width = fn();
+	return { ms: (Bun.nanoseconds() - start) / 1e6 / ITERATIONS, width };
+};
+
+console.log(`Benchmark: ${ITERATIONS} iterations per case\n`);
+
+for (const c of cases) {
+	const buf = Buffer.from(c.text);
+	const native = time(() => visibleWidth(c.text));
+	const utf8 = time(() => visibleWidthUtf8(buf));
+	const bun = time(() => Bun.stringWidth(c.text));
+	const mbps = (ms: number) => (c.text.length / 1024 / 1024 / (ms / 1000)).toFixed(0);
+
+	console.log(`${c.name}:`);
+	console.log(`  visibleWidth:      ${native.ms.toFixed(3)}ms (${mbps(native.ms)} MB/s, width ${native.width})`);
+	console.log(`  visibleWidthUtf8:  ${utf8.ms.toFixed(3)}ms (${mbps(utf8.ms)} MB/s, width ${utf8.width})`);
+	console.log(`  Bun.stringWidth:   ${bun.ms.toFixed(3)}ms (${mbps(bun.ms)} MB/s, width ${bun.width})\n`);
+}
+
+// Repeated short glyphs hit the native segment cache.
+const glyphMs = time(() => {
+	let width = 0;
+	for (let i = 0; i < 10_000; i++) width += visibleWidth(glyphs[i % glyphs.length]);
+	return width;
+}).ms;
+console.log(`Status glyphs (10k calls): ${glyphMs.toFixed(3)}ms`);
+
+// A 200-row viewport of short styled lines, all narrower than the viewport, so
+// every slice covers its whole line and returns the input without copying.
+// Slicing each line one column short of its width takes the copying path the
+// whole-line case used before, for comparison.
+const rows = Array.from(
+	{ length: 200 },
+	(_, i) => `\x1b[32m✓\x1b[0m src/file-${i}.ts \x1b[2m${"·".repeat(i % 40)}\x1b[0m 状态 ${i}`,
+);
+const rowWidths = rows.map(row => visibleWidth(row));
+const sliceRows = (length: (i: number) => number) => {
+	let width = 0;
+	for (let i = 0; i < rows.length; i++) width += sliceWithWidth(rows[i], 0, length(i), true).width;
+	return width;
+};
+const wholeMs = time(() => sliceRows(() => 120)).ms;
+const copyMs = time(() => sliceRows(i => rowWidths[i] - 1)).ms;
+const viewportMs = time(() => sliceViewport(rows, 0, 120, true).length).ms;
+console.log("\n200-row viewport, 120 columns:");
+console.log(`  sliceWithWidth, whole line:       ${wholeMs.toFixed(3)}ms`);
+console.log(`  sliceWithWidth, copied (1 short): ${copyMs.toFixed(3)}ms`);
+console.log(`  sliceViewport, whole lines:       ${viewportMs.toFixed(3)}ms`);
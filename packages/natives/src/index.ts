@@ -50,13 +50,111 @@ export { ImageFormat, PhotonImage, SamplingFilter } from "./image";
 // =============================================================================
 
 export {
+	alignBlock,
+	alignText,
+	analyzeText,
+	type AnsiSegment,
+	type AnsiSpan,
+	AnsiTracker,
+	type AnsiTrackerConstructor,
+	ansiToHtml,
+	type AnsiToHtmlOptions,
+	applyStyleToRange,
+	type BalancedSubstring,
+	type BudgetPart,
+	type ColorInfo,
+	type ColumnSpec,
+	concatStyled,
+	convertOverstrike,
+	diffVisible,
+	downsampleColors,
 	Ellipsis,
+	expandTabs,
+	extractLinks,
 	type ExtractSegmentsResult,
 	extractSegments,
+	filterStyles,
+	type FilterStylesOptions,
+	findVisible,
+	type FindVisibleOptions,
+	fitBudget,
+	fitsWithin,
+	fitsWithinEach,
+	type GraphemeInfo,
+	type GraphemeMetrics,
+	graphemeAtColumn,
+	graphemeCount,
+	highlightMatches,
+	type HighlightOptions,
+	insertAtColumn,
+	layoutColumns,
+	type LineMetrics,
+	type LinkRange,
+	type LoneCrMode,
+	mapTextPreservingAnsi,
+	MeasuredLine,
+	type MeasuredLineConstructor,
+	measureGraphemes,
+	measureLines,
+	measureTrimmed,
+	nextGraphemeBoundary,
+	normalizeNewlines,
+	optimizeAnsi,
+	type OverlayOptions,
+	overlayAtColumn,
+	type PadOptions,
+	padToWidth,
+	parseAnsiSpans,
+	type ParseSpansOptions,
+	prevGraphemeBoundary,
+	type ReflowOptions,
+	reflow,
+	repeatToWidth,
+	replaceRange,
+	resolveColor,
+	type RgbColor,
+	sanitizeAnsi,
+	sanitizeForTerminal,
+	setDefaultPalette,
+	setWidthOverrides,
+	type SanitizePolicy,
+	type SegmentWindow,
+	type SliceOptions,
+	type SliceUtf8Result,
+	sliceViewport,
 	type SliceWithWidthResult,
 	sliceWithWidth,
+	sliceWithWidthUtf8,
+	spansToAnsi,
+	splitAnsiLines,
+	splitAnsiSegments,
+	type SplitLinesResult,
+	stripAnsi,
+	styleAtColumn,
+	type StyleAttribute,
+	type StyleInfo,
+	styleToSgr,
+	substringBalanced,
+	type TerminalSanitizeOptions,
+	type TextAnalysis,
+	type TrimmedWidth,
+	truncateLines,
+	truncateMiddle,
+	truncatePath,
+	truncateStart,
 	truncateToWidth,
+	truncateToWidthEx,
+	truncateToWidthUtf8,
+	type TruncateResult,
+	type VisibleDiff,
+	visibleLineWidths,
+	type VisibleMatch,
 	visibleWidth,
+	visibleWidths,
+	visibleWidthUtf8,
+	type WidthOptions,
+	type WidthOverride,
+	type WrapOptions,
 	wrapTextWithAnsi,
 } from "./text";
 
@@ -102,13 +200,31 @@ export { getSystemInfo, type SystemInfo } from "./system-info";
 // =============================================================================
 
 export {
+	type CommandPolicyDecision,
+	type CommandPolicyRequest,
+	type CommandPreview,
+	createSnapshot,
 	executeShell,
+	invalidateWindowsPathCache,
+	type PreviewedCommand,
+	type ProcessStats,
 	Shell,
 	type ShellExecuteOptions,
 	type ShellExecuteResult,
+	type ShellIsolation,
+	type ShellJob,
+	type ShellJobs,
 	type ShellOptions,
+	type ShellOutputEncoding,
+	type ShellPathDiagnostics,
+	type ShellPathProbe,
+	shellPathDiagnostics,
 	type ShellRunOptions,
 	type ShellRunResult,
+	type SnapshotInfo,
+	type SnapshotOptions,
+	type UnixToolsProvider,
+	type WindowsPathOverride,
 } from "./shell";
 
 // =============================================================================
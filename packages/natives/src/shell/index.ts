@@ -5,16 +5,38 @@
 import { native } from "../native";
 import type { ShellExecuteOptions, ShellExecuteResult } from "./types";
 
-export type { ShellExecuteOptions, ShellExecuteResult, ShellOptions, ShellRunOptions, ShellRunResult } from "./types";
+export type {
+	CommandPolicyDecision,
+	CommandPolicyRequest,
+	CommandPreview,
+	PreviewedCommand,
+	ProcessStats,
+	ShellExecuteOptions,
+	ShellExecuteResult,
+	ShellIsolation,
+	ShellJob,
+	ShellJobs,
+	ShellOptions,
+	ShellOutputEncoding,
+	ShellPathDiagnostics,
+	ShellPathProbe,
+	ShellRunOptions,
+	ShellRunResult,
+	SnapshotInfo,
+	SnapshotOptions,
+	UnixToolsProvider,
+	WindowsPathOverride,
+} from "./types";
 
-export const { Shell } = native;
+export const { Shell, createSnapshot, invalidateWindowsPathCache, shellPathDiagnostics } = native;
 export type Shell = import("./types").Shell;
 
 /**
  * Execute a shell command using brush-core.
  *
  * @param options - Execution options including command, cwd, env, timeout
- * @param onChunk - Optional callback for streaming output chunks
+ * @param onChunk - Optional callback for streaming output chunks; each stream's chunks arrive in order, but output
+ *   interleaved between stdout and stderr may not
  * @returns Promise resolving to execution result with exit code and status
  */
 export async function executeShell(
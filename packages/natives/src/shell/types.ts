@@ -6,14 +6,91 @@ import type { Cancellable, TsFunc } from "../bindings";
 
 /**
  * Configuration for a persistent brush-core shell session.
+ *
+ * On Windows every process a session starts is placed in a job object that is closed with the session, killing
+ * whatever is still running, background jobs and disowned processes included. A session ends on `dispose()`, when a
+ * command is cancelled or runs `exit`, and when the shell is garbage collected.
  */
 export interface ShellOptions {
 	/** Environment variables to set once per session. */
 	sessionEnv?: Record<string, string>;
 	/** Optional snapshot path to source for bash sessions. */
 	snapshotPath?: string;
+	/**
+	 * How PATH is augmented with Unix tools on Windows: `"auto"` (default) uses `PI_SHELL_UNIX_TOOLS` or discovers Git,
+	 * `"off"` leaves PATH alone.
+	 */
+	windowsPathAugmentation?: "auto" | "off" | WindowsPathOverride;
+	/** Have `getCwd()` return native Windows paths (`C:\Users\me`) instead of the `$PWD` form (`C:/Users/me`). */
+	nativePaths?: boolean;
+	/** Export a UTF-8 `LANG`/`LC_ALL` and `PYTHONIOENCODING` for child processes on Windows (default true). */
+	utf8Env?: boolean;
+	/** Give the session its own `HOME` and/or temp directory, exported before the snapshot is sourced. */
+	isolate?: ShellIsolation;
+	/**
+	 * Consulted before each external program and directory-, signal-, or script-related builtin runs, including inside
+	 * functions and subshells. A denied command fails with exit code 126 and the reason on stderr; a callback that
+	 * throws denies it too.
+	 */
+	onCommandPolicy?: (request: CommandPolicyRequest) => CommandPolicyDecision | Promise<CommandPolicyDecision>;
+	/** Most background jobs (`cmd &`) the session runs at once (at least 1), counting those started from subshells. */
+	maxBackgroundJobs?: number;
+	/**
+	 * What launching a job past `maxBackgroundJobs` does: `"fail"` (default) reports the limit on stderr with status 1,
+	 * `"wait"` blocks until a slot frees.
+	 */
+	backgroundJobPolicy?: "fail" | "wait";
+	/**
+	 * What the `exec` builtin may do: `"none"` (default) disables it, `"redirections-only"` allows `exec` without a
+	 * command (e.g. `exec 3>log`) but rejects replacing the shell, and `"full"` enables it entirely. Command replacement
+	 * is only available on Unix.
+	 */
+	allowExec?: "full" | "redirections-only" | "none";
+	/** Enable the `suspend` builtin (default false). Unix only. */
+	allowSuspend?: boolean;
+}
+
+/** A command about to run, as passed to `onCommandPolicy`. */
+export interface CommandPolicyRequest {
+	/** The command name followed by its expanded arguments. */
+	argv: string[];
+	/** Resolved path of the external program; absent for builtins. */
+	program?: string;
 }
 
+/** Verdict returned by `onCommandPolicy`. */
+export interface CommandPolicyDecision {
+	/** Whether the command may run. */
+	allow: boolean;
+	/** Why it was denied, printed to stderr. */
+	reason?: string;
+}
+
+/** Throwaway directories for `isolate`. */
+export interface ShellIsolation {
+	/** `true` for a scratch `HOME` removed by `dispose()`, or a directory to use instead. */
+	home?: boolean | string;
+	/** Same for `TMPDIR`, `TEMP`, and `TMP`. */
+	tmp?: boolean | string;
+}
+
+/** Explicit Unix tool directory or provider order for `windowsPathAugmentation`; setting both throws. */
+export interface WindowsPathOverride {
+	/**
+	 * Directory to append to PATH instead of `PI_SHELL_UNIX_TOOLS` or discovery. Used only if it holds `ls.exe`;
+	 * otherwise discovery runs as usual and `Shell.pathDiagnostics()` reports a warning.
+	 */
+	unixTools?: string;
+	/** Providers to discover, in preference order (default: git, msys2, cygwin). Only the first found is used. */
+	providers?: UnixToolsProvider[];
+}
+
+/** A distribution of Unix tools for Windows. */
+export type UnixToolsProvider = "git" | "msys2" | "cygwin";
+
+/** Code page command output is decoded from; `"auto"` uses the Windows OEM code page. */
+export type ShellOutputEncoding = "utf8" | "cp437" | "cp1252" | "auto";
+
 /**
  * Options for running a single shell command.
  */
@@ -24,6 +101,26 @@ export interface ShellRunOptions extends Cancellable {
 	cwd?: string;
 	/** Environment variables to apply for this command. */
 	env?: Record<string, string>;
+	/** Code page to decode output from (default `"utf8"`). */
+	outputEncoding?: ShellOutputEncoding;
+	/** Create a private temp directory, exported as `$PI_SCRATCH` and removed after the run, even when cancelled. */
+	scratchDir?: boolean;
+	/**
+	 * Run with only PATH and `env` exported, like `env -i`. Inherited variables stay readable as shell variables and are
+	 * exported again after the run. The session marker (`PI_SHELL_SESSION`) stays exported so killing the session still
+	 * reaches processes the run leaves behind.
+	 */
+	cleanEnv?: boolean;
+	/**
+	 * How invalid UTF-8 in output is handled: `"lossy"` (default) replaces it with U+FFFD, `"strict"` stops the command
+	 * and reports where it occurred. Only applies when `outputEncoding` is `"utf8"`.
+	 */
+	outputUtf8?: "lossy" | "strict";
+	/**
+	 * When `timeoutMs` starts counting: `"enqueue"` (default) when `run` is called, so waiting for an earlier command
+	 * counts against it, or `"start"` when the command begins.
+	 */
+	timeoutStartsAt?: "enqueue" | "start";
 }
 
 /**
@@ -36,6 +133,35 @@ export interface ShellRunResult {
 	cancelled: boolean;
 	/** Whether the command timed out. */
 	timedOut: boolean;
+	/** Milliseconds between `run` being called and the command starting, waiting for earlier commands. */
+	queuedMs: number;
+	/** Milliseconds from the command starting until it finished or was cancelled; 0 when cancelled while queued. */
+	durationMs: number;
+	/** Processes killed when the command was cancelled, in total. */
+	terminatedProcesses?: number;
+	/** Processes killed by each cancellation wave: SIGTERM then SIGKILL on Unix, one job termination on Windows. */
+	killWaves?: number[];
+	/** Bytes the command wrote to stdout, including any no callback received. */
+	stdoutBytes: number;
+	/** Bytes the command wrote to stderr, including any no callback received. */
+	stderrBytes: number;
+	/** Whether the command wrote anything to stderr, e.g. warnings from a command that still exited 0. */
+	hadStderr: boolean;
+	/**
+	 * `PIPESTATUS` after the command: the exit code of each stage of the last pipeline it ran, in order, when that
+	 * pipeline had more than one stage. Unlike `exitCode`, shows a failing stage before the last.
+	 */
+	pipeStatus?: number[];
+	/** Whether `outputUtf8: "strict"` stopped the command at invalid UTF-8. */
+	invalidUtf8: boolean;
+	/** Byte offset of the invalid sequence within its stream. */
+	invalidUtf8Offset?: number;
+	/** Stream the invalid sequence appeared on. */
+	invalidUtf8Stream?: "stdout" | "stderr";
+	/** The `scratchDir` directory exported as `$PI_SCRATCH`. */
+	scratchDir?: string;
+	/** Whether the scratch directory was fully removed; files a surviving job holds open are retried on `dispose()`. */
+	scratchRemoved?: boolean;
 }
 
 /**
@@ -52,18 +178,117 @@ export interface ShellExecuteOptions extends Cancellable {
 	sessionEnv?: Record<string, string>;
 	/** Optional snapshot path to source for bash sessions. */
 	snapshotPath?: string;
+	/**
+	 * How PATH is augmented with Unix tools on Windows: `"auto"` (default) uses `PI_SHELL_UNIX_TOOLS` or discovers Git,
+	 * `"off"` leaves PATH alone.
+	 */
+	windowsPathAugmentation?: "auto" | "off" | WindowsPathOverride;
+	/** Export a UTF-8 `LANG`/`LC_ALL` and `PYTHONIOENCODING` for child processes on Windows (default true). */
+	utf8Env?: boolean;
+	/** Code page to decode output from (default `"utf8"`). */
+	outputEncoding?: ShellOutputEncoding;
+	/** `"lossy"` (default) replaces invalid UTF-8 with U+FFFD; `"strict"` stops the command, as for `Shell.run()`. */
+	outputUtf8?: "lossy" | "strict";
 }
 
 /**
 /** Internal result from the native brush-core binding. */
-export interface ShellExecuteResult extends ShellRunResult {}
+export interface ShellExecuteResult {
+	/** Exit code of the command (undefined if cancelled or timed out). */
+	exitCode?: number;
+	/** Whether the command was cancelled via abort. */
+	cancelled: boolean;
+	/** Whether the command timed out. */
+	timedOut: boolean;
+	/** Processes killed when the command was cancelled, in total. */
+	terminatedProcesses?: number;
+	/** Processes killed by each cancellation wave: SIGTERM then SIGKILL on Unix, one job termination on Windows. */
+	killWaves?: number[];
+	/** Bytes the command wrote to stdout, including any no callback received. */
+	stdoutBytes: number;
+	/** Bytes the command wrote to stderr, including any no callback received. */
+	stderrBytes: number;
+	/** Whether the command wrote anything to stderr. */
+	hadStderr: boolean;
+	/** `PIPESTATUS` after the command, as in `ShellRunResult`. */
+	pipeStatus?: number[];
+	/** Whether `outputUtf8: "strict"` stopped the command at invalid UTF-8. */
+	invalidUtf8: boolean;
+	/** Byte offset of the invalid sequence within its stream. */
+	invalidUtf8Offset?: number;
+	/** Stream the invalid sequence appeared on. */
+	invalidUtf8Stream?: "stdout" | "stderr";
+}
+
+/** A probe made while locating Git's Unix tools on Windows. */
+export interface ShellPathProbe {
+	/** Where the probe looked. */
+	source:
+		| "option"
+		| "env"
+		| "registry"
+		| "registry-user"
+		| "where"
+		| "scoop"
+		| "user-install"
+		| "program-files"
+		| "msys2"
+		| "cygwin";
+	/** Install root the probe pointed at, if any. */
+	path?: string;
+	/** Whether the install was accepted (its tools directory holds `ls.exe`). */
+	accepted: boolean;
+}
+
+/** How shell sessions augment PATH on Windows (empty on other platforms). */
+export interface ShellPathDiagnostics {
+	/** Source of the install whose directories are added to PATH. */
+	source?: ShellPathProbe["source"];
+	/** Provider of that install. */
+	provider?: UnixToolsProvider;
+	/** Directories appended to PATH, in order. */
+	paths: string[];
+	/** Every probe made, in probing order. */
+	probes: ShellPathProbe[];
+	/** Overrides (`PI_SHELL_UNIX_TOOLS`, a shell's `unixTools`) ignored because they don't hold `ls.exe`. */
+	warnings: string[];
+}
+
+/** Options for `createSnapshot()`; `timeoutMs` defaults to 10000. */
+export interface SnapshotOptions extends Cancellable {
+	/** Login shell to capture (default `$SHELL`). */
+	shellPath?: string;
+	/** File to write the snapshot to, for use as `snapshotPath`; replaced atomically and readable only by the owner. */
+	outputPath: string;
+}
+
+/** What `createSnapshot()` captured. */
+export interface SnapshotInfo {
+	/** File the snapshot was written to. */
+	path: string;
+	/** Login shell that was captured. */
+	shell: string;
+	/** Exported variables written. */
+	variables: number;
+	/** Functions written. */
+	functions: number;
+	/** Aliases written. */
+	aliases: number;
+	/** Variables left out: shell-managed ones the session sets itself (`PWD`, `SHLVL`, `PS1`, ...) and invalid names. */
+	skippedVariables: string[];
+	/** Functions left out because brush can't parse them. */
+	skippedFunctions: string[];
+	/** Aliases left out: zsh global and suffix aliases, and those brush can't parse. */
+	skippedAliases: string[];
+}
 
 /** Native Shell class instance. */
 export interface Shell {
 	/**
 	 * Run a command in the shell.
 	 * @param options Command execution options.
-	 * @param onChunk Optional callback for streamed output.
+	 * @param onChunk Optional callback for streamed output. stdout and stderr are read from separate pipes, so each
+	 * stream's chunks arrive in order, but output interleaved between the two may not.
 	 * @returns Promise resolving to the command result.
 	 */
 	run(options: ShellRunOptions, onChunk?: TsFunc<string>): Promise<ShellRunResult>;
@@ -72,6 +297,86 @@ export interface Shell {
 	 * @param reason Optional reason for the abort.
 	 */
 	abort(reason?: string): void;
+	/**
+	 * Current directory of the session, or `null` before its first command. On Windows this is the forward-slash form
+	 * scripts see as `$PWD`, unless `nativePaths` is set.
+	 */
+	getCwd(): Promise<string | null>;
+	/**
+	 * How this shell's sessions augment PATH on Windows: its `unixTools` directory, else the cached discovery for its
+	 * provider order, with a warning if `unixTools` was ignored. Empty with `"off"` and on other platforms.
+	 */
+	pathDiagnostics(): ShellPathDiagnostics;
+	/**
+	 * Show what a command would run without running it: each simple command with aliases resolved, words expanded
+	 * against the session's current variables, and the target classified. Words that need a command substitution,
+	 * including one stored in a variable's value (`${p@P}`, `${!p}`), are returned unexpanded.
+	 * @param command The command to inspect.
+	 */
+	preview(command: string): Promise<CommandPreview>;
+	/**
+	 * Count the session's live child processes, first reaping any that exited without being waited on. Unix reaps
+	 * only children the shell spawned, and skips reaping while a command runs; Windows reports the session's job.
+	 */
+	processStats(): Promise<ProcessStats>;
+	/**
+	 * List the session's jobs, dropping those that have finished, and how close it is to `maxBackgroundJobs`. Waits
+	 * for a running command to finish.
+	 */
+	listJobs(): Promise<ShellJobs>;
+	/**
+	 * End the session, stopping its background jobs, and remove the directories created for `isolate`. Afterwards
+	 * `run()`, `preview()`, and `listJobs()` reject instead of starting a new session.
+	 * @returns Paths that couldn't be removed, typically files still held open by a lingering process.
+	 */
+	dispose(): Promise<string[]>;
+}
+
+/** What `Shell.preview()` found for one simple command. */
+export interface PreviewedCommand {
+	/** Command name and arguments after alias resolution and expansion. */
+	argv: string[];
+	/** Indices into `argv` of words kept as written because expanding them would run a command substitution. */
+	unevaluated: number[];
+	/** What the command name resolves to; `"unknown"` when not found or itself unevaluated. */
+	kind: "builtin" | "function" | "external" | "unknown";
+	/** Resolved path of an external program. */
+	path?: string;
+}
+
+/** Result of `Shell.preview()`. */
+export interface CommandPreview {
+	/** Simple commands in source order, including those inside loops, conditionals, and subshells. */
+	commands: PreviewedCommand[];
+}
+
+/** A background or stopped job tracked by a shell session. */
+export interface ShellJob {
+	/** Job number, as used in `%1`. */
+	id: number;
+	/** Command line the job runs. */
+	command: string;
+	state: "running" | "stopped" | "done" | "unknown";
+	/** Process id of the job's first external process, if any. */
+	pid?: number;
+}
+
+/** Result of `Shell.listJobs()`. */
+export interface ShellJobs {
+	/** Jobs in the order they were started. */
+	jobs: ShellJob[];
+	/** Background jobs holding a slot under `maxBackgroundJobs`, including those started from subshells. */
+	runningBackgroundJobs: number;
+	/** The `maxBackgroundJobs` limit, if set. */
+	maxBackgroundJobs?: number;
+}
+
+/** Child process counts from `Shell.processStats()`. */
+export interface ProcessStats {
+	/** Children the shell spawned that haven't been waited on yet. */
+	liveChildren: number;
+	/** Exited children nothing waited on, reaped by the shell so far. */
+	reapedZombies: number;
 }
 
 /** Native Shell class constructor. */
@@ -89,10 +394,29 @@ declare module "../bindings" {
 		/**
 		 * Execute a shell command with explicit session metadata.
 		 * @param options Execution options including session identifiers.
-		 * @param onChunk Optional callback for streamed output.
+		 * @param onChunk Optional callback for streamed output. stdout and stderr are read from separate pipes, so
+		 * each stream's chunks arrive in order, but output interleaved between the two may not.
 		 * @returns Promise resolving to the command result.
 		 */
 		executeShell(options: ShellExecuteOptions, onChunk?: TsFunc<string>): Promise<ShellExecuteResult>;
+		/**
+		 * Report how shell sessions with the default `windowsPathAugmentation` locate Unix tools on Windows
+		 * (`PI_SHELL_UNIX_TOOLS`, else Git for Windows, else MSYS2, else Cygwin): each probe in order and the install that
+		 * won. Discovery runs once per process and is cached; `Shell.pathDiagnostics()` reports a shell's own options.
+		 */
+		shellPathDiagnostics(): ShellPathDiagnostics;
+		/**
+		 * Capture the user's login shell (`shellPath`, else `$SHELL`) as a snapshot file: its exported variables,
+		 * functions, and aliases after running its startup files in interactive login mode.
+		 * @param options Shell, output file, timeout, and abort signal.
+		 * @returns Promise resolving to what was captured and skipped.
+		 */
+		createSnapshot(options: SnapshotOptions): Promise<SnapshotInfo>;
+		/**
+		 * Forget the cached Windows Git discovery so the next session probes again (e.g. after installing Git
+		 * mid-process). A no-op on other platforms.
+		 */
+		invalidateWindowsPathCache(): void;
 
 		/** Shell class constructor for creating sessions. */
 		Shell: ShellConstructor;
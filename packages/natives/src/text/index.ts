@@ -2,10 +2,59 @@
  * ANSI-aware text utilities powered by native bindings.
  */
 
-import { Ellipsis, type SliceWithWidthResult } from "@oh-my-pi/pi-natives";
+import {
+	Ellipsis,
+	type PadOptions,
+	type SliceOptions,
+	type SliceUtf8Result,
+	type SliceWithWidthResult,
+	type TruncateResult,
+	type WidthOptions,
+} from "@oh-my-pi/pi-natives";
 import { native } from "../native";
 
-export type { ExtractSegmentsResult, SliceWithWidthResult } from "./types";
+export type {
+	AnsiSegment,
+	AnsiSpan,
+	AnsiTrackerConstructor,
+	AnsiToHtmlOptions,
+	BalancedSubstring,
+	BudgetPart,
+	ColorInfo,
+	ColumnSpec,
+	ExtractSegmentsResult,
+	FilterStylesOptions,
+	FindVisibleOptions,
+	GraphemeInfo,
+	GraphemeMetrics,
+	HighlightOptions,
+	LineMetrics,
+	LinkRange,
+	LoneCrMode,
+	MeasuredLineConstructor,
+	OverlayOptions,
+	PadOptions,
+	ParseSpansOptions,
+	ReflowOptions,
+	RgbColor,
+	SanitizePolicy,
+	SegmentWindow,
+	SliceOptions,
+	SliceUtf8Result,
+	SliceWithWidthResult,
+	SplitLinesResult,
+	StyleAttribute,
+	StyleInfo,
+	TerminalSanitizeOptions,
+	TextAnalysis,
+	TrimmedWidth,
+	TruncateResult,
+	VisibleDiff,
+	VisibleMatch,
+	WidthOptions,
+	WidthOverride,
+	WrapOptions,
+} from "./types";
 export { Ellipsis } from "./types";
 
 /**
@@ -15,30 +64,279 @@ export { Ellipsis } from "./types";
  *
  * @param text - Text to truncate (may contain ANSI codes)
  * @param maxWidth - Maximum visible width
- * @param ellipsis - Ellipsis kind to append when truncating (default: Unicode "…")
+ * @param ellipsis - Ellipsis kind or custom string to append when truncating (default: Unicode "…")
  * @param pad - If true, pad result with spaces to exactly maxWidth (default: false)
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @param fill - Padding fill character and styling (see {@link PadOptions})
  * @returns Truncated text, optionally padded to exactly maxWidth
  */
 export function truncateToWidth(
 	text: string,
 	maxWidth: number,
-	ellipsis: Ellipsis = Ellipsis.Unicode,
+	ellipsis: Ellipsis | string = Ellipsis.Unicode,
 	pad = false,
+	options?: WidthOptions,
+	fill?: PadOptions,
 ): string {
-	return native.truncateToWidth(text, maxWidth, ellipsis, pad);
+	return native.truncateToWidth(text, maxWidth, ellipsis, pad, options, fill);
+}
+
+/**
+ * Truncate UTF-8 bytes like {@link truncateToWidth} without decoding them to a string.
+ *
+ * @param buf - UTF-8 bytes to truncate (ANSI codes are preserved)
+ * @param maxWidth - Maximum visible width
+ * @param ellipsis - Ellipsis kind or custom string to append when truncating (default: Unicode "…")
+ * @param pad - If true, pad result with spaces to exactly maxWidth (default: false)
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @returns Truncated bytes, optionally padded to exactly maxWidth
+ */
+export function truncateToWidthUtf8(
+	buf: Uint8Array,
+	maxWidth: number,
+	ellipsis: Ellipsis | string = Ellipsis.Unicode,
+	pad = false,
+	options?: WidthOptions,
+): Buffer {
+	return native.truncateToWidthUtf8(buf, maxWidth, ellipsis, pad, options);
+}
+
+/**
+ * Truncate text like {@link truncateToWidth}, returning a result object with the
+ * visible width of the output and whether content was dropped.
+ * With `carryStyle`, the result includes the SGR restore sequence for the style
+ * active at the end of the kept text, so a following fragment can continue it.
+ *
+ * @param text - Text to truncate (may contain ANSI codes)
+ * @param maxWidth - Maximum visible width
+ * @param ellipsis - Ellipsis kind or custom string to append when truncating (default: Unicode "…")
+ * @param pad - If true, pad result with spaces to exactly maxWidth (default: false)
+ * @param carryStyle - If true, report `carriedStyle` (default: false)
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @param fill - Padding fill character and styling (see {@link PadOptions})
+ * @returns Truncation result
+ */
+export function truncateToWidthEx(
+	text: string,
+	maxWidth: number,
+	ellipsis: Ellipsis | string = Ellipsis.Unicode,
+	pad = false,
+	carryStyle = false,
+	options?: WidthOptions,
+	fill?: PadOptions,
+): TruncateResult {
+	return native.truncateToWidthEx(text, maxWidth, ellipsis, pad, carryStyle, options, fill);
+}
+
+/**
+ * Truncate multi-line text to a visible width per line and a maximum number of lines.
+ * Lines are truncated independently like {@link truncateToWidth}; each restores the
+ * style carried from earlier lines and ends with a reset. When lines are dropped,
+ * the last kept line is replaced by `indicator`.
+ *
+ * @param text - Text to truncate (may contain ANSI codes and newlines)
+ * @param maxWidth - Maximum visible width of each line
+ * @param maxLines - Maximum number of lines, including the indicator
+ * @param ellipsis - Ellipsis kind or custom string to append to truncated lines (default: Unicode "…")
+ * @param pad - If true, pad each line with spaces to exactly maxWidth (default: false)
+ * @param indicator - Line shown when lines are dropped; `{n}` is the number dropped (default: "… (+{n} more lines)", "" to omit)
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @returns Truncated lines joined with "\n"
+ */
+export function truncateLines(
+	text: string,
+	maxWidth: number,
+	maxLines: number,
+	ellipsis: Ellipsis | string = Ellipsis.Unicode,
+	pad = false,
+	indicator?: string,
+	options?: WidthOptions,
+): string {
+	return native.truncateLines(text, maxWidth, maxLines, ellipsis, pad, indicator, options);
+}
+
+/**
+ * Truncate text to fit within a maximum visible width by dropping leading content.
+ * The ellipsis is prepended and the ANSI state active at the cut is restored,
+ * so the kept tail renders with its original styling.
+ *
+ * @param text - Text to truncate (may contain ANSI codes)
+ * @param maxWidth - Maximum visible width
+ * @param ellipsis - Ellipsis kind or custom string to prepend when truncating (default: Unicode "…")
+ * @param pad - If true, pad result with spaces to exactly maxWidth (default: false)
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @returns Truncated text, optionally padded to exactly maxWidth
+ */
+export function truncateStart(
+	text: string,
+	maxWidth: number,
+	ellipsis: Ellipsis | string = Ellipsis.Unicode,
+	pad = false,
+	options?: WidthOptions,
+): string {
+	return native.truncateStart(text, maxWidth, ellipsis, pad, options);
+}
+
+/**
+ * Truncate text to fit within a maximum visible width by eliding the middle.
+ * Both cuts respect grapheme boundaries, and the tail is prefixed with the
+ * ANSI state active at its start.
+ *
+ * @param text - Text to truncate (may contain ANSI codes)
+ * @param maxWidth - Maximum visible width
+ * @param ellipsis - Ellipsis kind or custom string to insert (default: Unicode "…")
+ * @param pad - If true, pad result with spaces to exactly maxWidth (default: false)
+ * @param headBias - Fraction of the budget kept from the head, 0..1 (default: 0.5)
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @returns Truncated text, optionally padded to exactly maxWidth
+ */
+export function truncateMiddle(
+	text: string,
+	maxWidth: number,
+	ellipsis: Ellipsis | string = Ellipsis.Unicode,
+	pad = false,
+	headBias = 0.5,
+	options?: WidthOptions,
+): string {
+	return native.truncateMiddle(text, maxWidth, ellipsis, pad, headBias, options);
+}
+
+/**
+ * Truncate a path to a visible width while keeping the basename intact.
+ * Keeps the first component and as many trailing components as fit, replacing
+ * the elided middle with a single "…" component
+ * (`/Users/…/pi-natives/src/shell.rs`).
+ *
+ * @param path - Path to truncate
+ * @param maxWidth - Maximum visible width
+ * @param separator - Characters to split on (default: "/"; use "/\\" for Windows paths)
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @returns Truncated path
+ */
+export function truncatePath(path: string, maxWidth: number, separator?: string, options?: WidthOptions): string {
+	return native.truncatePath(path, maxWidth, separator, options);
 }
 
 /**
  * Slice a range of visible columns from a line.
  * @param line - The line to slice
- * @param startCol - The starting column
+ * @param startCol - The starting column; negative counts from the end (`-40` is the last 40 columns)
  * @param length - The length of the slice
  * @param strict - Whether to strictly enforce the length
+ * @param options - `carryStyle`, `padClipped`, and width measurement options (see {@link SliceOptions})
  * @returns The sliced line
  */
-export function sliceWithWidth(line: string, startCol: number, length: number, strict = false): SliceWithWidthResult {
-	if (length <= 0) return { text: "", width: 0 };
-	return native.sliceWithWidth(line, startCol, length, strict);
+export function sliceWithWidth(
+	line: string,
+	startCol: number,
+	length: number,
+	strict = false,
+	options?: SliceOptions,
+): SliceWithWidthResult {
+	if (length <= 0 && !options?.carryStyle) return { text: "", width: 0, clippedAtStart: false, clippedAtEnd: false };
+	return native.sliceWithWidth(line, startCol, Math.max(length, 0), strict, options);
+}
+
+/**
+ * Slice the same column range from many lines in one native call, e.g. the rows of a
+ * horizontally scrolled viewport. Each result is identical to {@link sliceWithWidth}.
+ *
+ * @param lines - The lines to slice
+ * @param startCol - The starting column; negative counts from the end of each line
+ * @param length - The length of each slice
+ * @param strict - Whether to strictly enforce the length
+ * @param options - `carryStyle`, `padClipped`, and width measurement options (see {@link SliceOptions})
+ * @returns One slice per line
+ */
+export function sliceViewport(
+	lines: string[],
+	startCol: number,
+	length: number,
+	strict = false,
+	options?: SliceOptions,
+): SliceWithWidthResult[] {
+	if (length <= 0 && !options?.carryStyle) {
+		return lines.map(() => ({ text: "", width: 0, clippedAtStart: false, clippedAtEnd: false }));
+	}
+	return native.sliceViewport(lines, startCol, Math.max(length, 0), strict, options);
+}
+
+/**
+ * Slice UTF-8 bytes like {@link sliceWithWidth} without decoding them to a string.
+ *
+ * @param buf - The UTF-8 line to slice
+ * @param startCol - The starting column
+ * @param length - The length of the slice
+ * @param strict - Whether to strictly enforce the length
+ * @param options - Width measurement options (e.g. `tabWidth`)
+ * @returns The sliced bytes
+ */
+export function sliceWithWidthUtf8(
+	buf: Uint8Array,
+	startCol: number,
+	length: number,
+	strict = false,
+	options?: WidthOptions,
+): SliceUtf8Result {
+	return native.sliceWithWidthUtf8(buf, Math.max(startCol, 0), Math.max(length, 0), strict, options);
 }
 
-export const { wrapTextWithAnsi, visibleWidth, extractSegments } = native;
+export const {
+	wrapTextWithAnsi,
+	reflow,
+	visibleWidth,
+	visibleWidthUtf8,
+	visibleWidths,
+	visibleLineWidths,
+	fitsWithin,
+	fitsWithinEach,
+	measureLines,
+	measureTrimmed,
+	splitAnsiLines,
+	splitAnsiSegments,
+	normalizeNewlines,
+	graphemeAtColumn,
+	measureGraphemes,
+	graphemeCount,
+	nextGraphemeBoundary,
+	prevGraphemeBoundary,
+	substringBalanced,
+	insertAtColumn,
+	replaceRange,
+	applyStyleToRange,
+	styleAtColumn,
+	styleToSgr,
+	resolveColor,
+	setDefaultPalette,
+	diffVisible,
+	parseAnsiSpans,
+	spansToAnsi,
+	extractLinks,
+	concatStyled,
+	ansiToHtml,
+	extractSegments,
+	overlayAtColumn,
+	stripAnsi,
+	sanitizeAnsi,
+	sanitizeForTerminal,
+	convertOverstrike,
+	optimizeAnsi,
+	downsampleColors,
+	mapTextPreservingAnsi,
+	filterStyles,
+	analyzeText,
+	expandTabs,
+	padToWidth,
+	layoutColumns,
+	fitBudget,
+	alignBlock,
+	alignText,
+	repeatToWidth,
+	findVisible,
+	highlightMatches,
+	setWidthOverrides,
+	MeasuredLine,
+	AnsiTracker,
+} = native;
+export type MeasuredLine = import("./types").MeasuredLine;
+export type AnsiTracker = import("./types").AnsiTracker;
@@ -8,6 +8,36 @@ export interface SliceWithWidthResult {
 	text: string;
 	/** Visible width of the slice in terminal cells. */
 	width: number;
+	/** SGR restore sequence for the state active at the end of the slice (only when requested). */
+	carriedStyle?: string;
+	/** A wide grapheme straddling the start column was excluded. */
+	clippedAtStart: boolean;
+	/** A wide grapheme straddling the end column was excluded (strict mode). */
+	clippedAtEnd: boolean;
+}
+
+/** Result of slicing UTF-8 bytes via `sliceWithWidthUtf8`. */
+export interface SliceUtf8Result {
+	/** UTF-8 slice containing the selected bytes. */
+	text: Buffer;
+	/** Visible width of the slice in terminal cells. */
+	width: number;
+	/** A wide grapheme straddling the start column was excluded. */
+	clippedAtStart: boolean;
+	/** A wide grapheme straddling the end column was excluded (strict mode). */
+	clippedAtEnd: boolean;
+}
+
+/** Result of truncating text via `truncateToWidthEx`. */
+export interface TruncateResult {
+	/** Truncated (and optionally padded) text. */
+	text: string;
+	/** Visible width of `text` in terminal cells, including the ellipsis and padding. */
+	width: number;
+	/** Whether content was dropped. */
+	truncated: boolean;
+	/** SGR restore sequence for the state active at the end of the kept text (only when requested). */
+	carriedStyle?: string;
 }
 
 /** Result of extracting before/after overlay segments. */
@@ -20,6 +50,466 @@ export interface ExtractSegmentsResult {
 	after: string;
 	/** Visible width of the `after` segment. */
 	afterWidth: number;
+	/** A wide grapheme straddling the start of the `before` segment was excluded. */
+	beforeClippedAtStart: boolean;
+	/** A wide grapheme straddling the end of the `before` segment was excluded (`strictBefore`). */
+	beforeClippedAtEnd: boolean;
+	/** A wide grapheme straddling the start of the `after` segment was excluded. */
+	afterClippedAtStart: boolean;
+	/** A wide grapheme straddling the end of the `after` segment was excluded (strict mode). */
+	afterClippedAtEnd: boolean;
+}
+
+/** A text run or escape sequence of a line, from `splitAnsiSegments`. */
+export interface AnsiSegment {
+	/**
+	 * `"text"`, `"csi"`, `"osc"`, `"esc"` (other escape sequences, including DCS and APC strings), or `"incomplete"`
+	 * (a sequence cut off by the end of the line).
+	 */
+	kind: "text" | "csi" | "osc" | "esc" | "incomplete";
+	/** UTF-16 index of the first code unit. */
+	start: number;
+	/** UTF-16 index just past the last code unit. */
+	end: number;
+}
+
+/** Width of a line without its trailing whitespace, from `measureTrimmed`. */
+export interface TrimmedWidth {
+	/** Visible width up to the end of the last non-whitespace grapheme. */
+	width: number;
+	/** Columns taken by trailing spaces and tabs. */
+	trailingWhitespaceCols: number;
+	/** UTF-16 index of the first trailing whitespace character (the line length when there is none). */
+	trailingWhitespaceIndex: number;
+}
+
+/** Per-line measurements of multi-line text. */
+export interface LineMetrics {
+	/** Widest visible line in terminal cells. */
+	maxWidth: number;
+	/** Visible width of each line. */
+	lineWidths: number[];
+	/** Number of lines (a trailing newline adds an empty last line). */
+	lineCount: number;
+}
+
+/** Result of splitting text into independently renderable lines. */
+export interface SplitLinesResult {
+	/** Lines, each prefixed with the SGR state active at its start. */
+	lines: string[];
+	/** Visible width of each line. */
+	widths: number[];
+}
+
+/** A visible grapheme located by column. */
+export interface GraphemeInfo {
+	/** UTF-16 text of the grapheme. */
+	text: string;
+	/** Width in terminal cells. */
+	width: number;
+	/** UTF-16 index of the first code unit. */
+	startIndex: number;
+	/** UTF-16 index just past the last code unit. */
+	endIndex: number;
+	/** Column the grapheme starts at. */
+	startColumn: number;
+}
+
+/** Per-grapheme metrics from `measureGraphemes`; entry `i` of each array describes the same grapheme. */
+export interface GraphemeMetrics {
+	/** Width of each grapheme in terminal cells. */
+	widths: Uint8Array;
+	/** UTF-16 index of each grapheme's first code unit. */
+	startIndices: Uint32Array;
+	/** Column each grapheme starts at. */
+	startColumns: Uint32Array;
+}
+
+/** Single-pass text analysis result. */
+export interface TextAnalysis {
+	/** Visible width in terminal cells. */
+	visibleWidth: number;
+	/** Total length in UTF-16 code units (including escapes). */
+	utf16Length: number;
+	/** Number of complete ANSI sequences. */
+	ansiCount: number;
+	/** Whether any non-SGR sequence (cursor movement, OSC, ...) is present. */
+	hasNonSgr: boolean;
+	/** Whether any grapheme is wider than one cell. */
+	hasWideChars: boolean;
+	/** Whether the text contains tabs. */
+	hasTabs: boolean;
+}
+
+/** Width measurement options shared by the text utilities. */
+export interface WidthOptions {
+	/** Cells per tab character, 1-16 (default 3). */
+	tabWidth?: number;
+	/** Advance tabs to the next multiple of `tabWidth` instead of a fixed width. */
+	accurateTabs?: boolean;
+	/**
+	 * Emoji width mode: `"presentation"` (default) measures emoji presentation sequences (VS16), ZWJ sequences,
+	 * and skin-tone modified emoji at 2 cells; `"spec"` sums per-codepoint widths.
+	 */
+	emojiWidth?: "presentation" | "spec";
+	/**
+	 * Carriage return / backspace handling: `"zero"` (default) measures them as 0 cells; `"interpret"` renders the
+	 * line first, so `\r` returns to column 0 and `\b` steps back one column (`"12345\rab"` becomes `"ab345"`, width
+	 * 5); `"replace"` shows them as 1-cell placeholders (`␍`, `␈`). Functions that return UTF-16 indices
+	 * (`graphemeAtColumn`, `findVisible`, `replaceRange`, `measureGraphemes`, `measureTrimmed`, and
+	 * `MeasuredLine.columnToIndex`) reject `"interpret"`, since its indices wouldn't point into the input.
+	 */
+	controlChars?: "zero" | "interpret" | "replace";
+	/**
+	 * Width of an unpaired surrogate, which is otherwise measured as U+FFFD: `1` (default) or `0`. Also applies to
+	 * invalid UTF-8 sequences in the Buffer variants. Lone surrogates are kept intact by every function.
+	 */
+	loneSurrogateWidth?: 0 | 1;
+	/**
+	 * Screen column the text starts at (default 0), e.g. for the continuation of a soft-wrapped line. With
+	 * `accurateTabs`, tabs advance to tab stops relative to this column; reported columns stay relative to the text.
+	 */
+	startColumn?: number;
+}
+
+/** Window options for `extractSegments`. */
+export interface SegmentWindow {
+	/** Column where the "before" segment starts (default 0). */
+	beforeStart?: number;
+	/** SGR sequence(s) for the style already active where the line begins (e.g. carried over from a previous wrap). */
+	initialState?: string;
+	/** Exclude a wide grapheme crossing the end of `before`, like `strictAfter` does for `after` (default false). */
+	strictBefore?: boolean;
+	/** Fill the cells of wide graphemes excluded from `before` with spaces (default false). */
+	padBefore?: boolean;
+}
+
+/** Options for `sliceWithWidth` and `sliceViewport`. */
+export interface SliceOptions {
+	/** Report the SGR restore sequence for the style active at the end of the slice as `carriedStyle` (default false). */
+	carryStyle?: boolean;
+	/** Replace wide graphemes clipped at either boundary with spaces, so the slice keeps its full width (default false). */
+	padClipped?: boolean;
+	/** Width measurement options. */
+	width?: WidthOptions;
+}
+
+/** Break options for `wrapTextWithAnsi`. */
+export interface WrapOptions {
+	/**
+	 * Characters after which a line may also break, in addition to spaces (e.g. `"/-."` for paths and URLs).
+	 * The character stays at the end of the first line; the rightmost break that fits is used.
+	 */
+	breakChars?: string;
+	/** Characters a `breakChars` break may not come before (e.g. `"/"`, so `https://` stays whole). */
+	noBreakChars?: string;
+	/**
+	 * Keep every space, e.g. for code blocks and diffs: runs are split across lines as needed and count toward the
+	 * width, and lines are not trimmed, so joining the lines reproduces the input.
+	 */
+	preserveWhitespace?: boolean;
+	/**
+	 * Widen the gaps between words so every line except the last of each paragraph fills the width exactly. Lines with
+	 * a single word are left as they are.
+	 */
+	justify?: boolean;
+	/** Width measurement options. */
+	width?: WidthOptions;
+}
+
+/** Paragraph detection options for `reflow`. */
+export interface ReflowOptions {
+	/**
+	 * Width the lines were wrapped at (default: the widest line). A line continues into the next one when the next
+	 * line's first word would not have fit after it at this width.
+	 */
+	oldWidth?: number;
+	/** Per line, whether it ends in a hard break; overrides the guess for the lines it covers. */
+	hardBreaks?: boolean[];
+}
+
+/** A width override for a range of code points, for `setWidthOverrides`. */
+export interface WidthOverride {
+	/** First code point of the range. */
+	start: number;
+	/** Last code point of the range (inclusive). */
+	end: number;
+	/** Width in terminal cells (0-2). */
+	width: number;
+}
+
+/** Width constraints for one column of `layoutColumns`. */
+export interface ColumnSpec {
+	/** Minimum width in cells (default 0). */
+	min?: number;
+	/** Maximum width in cells (default unbounded). */
+	max?: number;
+	/** Share of the space beyond `min` (default 1; 0 sizes the column to its content and never stretches it). */
+	weight?: number;
+}
+
+/** One string for `fitBudget`. */
+export interface BudgetPart {
+	/** Text with optional ANSI escapes. */
+	text: string;
+	/** Width kept until every part has shrunk to its minimum (default 0). */
+	minWidth?: number;
+	/** Parts with higher priority shrink later (default 0). */
+	priority?: number;
+}
+
+/** Options for `overlayAtColumn`. */
+export interface OverlayOptions {
+	/** Width of the overlay region (default: the overlay's visible width). */
+	width?: number;
+	/** Width of the composed line (default: wide enough for base and overlay). */
+	totalWidth?: number;
+	/** Drop wide chars that overflow the base's `after` segment, as in `extractSegments` (default true). */
+	strict?: boolean;
+}
+
+/** A structured SGR color. */
+export interface ColorInfo {
+	/** `"indexed"` covers the 256-color palette; values 0-15 are the basic colors. */
+	kind: "none" | "indexed" | "rgb";
+	/** Palette index, or `0xRRGGBB` for `"rgb"`. */
+	value: number;
+	/** Resolved color, when requested (absent for `"none"`). */
+	rgb?: RgbColor;
+}
+
+/** An RGB color with 0-255 channels, from `resolveColor`. */
+export interface RgbColor {
+	r: number;
+	g: number;
+	b: number;
+}
+
+/** A structured SGR style. */
+export interface StyleInfo {
+	bold: boolean;
+	dim: boolean;
+	italic: boolean;
+	underline: boolean;
+	blink: boolean;
+	inverse: boolean;
+	hidden: boolean;
+	strikethrough: boolean;
+	fg: ColorInfo;
+	bg: ColorInfo;
+}
+
+/** A run of plain text sharing one style and link. */
+export interface AnsiSpan {
+	/** Plain text of the span (no escape sequences). */
+	text: string;
+	/** Visible width of `text` in terminal cells. */
+	width: number;
+	/** SGR style applied to the span. */
+	style: StyleInfo;
+	/** OSC 8 hyperlink target, if the span is inside a link. */
+	link?: string;
+	/** Raw non-SGR control sequence (only with `keepSequences`); such spans carry no text. */
+	sequence?: string;
+}
+
+/** An OSC 8 hyperlink found by `extractLinks`. */
+export interface LinkRange {
+	/** Target of the link. */
+	uri: string;
+	/** Column where the link's visible text starts. */
+	startCol: number;
+	/** Column just past the link's visible text. */
+	endCol: number;
+	/** UTF-16 index just past the opening sequence. */
+	startIndex: number;
+	/** UTF-16 index of the sequence ending the link, or the line length. */
+	endIndex: number;
+}
+
+/** Result of `substringBalanced`. */
+export interface BalancedSubstring {
+	/** The slice, opened with the style active at `start` and closed with a reset when a style is active at `end`. */
+	text: string;
+	/** `start` after snapping to a sequence or grapheme boundary. */
+	start: number;
+	/** `end` after snapping to a sequence or grapheme boundary. */
+	end: number;
+}
+
+/** Options for `ansiToHtml`. */
+export interface AnsiToHtmlOptions {
+	/** Colors for the 16 basic colors and inverse defaults (default: `"dark"`, xterm colors). */
+	palette?: "dark" | "light";
+	/**
+	 * Emit classes (`{prefix}bold`, `{prefix}fg-1`, ...) for attributes and basic colors instead of inline styles;
+	 * 256-color and truecolor values stay inline. Inverse swaps the colors rather than adding a class.
+	 */
+	classPrefix?: string;
+}
+
+/** Options for `parseAnsiSpans`. */
+export interface ParseSpansOptions {
+	/** Surface non-SGR sequences (cursor movement, ...) as `sequence` spans instead of dropping them. */
+	keepSequences?: boolean;
+	/** Fill each color's `rgb` from the default palette (see `setDefaultPalette`). */
+	resolveColors?: boolean;
+	/** Width measurement options. */
+	width?: WidthOptions;
+}
+
+/** Result of `diffVisible`. */
+export interface VisibleDiff {
+	/** Columns at the start of both lines with identical text and style. */
+	commonPrefixCols: number;
+	/** Columns at the end of both lines with identical text and style (never overlapping the prefix). */
+	commonSuffixCols: number;
+	/** Whether the lines render differently. */
+	changed: boolean;
+}
+
+/** SGR attribute named by `filterStyles`. */
+export type StyleAttribute = "bold" | "dim" | "italic" | "underline" | "blink" | "inverse" | "hidden" | "strikethrough";
+
+/** Options for `filterStyles`. */
+export interface FilterStylesOptions {
+	/** Drop background colors (basic, bright, 256-color, and truecolor). */
+	dropBackground?: boolean;
+	/** Drop foreground colors (basic, bright, 256-color, and truecolor). */
+	dropForeground?: boolean;
+	/** Attributes to drop, named as in `StyleInfo` (e.g. `"bold"`, `"italic"`). */
+	dropAttributes?: StyleAttribute[];
+	/**
+	 * Attributes to keep, dropping every other one, including codes with no named attribute (fonts, overline, ...).
+	 * Underline colors follow `"underline"`.
+	 */
+	allowAttributes?: StyleAttribute[];
+	/** Keep foreground colors (default true); `false` is `dropForeground`. */
+	allowForeground?: boolean;
+	/** Keep background colors (default true); `false` is `dropBackground`. */
+	allowBackground?: boolean;
+	/** Also downsample the kept colors, as `downsampleColors` with this target, in the same pass. */
+	downsampleColors?: "256" | "16";
+}
+
+/** Padding fill for `padToWidth` and the `pad` behavior of `truncateToWidth`. */
+export interface PadOptions {
+	/** Single grapheme of width 1 used for padding (default: space). */
+	fillChar?: string;
+	/** Draw the fill in the style active at the end of the content instead of after a reset (default: false). */
+	styledFill?: boolean;
+}
+
+/** How `sanitizeAnsi` treats control sequences other than SGR and OSC 8. */
+export type SanitizePolicy = "drop" | "escape" | "keep";
+
+/**
+ * Lone `\r` (not followed by `\n`) handling when splitting lines: `"zero"` (default) keeps it as content measured
+ * per `controlChars`, `"break"` treats it as a line break, `"error"` rejects the input. `\r\n` is always one break.
+ */
+export type LoneCrMode = "zero" | "break" | "error";
+
+/** Native MeasuredLine class instance: a line pre-scanned for repeated slicing. */
+export interface MeasuredLine {
+	/** Visible width of the line in terminal cells. */
+	width(): number;
+	/**
+	 * Slice a range of visible columns, like `sliceWithWidth`; the style in effect before the slice is restored at
+	 * its start.
+	 * @param startCol Starting visible column (0-based).
+	 * @param length Number of visible columns to include.
+	 * @param strict When true, exclude graphemes that would exceed the range.
+	 */
+	slice(startCol: number, length: number, strict: boolean): SliceWithWidthResult;
+	/**
+	 * SGR style in effect at a visible column, like `styleAtColumn`.
+	 * @param column Visible column (0-based).
+	 */
+	styleAt(column: number): StyleInfo;
+	/**
+	 * UTF-16 index of the grapheme occupying a visible column (the line length past the end). Throws if the line
+	 * was built with `controlChars: "interpret"`.
+	 * @param column Visible column (0-based).
+	 */
+	columnToIndex(column: number): number;
+}
+
+/** Native MeasuredLine class constructor. */
+export interface MeasuredLineConstructor {
+	/**
+	 * Scan a line once, recording grapheme columns and style checkpoints.
+	 * @param line UTF-16 line with optional ANSI escapes.
+	 * @param options Width measurement options.
+	 */
+	new (line: string, options?: WidthOptions): MeasuredLine;
+}
+
+/** Native AnsiTracker class instance: tracks the style of a stream fed in arbitrary chunks. */
+export interface AnsiTracker {
+	/**
+	 * Feed the next chunk. Returns the text up to the last complete escape sequence; a sequence split at the end of
+	 * the chunk is held back and returned with the next one.
+	 * @param chunk UTF-16 chunk with optional ANSI escapes.
+	 */
+	push(chunk: string): string;
+	/** Return any held-back partial sequence as-is, e.g. at the end of the stream. */
+	flush(): string;
+	/** SGR style in effect after the text returned so far. */
+	currentStyle(): StyleInfo;
+	/** Minimal SGR sequence restoring the current style (empty when unstyled). */
+	currentSgr(): string;
+	/** Forget the style and any held-back sequence. */
+	reset(): void;
+}
+
+/** Native AnsiTracker class constructor. */
+export interface AnsiTrackerConstructor {
+	/** Start tracking an unstyled stream. */
+	new (): AnsiTracker;
+}
+
+/** Options for `sanitizeForTerminal`. */
+export interface TerminalSanitizeOptions {
+	/** `"strip"` (default) removes unsafe characters, `"caret"` shows `^[`/`^?`, `"pictures"` shows `␛`/`␀`. */
+	mode?: "strip" | "caret" | "pictures";
+	/** Keep SGR sequences (default true). */
+	allowSgr?: boolean;
+	/** Keep OSC 8 hyperlinks (default false). */
+	allowHyperlinks?: boolean;
+	/** Keep OSC 0/1/2 window and icon title sequences (default false). */
+	allowTitle?: boolean;
+}
+
+/** Options for `findVisible`. */
+export interface FindVisibleOptions {
+	/** Match case-insensitively (Unicode lowercase folding). */
+	caseInsensitive?: boolean;
+	/** Only report matches starting at or after this visible column (default 0). */
+	startColumn?: number;
+	/** Width measurement options. */
+	width?: WidthOptions;
+}
+
+/** Options for `highlightMatches`. */
+export interface HighlightOptions {
+	/** Match case-insensitively (Unicode lowercase folding). */
+	caseInsensitive?: boolean;
+	/** Only highlight matches not adjoined by letters, digits, or `_`. */
+	wholeWord?: boolean;
+	/** Width measurement options. */
+	width?: WidthOptions;
+}
+
+/** A match reported by `findVisible`. */
+export interface VisibleMatch {
+	/** First visible column of the match. */
+	startCol: number;
+	/** Visible column just past the match. */
+	endCol: number;
+	/** UTF-16 index of the first matched grapheme. */
+	startIndex: number;
+	/** UTF-16 index just past the last matched grapheme. */
+	endIndex: number;
 }
 
 /** Ellipsis strategy for truncation. */
@@ -38,35 +528,467 @@ declare module "../bindings" {
 		 * Truncate text to a visible width, optionally padding with spaces.
 		 * @param text UTF-16 input text.
 		 * @param maxWidth Maximum visible width in terminal cells.
-		 * @param ellipsisKind Ellipsis strategy (see {@link Ellipsis}).
+		 * @param ellipsis Ellipsis strategy (see {@link Ellipsis}) or custom ellipsis string.
+		 * @param pad Whether to pad the output to `maxWidth`.
+		 * @param options Width measurement options.
+		 * @param fill Padding fill; spaces appended as-is when omitted.
+		 */
+		truncateToWidth(
+			text: string,
+			maxWidth: number,
+			ellipsis: Ellipsis | string,
+			pad: boolean,
+			options?: WidthOptions,
+			fill?: PadOptions,
+		): string;
+		/**
+		 * Truncate text to a visible width, returning a result object.
+		 * @param text UTF-16 input text.
+		 * @param maxWidth Maximum visible width in terminal cells.
+		 * @param ellipsis Ellipsis strategy (see {@link Ellipsis}) or custom ellipsis string.
 		 * @param pad Whether to pad the output to `maxWidth`.
+		 * @param carryStyle Whether to report the SGR state active at the end of the kept text.
+		 * @param options Width measurement options.
+		 * @param fill Padding fill; spaces appended as-is when omitted.
 		 */
-		truncateToWidth(text: string, maxWidth: number, ellipsisKind: number, pad: boolean): string;
+		truncateToWidthEx(
+			text: string,
+			maxWidth: number,
+			ellipsis: Ellipsis | string,
+			pad: boolean,
+			carryStyle?: boolean,
+			options?: WidthOptions,
+			fill?: PadOptions,
+		): TruncateResult;
+		/**
+		 * Pad text to a visible width without truncating; the fill follows a reset unless `styledFill` is set.
+		 * @param text UTF-16 input text.
+		 * @param width Target visible width in terminal cells.
+		 * @param fill Padding fill (default: unstyled spaces).
+		 * @param options Width measurement options.
+		 */
+		padToWidth(text: string, width: number, fill?: PadOptions, options?: WidthOptions): string;
+		/**
+		 * Truncate each line to a visible width and cap the number of lines; each line is self-contained in style.
+		 * @param text UTF-16 input text.
+		 * @param maxWidth Maximum visible width of each line in terminal cells.
+		 * @param maxLines Maximum number of lines, including the indicator.
+		 * @param ellipsis Ellipsis strategy (see {@link Ellipsis}) or custom ellipsis string.
+		 * @param pad Whether to pad each line to `maxWidth`.
+		 * @param indicator Last line shown when lines are dropped; `{n}` is the number dropped.
+		 * @param options Width measurement options.
+		 */
+		truncateLines(
+			text: string,
+			maxWidth: number,
+			maxLines: number,
+			ellipsis: Ellipsis | string,
+			pad: boolean,
+			indicator?: string,
+			options?: WidthOptions,
+		): string;
+		/**
+		 * Lay out a table row: allocate column widths summing to `totalWidth` (minimums, then content, then surplus by
+		 * weight) and truncate with "…" or pad each cell to exactly its width, resetting styled cells.
+		 * @param cells UTF-16 cell texts with optional ANSI escapes.
+		 * @param totalWidth Width of the row in terminal cells.
+		 * @param spec One column spec per cell.
+		 * @param options Width measurement options.
+		 */
+		layoutColumns(cells: string[], totalWidth: number, spec: ColumnSpec[], options?: WidthOptions): string[];
+		/**
+		 * Truncate parts so their widths sum to at most `totalWidth`. The lowest priority shrinks first, widest parts
+		 * first within a priority, none below `minWidth` until all are at their minimums.
+		 * @param parts Texts with their minimum widths and priorities.
+		 * @param totalWidth Budget in terminal cells.
+		 * @param ellipsis Ellipsis strategy (see {@link Ellipsis}) or custom ellipsis string.
+		 * @param options Width measurement options.
+		 */
+		fitBudget(parts: BudgetPart[], totalWidth: number, ellipsis: Ellipsis | string, options?: WidthOptions): string[];
+		/**
+		 * Align each line within exactly `width` visible columns, truncating with "…" when too wide; styled lines end
+		 * with a reset before the right padding.
+		 * @param lines UTF-16 lines with optional ANSI escapes.
+		 * @param width Target width in terminal cells.
+		 * @param align Alignment; `"center"` puts an odd extra cell on the right.
+		 * @param options Width measurement options.
+		 */
+		alignBlock(
+			lines: string[],
+			width: number,
+			align: "left" | "center" | "right",
+			options?: WidthOptions,
+		): string[];
+		/**
+		 * Align every line of `text` like `alignBlock`, splitting on newlines and joining the result with `\n`.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param width Target width in terminal cells.
+		 * @param align Alignment; `"center"` puts an odd extra cell on the right.
+		 * @param options Width measurement options.
+		 */
+		alignText(text: string, width: number, align: "left" | "center" | "right", options?: WidthOptions): string;
+		/**
+		 * Tile a pattern to exactly `width` visible columns, cutting the last repetition on a grapheme boundary and
+		 * replacing a wide grapheme that would overshoot with a space. ANSI codes repeat and end with one reset.
+		 * @param pattern UTF-16 pattern with optional ANSI escapes.
+		 * @param width Target width in terminal cells.
+		 */
+		repeatToWidth(pattern: string, width: number): string;
+		/**
+		 * Find non-overlapping occurrences of `needle` in the visible text of `haystack`, ignoring ANSI escapes.
+		 * Matches never start or end inside a grapheme cluster.
+		 * @param haystack UTF-16 text with optional ANSI escapes.
+		 * @param needle Plain text to search for.
+		 * @param options Search and width options.
+		 */
+		findVisible(haystack: string, needle: string, options?: FindVisibleOptions): VisibleMatch[];
+		/**
+		 * Wrap every match of `query` in the visible text of `line` in `sgr`, restoring the previous style after each
+		 * match. Returns `line` unchanged when nothing matches.
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param query Plain text to search for.
+		 * @param sgr SGR sequence for the highlight (e.g. `"\x1b[43m"`).
+		 * @param options Search and width options.
+		 */
+		highlightMatches(line: string, query: string, sgr: string, options?: HighlightOptions): string;
+		/**
+		 * Truncate text to a visible width by dropping leading content.
+		 * @param text UTF-16 input text.
+		 * @param maxWidth Maximum visible width in terminal cells.
+		 * @param ellipsis Ellipsis strategy (see {@link Ellipsis}) or custom ellipsis string.
+		 * @param pad Whether to pad the output to `maxWidth`.
+		 * @param options Width measurement options.
+		 */
+		truncateStart(
+			text: string,
+			maxWidth: number,
+			ellipsis: Ellipsis | string,
+			pad: boolean,
+			options?: WidthOptions,
+		): string;
+		/**
+		 * Truncate text to a visible width by eliding the middle.
+		 * @param text UTF-16 input text.
+		 * @param maxWidth Maximum visible width in terminal cells.
+		 * @param ellipsis Ellipsis strategy (see {@link Ellipsis}) or custom ellipsis string.
+		 * @param pad Whether to pad the output to `maxWidth`.
+		 * @param headBias Fraction of the budget kept from the head (0..1).
+		 * @param options Width measurement options.
+		 */
+		truncateMiddle(
+			text: string,
+			maxWidth: number,
+			ellipsis: Ellipsis | string,
+			pad: boolean,
+			headBias?: number,
+			options?: WidthOptions,
+		): string;
+		/**
+		 * Truncate a path to a visible width, preserving the basename.
+		 * @param path UTF-16 path.
+		 * @param maxWidth Maximum visible width in terminal cells.
+		 * @param separator Characters to split components on (default "/").
+		 * @param options Width measurement options.
+		 */
+		truncatePath(path: string, maxWidth: number, separator?: string, options?: WidthOptions): string;
 		/**
 		 * Wrap text to a visible width, preserving ANSI codes across line breaks.
 		 * @param text UTF-16 input text with optional ANSI escapes.
 		 * @param width Maximum visible width per line.
+		 * @param options Extra break opportunities besides spaces, and width options.
+		 */
+		wrapTextWithAnsi(text: string, width: number, options?: WrapOptions): string[];
+		/**
+		 * Re-wrap previously wrapped lines to a new width, joining the lines of each paragraph first.
+		 * @param lines UTF-16 wrapped lines with optional ANSI escapes.
+		 * @param newWidth Maximum visible width per output line.
+		 * @param options Old width hint and explicit hard breaks.
+		 * @param wrap Extra break opportunities besides spaces, and width options.
 		 */
-		wrapTextWithAnsi(text: string, width: number): string[];
+		reflow(lines: string[], newWidth: number, options?: ReflowOptions, wrap?: WrapOptions): string[];
 		/**
 		 * Slice a range of visible columns from a line.
 		 * @param line UTF-16 input line with optional ANSI escapes.
-		 * @param startCol Starting column in terminal cells.
+		 * @param startCol Starting column in terminal cells; negative counts from the end of the line and restores
+		 * the SGR state in effect before the slice.
+		 * @param length Number of visible cells to include.
+		 * @param strict Whether to drop graphemes that overflow the range.
+		 * @param options Style carrying, clipped-grapheme padding, and width options.
+		 */
+		sliceWithWidth(
+			line: string,
+			startCol: number,
+			length: number,
+			strict: boolean,
+			options?: SliceOptions,
+		): SliceWithWidthResult;
+		/**
+		 * Slice the same column range from many lines in one call, each result identical to `sliceWithWidth`.
+		 * @param lines UTF-16 input lines with optional ANSI escapes.
+		 * @param startCol Starting column in terminal cells; negative counts from the end of each line.
 		 * @param length Number of visible cells to include.
 		 * @param strict Whether to drop graphemes that overflow the range.
+		 * @param options Style carrying, clipped-grapheme padding, and width options.
 		 */
-		sliceWithWidth(line: string, startCol: number, length: number, strict: boolean): SliceWithWidthResult;
+		sliceViewport(
+			lines: string[],
+			startCol: number,
+			length: number,
+			strict: boolean,
+			options?: SliceOptions,
+		): SliceWithWidthResult[];
 		/**
 		 * Measure the visible width of text (excluding ANSI codes).
 		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param options Width measurement options.
 		 */
-		visibleWidth(text: string): number;
+		visibleWidth(text: string, options?: WidthOptions): number;
+		/**
+		 * Check whether text fits within a visible width, stopping at the first cell past it.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param maxWidth Maximum visible width in terminal cells.
+		 * @param options Width measurement options.
+		 */
+		/**
+		 * Measure a line's visible width without trailing spaces and tabs, plus the trailing whitespace's columns and
+		 * start index. Escapes after the whitespace (e.g. a reset) don't make it content.
+		 * @param line UTF-16 line with optional ANSI escapes.
+		 * @param options Width measurement options.
+		 */
+		measureTrimmed(line: string, options?: WidthOptions): TrimmedWidth;
+		fitsWithin(text: string, maxWidth: number, options?: WidthOptions): boolean;
+		/**
+		 * Check many lines against a visible width in one call; results align with `lines`.
+		 * @param lines UTF-16 input lines with optional ANSI escapes.
+		 * @param maxWidth Maximum visible width in terminal cells.
+		 * @param options Width measurement options.
+		 */
+		fitsWithinEach(lines: string[], maxWidth: number, options?: WidthOptions): boolean[];
+		/**
+		 * Measure the visible width of UTF-8 bytes like `visibleWidth`, without decoding to a string.
+		 * Invalid UTF-8 sequences measure like lone surrogates; `controlChars` other than `"zero"` is rejected.
+		 * @param buf UTF-8 input with optional ANSI escapes.
+		 * @param options Width measurement options.
+		 */
+		visibleWidthUtf8(buf: Uint8Array, options?: WidthOptions): number;
+		/**
+		 * Truncate UTF-8 bytes like `truncateToWidth`, without decoding to a string.
+		 * @param buf UTF-8 input with optional ANSI escapes.
+		 * @param maxWidth Maximum visible width in terminal cells.
+		 * @param ellipsis Ellipsis strategy (see {@link Ellipsis}) or custom ellipsis string.
+		 * @param pad Whether to pad the output to `maxWidth`.
+		 * @param options Width measurement options.
+		 */
+		truncateToWidthUtf8(
+			buf: Uint8Array,
+			maxWidth: number,
+			ellipsis: Ellipsis | string,
+			pad: boolean,
+			options?: WidthOptions,
+		): Buffer;
+		/**
+		 * Slice a range of visible columns from UTF-8 bytes like `sliceWithWidth`, without decoding to a string.
+		 * @param buf UTF-8 input line with optional ANSI escapes.
+		 * @param startCol Starting column in terminal cells.
+		 * @param length Number of visible cells to include.
+		 * @param strict Whether to drop graphemes that overflow the range.
+		 * @param options Width measurement options.
+		 */
+		sliceWithWidthUtf8(
+			buf: Uint8Array,
+			startCol: number,
+			length: number,
+			strict: boolean,
+			options?: WidthOptions,
+		): SliceUtf8Result;
+		/**
+		 * Measure the visible widths of many lines in one call (aligned with the input).
+		 * @param lines UTF-16 input lines with optional ANSI escapes.
+		 * @param options Width measurement options.
+		 */
+		visibleWidths(lines: string[], options?: WidthOptions): number[];
+		/**
+		 * Measure the visible width of each line (split as in {@link NativeBindings.measureLines}).
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param options Width measurement options.
+		 */
+		visibleLineWidths(text: string, options?: WidthOptions): number[];
+		/**
+		 * Measure every line of multi-line text in a single pass.
+		 * `\n` and `\r\n` break lines, a lone `\r` is zero-width content unless `loneCr` says otherwise, and a
+		 * trailing newline yields an empty last line (matching `String.prototype.split("\n")`).
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param options Width measurement options.
+		 * @param loneCr Lone carriage return handling (default `"zero"`).
+		 */
+		measureLines(text: string, options?: WidthOptions, loneCr?: LoneCrMode): LineMetrics;
+		/**
+		 * Split text into lines that render independently: each line is prefixed with the SGR state carried
+		 * over from earlier lines and ends with a reset while style is active. Lines split as in `measureLines`.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param options Width measurement options.
+		 * @param loneCr Lone carriage return handling (default `"zero"`).
+		 */
+		splitAnsiLines(text: string, options?: WidthOptions, loneCr?: LoneCrMode): SplitLinesResult;
+		/**
+		 * Convert line endings: `"lf"` turns `\r\n` into `\n`, `"crlf"` turns bare `\n` into `\r\n`. Lone `\r` is
+		 * kept as content. Returns the input unchanged when there is nothing to rewrite.
+		 * @param text UTF-16 input text.
+		 * @param mode Target line ending.
+		 */
+		normalizeNewlines(text: string, mode: "lf" | "crlf"): string;
+		/**
+		 * Find the grapheme occupying a visible column (every cell of a wide grapheme maps to it).
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param column Visible column in terminal cells.
+		 * @param options Width measurement options.
+		 * @returns The grapheme, or `null` past the end of the line.
+		 */
+		graphemeAtColumn(line: string, column: number, options?: WidthOptions): GraphemeInfo | null;
+		/**
+		 * Measure every grapheme of a line in one call (ANSI escapes skipped), consistent with `graphemeAtColumn`.
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param options Width measurement options.
+		 */
+		measureGraphemes(line: string, options?: WidthOptions): GraphemeMetrics;
+		/**
+		 * Count grapheme clusters, ignoring ANSI escapes.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 */
+		graphemeCount(text: string): number;
+		/**
+		 * UTF-16 index just past the grapheme after `index` (cursor right); never lands inside an ANSI escape.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param index UTF-16 cursor index.
+		 * @returns The boundary, or the text length at the end.
+		 */
+		nextGraphemeBoundary(text: string, index: number): number;
+		/**
+		 * UTF-16 index of the start of the grapheme before `index` (cursor left); never lands inside an ANSI escape.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param index UTF-16 cursor index.
+		 * @returns The boundary, or 0 at the start.
+		 */
+		prevGraphemeBoundary(text: string, index: number): number;
+		/**
+		 * Substring a styled line by raw UTF-16 indices without corrupting its styles. Indices inside an escape
+		 * sequence move back to its start; indices inside a surrogate pair or grapheme cluster move outward.
+		 * @param line UTF-16 input text with optional ANSI escapes.
+		 * @param start UTF-16 index of the first code unit to keep.
+		 * @param end UTF-16 index just past the last code unit to keep.
+		 * @returns The balanced slice and the snapped indices.
+		 */
+		substringBalanced(line: string, start: number, end: number): BalancedSubstring;
+		/**
+		 * Insert text into a line at a visible column, restoring the line's style after the insertion.
+		 * Short lines are padded with spaces; a wide char under `column` moves after the insertion.
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param column Visible column in terminal cells.
+		 * @param insert Text to insert (may contain ANSI codes).
+		 * @param options Width measurement options.
+		 */
+		insertAtColumn(line: string, column: number, insert: string, options?: WidthOptions): string;
+		/**
+		 * Replace the visible content in `[startCol, endCol)` of a line, keeping everything before `startCol`
+		 * byte-identical and restoring the line's style after `replacement`. Ranges that cut a wide char grow to
+		 * cover it; the rest of the line shifts by the width difference.
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param startCol First visible column to replace.
+		 * @param endCol Visible column just past the replaced range.
+		 * @param replacement Replacement text (may contain ANSI codes).
+		 * @param options Width measurement options.
+		 */
+		replaceRange(
+			line: string,
+			startCol: number,
+			endCol: number,
+			replacement: string,
+			options?: WidthOptions,
+		): string;
+		/**
+		 * Apply an SGR sequence to a range of visible columns, restoring the original style at `end`.
+		 * SGR codes inside the range are followed by `sgr` again so the highlight wins; wide chars
+		 * straddling a boundary join the range.
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param start First column of the range.
+		 * @param end Column just past the range.
+		 * @param sgr Full SGR sequence to apply (e.g. `"\x1b[7m"`).
+		 * @param options Width measurement options.
+		 */
+		applyStyleToRange(line: string, start: number, end: number, sgr: string, options?: WidthOptions): string;
+		/**
+		 * Query the SGR style in effect at a visible column (past the end: the style after the last sequence).
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param column Visible column in terminal cells.
+		 * @param options Width measurement options.
+		 * @param resolveColors Fill each color's `rgb` from the default palette (default false).
+		 */
+		styleAtColumn(line: string, column: number, options?: WidthOptions, resolveColors?: boolean): StyleInfo;
+		/**
+		 * Serialize a style to the SGR sequence that establishes it (empty for the default style).
+		 * @param style Structured style, e.g. from `styleAtColumn`.
+		 */
+		styleToSgr(style: StyleInfo): string;
+		/**
+		 * Resolve a color to RGB. Indexed colors 0-15 come from the palette; the cube and grayscale ramp are fixed.
+		 * Returns `null` for the default color and out-of-range values.
+		 * @param color Structured color, e.g. from `styleAtColumn`.
+		 * @param palette 16 base colors as `0xRRGGBB` (default: the palette set by `setDefaultPalette`).
+		 */
+		resolveColor(color: ColorInfo, palette?: number[]): RgbColor | null;
+		/**
+		 * Replace the process-wide base palette used by `resolveColor` and `resolveColors` options.
+		 * @param colors 16 base colors as `0xRRGGBB`; pass `[]` to restore the xterm defaults.
+		 */
+		setDefaultPalette(colors: number[]): void;
+		/**
+		 * Compare two rendered lines for a minimal redraw; regions match only when text and style both match.
+		 * Boundaries fall between graphemes, so wide characters are never split.
+		 * @param oldLine Previously rendered line.
+		 * @param newLine Line to render.
+		 */
+		diffVisible(oldLine: string, newLine: string): VisibleDiff;
+		/**
+		 * Parse a styled line into spans of plain text with structured styles.
+		 * A new span starts whenever the SGR style or OSC 8 link changes.
+		 * @param line UTF-16 input line with optional ANSI escapes.
+		 * @param options Parse options.
+		 */
+		parseAnsiSpans(line: string, options?: ParseSpansOptions): AnsiSpan[];
+		/**
+		 * Serialize spans back to an ANSI string with the same visible result.
+		 * @param spans Spans, e.g. from `parseAnsiSpans`.
+		 */
+		spansToAnsi(spans: AnsiSpan[]): string;
+		/**
+		 * Find OSC 8 hyperlinks (BEL- or ST-terminated) with the columns and UTF-16 range of the text each covers.
+		 * Opening a link while another is open ends the first, an unclosed link extends to the end of the line, and
+		 * links with no visible text are omitted.
+		 * @param line UTF-16 input text with optional ANSI escapes.
+		 */
+		extractLinks(line: string): LinkRange[];
+		/**
+		 * Join styled fragments, inserting a reset between parts only when the preceding part leaves attributes
+		 * active. The separator is written unstyled; the last part's trailing style is left as-is.
+		 * @param parts UTF-16 fragments with optional ANSI escapes.
+		 * @param separator Text placed between parts.
+		 */
+		concatStyled(parts: string[], separator?: string): string;
+		/**
+		 * Render ANSI-styled text as HTML for a `<pre>` block: SGR runs become `<span>`s, text is escaped,
+		 * OSC 8 links (http, https, mailto, file) become `<a href>`, and other control sequences are dropped.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param options Palette and class output options.
+		 */
+		ansiToHtml(text: string, options?: AnsiToHtmlOptions): string;
 		/** Extract before/after segments around an overlay region.
 		 * @param line UTF-16 input line with optional ANSI escapes.
 		 * @param beforeEnd Column where the "before" segment ends.
 		 * @param afterStart Column where the "after" segment starts.
 		 * @param afterLen Visible width of the "after" segment.
 		 * @param strictAfter Whether to drop graphemes that overflow `afterLen`.
+		 * @param options Width measurement options.
+		 * @param window Start column of "before" and the style active at the line start; both segments restore it.
 		 */
 		extractSegments(
 			line: string,
@@ -74,6 +996,101 @@ declare module "../bindings" {
 			afterStart: number,
 			afterLen: number,
 			strictAfter: boolean,
+			options?: WidthOptions,
+			window?: SegmentWindow,
 		): ExtractSegmentsResult;
+		/**
+		 * Composite an overlay onto a base line at a visible column.
+		 * The overlay is clipped and padded to its region, the base's ANSI state is restored after it,
+		 * and the line is padded with spaces when the base is too short.
+		 * @param base UTF-16 base line with optional ANSI escapes.
+		 * @param overlay UTF-16 overlay text with optional ANSI escapes.
+		 * @param column Column where the overlay starts.
+		 * @param options Overlay region and strictness options.
+		 */
+		overlayAtColumn(base: string, overlay: string, column: number, options?: OverlayOptions): SliceWithWidthResult;
+		/**
+		 * Split a line into plain-text runs and escape sequences without interpreting them. Ranges are UTF-16
+		 * indices into `line`; an ESC that starts no valid sequence is part of the text.
+		 * @param line UTF-16 line with optional ANSI escapes.
+		 */
+		splitAnsiSegments(line: string): AnsiSegment[];
+		/**
+		 * Remove ANSI escape sequences (CSI and OSC) from text.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 */
+		stripAnsi(text: string): string;
+		/**
+		 * Neutralize control sequences other than SGR and OSC 8 hyperlinks (cursor movement, clears, titles, DCS).
+		 * Truncated sequences at the end of the text are treated as foreign.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param policy `"drop"` (default) removes them, `"escape"` shows them as `␛[2K`-style text, `"keep"` leaves them.
+		 */
+		sanitizeAnsi(text: string, policy?: SanitizePolicy): string;
+		/**
+		 * Neutralize control characters in untrusted text: C0 other than `\t`/`\n`, DEL, C1, lone ESC, and escape
+		 * sequences outside the allowlist (SGR by default) are stripped or shown in caret or Control Pictures notation.
+		 * @param text UTF-16 input text.
+		 * @param options Notation and allowed sequences.
+		 */
+		sanitizeForTerminal(text: string, options?: TerminalSanitizeOptions): string;
+		/**
+		 * Rewrite backspace overstrike from `man`/`nroff` (`X\bX` bold, `_\bX` underline) to SGR sequences,
+		 * merging consecutive overstruck characters; other backspaces are left alone.
+		 * @param text UTF-16 input text.
+		 */
+		convertOverstrike(text: string): string;
+		/**
+		 * Collapse redundant ANSI escape sequences without changing the rendered result.
+		 * SGR sequences that don't change the style before the next visible text are removed and adjacent
+		 * ones merged; trailing style changes are dropped except a final reset.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 */
+		optimizeAnsi(text: string): string;
+		/**
+		 * Rewrite truecolor and 256-color SGR colors to a smaller palette, leaving other sequences untouched.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param target `"256"` (xterm cube and grayscale ramp) or `"16"` (nearest basic color).
+		 */
+		downsampleColors(text: string, target: "256" | "16"): string;
+		/**
+		 * Upper- or lowercase the visible text of a line, leaving ANSI escape sequences byte-identical.
+		 * Full Unicode mappings are used, so the visible width can change (`ß` uppercases to `SS`).
+		 * @param line UTF-16 input text with optional ANSI escapes.
+		 * @param op `"upper"` or `"lower"`.
+		 */
+		mapTextPreservingAnsi(line: string, op: "upper" | "lower"): string;
+		/**
+		 * Remove selected style categories from SGR sequences, by naming what to drop or allowlisting what to keep;
+		 * sequences left empty are dropped and resets kept. Kept colors can be downsampled in the same pass.
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param options Categories to drop or keep.
+		 */
+		filterStyles(text: string, options: FilterStylesOptions): string;
+		/**
+		 * Analyze text in a single pass (width, length, ANSI usage, wide chars, tabs).
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param options Width measurement options; the reported length is always the input's.
+		 */
+		analyzeText(text: string, options?: WidthOptions): TextAnalysis;
+		/**
+		 * Replace tabs with spaces up to the next tab stop (ANSI escapes don't advance the column).
+		 * @param text UTF-16 input text with optional ANSI escapes.
+		 * @param tabStop Tab stop interval in cells (1-16).
+		 * @param startColumn Column the text starts at (default 0).
+		 */
+		expandTabs(text: string, tabStop: number, startColumn?: number): string;
+		/**
+		 * Replace the process-wide code point width overrides used by every measuring, slicing, and truncation
+		 * function. A grapheme whose first code point falls in a range takes that width (e.g. Nerd Font glyphs
+		 * rendered 2 cells wide). Ranges must not overlap or include ASCII; pass `[]` to clear.
+		 * @param overrides Inclusive code point ranges with their widths.
+		 */
+		setWidthOverrides(overrides: WidthOverride[]): void;
+
+		/** MeasuredLine class constructor for pre-scanned lines. */
+		MeasuredLine: MeasuredLineConstructor;
+		/** AnsiTracker class constructor for chunked streams. */
+		AnsiTracker: AnsiTrackerConstructor;
 	}
 }
@@ -2,7 +2,7 @@ import { beforeAll, describe, expect, it } from "bun:test";
 import * as fs from "node:fs/promises";
 import * as os from "node:os";
 import * as path from "node:path";
-import { FileType, type GlobMatch, glob, grep, htmlToMarkdown } from "../src/index";
+import { type CommandPolicyRequest, FileType, type GlobMatch, glob, grep, htmlToMarkdown, Shell } from "../src/index";
 
 let testDir: string;
 
@@ -153,4 +153,63 @@ describe("pi-natives", () => {
 			// Navigation/footer may or may not be removed depending on preprocessing
 		});
 	});
+
+	describe("Shell output", () => {
+		it.skipIf(process.platform === "win32")("should keep each stream's order", async () => {
+			const shell = new Shell();
+			let output = "";
+			const result = await shell.run({ command: "echo a; echo err >&2; echo b" }, (error, chunk) => {
+				if (!error) output += chunk;
+			});
+
+			expect(result.stdoutBytes).toBe(4);
+			expect(result.stderrBytes).toBe(4);
+			expect(result.hadStderr).toBe(true);
+			// stdout and stderr use separate pipes, so only the order within each stream is kept.
+			expect(output.replace("err\n", "")).toBe("a\nb\n");
+			expect(output).toContain("err\n");
+		});
+	});
+
+	describe("Shell onCommandPolicy", () => {
+		it.skipIf(process.platform === "win32")("should consult the policy for exec", async () => {
+			// Were the policy skipped, `false` would replace the process running the shell, so run it in a child.
+			const script = `
+				import { Shell } from ${JSON.stringify(path.join(import.meta.dir, "../src/index"))};
+				const asked = [];
+				const shell = new Shell({
+					allowExec: "full",
+					onCommandPolicy: async request => {
+						asked.push(request);
+						return { allow: request.argv[0] !== "false", reason: "not allowed" };
+					},
+				});
+				const result = await shell.run({ command: "exec false" });
+				console.log(JSON.stringify({ exitCode: result.exitCode, asked }));
+				process.exit(0);
+			`;
+			const child = Bun.spawn([process.execPath, "-e", script], { stdout: "pipe" });
+			const [stdout, exitCode] = await Promise.all([new Response(child.stdout).text(), child.exited]);
+			expect(exitCode).toBe(0);
+			const { exitCode: runExitCode, asked } = JSON.parse(stdout) as {
+				exitCode: number;
+				asked: CommandPolicyRequest[];
+			};
+
+			expect(runExitCode).toBe(126);
+			expect(asked.map(request => request.argv)).toEqual([["false"]]);
+			expect(asked[0].program).toBeDefined();
+		});
+
+		it("should deny when the callback throws", async () => {
+			const shell = new Shell({
+				onCommandPolicy: () => {
+					throw new Error("boom");
+				},
+			});
+			const result = await shell.run({ command: "cd /" });
+
+			expect(result.exitCode).toBe(126);
+		});
+	});
 });